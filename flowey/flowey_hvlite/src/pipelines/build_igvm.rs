@@ -32,6 +32,23 @@ pub enum OpenhclRecipeCli {
     X64Devkern,
 }
 
+impl From<OpenhclRecipeCli> for OpenhclIgvmRecipe {
+    fn from(recipe: OpenhclRecipeCli) -> Self {
+        match recipe {
+            OpenhclRecipeCli::X64 => OpenhclIgvmRecipe::X64,
+            OpenhclRecipeCli::X64Devkern => OpenhclIgvmRecipe::X64Devkern,
+            OpenhclRecipeCli::X64TestLinuxDirect => OpenhclIgvmRecipe::X64TestLinuxDirect,
+            OpenhclRecipeCli::X64TestLinuxDirectDevkern => {
+                OpenhclIgvmRecipe::X64TestLinuxDirectDevkern
+            }
+            OpenhclRecipeCli::X64Cvm => OpenhclIgvmRecipe::X64Cvm,
+            OpenhclRecipeCli::X64CvmDevkern => OpenhclIgvmRecipe::X64CvmDevkern,
+            OpenhclRecipeCli::Aarch64 => OpenhclIgvmRecipe::Aarch64,
+            OpenhclRecipeCli::Aarch64Devkern => OpenhclIgvmRecipe::Aarch64Devkern,
+        }
+    }
+}
+
 /// Build OpenHCL IGVM files for local development. DO NOT USE IN CI.
 #[derive(clap::Args)]
 pub struct BuildIgvmCli<Recipe = OpenhclRecipeCli>
@@ -296,18 +313,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                 artifact_dir: ctx.publish_artifact(pub_out_dir),
                 done: ctx.new_done_handle(),
 
-                base_recipe: match recipe {
-                    OpenhclRecipeCli::X64 => OpenhclIgvmRecipe::X64,
-                    OpenhclRecipeCli::X64Devkern => OpenhclIgvmRecipe::X64Devkern,
-                    OpenhclRecipeCli::X64TestLinuxDirect => OpenhclIgvmRecipe::X64TestLinuxDirect,
-                    OpenhclRecipeCli::X64TestLinuxDirectDevkern => {
-                        OpenhclIgvmRecipe::X64TestLinuxDirectDevkern
-                    }
-                    OpenhclRecipeCli::X64Cvm => OpenhclIgvmRecipe::X64Cvm,
-                    OpenhclRecipeCli::X64CvmDevkern => OpenhclIgvmRecipe::X64CvmDevkern,
-                    OpenhclRecipeCli::Aarch64 => OpenhclIgvmRecipe::Aarch64,
-                    OpenhclRecipeCli::Aarch64Devkern => OpenhclIgvmRecipe::Aarch64Devkern,
-                },
+                base_recipe: recipe.into(),
                 release,
 
                 customizations: flowey_lib_hvlite::_jobs::local_build_igvm::Customizations {
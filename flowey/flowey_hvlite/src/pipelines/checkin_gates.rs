@@ -381,6 +381,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                         build_mode: flowey_lib_hvlite::build_nextest_vmm_tests::BuildNextestVmmTestsMode::Archive(
                             ctx.publish_typed_artifact(pub_vmm_tests_archive_windows_x86),
                         ),
+                        rust_toolchain: None,
                     });
                 }
                 CommonArch::Aarch64 => {
@@ -392,6 +393,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                         build_mode: flowey_lib_hvlite::build_nextest_vmm_tests::BuildNextestVmmTestsMode::Archive(
                             ctx.publish_typed_artifact(pub_vmm_tests_archive_windows_aarch64),
                         ),
+                        rust_toolchain: None,
                     });
                 }
             }
@@ -527,6 +529,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                     build_mode: flowey_lib_hvlite::build_nextest_vmm_tests::BuildNextestVmmTestsMode::Archive(
                         ctx.publish_typed_artifact(pub_vmm_tests_archive_linux_x86),
                     ),
+                    rust_toolchain: None,
                 });
             }
 
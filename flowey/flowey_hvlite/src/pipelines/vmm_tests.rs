@@ -1,8 +1,11 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use crate::pipelines::build_igvm::OpenhclRecipeCli;
 use flowey::node::prelude::ReadVar;
 use flowey::pipeline::prelude::*;
+use flowey_lib_hvlite::_jobs::local_build_and_run_nextest_vmm_tests::BuildSelections;
+use flowey_lib_hvlite::_jobs::local_build_and_run_nextest_vmm_tests::RemoteTestHost;
 use flowey_lib_hvlite::_jobs::local_build_and_run_nextest_vmm_tests::VmmTestSelectionFlags;
 use flowey_lib_hvlite::_jobs::local_build_and_run_nextest_vmm_tests::VmmTestSelections;
 use flowey_lib_hvlite::install_vmm_tests_deps::VmmTestsDepSelections;
@@ -39,6 +42,10 @@ pub struct VmmTestsCli {
     /// Custom list of artifacts to download
     #[clap(long, conflicts_with("flags"), requires("filter"))]
     artifacts: Vec<KnownTestArtifacts>,
+    /// Only build these OpenHCL recipes, instead of all the recipes used by
+    /// the tests
+    #[clap(long, requires("filter"), value_delimiter = ',')]
+    openhcl_recipes: Vec<OpenhclRecipeCli>,
     /// Flags used to generate the VMM test filter
     ///
     /// Syntax: `--flags=<+|-><flag>,..`
@@ -63,13 +70,68 @@ pub struct VmmTestsCli {
     /// Release build instead of debug build
     #[clap(long)]
     release: bool,
+    /// Build the VMM tests with this rustup toolchain (e.g. `nightly` or
+    /// `1.85.0`) instead of the repo's default one
+    #[clap(long, conflicts_with("prebuilt_archive"))]
+    rust_toolchain: Option<String>,
 
     /// Build only, do not run
     #[clap(long)]
     build_only: bool,
+    /// Skip building and run the tests from this nextest archive, such as
+    /// the one from a previous `--build-only` run into the same output dir
+    ///
+    /// The other test binaries must already be in the output dir.
+    #[clap(long, conflicts_with("build_only"))]
+    prebuilt_archive: Option<PathBuf>,
     /// Copy extras to output dir (symbols, etc)
     #[clap(long)]
     copy_extras: bool,
+
+    /// Number of times to retry failing tests
+    ///
+    /// Tests that pass on retry are reported as flaky in the summary at the
+    /// end of the run.
+    #[clap(long)]
+    retries: Option<u32>,
+    /// Capture nextest's machine-readable libtest-json output to a file, and
+    /// summarize the results from it
+    ///
+    /// This relies on an experimental nextest feature.
+    #[clap(long, conflicts_with("remote_host"))]
+    libtest_json: bool,
+
+    /// Additional argument to pass to `cargo nextest run`, which may start
+    /// with a hyphen. Can be repeated.
+    ///
+    /// These are appended verbatim after all other arguments, and are not
+    /// validated.
+    #[clap(long, allow_hyphen_values = true)]
+    nextest_args: Vec<String>,
+    /// Additional arguments to pass to `cargo nextest run`, after a trailing
+    /// `--`. These are appended after any `--nextest-args`.
+    #[clap(last = true)]
+    trailing_nextest_args: Vec<String>,
+
+    /// Run the tests on a remote host over SSH (e.g. `user@host`)
+    ///
+    /// The test content is copied to the remote host, the tests are run
+    /// there, and the results are copied back to the output dir. Test
+    /// dependencies must already be installed on the remote host.
+    #[clap(long, conflicts_with("build_only"))]
+    remote_host: Option<String>,
+    /// Directory on the remote host to copy the test content to
+    #[clap(long, requires("remote_host"), default_value = "vmm_tests")]
+    remote_dir: String,
+
+    /// Remove Hyper-V VMs left behind by previous test runs before running
+    /// the tests, and any left behind by this run afterwards
+    #[clap(long, conflicts_with_all(["build_only", "remote_host"]))]
+    clean_environment: bool,
+    /// When cleaning the environment, also remove the virtual switches and NAT
+    /// rules whose names start with this prefix
+    #[clap(long, requires("clean_environment"))]
+    clean_network_prefix: Option<String>,
 }
 
 impl IntoPipeline for VmmTestsCli {
@@ -83,15 +145,28 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
             dir,
             filter,
             artifacts,
+            openhcl_recipes,
             flags,
             verbose,
             install_missing_deps,
             unstable_whp,
             release,
+            rust_toolchain,
             build_only,
+            prebuilt_archive,
             copy_extras,
+            retries,
+            libtest_json,
+            mut nextest_args,
+            trailing_nextest_args,
+            remote_host,
+            remote_dir,
+            clean_environment,
+            clean_network_prefix,
         } = self;
 
+        nextest_args.extend(trailing_nextest_args);
+
         let openvmm_repo = flowey_lib_common::git_checkout::RepoSource::ExistingClone(
             ReadVar::from_static(crate::repo_root()),
         );
@@ -152,9 +227,13 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                         VmmTestSelections::Custom {
                             filter,
                             artifacts,
-                            // TODO: add a way to manually specify these
-                            // For now, just build and install everything.
-                            build: Default::default(),
+                            // TODO: add a way to manually specify the rest of
+                            // these. For now, just build and install everything.
+                            build: BuildSelections {
+                                openhcl_recipes: (!openhcl_recipes.is_empty())
+                                    .then(|| openhcl_recipes.into_iter().map(Into::into).collect()),
+                                ..Default::default()
+                            },
                             deps: match target_os {
                                 target_lexicon::OperatingSystem::Windows => {
                                     VmmTestsDepSelections::Windows {
@@ -183,8 +262,19 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                     },
                     unstable_whp,
                     release,
+                    rust_toolchain,
                     build_only,
+                    prebuilt_archive,
                     copy_extras,
+                    retries,
+                    libtest_json,
+                    nextest_args,
+                    remote: remote_host.map(|destination| RemoteTestHost {
+                        destination,
+                        dir: remote_dir,
+                    }),
+                    clean_environment,
+                    clean_network_prefix,
                     done: ctx.new_done_handle(),
                 },
             )
@@ -193,3 +283,42 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
         Ok(pipeline)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::VmmTestsCli;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        vmm_tests: VmmTestsCli,
+    }
+
+    #[test]
+    fn nextest_args() {
+        let cli = Cli::try_parse_from([
+            "vmm-tests",
+            "--dir",
+            "out",
+            "--nextest-args",
+            "--no-capture",
+            "--nextest-args=--test-threads=1",
+            "--copy-extras",
+            "--",
+            "--retries",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.vmm_tests.nextest_args,
+            ["--no-capture", "--test-threads=1"]
+        );
+        assert_eq!(cli.vmm_tests.trailing_nextest_args, ["--retries", "2"]);
+        assert!(cli.vmm_tests.copy_extras);
+
+        let cli = Cli::try_parse_from(["vmm-tests", "--dir", "out"]).unwrap();
+        assert!(cli.vmm_tests.nextest_args.is_empty());
+        assert!(cli.vmm_tests.trailing_nextest_args.is_empty());
+    }
+}
@@ -5,6 +5,8 @@
 
 use flowey::node::prelude::FlowPlatformKind;
 use flowey::node::prelude::RustRuntimeServices;
+use flowey::node::prelude::fs_err;
+use std::path::Path;
 
 pub mod cargo_output;
 pub mod extract;
@@ -31,3 +33,64 @@ pub fn bsdtar_name(rt: &mut RustRuntimeServices<'_>) -> &'static str {
         FlowPlatformKind::Unix => "bsdtar",
     }
 }
+
+/// Returns whether `output` is missing or older than any of `inputs`, and so
+/// needs to be regenerated.
+///
+/// Returns an error if any of the inputs do not exist.
+pub fn needs_update(
+    inputs: impl IntoIterator<Item = impl AsRef<Path>>,
+    output: &Path,
+) -> anyhow::Result<bool> {
+    let output_modified = match fs_err::metadata(output) {
+        Ok(meta) => meta.modified()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.into()),
+    };
+    for input in inputs {
+        // Treat equal timestamps as out of date, since the filesystem's
+        // timestamp granularity may hide a change.
+        if fs_err::metadata(input.as_ref())?.modified()? >= output_modified {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::needs_update;
+    use flowey::node::prelude::fs_err;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    fn set_modified(path: &std::path::Path, time: SystemTime) {
+        fs_err::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn needs_update_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let output = dir.path().join("output");
+        let now = SystemTime::now();
+
+        fs_err::write(&input, "in").unwrap();
+        set_modified(&input, now - Duration::from_secs(60));
+        assert!(needs_update([&input], &output).unwrap());
+
+        fs_err::write(&output, "out").unwrap();
+        set_modified(&output, now);
+        assert!(!needs_update([&input], &output).unwrap());
+
+        set_modified(&input, now + Duration::from_secs(60));
+        assert!(needs_update([&input], &output).unwrap());
+
+        needs_update([dir.path().join("missing")], &output).unwrap_err();
+    }
+}
@@ -84,18 +84,15 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                     let cache_dir = rt.read(cache_dir);
                     let target = rt.read(target);
 
-                    let cargo_nextest_bin = match target.operating_system {
-                        target_lexicon::OperatingSystem::Windows => "cargo-nextest.exe",
-                        _ => "cargo-nextest",
-                    };
+                    let cargo_nextest_bin = cargo_nextest_bin(&target);
                     let cached_bin_path = cache_dir.join(cargo_nextest_bin);
-                    let target = target.to_string();
 
                     if !matches!(rt.read(hitvar), CacheHit::Hit) {
                         let sh = xshell::Shell::new()?;
 
                         let nextest_archive = "nextest.tar.gz";
-                        xshell::cmd!(sh, "curl --fail -L https://get.nexte.st/{version}/{target}.tar.gz -o {nextest_archive}").run()?;
+                        let url = download_url(&version, &target);
+                        xshell::cmd!(sh, "curl --fail -L {url} -o {nextest_archive}").run()?;
                         xshell::cmd!(sh, "tar -xf {nextest_archive}").run()?;
 
                         // move the downloaded bin into the cache dir
@@ -116,3 +113,39 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
         Ok(())
     }
 }
+
+/// The name of the `cargo-nextest` binary in the release archive for `target`.
+fn cargo_nextest_bin(target: &target_lexicon::Triple) -> &'static str {
+    match target.operating_system {
+        target_lexicon::OperatingSystem::Windows => "cargo-nextest.exe",
+        _ => "cargo-nextest",
+    }
+}
+
+/// The URL of the `cargo-nextest` release archive for `target`, which is named
+/// after the target's full triple.
+fn download_url(version: &str, target: &target_lexicon::Triple) -> String {
+    format!("https://get.nexte.st/{version}/{target}.tar.gz")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_targets() {
+        let linux: target_lexicon::Triple = "x86_64-unknown-linux-gnu".parse().unwrap();
+        assert_eq!(
+            download_url("0.9.57", &linux),
+            "https://get.nexte.st/0.9.57/x86_64-unknown-linux-gnu.tar.gz"
+        );
+        assert_eq!(cargo_nextest_bin(&linux), "cargo-nextest");
+
+        let windows: target_lexicon::Triple = "x86_64-pc-windows-msvc".parse().unwrap();
+        assert_eq!(
+            download_url("0.9.57", &windows),
+            "https://get.nexte.st/0.9.57/x86_64-pc-windows-msvc.tar.gz"
+        );
+        assert_eq!(cargo_nextest_bin(&windows), "cargo-nextest.exe");
+    }
+}
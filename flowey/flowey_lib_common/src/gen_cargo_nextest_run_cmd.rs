@@ -28,6 +28,19 @@ pub struct Request {
         pub run_ignored: bool,
         /// Override fail fast setting
         pub fail_fast: Option<bool>,
+        /// Override the number of times to retry failing tests
+        pub retries: Option<u32>,
+        /// Emit machine-readable libtest-json output on stdout.
+        ///
+        /// This format is experimental in nextest, and requires
+        /// [`NEXTEST_EXPERIMENTAL_LIBTEST_JSON`] to be set, which this node
+        /// does automatically.
+        pub libtest_json: bool,
+        /// Additional arguments passed verbatim to `nextest run`, after all
+        /// other arguments.
+        ///
+        /// These are not validated in any way.
+        pub extra_args: Vec<String>,
         /// Additional env vars set when executing the tests.
         pub extra_env: Option<ReadVar<BTreeMap<String, String>>>,
         /// Generate a portable command with paths relative to `test_content_dir`
@@ -66,6 +79,66 @@ pub struct Command {
     pub shell: CommandShell,
 }
 
+/// The outcome of running a nextest command, parsed from nextest's
+/// documented exit codes.
+///
+/// <https://github.com/nextest-rs/nextest/blob/main/nextest-metadata/src/exit_codes.rs>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextestExitStatus {
+    /// All tests passed.
+    Passed,
+    /// At least one test failed.
+    TestsFailed,
+    /// No tests matched the filter.
+    NoTestsRun,
+    /// Building the tests failed.
+    BuildFailed,
+    /// Listing the tests in a test binary failed.
+    TestListFailed,
+    /// The filter expression was invalid.
+    InvalidFilter,
+    /// nextest failed to set up, e.g. due to an invalid config.
+    SetupError,
+    /// The installed nextest is older than the version the config requires.
+    RequiredVersionNotMet,
+    /// Any other exit code, or `None` if nextest was killed by a signal.
+    Other(Option<i32>),
+}
+
+impl NextestExitStatus {
+    /// Parses the exit code of a nextest command.
+    pub fn from_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => Self::Passed,
+            Some(100) => Self::TestsFailed,
+            Some(4) => Self::NoTestsRun,
+            Some(101) => Self::BuildFailed,
+            Some(104) => Self::TestListFailed,
+            Some(94) => Self::InvalidFilter,
+            Some(96) => Self::SetupError,
+            Some(92) => Self::RequiredVersionNotMet,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl std::fmt::Display for NextestExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passed => write!(f, "all tests passed"),
+            Self::TestsFailed => write!(f, "tests failed"),
+            Self::NoTestsRun => write!(f, "no tests were run"),
+            Self::BuildFailed => write!(f, "build failed"),
+            Self::TestListFailed => write!(f, "failed to list tests"),
+            Self::InvalidFilter => write!(f, "invalid filter expression"),
+            Self::SetupError => write!(f, "setup error"),
+            Self::RequiredVersionNotMet => write!(f, "required nextest version not met"),
+            Self::Other(Some(code)) => write!(f, "exit code {code}"),
+            Self::Other(None) => write!(f, "terminated by a signal"),
+        }
+    }
+}
+
 new_flow_node!(struct Node);
 
 impl FlowNode for Node {
@@ -89,6 +162,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
             nextest_filter_expr,
             run_ignored,
             fail_fast,
+            retries,
+            libtest_json,
+            extra_args,
             portable,
             command,
         } in requests
@@ -191,6 +267,8 @@ enum NextestInvocation {
                                     target,
                                     profile,
                                     extra_env,
+                                    // already resolved into `rust_toolchain`
+                                    rust_toolchain: _,
                                 },
                             nextest_installed: _, // side-effect
                             rust_toolchain,
@@ -260,12 +338,9 @@ enum NextestInvocation {
                         }
                         .into(),
                         NextestInvocation::WithCargo { rust_toolchain } => {
-                            if let Some(rust_toolchain) = rust_toolchain {
-                                args.extend(["run".into(), rust_toolchain.into(), "cargo".into()]);
-                                "rustup".into()
-                            } else {
-                                "cargo".into()
-                            }
+                            let (argv0, cargo_args) = cargo_invocation(rust_toolchain);
+                            args.extend(cargo_args);
+                            argv0
                         }
                     };
 
@@ -294,23 +369,15 @@ enum NextestInvocation {
 
                     args.extend(build_args.into_iter().map(Into::into));
 
-                    if let Some(nextest_filter_expr) = nextest_filter_expr {
-                        args.push("--filter-expr".into());
-                        args.push(nextest_filter_expr.into());
-                    }
-
-                    if run_ignored {
-                        args.push("--run-ignored".into());
-                        args.push("all".into());
-                    }
-
-                    if let Some(fail_fast) = fail_fast {
-                        if fail_fast {
-                            args.push("--fail-fast".into());
-                        } else {
-                            args.push("--no-fail-fast".into());
-                        }
+                    RunArgs {
+                        nextest_filter_expr,
+                        run_ignored,
+                        fail_fast,
+                        retries,
+                        libtest_json,
+                        extra_args,
                     }
+                    .push(&mut args, &mut with_env);
 
                     // useful default to have
                     if !with_env.contains_key("RUST_BACKTRACE") {
@@ -369,6 +436,67 @@ enum NextestInvocation {
     }
 }
 
+/// Env var that must be set for nextest to accept `--message-format
+/// libtest-json`.
+pub const NEXTEST_EXPERIMENTAL_LIBTEST_JSON: &str = "NEXTEST_EXPERIMENTAL_LIBTEST_JSON";
+
+/// Arguments controlling the behavior of `nextest run`, which come after the
+/// build (or archive) arguments.
+#[derive(Default)]
+struct RunArgs {
+    nextest_filter_expr: Option<String>,
+    run_ignored: bool,
+    fail_fast: Option<bool>,
+    retries: Option<u32>,
+    libtest_json: bool,
+    extra_args: Vec<String>,
+}
+
+impl RunArgs {
+    fn push(self, args: &mut Vec<OsString>, env: &mut BTreeMap<String, String>) {
+        let Self {
+            nextest_filter_expr,
+            run_ignored,
+            fail_fast,
+            retries,
+            libtest_json,
+            extra_args,
+        } = self;
+
+        if let Some(nextest_filter_expr) = nextest_filter_expr {
+            args.push("--filter-expr".into());
+            args.push(nextest_filter_expr.into());
+        }
+
+        if run_ignored {
+            args.push("--run-ignored".into());
+            args.push("all".into());
+        }
+
+        if let Some(fail_fast) = fail_fast {
+            if fail_fast {
+                args.push("--fail-fast".into());
+            } else {
+                args.push("--no-fail-fast".into());
+            }
+        }
+
+        if let Some(retries) = retries {
+            args.push("--retries".into());
+            args.push(retries.to_string().into());
+        }
+
+        if libtest_json {
+            args.push("--message-format".into());
+            args.push("libtest-json".into());
+            env.insert(NEXTEST_EXPERIMENTAL_LIBTEST_JSON.into(), "1".into());
+        }
+
+        // passed through last, so that they can override anything above
+        args.extend(extra_args.into_iter().map(Into::into));
+    }
+}
+
 // shared with `cargo_nextest_archive`
 pub(crate) fn cargo_nextest_build_args_and_env(
     cargo_flags: crate::cfg_cargo_common_flags::Flags,
@@ -463,6 +591,18 @@ pub(crate) fn cargo_nextest_build_args_and_env(
     (args, env)
 }
 
+/// Returns the program and leading arguments used to invoke `cargo`, going
+/// through `rustup run <toolchain> cargo` when a specific toolchain is set.
+fn cargo_invocation(rust_toolchain: Option<String>) -> (OsString, Vec<OsString>) {
+    match rust_toolchain {
+        Some(rust_toolchain) => (
+            "rustup".into(),
+            vec!["run".into(), rust_toolchain.into(), "cargo".into()],
+        ),
+        None => ("cargo".into(), Vec::new()),
+    }
+}
+
 // FUTURE: this seems like something a proc-macro can help with...
 impl RunKindDeps {
     pub fn claim(self, ctx: &mut StepCtx<'_>) -> RunKindDeps<VarClaimed> {
@@ -529,3 +669,102 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} {} {}", env_string, argv0_string, arg_string)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::NEXTEST_EXPERIMENTAL_LIBTEST_JSON;
+    use super::NextestExitStatus;
+    use super::RunArgs;
+    use super::cargo_invocation;
+    use std::collections::BTreeMap;
+    use std::ffi::OsString;
+
+    fn run_args(run_args: RunArgs) -> (Vec<OsString>, BTreeMap<String, String>) {
+        let mut args = Vec::new();
+        let mut env = BTreeMap::new();
+        run_args.push(&mut args, &mut env);
+        (args, env)
+    }
+
+    #[test]
+    fn libtest_json_args() {
+        let (args, env) = run_args(RunArgs::default());
+        assert!(args.is_empty());
+        assert!(env.is_empty());
+
+        let (args, env) = run_args(RunArgs {
+            libtest_json: true,
+            ..Default::default()
+        });
+        assert_eq!(args, ["--message-format", "libtest-json"]);
+        assert_eq!(
+            env.get(NEXTEST_EXPERIMENTAL_LIBTEST_JSON)
+                .map(String::as_str),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn retries_args() {
+        let (args, _) = run_args(RunArgs {
+            retries: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(args, ["--retries", "2"]);
+    }
+
+    #[test]
+    fn extra_args_are_last() {
+        let (args, _) = run_args(RunArgs {
+            nextest_filter_expr: Some("test(foo)".into()),
+            fail_fast: Some(false),
+            extra_args: vec!["--test-threads".into(), "1".into()],
+            ..Default::default()
+        });
+        assert_eq!(
+            args,
+            [
+                "--filter-expr",
+                "test(foo)",
+                "--no-fail-fast",
+                "--test-threads",
+                "1"
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_toolchain_invocation() {
+        let (argv0, args) = cargo_invocation(None);
+        assert_eq!(argv0, "cargo");
+        assert!(args.is_empty());
+
+        let (argv0, args) = cargo_invocation(Some("1.85.0".into()));
+        assert_eq!(argv0, "rustup");
+        assert_eq!(args, ["run", "1.85.0", "cargo"]);
+    }
+
+    #[test]
+    fn exit_status() {
+        assert_eq!(
+            NextestExitStatus::from_code(Some(0)),
+            NextestExitStatus::Passed
+        );
+        assert_eq!(
+            NextestExitStatus::from_code(Some(100)),
+            NextestExitStatus::TestsFailed
+        );
+        assert_eq!(
+            NextestExitStatus::from_code(Some(101)),
+            NextestExitStatus::BuildFailed
+        );
+        assert_eq!(
+            NextestExitStatus::from_code(Some(1)),
+            NextestExitStatus::Other(Some(1))
+        );
+        assert_eq!(
+            NextestExitStatus::from_code(None).to_string(),
+            "terminated by a signal"
+        );
+    }
+}
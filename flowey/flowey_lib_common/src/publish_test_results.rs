@@ -34,6 +34,12 @@ pub struct Request {
         /// these attachments will not be uploaded as distinct artifacts and
         /// will instead be uploaded via the JUnit integration.
         pub attachments: BTreeMap<String, (ReadVar<PathBuf>, bool)>,
+        /// Output directories of failing tests, keyed by test name.
+        ///
+        /// The contents of each directory are gathered into a single
+        /// `failed-tests` attachment, which is only uploaded if any tests
+        /// failed.
+        pub failed_test_output_dirs: Option<ReadVar<BTreeMap<String, PathBuf>>>,
         /// Copy the xml file and attachments to the provided directory.
         /// Only supported on local backend.
         pub output_dir: Option<ReadVar<PathBuf>>,
@@ -58,7 +64,8 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
         for Request {
             junit_xml,
             test_label: label,
-            attachments,
+            mut attachments,
+            failed_test_output_dirs,
             output_dir,
             done,
         } in requests
@@ -137,6 +144,22 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                 }
             }
 
+            if let Some(failed_test_output_dirs) = failed_test_output_dirs {
+                let staging_dir =
+                    ctx.emit_rust_stepv(format!("gather failing test output: {label}"), |ctx| {
+                        let failed_test_output_dirs = failed_test_output_dirs.claim(ctx);
+                        move |rt| {
+                            let failed_test_output_dirs = rt.read(failed_test_output_dirs);
+                            let staging_dir = std::env::current_dir()?.join("failed-tests");
+                            stage_failed_test_output(&failed_test_output_dirs, &staging_dir)?;
+                            Ok(staging_dir)
+                        }
+                    });
+                // Like other logs, these are referenced by the JUnit XML
+                // file and so are uploaded via the JUnit integration on ADO.
+                attachments.insert("failed-tests".into(), (staging_dir, false));
+            }
+
             for (attachment_label, (attachment_path, publish_on_ado)) in attachments {
                 let step_name = format!("publish test results: {label} ({attachment_label})");
                 let artifact_name = format!("{label}-{attachment_label}");
@@ -235,3 +258,57 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
         Ok(())
     }
 }
+
+/// Copies the output directory of each failing test into a subdirectory of
+/// `staging_dir` named after the test.
+fn stage_failed_test_output(
+    failed_test_output_dirs: &BTreeMap<String, PathBuf>,
+    staging_dir: &Path,
+) -> anyhow::Result<()> {
+    fs_err::create_dir_all(staging_dir)?;
+    for (test_name, output_dir) in failed_test_output_dirs {
+        if !output_dir.exists() {
+            log::warn!(
+                "output dir for failing test {test_name} does not exist: {}",
+                output_dir.display()
+            );
+            continue;
+        }
+        // Test names contain `::`, which is not valid in Windows paths or
+        // artifact names.
+        let dst = staging_dir.join(test_name.replace("::", "__"));
+        log::info!("attaching output of failing test {test_name}");
+        copy_dir_all(output_dir, dst)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stage_failed_test_output;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn failed_test_output_is_attached() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_dir = dir.path().join("multiarch__boot");
+        fs_err::create_dir_all(test_dir.join("screenshots")).unwrap();
+        fs_err::write(test_dir.join("petri.failed"), "multiarch::boot").unwrap();
+        fs_err::write(test_dir.join("screenshots/screenshot.png"), "png").unwrap();
+
+        let staging_dir = dir.path().join("staging");
+        stage_failed_test_output(
+            &BTreeMap::from([
+                ("multiarch::boot".to_string(), test_dir),
+                ("multiarch::gone".to_string(), dir.path().join("missing")),
+            ]),
+            &staging_dir,
+        )
+        .unwrap();
+
+        let staged = staging_dir.join("multiarch__boot");
+        assert!(staged.join("petri.failed").is_file());
+        assert!(staged.join("screenshots/screenshot.png").is_file());
+        assert!(!staging_dir.join("multiarch__gone").exists());
+    }
+}
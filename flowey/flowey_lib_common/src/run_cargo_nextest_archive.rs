@@ -41,7 +41,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
 
         let nextest_installed = ctx.reqv(crate::install_cargo_nextest::Request);
 
-        let rust_toolchain = ctx.reqv(crate::install_rust::Request::GetRustupToolchain);
+        let default_rust_toolchain = ctx.reqv(crate::install_rust::Request::GetRustupToolchain);
 
         for Request {
             friendly_label,
@@ -55,14 +55,21 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                     target,
                     profile,
                     extra_env,
+                    rust_toolchain: rust_toolchain_override,
                 },
             pre_run_deps,
             archive_file,
         } in requests
         {
-            ctx.req(crate::install_rust::Request::InstallTargetTriple(
-                target.clone(),
-            ));
+            let rust_toolchain = match rust_toolchain_override {
+                Some(rust_toolchain) => ReadVar::from_static(Some(rust_toolchain)),
+                None => {
+                    ctx.req(crate::install_rust::Request::InstallTargetTriple(
+                        target.clone(),
+                    ));
+                    default_rust_toolchain.clone()
+                }
+            };
 
             ctx.emit_rust_step(
                 format!("build + archive '{friendly_label}' nextests"),
@@ -70,7 +77,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                     pre_run_deps.claim(ctx);
                     nextest_installed.clone().claim(ctx);
                     let cargo_flags = cargo_flags.clone().claim(ctx);
-                    let rust_toolchain = rust_toolchain.clone().claim(ctx);
+                    let rust_toolchain = rust_toolchain.claim(ctx);
                     let working_dir = working_dir.claim(ctx);
                     let archive_file = archive_file.claim(ctx);
                     let packages = packages.claim(ctx);
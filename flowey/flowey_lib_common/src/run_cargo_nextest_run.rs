@@ -3,6 +3,7 @@
 
 //! Run cargo-nextest tests.
 
+use crate::gen_cargo_nextest_run_cmd::NextestExitStatus;
 use crate::gen_cargo_nextest_run_cmd::RunKindDeps;
 use flowey::node::prelude::*;
 use std::collections::BTreeMap;
@@ -11,6 +12,116 @@ pub struct TestResults {
     pub all_tests_passed: bool,
     /// Path to JUnit XML output (if enabled by the nextest profile)
     pub junit_xml: Option<PathBuf>,
+    /// Path to libtest-json output, and the summary parsed from it (if
+    /// enabled via [`Run::libtest_json`])
+    pub libtest_json: Option<(PathBuf, TestSummary)>,
+    /// Names of the tests that failed, but then passed when retried, from the
+    /// JUnit XML and libtest-json output
+    pub flaky: Vec<String>,
+}
+
+/// Summary of a test run, parsed from nextest's libtest-json output.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct TestSummary {
+    /// Number of tests that passed on their first attempt
+    pub passed: usize,
+    pub ignored: usize,
+    /// Names of the tests that failed on every attempt
+    pub failed: Vec<String>,
+    /// Names of the tests that failed, but then passed when retried
+    pub flaky: Vec<String>,
+}
+
+/// Parse the libtest-json output emitted by `cargo nextest run
+/// --message-format libtest-json` into a [`TestSummary`].
+///
+/// When tests are retried, each attempt is reported as a separate event for
+/// the same test. A test with a failing attempt followed by a passing one is
+/// reported as flaky rather than passed.
+///
+/// Lines that are not JSON objects are skipped.
+pub fn parse_libtest_json(output: &str) -> anyhow::Result<TestSummary> {
+    let mut summary = TestSummary::default();
+    for line in output.lines().filter(|l| l.starts_with('{')) {
+        let event: serde_json::Value =
+            serde_json::from_str(line).with_context(|| format!("invalid libtest-json: {line}"))?;
+        if event["type"] != "test" {
+            continue;
+        }
+        let name = || event["name"].as_str().context("test event missing name");
+        match event["event"].as_str() {
+            Some("ok") => {
+                let name = name()?;
+                if let Some(i) = summary.failed.iter().position(|n| n == name) {
+                    summary.failed.remove(i);
+                    summary.flaky.push(name.to_owned());
+                } else {
+                    summary.passed += 1;
+                }
+            }
+            Some("ignored") => summary.ignored += 1,
+            Some("failed") => {
+                let name = name()?;
+                if !summary.failed.iter().any(|n| n == name) {
+                    summary.failed.push(name.to_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(summary)
+}
+
+/// Parse the names of the flaky tests from the JUnit XML emitted by nextest.
+///
+/// Nextest records each failed attempt of a test that passed on retry as a
+/// `<flakyFailure>` element of the test's `<testcase>`. Test names are
+/// reported as `<classname>::<name>`.
+pub fn parse_junit_flaky(xml: &str) -> anyhow::Result<Vec<String>> {
+    use quick_xml::events::BytesStart;
+    use quick_xml::events::Event;
+
+    fn test_name(testcase: &BytesStart<'_>) -> anyhow::Result<Option<String>> {
+        let attr = |name: &str| -> anyhow::Result<Option<String>> {
+            Ok(match testcase.try_get_attribute(name)? {
+                Some(attr) => Some(attr.unescape_value()?.into_owned()),
+                None => None,
+            })
+        };
+        let Some(name) = attr("name")? else {
+            return Ok(None);
+        };
+        Ok(Some(match attr("classname")? {
+            Some(classname) => format!("{classname}::{name}"),
+            None => name,
+        }))
+    }
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut flaky = Vec::new();
+    // The name of the enclosing `<testcase>`, and whether it has been seen to
+    // be flaky.
+    let mut current: Option<(Option<String>, bool)> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"testcase" => {
+                current = Some((test_name(&e)?, false));
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"flakyFailure" => {
+                if let Some((_, is_flaky)) = &mut current {
+                    *is_flaky = true;
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"testcase" => {
+                if let Some((Some(name), true)) = current.take() {
+                    flaky.push(name);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(flaky)
 }
 
 /// Parameters related to building nextest tests
@@ -66,6 +177,10 @@ pub struct NextestBuildParams<C = VarNotClaimed> {
         pub profile: CargoBuildProfile,
         /// Additional env vars set when building the tests
         pub extra_env: ReadVar<BTreeMap<String, String>, C>,
+        /// Build and run the tests with this rustup toolchain, instead of the
+        /// one selected via [`crate::install_rust`]. The toolchain (and the
+        /// target) must already be installed.
+        pub rust_toolchain: Option<String>,
     }
 }
 
@@ -101,6 +216,17 @@ pub struct Run {
     pub nextest_filter_expr: Option<String>,
     /// Whether to run ignored tests
     pub run_ignored: bool,
+    /// Override the number of times to retry failing tests. Tests that pass
+    /// on retry are reported as flaky in the JUnit output and [`TestSummary`].
+    pub retries: Option<u32>,
+    /// Capture machine-readable libtest-json output to a file, and parse it
+    /// into a [`TestSummary`].
+    ///
+    /// This relies on an experimental nextest feature.
+    pub libtest_json: bool,
+    /// Additional arguments passed verbatim to `nextest run`, after all other
+    /// arguments. These are not validated.
+    pub extra_args: Vec<String>,
     /// Set rlimits to allow unlimited sized coredump file (if supported)
     pub with_rlimit_unlimited_core_size: bool,
     /// Additional env vars set when executing the tests.
@@ -168,6 +294,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
             with_rlimit_unlimited_core_size,
             nextest_filter_expr,
             run_ignored,
+            retries,
+            libtest_json,
+            extra_args,
             pre_run_deps,
             results,
         } in run
@@ -178,11 +307,15 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
 
                     let nextest_installed = ctx.reqv(crate::install_cargo_nextest::Request);
 
-                    let rust_toolchain = ctx.reqv(crate::install_rust::Request::GetRustupToolchain);
-
-                    ctx.req(crate::install_rust::Request::InstallTargetTriple(
-                        params.target.clone(),
-                    ));
+                    let rust_toolchain = match &params.rust_toolchain {
+                        Some(rust_toolchain) => ReadVar::from_static(Some(rust_toolchain.clone())),
+                        None => {
+                            ctx.req(crate::install_rust::Request::InstallTargetTriple(
+                                params.target.clone(),
+                            ));
+                            ctx.reqv(crate::install_rust::Request::GetRustupToolchain)
+                        }
+                    };
 
                     RunKindDeps::BuildAndRun {
                         params,
@@ -220,6 +353,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                 nextest_filter_expr,
                 run_ignored,
                 fail_fast,
+                retries,
+                libtest_json,
+                extra_args,
                 extra_env,
                 portable: false,
                 command: v,
@@ -227,6 +363,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
 
             let (all_tests_passed_read, all_tests_passed_write) = ctx.new_var();
             let (junit_xml_read, junit_xml_write) = ctx.new_var();
+            let (libtest_json_read, libtest_json_write) = ctx.new_var();
 
             ctx.emit_rust_step(format!("run '{friendly_name}' nextest tests"), |ctx| {
                 pre_run_deps.claim(ctx);
@@ -235,6 +372,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                 let config_file = config_file.claim(ctx);
                 let all_tests_passed_var = all_tests_passed_write.claim(ctx);
                 let junit_xml_write = junit_xml_write.claim(ctx);
+                let libtest_json_write = libtest_json_write.claim(ctx);
                 let cmd = cmd.claim(ctx);
 
                 move |rt| {
@@ -316,6 +454,16 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                         .envs(&cmd.env)
                         .current_dir(&working_dir);
 
+                    // libtest-json events are written to stdout, so redirect
+                    // it into a file that can be parsed once the run is done.
+                    let libtest_json_path = if libtest_json {
+                        let path = std::env::current_dir()?.join("nextest-libtest.json");
+                        command.stdout(fs_err::File::create(&path)?.into_parts().0);
+                        Some(path)
+                    } else {
+                        None
+                    };
+
                     let mut child = command.spawn().with_context(|| {
                         format!("failed to spawn '{}'", cmd.argv0.to_string_lossy())
                     })?;
@@ -327,12 +475,11 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                         rlimit::setrlimit(rlimit::Resource::CORE, soft, hard)?;
                     }
 
-                    let all_tests_passed = match (status.success(), status.code()) {
-                        (true, _) => true,
-                        // documented nextest exit code for when a test has failed
-                        (false, Some(100)) => false,
-                        // any other exit code means something has gone disastrously wrong
-                        (false, _) => anyhow::bail!("failed to run nextest"),
+                    let all_tests_passed = match NextestExitStatus::from_code(status.code()) {
+                        NextestExitStatus::Passed => true,
+                        NextestExitStatus::TestsFailed => false,
+                        // anything else means something has gone disastrously wrong
+                        status => anyhow::bail!("failed to run nextest: {status}"),
                     };
 
                     rt.write(all_tests_passed_var, &all_tests_passed);
@@ -369,26 +516,67 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
 
                     rt.write(junit_xml_write, &junit_xml);
 
+                    let libtest_json = if let Some(path) = libtest_json_path {
+                        let summary = parse_libtest_json(&fs_err::read_to_string(&path)?)?;
+                        log::info!(
+                            "{} passed, {} flaky, {} failed, {} ignored",
+                            summary.passed,
+                            summary.flaky.len(),
+                            summary.failed.len(),
+                            summary.ignored
+                        );
+                        for name in &summary.flaky {
+                            log::warn!("flaky: {name}");
+                        }
+                        for name in &summary.failed {
+                            log::warn!("failed: {name}");
+                        }
+                        Some((path.absolute()?, summary))
+                    } else {
+                        None
+                    };
+
+                    rt.write(libtest_json_write, &libtest_json);
+
                     Ok(())
                 }
             });
 
-            ctx.emit_minor_rust_step("write results", |ctx| {
+            ctx.emit_rust_step("write results", |ctx| {
                 let all_tests_passed = all_tests_passed_read.claim(ctx);
                 let junit_xml = junit_xml_read.claim(ctx);
+                let libtest_json = libtest_json_read.claim(ctx);
                 let results = results.claim(ctx);
 
                 move |rt| {
                     let all_tests_passed = rt.read(all_tests_passed);
                     let junit_xml = rt.read(junit_xml);
+                    let libtest_json = rt.read(libtest_json);
+
+                    let mut flaky = match &junit_xml {
+                        Some(path) => parse_junit_flaky(&fs_err::read_to_string(path)?)
+                            .context("failed to parse junit xml")?,
+                        None => Vec::new(),
+                    };
+                    if let Some((_, summary)) = &libtest_json {
+                        for name in &summary.flaky {
+                            if !flaky.contains(name) {
+                                flaky.push(name.clone());
+                            }
+                        }
+                    }
 
                     rt.write(
                         results,
                         &TestResults {
                             all_tests_passed,
                             junit_xml,
+                            libtest_json,
+                            flaky,
                         },
                     );
+
+                    Ok(())
                 }
             });
         }
@@ -408,6 +596,7 @@ pub fn claim(self, ctx: &mut StepCtx<'_>) -> build_params::NextestBuildParams<Va
             target,
             profile,
             extra_env,
+            rust_toolchain,
         } = self;
 
         build_params::NextestBuildParams {
@@ -418,6 +607,78 @@ pub fn claim(self, ctx: &mut StepCtx<'_>) -> build_params::NextestBuildParams<Va
             target,
             profile,
             extra_env: extra_env.claim(ctx),
+            rust_toolchain,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TestSummary;
+    use super::parse_junit_flaky;
+    use super::parse_libtest_json;
+
+    #[test]
+    fn parse_summary() {
+        let output = r#"{"type":"suite","event":"started","test_count":3}
+{"type":"test","event":"started","name":"a::one"}
+{"type":"test","event":"ok","name":"a::one","exec_time":0.1}
+{"type":"test","event":"failed","name":"a::two","exec_time":0.1}
+{"type":"test","event":"ignored","name":"a::three"}
+{"type":"suite","event":"failed","passed":1,"failed":1,"ignored":1}
+"#;
+        assert_eq!(
+            parse_libtest_json(output).unwrap(),
+            TestSummary {
+                passed: 1,
+                ignored: 1,
+                failed: vec!["a::two".into()],
+                flaky: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_flaky() {
+        let output = r#"{"type":"test","event":"failed","name":"a::flaky"}
+{"type":"test","event":"ok","name":"a::flaky"}
+{"type":"test","event":"failed","name":"a::broken"}
+{"type":"test","event":"failed","name":"a::broken"}
+{"type":"test","event":"ok","name":"a::solid"}
+"#;
+        assert_eq!(
+            parse_libtest_json(output).unwrap(),
+            TestSummary {
+                passed: 1,
+                ignored: 0,
+                failed: vec!["a::broken".into()],
+                flaky: vec!["a::flaky".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_junit() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="nextest-run" tests="3" failures="1">
+    <testsuite name="vmm_tests::tests" tests="3" failures="1">
+        <testcase classname="vmm_tests::tests" name="a::flaky" timestamp="2025-01-01T00:00:00Z" time="1.0">
+            <flakyFailure type="test failure" message="code 1"/>
+        </testcase>
+        <testcase name="a::solid" classname="vmm_tests::tests" time="1.0">
+        </testcase>
+        <testcase name="a::broken" classname="vmm_tests::tests" time="1.0">
+            <failure type="test failure"/>
+            <rerunFailure type="test failure"/>
+        </testcase>
+    </testsuite>
+</testsuites>
+"#;
+        assert_eq!(
+            parse_junit_flaky(xml).unwrap(),
+            ["vmm_tests::tests::a::flaky"]
+        );
+        assert!(parse_junit_flaky("").unwrap().is_empty());
+        assert!(parse_junit_flaky(r#"<testsuites><testcase name="a"></testsuites>"#).is_err());
+    }
+}
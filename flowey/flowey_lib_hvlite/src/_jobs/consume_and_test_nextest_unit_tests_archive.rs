@@ -60,6 +60,7 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             junit_xml,
             test_label: junit_test_label,
             attachments: BTreeMap::new(),
+            failed_test_output_dirs: None,
             output_dir: artifact_dir,
             done: v,
         });
@@ -127,6 +127,7 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
         let pre_run_deps = vec![ctx.reqv(crate::install_vmm_tests_deps::Request::Install)];
 
         let (test_log_path, get_test_log_path) = ctx.new_var();
+        let (failed_test_output_dirs, write_failed_test_output_dirs) = ctx.new_var();
 
         let extra_env = ctx.reqv(|v| crate::init_vmm_tests_env::Request {
             test_content_dir,
@@ -154,7 +155,11 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             nextest_bin: None,
             target: None,
             extra_env,
+            retries: None,
+            libtest_json: false,
+            extra_args: Vec::new(),
             pre_run_deps,
+            failed_test_output_dirs: Some((test_log_path.clone(), write_failed_test_output_dirs)),
             results: v,
         });
 
@@ -167,6 +172,7 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             junit_xml,
             test_label: junit_test_label,
             attachments: BTreeMap::from([("logs".to_string(), (test_log_path, false))]),
+            failed_test_output_dirs: Some(failed_test_output_dirs),
             output_dir: artifact_dir,
             done: v,
         });
@@ -14,6 +14,7 @@
 use crate::run_cargo_build::common::CommonTriple;
 use flowey::node::prelude::*;
 use flowey_lib_common::gen_cargo_nextest_run_cmd::CommandShell;
+use flowey_lib_common::gen_cargo_nextest_run_cmd::NextestExitStatus;
 use flowey_lib_common::gen_cargo_nextest_run_cmd::RunKindDeps;
 use std::collections::BTreeMap;
 use std::str::FromStr;
@@ -110,6 +111,26 @@ pub struct BuildSelections {
     pub tmks: bool,
     pub tmk_vmm_windows: bool,
     pub tmk_vmm_linux: bool,
+    /// OpenHCL recipes to build, instead of all the recipes for the target
+    /// architecture
+    pub openhcl_recipes: Option<Vec<OpenhclIgvmRecipe>>,
+}
+
+impl BuildSelections {
+    /// Selects nothing to build.
+    pub fn none() -> Self {
+        Self {
+            openhcl: false,
+            openvmm: false,
+            pipette_windows: false,
+            pipette_linux: false,
+            guest_test_uefi: false,
+            tmks: false,
+            tmk_vmm_windows: false,
+            tmk_vmm_linux: false,
+            openhcl_recipes: None,
+        }
+    }
 }
 
 // Build everything we can by default
@@ -124,10 +145,20 @@ fn default() -> Self {
             tmks: true,
             tmk_vmm_windows: true,
             tmk_vmm_linux: true,
+            openhcl_recipes: None,
         }
     }
 }
 
+/// A remote host to copy the test content to and run the tests on, over SSH
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RemoteTestHost {
+    /// The SSH destination, e.g. `user@host`
+    pub destination: String,
+    /// The directory on the remote host to copy the test content into
+    pub dir: String,
+}
+
 flowey_request! {
     pub struct Params {
         pub target: CommonTriple,
@@ -140,12 +171,36 @@ pub struct Params {
         pub unstable_whp: bool,
         /// Release build instead of debug build
         pub release: bool,
+        /// Build the VMM tests with this rustup toolchain instead of the
+        /// repo's default one
+        pub rust_toolchain: Option<String>,
 
         /// Whether to run the tests or just build and archive
         pub build_only: bool,
+        /// Run the tests from this existing nextest archive, such as one from
+        /// a previous build-only run, instead of building anything. The test
+        /// binaries must already be in the test content dir.
+        pub prebuilt_archive: Option<PathBuf>,
         /// Copy extras to output dir (symbols, etc)
         pub copy_extras: bool,
 
+        /// Number of times to retry failing tests
+        pub retries: Option<u32>,
+        /// Capture nextest's libtest-json output, and report the tests that
+        /// only passed on retry as flaky
+        pub libtest_json: bool,
+        /// Additional arguments passed verbatim to `nextest run`
+        pub nextest_args: Vec<String>,
+        /// Run the tests on a remote host instead of locally
+        pub remote: Option<RemoteTestHost>,
+        /// Remove test VMs left behind by previous runs before running the
+        /// tests, and any left behind by this run afterwards
+        pub clean_environment: bool,
+        /// When cleaning the environment, also remove the virtual switches
+        /// and NAT rules whose names start with this prefix. This does not
+        /// affect which test VMs are removed.
+        pub clean_network_prefix: Option<String>,
+
         pub done: WriteVar<SideEffect>,
     }
 }
@@ -160,6 +215,7 @@ fn imports(ctx: &mut ImportCtx<'_>) {
         ctx.import::<crate::build_nextest_vmm_tests::Node>();
         ctx.import::<crate::build_openhcl_igvm_from_recipe::Node>();
         ctx.import::<crate::build_openvmm::Node>();
+        ctx.import::<crate::build_petri_tool::Node>();
         ctx.import::<crate::build_pipette::Node>();
         ctx.import::<crate::build_tmks::Node>();
         ctx.import::<crate::build_tmk_vmm::Node>();
@@ -180,11 +236,39 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             selections,
             unstable_whp,
             release,
+            rust_toolchain,
             build_only,
+            prebuilt_archive,
             copy_extras,
+            retries,
+            libtest_json,
+            nextest_args,
+            remote,
+            clean_environment,
+            clean_network_prefix,
             done,
         } = request;
 
+        if build_only && prebuilt_archive.is_some() {
+            anyhow::bail!("cannot only build when using a prebuilt archive");
+        }
+        if rust_toolchain.is_some() && prebuilt_archive.is_some() {
+            anyhow::bail!("cannot select a rust toolchain when using a prebuilt archive");
+        }
+
+        if build_only && remote.is_some() {
+            anyhow::bail!("cannot run on a remote host when only building");
+        }
+        if libtest_json && remote.is_some() {
+            anyhow::bail!("cannot capture libtest-json output on a remote host");
+        }
+        if clean_environment && (build_only || remote.is_some()) {
+            anyhow::bail!("can only clean the environment when running tests locally");
+        }
+        if clean_network_prefix.is_some() && !clean_environment {
+            anyhow::bail!("can only clean up networking when cleaning the environment");
+        }
+
         let target_triple = target.as_triple();
         let arch = target.common_arch().unwrap();
         let arch_tag = match arch {
@@ -204,7 +288,7 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
         let mut copy_to_dir = Vec::new();
         let extras_dir = Path::new("extras");
 
-        let (nextest_filter_expr, test_artifacts, mut build, deps) = match selections {
+        let (nextest_filter_expr, test_artifacts, build, deps) = match selections {
             VmmTestSelections::Custom {
                 filter,
                 artifacts,
@@ -358,36 +442,19 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             }
         };
 
-        if !linux_host {
-            build.openhcl = false;
-            build.pipette_linux = false;
-            build.tmk_vmm_linux = false;
-        }
+        let (mut build, archive_source) = select_builds(build, linux_host, prebuilt_archive);
 
+        let openhcl_recipes = openhcl_recipes_to_build(arch, build.openhcl_recipes.take())?;
         let register_openhcl_igvm_files = build.openhcl.then(|| {
             let openvmm_hcl_profile = if release {
                 OpenvmmHclBuildProfile::OpenvmmHclShip
             } else {
                 OpenvmmHclBuildProfile::Debug
             };
-            let openhcl_recipies = match arch {
-                CommonArch::X86_64 => vec![
-                    OpenhclIgvmRecipe::X64,
-                    OpenhclIgvmRecipe::X64Devkern,
-                    OpenhclIgvmRecipe::X64TestLinuxDirect,
-                    OpenhclIgvmRecipe::X64Cvm,
-                ],
-                CommonArch::Aarch64 => {
-                    vec![
-                        OpenhclIgvmRecipe::Aarch64,
-                        OpenhclIgvmRecipe::Aarch64Devkern,
-                    ]
-                }
-            };
             let openhcl_extras_dir = extras_dir.join("openhcl");
 
             let mut register_openhcl_igvm_files = Vec::new();
-            for recipe in openhcl_recipies {
+            for recipe in openhcl_recipes {
                 let (read_built_openvmm_hcl, built_openvmm_hcl) = ctx.new_var();
                 let (read_built_openhcl_igvm, built_openhcl_igvm) = ctx.new_var();
                 let (read_built_openhcl_boot, built_openhcl_boot) = ctx.new_var();
@@ -593,11 +660,17 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             output
         });
 
-        let nextest_archive = ctx.reqv(|v| crate::build_nextest_vmm_tests::Request {
-            target: target.as_triple(),
-            profile: CommonProfile::from_release(release),
-            build_mode: crate::build_nextest_vmm_tests::BuildNextestVmmTestsMode::Archive(v),
-        });
+        let nextest_archive = match archive_source {
+            NextestArchiveSource::Prebuilt(archive_file) => {
+                ReadVar::from_static(NextestVmmTestsArchive { archive_file })
+            }
+            NextestArchiveSource::Build => ctx.reqv(|v| crate::build_nextest_vmm_tests::Request {
+                target: target.as_triple(),
+                profile: CommonProfile::from_release(release),
+                build_mode: crate::build_nextest_vmm_tests::BuildNextestVmmTestsMode::Archive(v),
+                rust_toolchain,
+            }),
+        };
         let nextest_archive_file = Path::new("vmm-tests-archive.tar.zst");
         copy_to_dir.push((
             nextest_archive_file.to_owned(),
@@ -674,7 +747,8 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             register_openhcl_igvm_files,
             get_test_log_path: None,
             get_env: v,
-            use_relative_paths: build_only,
+            // The test content dir is relocated before running the tests
+            use_relative_paths: build_only || remote.is_some(),
         });
 
         let mut side_effects = Vec::new();
@@ -701,6 +775,13 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                                 test_content_dir.join(dst)
                             };
 
+                            // A prebuilt archive may already be in place.
+                            if dst.exists()
+                                && fs_err::canonicalize(&src)? == fs_err::canonicalize(&dst)?
+                            {
+                                continue;
+                            }
+
                             fs_err::create_dir_all(dst.parent().context("no parent")?)?;
                             fs_err::copy(src, dst)?;
                         }
@@ -746,6 +827,9 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             nextest_filter_expr: Some(nextest_filter_expr.clone()),
             run_ignored: false,
             fail_fast: None,
+            retries,
+            libtest_json,
+            extra_args: nextest_args.clone(),
             extra_env: Some(extra_env.clone()),
             portable: true,
             command: v,
@@ -773,9 +857,57 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
 
         if build_only {
             ctx.emit_side_effect_step(side_effects, [done]);
+        } else if let Some(remote) = remote {
+            let remote_windows = matches!(
+                target.operating_system,
+                target_lexicon::OperatingSystem::Windows
+            );
+            ctx.emit_rust_step("run tests on remote host", |ctx| {
+                side_effects.claim(ctx);
+                done.claim(ctx);
+                move |_rt| {
+                    log::info!(
+                        "dependencies are not installed automatically on the remote host, run install_deps.ps1 there if needed"
+                    );
+                    if run_remote(&remote, remote_windows, &test_content_dir)? {
+                        log::info!("all tests passed!");
+                    } else {
+                        log::error!("encountered test failures.");
+                    }
+                    Ok(())
+                }
+            });
         } else {
             side_effects.push(ctx.reqv(crate::install_vmm_tests_deps::Request::Install));
 
+            // Only Hyper-V VMs outlive the test process.
+            let clean_environment = clean_environment
+                && matches!(
+                    target.operating_system,
+                    target_lexicon::OperatingSystem::Windows
+                );
+            let petri_tool = clean_environment.then(|| {
+                ctx.reqv(|v| crate::build_petri_tool::Request {
+                    target: CommonTriple::Common {
+                        arch,
+                        platform: CommonPlatform::WindowsMsvc,
+                    },
+                    profile: CommonProfile::from_release(release),
+                    petri_tool: v,
+                })
+            });
+            let clean_args = clean_environment_args(clean_network_prefix.as_deref());
+            if let Some(petri_tool) = &petri_tool {
+                side_effects.push(ctx.emit_rust_step(
+                    "clean up test environment before tests",
+                    |ctx| {
+                        let petri_tool = petri_tool.clone().claim(ctx);
+                        let args = clean_args.clone();
+                        move |rt| clean_environment_with(&rt.read(petri_tool).exe, &args)
+                    },
+                ));
+            }
+
             let results = ctx.reqv(|v| crate::test_nextest_vmm_tests_archive::Request {
                 nextest_archive_file: ReadVar::from_static(NextestVmmTestsArchive {
                     archive_file: nextest_archive_file,
@@ -787,7 +919,11 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                 nextest_bin: Some(ReadVar::from_static(nextest_bin)),
                 target: Some(ReadVar::from_static(target)),
                 extra_env,
+                retries,
+                libtest_json,
+                extra_args: nextest_args,
                 pre_run_deps: side_effects,
+                failed_test_output_dirs: None,
                 results: v,
             });
 
@@ -797,10 +933,21 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                     junit_xml,
                     test_label,
                     attachments: BTreeMap::new(), // the logs are already there
+                    failed_test_output_dirs: None,
                     output_dir: Some(ReadVar::from_static(test_content_dir)),
                     done: v,
                 });
 
+            let published_results = if let Some(petri_tool) = petri_tool {
+                ctx.emit_rust_step("clean up test environment after tests", |ctx| {
+                    published_results.claim(ctx);
+                    let petri_tool = petri_tool.claim(ctx);
+                    move |rt| clean_environment_with(&rt.read(petri_tool).exe, &clean_args)
+                })
+            } else {
+                published_results
+            };
+
             ctx.emit_rust_step("report test results", |ctx| {
                 published_results.claim(ctx);
                 done.claim(ctx);
@@ -808,6 +955,16 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                 let results = results.clone().claim(ctx);
                 move |rt| {
                     let results = rt.read(results);
+                    if let Some((path, _)) = &results.libtest_json {
+                        log::info!("libtest-json output: {}", path.display());
+                    }
+                    if !results.flaky.is_empty() {
+                        log::warn!(
+                            "{} test(s) only passed on retry: {}",
+                            results.flaky.len(),
+                            results.flaky.join(", ")
+                        );
+                    }
                     if results.all_tests_passed {
                         log::info!("all tests passed!");
                     } else {
@@ -822,3 +979,375 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
         Ok(())
     }
 }
+
+/// Removes the Hyper-V VMs created by petri, such as ones left behind by
+/// test runs that were killed, using `petri_tool`.
+fn clean_environment_with(petri_tool: &Path, args: &[String]) -> anyhow::Result<()> {
+    let status = std::process::Command::new(petri_tool)
+        .args(args)
+        .status()
+        .context("failed to run petri-tool")?;
+    if !status.success() {
+        anyhow::bail!("failed to clean up test environment: {status}");
+    }
+    Ok(())
+}
+
+fn clean_environment_args(network_prefix: Option<&str>) -> Vec<String> {
+    let mut args = vec!["clean-hyperv".into()];
+    if let Some(prefix) = network_prefix {
+        args.extend(["--network-prefix".into(), prefix.into()]);
+    }
+    args
+}
+
+/// Where the nextest archive of the VMM tests comes from.
+#[derive(Debug, PartialEq)]
+enum NextestArchiveSource {
+    /// Build the archive.
+    Build,
+    /// Use an archive built by a previous run.
+    Prebuilt(PathBuf),
+}
+
+/// Returns the test dependencies to build out of `build`, and where to get
+/// the nextest archive. Nothing is built when running from a prebuilt
+/// archive.
+fn select_builds(
+    mut build: BuildSelections,
+    linux_host: bool,
+    prebuilt_archive: Option<PathBuf>,
+) -> (BuildSelections, NextestArchiveSource) {
+    if let Some(archive_file) = prebuilt_archive {
+        return (
+            BuildSelections::none(),
+            NextestArchiveSource::Prebuilt(archive_file),
+        );
+    }
+    if !linux_host {
+        build.openhcl = false;
+        build.pipette_linux = false;
+        build.tmk_vmm_linux = false;
+    }
+    (build, NextestArchiveSource::Build)
+}
+
+/// Returns the OpenHCL recipes to build: the `selected` ones if specified,
+/// otherwise all the recipes used by the tests for `arch`.
+///
+/// Fails if any of the `selected` recipes is not used by the tests for
+/// `arch`, since the tests would not find it.
+fn openhcl_recipes_to_build(
+    arch: CommonArch,
+    selected: Option<Vec<OpenhclIgvmRecipe>>,
+) -> anyhow::Result<Vec<OpenhclIgvmRecipe>> {
+    let known = match arch {
+        CommonArch::X86_64 => vec![
+            OpenhclIgvmRecipe::X64,
+            OpenhclIgvmRecipe::X64Devkern,
+            OpenhclIgvmRecipe::X64TestLinuxDirect,
+            OpenhclIgvmRecipe::X64Cvm,
+        ],
+        CommonArch::Aarch64 => {
+            vec![
+                OpenhclIgvmRecipe::Aarch64,
+                OpenhclIgvmRecipe::Aarch64Devkern,
+            ]
+        }
+    };
+    let Some(selected) = selected else {
+        return Ok(known);
+    };
+    for recipe in &selected {
+        if !known
+            .iter()
+            .any(|k| std::mem::discriminant(k) == std::mem::discriminant(recipe))
+        {
+            anyhow::bail!(
+                "OpenHCL recipe {recipe:?} is not used by the vmm tests for {arch:?}; expected one of {known:?}"
+            );
+        }
+    }
+    Ok(selected)
+}
+
+/// Copies the test content dir to `remote`, runs the tests there, and copies
+/// the test results back. Returns whether all the tests passed.
+fn run_remote(
+    remote: &RemoteTestHost,
+    remote_windows: bool,
+    test_content_dir: &Path,
+) -> anyhow::Result<bool> {
+    check_remote_dir(remote, remote_windows)?;
+
+    let status = run_remote_cmd(&ssh_mkdir_args(remote, remote_windows))?;
+    if !status.success() {
+        anyhow::bail!(
+            "failed to create {} on {}: {status}",
+            remote.dir,
+            remote.destination
+        );
+    }
+
+    let mut entries = Vec::new();
+    for entry in fs_err::read_dir(test_content_dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+    let status = run_remote_cmd(&scp_upload_args(remote, &entries))?;
+    if !status.success() {
+        anyhow::bail!("failed to copy test content to {}", remote.destination);
+    }
+
+    let passed = remote_tests_passed(run_remote_cmd(&ssh_run_args(remote, remote_windows))?)?;
+
+    let status = run_remote_cmd(&scp_download_args(remote, test_content_dir))?;
+    if !status.success() {
+        anyhow::bail!("failed to copy test results from {}", remote.destination);
+    }
+
+    Ok(passed)
+}
+
+fn run_remote_cmd(args: &[String]) -> anyhow::Result<std::process::ExitStatus> {
+    log::info!("$ {}", args.join(" "));
+    std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .status()
+        .with_context(|| format!("failed to spawn '{}'", args[0]))
+}
+
+/// Checks that the remote dir can be quoted for the remote host's shell by
+/// [`quote_remote_dir`].
+fn check_remote_dir(remote: &RemoteTestHost, remote_windows: bool) -> anyhow::Result<()> {
+    // cmd.exe has no way to escape a double quote within a quoted string, and
+    // expands environment variables even within one.
+    if remote_windows && remote.dir.contains(['"', '%']) {
+        anyhow::bail!(
+            "remote dir {} must not contain '\"' or '%' on Windows",
+            remote.dir
+        );
+    }
+    Ok(())
+}
+
+/// Quotes the remote dir for the remote host's shell, which is cmd.exe for
+/// Windows OpenSSH and a POSIX shell otherwise.
+fn quote_remote_dir(remote: &RemoteTestHost, remote_windows: bool) -> String {
+    if remote_windows {
+        format!("\"{}\"", remote.dir)
+    } else {
+        format!("'{}'", remote.dir.replace('\'', r"'\''"))
+    }
+}
+
+fn ssh_mkdir_args(remote: &RemoteTestHost, remote_windows: bool) -> Vec<String> {
+    let dir = quote_remote_dir(remote, remote_windows);
+    // Succeed if the directory already exists.
+    let mkdir = if remote_windows {
+        format!("if not exist {dir} mkdir {dir}")
+    } else {
+        format!("mkdir -p {dir}")
+    };
+    vec!["ssh".into(), remote.destination.clone(), mkdir]
+}
+
+// scp's remote paths are not quoted, since scp uses SFTP by default and does
+// not pass them through the remote shell.
+fn scp_upload_args(remote: &RemoteTestHost, entries: &[PathBuf]) -> Vec<String> {
+    let mut args = vec!["scp".into(), "-r".into()];
+    args.extend(entries.iter().map(|e| e.display().to_string()));
+    args.push(format!("{}:{}", remote.destination, remote.dir));
+    args
+}
+
+fn ssh_run_args(remote: &RemoteTestHost, remote_windows: bool) -> Vec<String> {
+    // The generated scripts use paths relative to the test content dir.
+    let dir = quote_remote_dir(remote, remote_windows);
+    let run = if remote_windows {
+        // Exit with the script's exit code so that test failures can be
+        // distinguished.
+        format!(
+            "cd /d {dir} && powershell -NoProfile -ExecutionPolicy Bypass -Command \"& .\\run.ps1; exit $LASTEXITCODE\""
+        )
+    } else {
+        format!("cd {dir} && sh ./run.sh")
+    };
+    vec!["ssh".into(), remote.destination.clone(), run]
+}
+
+fn scp_download_args(remote: &RemoteTestHost, test_content_dir: &Path) -> Vec<String> {
+    vec![
+        "scp".into(),
+        "-r".into(),
+        format!("{}:{}/test_results", remote.destination, remote.dir),
+        test_content_dir.display().to_string(),
+    ]
+}
+
+/// Interprets the exit status of the remote nextest run.
+fn remote_tests_passed(status: std::process::ExitStatus) -> anyhow::Result<bool> {
+    match NextestExitStatus::from_code(status.code()) {
+        NextestExitStatus::Passed => Ok(true),
+        NextestExitStatus::TestsFailed => Ok(false),
+        status => anyhow::bail!("failed to run nextest on remote host: {status}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote() -> RemoteTestHost {
+        RemoteTestHost {
+            destination: "user@testhost".into(),
+            dir: "C:/vmm_tests".into(),
+        }
+    }
+
+    #[test]
+    fn single_openhcl_recipe() {
+        let all = openhcl_recipes_to_build(CommonArch::X86_64, None).unwrap();
+        assert!(all.len() > 1);
+        assert!(all.iter().any(|r| matches!(r, OpenhclIgvmRecipe::X64Cvm)));
+
+        let selected =
+            openhcl_recipes_to_build(CommonArch::X86_64, Some(vec![OpenhclIgvmRecipe::X64Cvm]))
+                .unwrap();
+        assert!(matches!(selected[..], [OpenhclIgvmRecipe::X64Cvm]));
+
+        // Recipes the tests don't use, including those for another
+        // architecture, are rejected.
+        for bad in [OpenhclIgvmRecipe::X64CvmDevkern, OpenhclIgvmRecipe::Aarch64] {
+            openhcl_recipes_to_build(CommonArch::X86_64, Some(vec![OpenhclIgvmRecipe::X64, bad]))
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn prebuilt_builds_nothing() {
+        let archive = PathBuf::from("vmm_tests/vmm-tests-archive.tar.zst");
+        let (build, source) =
+            select_builds(BuildSelections::default(), true, Some(archive.clone()));
+        assert_eq!(source, NextestArchiveSource::Prebuilt(archive));
+        // Lists every field, so that new builds must be considered here.
+        let BuildSelections {
+            openhcl: false,
+            openvmm: false,
+            pipette_windows: false,
+            pipette_linux: false,
+            guest_test_uefi: false,
+            tmks: false,
+            tmk_vmm_windows: false,
+            tmk_vmm_linux: false,
+            openhcl_recipes: None,
+        } = build
+        else {
+            panic!("prebuilt archive runs must not build anything");
+        };
+
+        let (build, source) = select_builds(BuildSelections::default(), false, None);
+        assert_eq!(source, NextestArchiveSource::Build);
+        assert!(build.openvmm && !build.openhcl && !build.pipette_linux && !build.tmk_vmm_linux);
+    }
+
+    #[test]
+    fn clean_environment_command() {
+        assert_eq!(clean_environment_args(None), ["clean-hyperv"]);
+        assert_eq!(
+            clean_environment_args(Some("petri")),
+            ["clean-hyperv", "--network-prefix", "petri"]
+        );
+    }
+
+    #[test]
+    fn remote_commands() {
+        let remote = remote();
+        assert_eq!(
+            ssh_mkdir_args(&remote, true),
+            [
+                "ssh",
+                "user@testhost",
+                r#"if not exist "C:/vmm_tests" mkdir "C:/vmm_tests""#
+            ]
+        );
+        assert_eq!(
+            ssh_mkdir_args(&remote, false),
+            ["ssh", "user@testhost", "mkdir -p 'C:/vmm_tests'"]
+        );
+        assert_eq!(
+            scp_upload_args(
+                &remote,
+                &[PathBuf::from("out/run.ps1"), PathBuf::from("out/images")]
+            ),
+            [
+                "scp",
+                "-r",
+                "out/run.ps1",
+                "out/images",
+                "user@testhost:C:/vmm_tests"
+            ]
+        );
+        assert_eq!(
+            ssh_run_args(&remote, true),
+            [
+                "ssh",
+                "user@testhost",
+                r#"cd /d "C:/vmm_tests" && powershell -NoProfile -ExecutionPolicy Bypass -Command "& .\run.ps1; exit $LASTEXITCODE""#
+            ]
+        );
+        assert_eq!(
+            ssh_run_args(&remote, false),
+            ["ssh", "user@testhost", "cd 'C:/vmm_tests' && sh ./run.sh"]
+        );
+    }
+
+    #[test]
+    fn remote_dir_quoting() {
+        let remote = RemoteTestHost {
+            destination: "user@testhost".into(),
+            dir: "/tmp/it's here".into(),
+        };
+        assert_eq!(
+            ssh_run_args(&remote, false)[2],
+            r"cd '/tmp/it'\''s here' && sh ./run.sh"
+        );
+        check_remote_dir(&remote, false).unwrap();
+
+        let remote = RemoteTestHost {
+            destination: "user@testhost".into(),
+            dir: r#"C:\tests" & calc & ""#.into(),
+        };
+        check_remote_dir(&remote, true).unwrap_err();
+        check_remote_dir(
+            &RemoteTestHost {
+                destination: "user@testhost".into(),
+                dir: "C:/%TEMP%".into(),
+            },
+            true,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn remote_results() {
+        assert_eq!(
+            scp_download_args(&remote(), Path::new("out")),
+            [
+                "scp",
+                "-r",
+                "user@testhost:C:/vmm_tests/test_results",
+                "out"
+            ]
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            let exit = |code: i32| std::process::ExitStatus::from_raw(code << 8);
+            assert!(remote_tests_passed(exit(0)).unwrap());
+            assert!(!remote_tests_passed(exit(100)).unwrap());
+            remote_tests_passed(exit(255)).unwrap_err();
+        }
+    }
+}
@@ -229,6 +229,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                         CommonProfile::Debug => CargoBuildProfile::Debug,
                     },
                     extra_env: injected_env,
+                    rust_toolchain: None,
                 };
 
             match build_mode {
@@ -245,6 +246,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                     nextest_working_dir: None,
                     nextest_config_file: None,
                     run_ignored: false,
+                    retries: None,
+                    libtest_json: false,
+                    extra_args: Vec::new(),
                     extra_env: None,
                     pre_run_deps,
                     results,
@@ -57,6 +57,9 @@ pub struct Request {
         pub profile: CommonProfile,
         /// Build mode to use when building the nextest VMM tests
         pub build_mode: BuildNextestVmmTestsMode,
+        /// Build the VMM tests with this rustup toolchain instead of the
+        /// repo's default one
+        pub rust_toolchain: Option<String>,
     }
 }
 
@@ -82,6 +85,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
             target,
             profile,
             build_mode,
+            rust_toolchain,
         } in requests
         {
             let mut ambient_deps = ambient_deps.clone();
@@ -128,6 +132,7 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                         CommonProfile::Debug => CargoBuildProfile::Debug,
                     },
                     extra_env: injected_env,
+                    rust_toolchain,
                 };
 
             match build_mode {
@@ -151,6 +156,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                         nextest_working_dir: None,
                         nextest_config_file: None,
                         run_ignored: false,
+                        retries: None,
+                        libtest_json: false,
+                        extra_args: Vec::new(),
                         extra_env: Some(extra_env),
                         pre_run_deps: ambient_deps,
                         results,
@@ -0,0 +1,73 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Build `petri-tool` binaries
+
+use crate::run_cargo_build::common::CommonProfile;
+use crate::run_cargo_build::common::CommonTriple;
+use flowey::node::prelude::*;
+
+#[derive(Serialize, Deserialize)]
+pub struct PetriToolOutput {
+    #[serde(rename = "petri-tool.exe")]
+    pub exe: PathBuf,
+    #[serde(rename = "petri-tool.pdb")]
+    pub pdb: PathBuf,
+}
+
+impl Artifact for PetriToolOutput {}
+
+flowey_request! {
+    pub struct Request {
+        pub target: CommonTriple,
+        pub profile: CommonProfile,
+        pub petri_tool: WriteVar<PetriToolOutput>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Request;
+
+    fn imports(ctx: &mut ImportCtx<'_>) {
+        ctx.import::<crate::run_cargo_build::Node>();
+    }
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Request {
+            target,
+            profile,
+            petri_tool,
+        } = request;
+        let output = ctx.reqv(|v| crate::run_cargo_build::Request {
+            crate_name: "petri-tool".into(),
+            out_name: "petri-tool".into(),
+            crate_type: flowey_lib_common::run_cargo_build::CargoCrateType::Bin,
+            profile: profile.into(),
+            features: [].into(),
+            target: target.as_triple(),
+            no_split_dbg_info: false,
+            extra_env: None,
+            pre_build_deps: Vec::new(),
+            output: v,
+        });
+
+        ctx.emit_minor_rust_step("report built petri-tool", |ctx| {
+            let petri_tool = petri_tool.claim(ctx);
+            let output = output.claim(ctx);
+            move |rt| {
+                let output = match rt.read(output) {
+                    crate::run_cargo_build::CargoBuildOutput::WindowsBin { exe, pdb } => {
+                        PetriToolOutput { exe, pdb }
+                    }
+                    _ => unreachable!(),
+                };
+
+                rt.write(petri_tool, &output);
+            }
+        });
+
+        Ok(())
+    }
+}
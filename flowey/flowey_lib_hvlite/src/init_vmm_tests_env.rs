@@ -283,8 +283,6 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
 
                 if let Some(openhcl_igvm_files) = openhcl_igvm_files {
                     for (recipe, openhcl_igvm) in rt.read(openhcl_igvm_files) {
-                        let crate::run_igvmfilegen::IgvmOutput { igvm_bin, .. } = openhcl_igvm;
-
                         let filename = match recipe {
                             OpenhclIgvmRecipe::X64 => "openhcl-x64.bin",
                             OpenhclIgvmRecipe::X64Devkern => "openhcl-x64-devkern.bin",
@@ -300,7 +298,10 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                             }
                         };
 
-                        fs_err::copy(igvm_bin, test_content_dir.join(filename))?;
+                        openhcl_igvm.validate().with_context(|| {
+                            format!("OpenHCL recipe {recipe:?} produced a bad IGVM file")
+                        })?;
+                        fs_err::copy(openhcl_igvm.igvm_bin, test_content_dir.join(filename))?;
                     }
                 }
 
@@ -23,6 +23,7 @@
 pub mod build_openhcl_initrd;
 pub mod build_openvmm;
 pub mod build_openvmm_hcl;
+pub mod build_petri_tool;
 pub mod build_pipette;
 pub mod build_rustdoc;
 pub mod build_sidecar;
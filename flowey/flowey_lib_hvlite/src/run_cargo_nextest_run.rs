@@ -43,6 +43,13 @@ pub struct Request {
         pub nextest_config_file: Option<ReadVar<PathBuf>>,
         /// Whether to run ignored test
         pub run_ignored: bool,
+        /// Override the number of times to retry failing tests
+        pub retries: Option<u32>,
+        /// Capture libtest-json output and summarize it, reporting tests that
+        /// passed on retry as flaky
+        pub libtest_json: bool,
+        /// Additional arguments passed verbatim to `nextest run`
+        pub extra_args: Vec<String>,
         /// Additional env vars set when executing the tests.
         pub extra_env: Option<ReadVar<BTreeMap<String, String>>>,
         /// Wait for specified side-effects to resolve before building / running any
@@ -88,6 +95,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
             nextest_working_dir,
             nextest_config_file,
             run_ignored,
+            retries,
+            libtest_json,
+            extra_args,
             mut pre_run_deps,
             results,
             extra_env,
@@ -129,6 +139,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                     with_rlimit_unlimited_core_size: true,
                     nextest_filter_expr,
                     run_ignored,
+                    retries,
+                    libtest_json,
+                    extra_args,
                     pre_run_deps,
                     results,
                 },
@@ -17,6 +17,50 @@ pub struct IgvmOutput {
     pub igvm_vbs_json: Option<PathBuf>,
 }
 
+impl IgvmOutput {
+    /// Checks that the IGVM file exists and has a valid IGVM header.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        use std::io::Read;
+
+        let file = fs_err::File::open(&self.igvm_bin)?;
+        let file_size = file.metadata()?.len();
+        let mut header = Vec::new();
+        file.take(IGVM_FIXED_HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        validate_igvm_header(&header, file_size)
+            .with_context(|| format!("invalid IGVM file {}", self.igvm_bin.display()))
+    }
+}
+
+/// Size of the IGVM fixed header: magic, format version, variable header
+/// offset and size, total file size, and checksum, each a little-endian u32.
+const IGVM_FIXED_HEADER_SIZE: usize = 24;
+
+/// Validates the IGVM fixed header of a file of size `file_size`.
+fn validate_igvm_header(header: &[u8], file_size: u64) -> anyhow::Result<()> {
+    let Some(header) = header.get(..IGVM_FIXED_HEADER_SIZE) else {
+        anyhow::bail!("file is too small ({file_size} bytes) to be an IGVM file");
+    };
+    let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+
+    if &header[..4] != b"IGVM" {
+        anyhow::bail!("bad magic {:x?}", &header[..4]);
+    }
+    let format_version = field(1);
+    if !matches!(format_version, 1 | 2) {
+        anyhow::bail!("unsupported format version {format_version}");
+    }
+    let total_file_size = field(4);
+    if u64::from(total_file_size) != file_size {
+        anyhow::bail!("header reports file size {total_file_size}, but file is {file_size} bytes");
+    }
+    let variable_header_end = u64::from(field(2)) + u64::from(field(3));
+    if variable_header_end > file_size {
+        anyhow::bail!("variable header ends at {variable_header_end}, past the end of the file");
+    }
+    Ok(())
+}
+
 flowey_request! {
     pub struct Request {
         /// Path to igvmfilegen bin to use
@@ -47,7 +91,12 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             igvm,
         } = request;
 
+        // Keep the generated files in the persistent dir (when available), so
+        // that they can be reused by later runs if none of the inputs change.
+        let persistent_dir = ctx.persistent_dir();
+
         ctx.emit_rust_step("building igvm file", |ctx| {
+            let persistent_dir = persistent_dir.claim(ctx);
             let igvm = igvm.claim(ctx);
             let igvmfilegen = igvmfilegen.claim(ctx);
             let manifest = manifest.claim(ctx);
@@ -57,27 +106,43 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                 let manifest = rt.read(manifest);
                 let resources = rt.read(resources);
 
+                let inputs = [&igvmfilegen, &manifest]
+                    .into_iter()
+                    .chain(resources.values())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let resources = igvmfilegen_config::Resources::new(resources.into_iter().collect())
+                    .context("creating igvm resources")?;
+                let resources_json = serde_json::to_string_pretty(&resources)?;
+
                 let sh = xshell::Shell::new()?;
+                if let Some(persistent_dir) = persistent_dir {
+                    // Each recipe gets its own dir, keyed by its inputs.
+                    let dir = rt
+                        .read(persistent_dir)
+                        .join(output_dir_name(&manifest, &resources_json));
+                    fs_err::create_dir_all(&dir)?;
+                    sh.change_dir(dir);
+                }
 
                 let igvm_file_stem = "igvm";
                 let igvm_path = sh.current_dir().join(format!("{igvm_file_stem}.bin"));
                 let resources_path = sh.current_dir().join("igvm.json");
 
-                let resources = igvmfilegen_config::Resources::new(resources.into_iter().collect())
-                    .context("creating igvm resources")?;
-                std::fs::write(&resources_path, serde_json::to_string_pretty(&resources)?)
-                    .context("writing resources")?;
-
-                xshell::cmd!(
-                    sh,
-                    "{igvmfilegen} manifest
-                            -m {manifest}
-                            -r {resources_path}
-                            --debug-validation
-                            -o {igvm_path}
-                        "
-                )
-                .run()?;
+                if igvm_needs_update(&inputs, &resources_json, &resources_path, &igvm_path)? {
+                    xshell::cmd!(
+                        sh,
+                        "{igvmfilegen} manifest
+                                -m {manifest}
+                                -r {resources_path}
+                                --debug-validation
+                                -o {igvm_path}
+                            "
+                    )
+                    .run()?;
+                } else {
+                    log::info!("{} is up to date", igvm_path.display());
+                }
 
                 let igvm_map_path = igvm_path.with_extension("bin.map");
                 let igvm_map_path = igvm_map_path.exists().then_some(igvm_map_path);
@@ -112,3 +177,124 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
         Ok(())
     }
 }
+
+/// Returns the name of the dir to generate the IGVM file for `manifest` and
+/// `resources_json` into.
+fn output_dir_name(manifest: &Path, resources_json: &str) -> String {
+    use std::hash::Hash;
+    use std::hash::Hasher;
+
+    // `DefaultHasher::new` is deterministic within a toolchain version, which
+    // is enough for reuse between local runs.
+    let mut hasher = std::hash::DefaultHasher::new();
+    manifest.hash(&mut hasher);
+    resources_json.hash(&mut hasher);
+    let stem = manifest
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    format!("{stem}-{:016x}", hasher.finish())
+}
+
+/// Writes the igvmfilegen resources file to `resources_path` if its contents
+/// changed, and returns whether the IGVM file at `igvm_path` needs to be
+/// regenerated from `inputs` and the resources file.
+fn igvm_needs_update(
+    inputs: &[PathBuf],
+    resources_json: &str,
+    resources_path: &Path,
+    igvm_path: &Path,
+) -> anyhow::Result<bool> {
+    // Only rewrite the resources file when it changes, so that its timestamp
+    // reflects the last time the set of resources changed.
+    let resources_changed = match fs_err::read_to_string(resources_path) {
+        Ok(old) => old != resources_json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e.into()),
+    };
+    if resources_changed {
+        fs_err::write(resources_path, resources_json).context("writing resources")?;
+    }
+
+    flowey_lib_common::_util::needs_update(
+        inputs.iter().map(|p| p.as_path()).chain([resources_path]),
+        igvm_path,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::igvm_needs_update;
+    use super::validate_igvm_header;
+    use flowey::node::prelude::fs_err;
+    use std::path::Path;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    fn header(magic: &[u8; 4], version: u32, total_file_size: u32) -> Vec<u8> {
+        let mut header = magic.to_vec();
+        for field in [version, 24, 0x100, total_file_size, 0] {
+            header.extend(field.to_le_bytes());
+        }
+        header
+    }
+
+    #[test]
+    fn igvm_header() {
+        validate_igvm_header(&header(b"IGVM", 2, 0x1000), 0x1000).unwrap();
+
+        // Bad magic
+        validate_igvm_header(&header(b"\x7fELF", 2, 0x1000), 0x1000).unwrap_err();
+        // Unknown version
+        validate_igvm_header(&header(b"IGVM", 3, 0x1000), 0x1000).unwrap_err();
+        // Truncated file
+        validate_igvm_header(&header(b"IGVM", 1, 0x1000), 0x800).unwrap_err();
+        validate_igvm_header(&header(b"IGVM", 1, 0x1000)[..16], 16).unwrap_err();
+        // Variable header past the end of the file
+        validate_igvm_header(&header(b"IGVM", 1, 0x80), 0x80).unwrap_err();
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        fs_err::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn unchanged_inputs_skip_regeneration() {
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = ["igvmfilegen", "manifest.json", "vmlinux", "openhcl_boot"]
+            .map(|name| dir.path().join(name));
+        let resources_path = dir.path().join("igvm.json");
+        let igvm_path = dir.path().join("igvm.bin");
+        let resources = r#"{"resources":{"underhill_kernel":"vmlinux"}}"#;
+
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        for input in &inputs {
+            fs_err::write(input, "").unwrap();
+            set_modified(input, earlier);
+        }
+
+        // First run: no igvm file yet.
+        assert!(igvm_needs_update(&inputs, resources, &resources_path, &igvm_path).unwrap());
+        fs_err::write(&igvm_path, "IGVM").unwrap();
+
+        // Unchanged inputs: nothing to do.
+        assert!(!igvm_needs_update(&inputs, resources, &resources_path, &igvm_path).unwrap());
+
+        // A rebuilt input forces regeneration.
+        set_modified(&inputs[2], SystemTime::now() + Duration::from_secs(60));
+        assert!(igvm_needs_update(&inputs, resources, &resources_path, &igvm_path).unwrap());
+        set_modified(&inputs[2], earlier);
+        assert!(!igvm_needs_update(&inputs, resources, &resources_path, &igvm_path).unwrap());
+
+        // So does a change to the set of resources.
+        let resources = r#"{"resources":{"underhill_kernel":"Image"}}"#;
+        set_modified(&resources_path, earlier);
+        fs_err::write(&igvm_path, "IGVM").unwrap();
+        assert!(igvm_needs_update(&inputs, resources, &resources_path, &igvm_path).unwrap());
+    }
+}
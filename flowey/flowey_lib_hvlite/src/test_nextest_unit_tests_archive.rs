@@ -60,6 +60,9 @@ fn emit(requests: Vec<Self::Request>, ctx: &mut NodeCtx<'_>) -> anyhow::Result<(
                 nextest_working_dir: None,
                 nextest_config_file: None,
                 run_ignored: false,
+                retries: None,
+                libtest_json: false,
+                extra_args: Vec::new(),
                 extra_env: None,
                 pre_run_deps: Vec::new(), // FIXME: ensure all deps are installed
                 results,
@@ -31,10 +31,21 @@ pub struct Request {
         pub target: Option<ReadVar<target_lexicon::Triple>>,
         /// Additional env vars set when executing the tests.
         pub extra_env: ReadVar<BTreeMap<String, String>>,
+        /// Override the number of times to retry failing tests
+        pub retries: Option<u32>,
+        /// Capture libtest-json output and summarize it, reporting tests that
+        /// passed on retry as flaky
+        pub libtest_json: bool,
+        /// Additional arguments passed verbatim to `nextest run`
+        pub extra_args: Vec<String>,
         /// Wait for specified side-effects to resolve before building / running
         /// any tests. (e.g: to allow for some ambient packages / dependencies
         /// to get installed).
         pub pre_run_deps: Vec<ReadVar<SideEffect>>,
+        /// Collect the output dirs of failing tests, keyed by test name, from
+        /// the provided test log dir once the tests have run
+        pub failed_test_output_dirs:
+            Option<(ReadVar<PathBuf>, WriteVar<BTreeMap<String, PathBuf>>)>,
         /// Results of running the tests
         pub results: WriteVar<TestResults>,
     }
@@ -59,7 +70,11 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             nextest_bin,
             target,
             extra_env,
+            retries,
+            libtest_json,
+            extra_args,
             mut pre_run_deps,
+            failed_test_output_dirs,
             results,
         } = request;
 
@@ -79,6 +94,7 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
 
         let nextest_archive = nextest_archive_file.map(ctx, |x| x.archive_file);
 
+        let (test_results, write_test_results) = ctx.new_var();
         ctx.req(crate::run_cargo_nextest_run::Request {
             friendly_name: "vmm_tests".into(),
             run_kind: flowey_lib_common::run_cargo_nextest_run::NextestRunKind::RunFromArchive {
@@ -91,11 +107,244 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
             nextest_working_dir,
             nextest_config_file,
             run_ignored: false,
+            retries,
+            libtest_json,
+            extra_args,
             extra_env: Some(extra_env),
             pre_run_deps,
-            results,
+            results: write_test_results,
+        });
+
+        ctx.emit_rust_step("collect test output", |ctx| {
+            let test_results = test_results.claim(ctx);
+            let results = results.claim(ctx);
+            let failed_test_output_dirs =
+                failed_test_output_dirs.map(|(dir, write)| (dir.claim(ctx), write.claim(ctx)));
+            move |rt| {
+                let test_results = rt.read(test_results);
+                if let Some((test_log_dir, write)) = failed_test_output_dirs {
+                    let test_log_dir = rt.read(test_log_dir);
+                    let dirs = find_failed_test_output_dirs(&test_log_dir)?;
+                    rt.write(write, &dirs);
+
+                    if let Some(junit_xml) = &test_results.junit_xml {
+                        let properties = find_test_properties(&test_log_dir)?;
+                        let xml = fs_err::read_to_string(junit_xml)?;
+                        fs_err::write(junit_xml, add_junit_properties(&xml, &properties)?)?;
+                    }
+                }
+                rt.write(results, &test_results);
+                Ok(())
+            }
         });
 
         Ok(())
     }
 }
+
+/// Finds the output dirs of failing tests in `test_log_dir`, keyed by test
+/// name.
+///
+/// Petri writes a `petri.failed` file containing the test name to the output
+/// dir of each failing test.
+fn find_failed_test_output_dirs(test_log_dir: &Path) -> anyhow::Result<BTreeMap<String, PathBuf>> {
+    let mut dirs = BTreeMap::new();
+    if !test_log_dir.exists() {
+        return Ok(dirs);
+    }
+    for entry in fs_err::read_dir(test_log_dir)? {
+        let path = entry?.path();
+        let failed = path.join("petri.failed");
+        if failed.is_file() {
+            let name = fs_err::read_to_string(&failed)?;
+            dirs.insert(name.trim().to_owned(), path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// Finds the properties to report for each test in `test_log_dir`, keyed by
+/// test name.
+///
+/// These are the fields of the `resource_usage.json` file petri writes to the
+/// output dir of each test, which is named by its `petri.passed` or
+/// `petri.failed` file. If a test was retried, the last run is reported.
+fn find_test_properties(
+    test_log_dir: &Path,
+) -> anyhow::Result<BTreeMap<String, Vec<(String, String)>>> {
+    let mut properties = BTreeMap::new();
+    if !test_log_dir.exists() {
+        return Ok(properties);
+    }
+    // The output dirs are named after the test and the time of the run.
+    let mut paths = fs_err::read_dir(test_log_dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    for path in paths {
+        let usage = path.join("resource_usage.json");
+        if !usage.is_file() {
+            continue;
+        }
+        let Some(result) = ["petri.passed", "petri.failed"]
+            .iter()
+            .map(|f| path.join(f))
+            .find(|f| f.is_file())
+        else {
+            continue;
+        };
+        let name = fs_err::read_to_string(result)?;
+        let usage: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&fs_err::read_to_string(&usage)?)?;
+        let mut usage = usage
+            .into_iter()
+            .map(|(k, v)| match v {
+                serde_json::Value::String(v) => (k, v),
+                v => (k, v.to_string()),
+            })
+            .collect::<Vec<_>>();
+        usage.sort();
+        properties.insert(name.trim().to_owned(), usage);
+    }
+    Ok(properties)
+}
+
+/// Adds a `<properties>` element to each `<testcase>` in the JUnit XML
+/// emitted by nextest whose name is in `properties`.
+fn add_junit_properties(
+    xml: &str,
+    properties: &BTreeMap<String, Vec<(String, String)>>,
+) -> anyhow::Result<String> {
+    use quick_xml::events::BytesEnd;
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut writer = quick_xml::Writer::new(Vec::new());
+    loop {
+        let event = reader.read_event()?;
+        let (Event::Start(e) | Event::Empty(e)) = &event else {
+            if matches!(event, Event::Eof) {
+                break;
+            }
+            writer.write_event(event)?;
+            continue;
+        };
+        let props = if e.name().as_ref() == b"testcase" {
+            match e.try_get_attribute("name")? {
+                Some(name) => properties.get(name.unescape_value()?.as_ref()),
+                None => None,
+            }
+        } else {
+            None
+        };
+        let Some(props) = props else {
+            writer.write_event(event)?;
+            continue;
+        };
+        writer.write_event(Event::Start(e.borrow()))?;
+        writer
+            .create_element("properties")
+            .write_inner_content(|writer| {
+                for (name, value) in props {
+                    writer
+                        .create_element("property")
+                        .with_attribute(("name", name.as_str()))
+                        .with_attribute(("value", value.as_str()))
+                        .write_empty()?;
+                }
+                Ok::<_, std::io::Error>(())
+            })?;
+        if matches!(event, Event::Empty(_)) {
+            writer.write_event(Event::End(BytesEnd::new("testcase")))?;
+        }
+    }
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::add_junit_properties;
+    use super::find_failed_test_output_dirs;
+    use super::find_test_properties;
+
+    #[test]
+    fn failed_test_output_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        for (test, result) in [
+            ("multiarch::boot", "petri.failed"),
+            ("multiarch::reboot", "petri.passed"),
+        ] {
+            let test_dir = dir.path().join(test.replace("::", "__"));
+            fs_err::create_dir(&test_dir).unwrap();
+            fs_err::write(test_dir.join("petri.log"), "log").unwrap();
+            fs_err::write(test_dir.join(result), test).unwrap();
+        }
+
+        let dirs = find_failed_test_output_dirs(dir.path()).unwrap();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs["multiarch::boot"], dir.path().join("multiarch__boot"));
+    }
+
+    #[test]
+    fn junit_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        for (run, test, usage) in [
+            ("multiarch__boot__1", "multiarch::boot", Some(1)),
+            ("multiarch__boot__2", "multiarch::boot", Some(2)),
+            ("multiarch__reboot__1", "multiarch::reboot", None),
+        ] {
+            let test_dir = dir.path().join(run);
+            fs_err::create_dir(&test_dir).unwrap();
+            fs_err::write(test_dir.join("petri.passed"), test).unwrap();
+            if let Some(usage) = usage {
+                fs_err::write(
+                    test_dir.join("resource_usage.json"),
+                    format!(r#"{{"peak_memory_bytes":{usage},"cpu_time_secs":0.5}}"#),
+                )
+                .unwrap();
+            }
+        }
+
+        let properties = find_test_properties(dir.path()).unwrap();
+        assert_eq!(properties.len(), 1);
+        let reboot = concat!(
+            r#"<testcase name="multiarch::reboot" classname="vmm_tests">"#,
+            "<system-out>x</system-out></testcase>",
+        );
+        let xml = format!(
+            r#"<testsuite><testcase name="multiarch::boot" classname="vmm_tests"/>{reboot}</testsuite>"#
+        );
+        assert_eq!(
+            add_junit_properties(&xml, &properties).unwrap(),
+            format!(
+                concat!(
+                    r#"<testsuite><testcase name="multiarch::boot" classname="vmm_tests">"#,
+                    "<properties>",
+                    r#"<property name="cpu_time_secs" value="0.5"/>"#,
+                    r#"<property name="peak_memory_bytes" value="2"/>"#,
+                    "</properties></testcase>{}</testsuite>",
+                ),
+                reboot
+            )
+        );
+
+        // Names are matched unescaped, and values are escaped.
+        let properties = [(
+            "a&b".to_owned(),
+            vec![("note".to_owned(), r#""<x>" & y"#.to_owned())],
+        )]
+        .into();
+        assert_eq!(
+            add_junit_properties(
+                r#"<testcase name="a&amp;b"><system-out>x &lt; y</system-out></testcase>"#,
+                &properties
+            )
+            .unwrap(),
+            concat!(
+                r#"<testcase name="a&amp;b"><properties>"#,
+                r#"<property name="note" value="&quot;&lt;x&gt;&quot; &amp; y"/>"#,
+                "</properties><system-out>x &lt; y</system-out></testcase>",
+            )
+        );
+    }
+}
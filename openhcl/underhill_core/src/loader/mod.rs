@@ -276,6 +276,7 @@ fn load_linux(params: LoadLinuxParams<'_>) -> Result<VpContext, Error> {
         with_psp: platform_config.general.psp_enabled,
         pm_base: crate::worker::PM_BASE,
         acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
+        extra_tables: &[],
     };
 
     if mem_layout.mmio().len() < 2 {
@@ -470,6 +471,7 @@ pub fn write_uefi_config(
             with_psp: platform_config.general.psp_enabled,
             pm_base: crate::worker::PM_BASE,
             acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
+            extra_tables: &[],
         };
 
         // Build the ACPI tables as specified.
@@ -1948,6 +1948,7 @@ async fn new_underhill_vm(
                 with_psp: dps.general.psp_enabled,
                 pm_base: PM_BASE,
                 acpi_irq: SYSTEM_IRQ_ACPI,
+                extra_tables: &[],
             };
 
             let config = firmware_pcat::config::PcatBiosConfig {
@@ -1148,6 +1148,7 @@ async fn load(
                             with_psp: cfg.chipset.with_generic_psp,
                             pm_base: PM_BASE,
                             acpi_irq: SYSTEM_IRQ_ACPI,
+                            extra_tables: &[],
                         };
                         let srat = acpi_tables_builder.build_srat();
                         firmware_pcat::config::PcatBiosConfig {
@@ -2328,6 +2329,12 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             with_pit: self.chipset_cfg.with_generic_pit,
             pm_base: PM_BASE,
             acpi_irq: SYSTEM_IRQ_ACPI,
+            extra_tables: match &self.load_mode {
+                LoadMode::Linux {
+                    custom_acpi_tables, ..
+                } => custom_acpi_tables.as_slice(),
+                _ => &[],
+            },
         };
 
         if vtl2_only {
@@ -2344,6 +2351,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                 ref cmdline,
                 enable_serial,
                 ref custom_dsdt,
+                custom_acpi_tables: _,
             } => {
                 let kernel_config = super::vm_loaders::linux::KernelConfig {
                     kernel,
@@ -2387,6 +2395,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                 ref cmdline,
                 enable_serial,
                 custom_dsdt: _,
+                custom_acpi_tables: _,
             } => {
                 let kernel_config = super::vm_loaders::linux::KernelConfig {
                     kernel,
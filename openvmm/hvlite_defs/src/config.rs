@@ -102,6 +102,8 @@ pub enum LoadMode {
         cmdline: String,
         enable_serial: bool,
         custom_dsdt: Option<Vec<u8>>,
+        /// Additional raw ACPI tables to append to the XSDT.
+        custom_acpi_tables: Vec<Vec<u8>>,
     },
     Uefi {
         firmware: File,
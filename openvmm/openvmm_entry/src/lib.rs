@@ -882,6 +882,7 @@ fn vm_config_from_command_line(
             initrd: initrd.map(Into::into),
             cmdline,
             custom_dsdt,
+            custom_acpi_tables: Vec::new(),
             enable_serial: any_serial_configured,
         };
     }
@@ -426,6 +426,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                     initrd: Some(initrd_file),
                     cmdline: boot.kernel_cmdline,
                     custom_dsdt: None,
+                    custom_acpi_tables: Vec::new(),
                     enable_serial: true,
                 }
             }
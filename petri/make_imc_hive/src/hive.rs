@@ -0,0 +1,319 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The registry contents of the IMC hive, independent of how they are
+//! written.
+
+use std::borrow::Cow;
+
+/// How pipette is started in the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipetteStartup {
+    /// Run pipette as an auto-start service.
+    Service,
+    /// Run pipette from a scheduled task, as SYSTEM, at system startup. The
+    /// task is registered, and started for the current boot, by a service
+    /// that runs `schtasks.exe`.
+    StartupTask,
+}
+
+/// A registry value to write into the hive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path of the key holding the value, relative to the hive root.
+    pub key: &'static [&'static str],
+    /// The value name.
+    pub name: Cow<'static, str>,
+    /// The value data.
+    pub value: Value,
+}
+
+/// Registry value data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A `REG_DWORD`.
+    Dword(u32),
+    /// A `REG_SZ`.
+    Sz(String),
+    /// A `REG_MULTI_SZ`.
+    MultiSz(Vec<String>),
+    /// A `REG_BINARY`.
+    Binary(Vec<u8>),
+}
+
+/// The unique GPT partition GUID of the agent disk's volume, in its on-disk
+/// byte order.
+///
+/// The hive assigns the agent drive letter to the partition with this GUID,
+/// so the letter does not depend on how many other disks the guest has.
+pub const AGENT_PARTITION_GUID: [u8; 16] = [
+    0x55, 0x29, 0x65, 0x69, 0x3A, 0xA7, 0x98, 0x41, 0xBA, 0xBD, 0xB5, 0x50, 0x77, 0x14, 0xA1, 0xF3,
+];
+
+const PIPETTE_SERVICE_KEY: &[&str] = &["SYSTEM", "CurrentControlSet", "Services", "pipette"];
+const PIPETTE_TASK_SERVICE_KEY: &[&str] =
+    &["SYSTEM", "CurrentControlSet", "Services", "pipette-task"];
+const PIPETTE_TASK_NAME: &str = "Petri pipette";
+
+/// Returns the values to write into the IMC hive.
+///
+/// `depend_on` names additional services, such as `Tcpip` or `Dhcp`, that must
+/// start before pipette's service. For [`PipetteStartup::StartupTask`], they
+/// delay registering the task instead.
+///
+/// If `log_file` is set, pipette copies its own logs, including any startup
+/// failures, to that guest path, such as a file on the agent disk.
+///
+/// `agent_drive` is the drive letter to give the agent disk, which holds
+/// pipette and the optional unattend file. The disk's partition must have the
+/// unique GUID [`AGENT_PARTITION_GUID`].
+pub fn entries(
+    startup: PipetteStartup,
+    depend_on: &[&str],
+    log_file: Option<&str>,
+    agent_drive: char,
+) -> anyhow::Result<Vec<Entry>> {
+    if !agent_drive.is_ascii_alphabetic() {
+        anyhow::bail!("invalid agent drive {agent_drive:?}");
+    }
+    let agent_drive = agent_drive.to_ascii_uppercase();
+
+    let mut pipette_args = String::new();
+    if let Some(log_file) = log_file {
+        // The path is embedded in command lines, so keep it to a single,
+        // unquoted argument.
+        if log_file.is_empty() || log_file.contains(|c: char| c.is_whitespace() || c == '"') {
+            anyhow::bail!("invalid pipette log file path {log_file:?}");
+        }
+        pipette_args = format!(" --log-file {log_file}");
+    }
+
+    let entry = |key, name: &'static str, value| Entry {
+        key,
+        name: name.into(),
+        value,
+    };
+    let sz = |s: &str| Value::Sz(s.to_owned());
+    let depend_on = |required: &str| {
+        Value::MultiSz(
+            std::iter::once(required)
+                .chain(depend_on.iter().copied())
+                .map(str::to_owned)
+                .collect(),
+        )
+    };
+    let mut entries = match startup {
+        PipetteStartup::Service => vec![
+            entry(PIPETTE_SERVICE_KEY, "Type", Value::Dword(0x10)), // win32 service
+            entry(PIPETTE_SERVICE_KEY, "Start", Value::Dword(2)),   // auto start
+            entry(PIPETTE_SERVICE_KEY, "ErrorControl", Value::Dword(1)), // normal
+            entry(
+                PIPETTE_SERVICE_KEY,
+                "ImagePath",
+                Value::Sz(format!(
+                    "{agent_drive}:\\pipette.exe --service{pipette_args}"
+                )),
+            ),
+            entry(
+                PIPETTE_SERVICE_KEY,
+                "DisplayName",
+                sz("Petri pipette agent"),
+            ),
+            entry(PIPETTE_SERVICE_KEY, "ObjectName", sz("LocalSystem")),
+            entry(PIPETTE_SERVICE_KEY, "DependOnService", depend_on("RpcSs")),
+        ],
+        PipetteStartup::StartupTask => vec![
+            // schtasks.exe is not a real service, so the service control
+            // manager reports a start failure once the commands exit without
+            // ever connecting to it. Ignore that failure.
+            entry(PIPETTE_TASK_SERVICE_KEY, "Type", Value::Dword(0x10)), // win32 service
+            entry(PIPETTE_TASK_SERVICE_KEY, "Start", Value::Dword(2)),   // auto start
+            entry(PIPETTE_TASK_SERVICE_KEY, "ErrorControl", Value::Dword(0)), // ignore
+            // `/F` replaces the task if it already exists, since this runs on
+            // every boot. The startup trigger has already fired by the time
+            // the task is first registered, so also start it explicitly; on
+            // later boots `/Run` finds it already running and does nothing.
+            // The image path is a plain string, so `cmd.exe` and
+            // `schtasks.exe` are found via the system directory rather than
+            // `%SystemRoot%`.
+            entry(
+                PIPETTE_TASK_SERVICE_KEY,
+                "ImagePath",
+                Value::Sz(format!(
+                    r#"cmd.exe /c schtasks.exe /Create /F /TN "{PIPETTE_TASK_NAME}" /SC ONSTART /RU SYSTEM /RL HIGHEST /TR "{agent_drive}:\pipette.exe{pipette_args}" && schtasks.exe /Run /TN "{PIPETTE_TASK_NAME}""#
+                )),
+            ),
+            entry(
+                PIPETTE_TASK_SERVICE_KEY,
+                "DisplayName",
+                sz("Petri pipette task registration"),
+            ),
+            entry(PIPETTE_TASK_SERVICE_KEY, "ObjectName", sz("LocalSystem")),
+            entry(
+                PIPETTE_TASK_SERVICE_KEY,
+                "DependOnService",
+                depend_on("Schedule"),
+            ),
+        ],
+    };
+
+    // Reserve the drive letter for the agent disk's volume before the mount
+    // manager letters any other volumes. A GPT partition is identified by
+    // `DMIO:ID:` followed by its unique partition GUID.
+    entries.push(Entry {
+        key: &["SYSTEM", "MountedDevices"],
+        name: format!("\\DosDevices\\{agent_drive}:").into(),
+        value: Value::Binary([b"DMIO:ID:".as_slice(), &AGENT_PARTITION_GUID].concat()),
+    });
+
+    // Point Windows Setup at an optional unattend file on the agent disk.
+    // Setup falls back to its usual search order if the file is absent.
+    entries.push(entry(
+        &["SYSTEM", "Setup"],
+        "UnattendFile",
+        Value::Sz(format!("{agent_drive}:\\unattend.xml")),
+    ));
+
+    // Windows defaults to 1, so we need to set it to 2 to cause Windows to
+    // apply the IMC changes on first boot.
+    entries.push(entry(&[], "Sequence", Value::Dword(2)));
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(entries: &[Entry], key: &[&str], name: &str) -> Option<Value> {
+        entries
+            .iter()
+            .find(|e| e.key == key && e.name == name)
+            .map(|e| e.value.clone())
+    }
+
+    fn image_path(entries: &[Entry], key: &[&str]) -> String {
+        let Some(Value::Sz(image_path)) = find(entries, key, "ImagePath") else {
+            panic!("missing image path");
+        };
+        image_path
+    }
+
+    #[test]
+    fn service() {
+        let entries = entries(PipetteStartup::Service, &[], None, 'D').unwrap();
+        assert_eq!(
+            image_path(&entries, PIPETTE_SERVICE_KEY),
+            "D:\\pipette.exe --service"
+        );
+        assert!(!entries.iter().any(|e| e.key == PIPETTE_TASK_SERVICE_KEY));
+        assert_eq!(
+            find(&entries, PIPETTE_SERVICE_KEY, "DependOnService"),
+            Some(Value::MultiSz(vec!["RpcSs".into()]))
+        );
+        assert_eq!(find(&entries, &[], "Sequence"), Some(Value::Dword(2)));
+    }
+
+    #[test]
+    fn depend_on() {
+        let entries = entries(PipetteStartup::Service, &["Tcpip", "Dhcp"], None, 'D').unwrap();
+        assert_eq!(
+            find(&entries, PIPETTE_SERVICE_KEY, "DependOnService"),
+            Some(Value::MultiSz(vec![
+                "RpcSs".into(),
+                "Tcpip".into(),
+                "Dhcp".into()
+            ]))
+        );
+
+        let entries = super::entries(PipetteStartup::StartupTask, &["Tcpip"], None, 'D').unwrap();
+        assert_eq!(
+            find(&entries, PIPETTE_TASK_SERVICE_KEY, "DependOnService"),
+            Some(Value::MultiSz(vec!["Schedule".into(), "Tcpip".into()]))
+        );
+    }
+
+    #[test]
+    fn startup_task() {
+        let entries = entries(PipetteStartup::StartupTask, &[], None, 'D').unwrap();
+        let command = image_path(&entries, PIPETTE_TASK_SERVICE_KEY);
+        assert!(command.starts_with("cmd.exe /c schtasks.exe /Create /F"));
+        assert!(command.contains("/SC ONSTART"));
+        assert!(!command.contains("ONLOGON"));
+        assert!(command.contains(r#"/TR "D:\pipette.exe" && "#));
+        assert!(command.ends_with(r#"&& schtasks.exe /Run /TN "Petri pipette""#));
+        assert_eq!(
+            find(&entries, PIPETTE_TASK_SERVICE_KEY, "Start"),
+            Some(Value::Dword(2))
+        );
+        assert!(!entries.iter().any(|e| e.key == PIPETTE_SERVICE_KEY));
+        assert_eq!(
+            find(&entries, &["SYSTEM", "Setup"], "UnattendFile"),
+            Some(Value::Sz("D:\\unattend.xml".into()))
+        );
+        assert_eq!(find(&entries, &[], "Sequence"), Some(Value::Dword(2)));
+    }
+
+    #[test]
+    fn log_file() {
+        let service = entries(PipetteStartup::Service, &[], Some("D:\\pipette.log"), 'D').unwrap();
+        assert_eq!(
+            image_path(&service, PIPETTE_SERVICE_KEY),
+            "D:\\pipette.exe --service --log-file D:\\pipette.log"
+        );
+
+        let task = entries(
+            PipetteStartup::StartupTask,
+            &[],
+            Some("D:\\pipette.log"),
+            'D',
+        )
+        .unwrap();
+        assert!(
+            image_path(&task, PIPETTE_TASK_SERVICE_KEY)
+                .contains(r#"/TR "D:\pipette.exe --log-file D:\pipette.log" && "#)
+        );
+
+        entries(
+            PipetteStartup::Service,
+            &[],
+            Some("D:\\pipette log.txt"),
+            'D',
+        )
+        .unwrap_err();
+        entries(PipetteStartup::Service, &[], Some(""), 'D').unwrap_err();
+    }
+
+    #[test]
+    fn agent_drive() {
+        let entries = entries(PipetteStartup::Service, &[], None, 'e').unwrap();
+        assert_eq!(
+            image_path(&entries, PIPETTE_SERVICE_KEY),
+            "E:\\pipette.exe --service"
+        );
+        assert_eq!(
+            find(&entries, &["SYSTEM", "Setup"], "UnattendFile"),
+            Some(Value::Sz("E:\\unattend.xml".into()))
+        );
+
+        let entries = super::entries(PipetteStartup::StartupTask, &[], None, 'E').unwrap();
+        assert!(
+            image_path(&entries, PIPETTE_TASK_SERVICE_KEY).contains(r#"/TR "E:\pipette.exe" && "#)
+        );
+
+        super::entries(PipetteStartup::Service, &[], None, '1').unwrap_err();
+    }
+
+    #[test]
+    fn mounted_device() {
+        for startup in [PipetteStartup::Service, PipetteStartup::StartupTask] {
+            let entries = entries(startup, &[], None, 'p').unwrap();
+            let mut data = b"DMIO:ID:".to_vec();
+            data.extend(AGENT_PARTITION_GUID);
+            assert_eq!(
+                find(&entries, &["SYSTEM", "MountedDevices"], "\\DosDevices\\P:"),
+                Some(Value::Binary(data))
+            );
+        }
+    }
+}
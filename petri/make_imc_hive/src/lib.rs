@@ -0,0 +1,10 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Builds IMC hives for injecting pipette into a Windows guest, either ahead
+//! of time with the `make_imc_hive` tool or when a test starts a VM.
+
+pub mod hive;
+pub mod regf;
+#[cfg(all(windows, test))]
+mod windows;
@@ -1,18 +1,59 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! Tool to make an IMC hive for injecting pipette into a Windows guest.
+//! Tool to make an IMC hive for injecting pipette into a Windows guest. It runs
+//! on any host OS.
+//!
+//! Usage: `make_imc_hive [--startup-task] [--depend-on SERVICE]...
+//! [--pipette-log GUEST_PATH] [--agent-drive LETTER] PATH`. By default
+//! pipette runs as a service; with `--startup-task` it runs from a scheduled
+//! task at system startup instead. Each `--depend-on` adds a service, such
+//! as `Tcpip`, that must start first. With `--pipette-log`, pipette copies its
+//! own logs to the given guest path, such as `D:\pipette.log` on the agent
+//! disk. `--agent-drive` sets the guest drive letter of the agent disk, which
+//! defaults to `D`.
 
-#[cfg(windows)]
-mod windows;
+use anyhow::Context;
+use make_imc_hive::hive;
+use make_imc_hive::hive::PipetteStartup;
+use make_imc_hive::regf;
 
-#[cfg(not(windows))]
-fn main() {
-    eprintln!("not supported on this OS");
-    std::process::exit(1);
-}
-
-#[cfg(windows)]
 fn main() -> anyhow::Result<()> {
-    windows::main()
+    let mut args = std::env::args_os().skip(1);
+    let mut startup = PipetteStartup::Service;
+    let mut depend_on = Vec::new();
+    let mut log_file = None;
+    let mut agent_drive = 'D';
+    let path = loop {
+        let arg = args.next().context("missing path")?;
+        if arg == "--startup-task" {
+            startup = PipetteStartup::StartupTask;
+        } else if arg == "--depend-on" {
+            let service = args.next().context("missing service name")?;
+            depend_on.push(service.into_string().ok().context("invalid service name")?);
+        } else if arg == "--pipette-log" {
+            let path = args.next().context("missing pipette log path")?;
+            log_file = Some(
+                path.into_string()
+                    .ok()
+                    .context("invalid pipette log path")?,
+            );
+        } else if arg == "--agent-drive" {
+            let drive = args.next().context("missing agent drive")?;
+            let drive = drive.to_str().context("invalid agent drive")?;
+            let mut chars = drive.chars();
+            agent_drive = chars
+                .next()
+                .filter(|_| matches!(chars.as_str(), "" | ":"))
+                .context("invalid agent drive")?;
+        } else {
+            break arg;
+        }
+    };
+    let depend_on = depend_on.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let entries = hive::entries(startup, &depend_on, log_file.as_deref(), agent_drive)?;
+    let hive = regf::write(&entries)?;
+    std::fs::write(&path, hive).context("failed to write hive")?;
+    Ok(())
 }
@@ -0,0 +1,434 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal writer for the Windows registry hive (regf) format, so that the
+//! IMC hive can be made without the Windows offline registry library.
+//!
+//! The output mirrors what the offline registry library produces for the same
+//! keys and values: a single hive bin, cells laid out in creation order, and
+//! the library's default security descriptor on every key. Timestamps are
+//! left zero so that the output is reproducible.
+
+use crate::hive::Entry;
+use crate::hive::Value;
+
+const BASE_BLOCK_SIZE: usize = 0x1000;
+const BIN_HEADER_SIZE: usize = 0x20;
+const NK_SIZE: usize = 76;
+const SK_SIZE: usize = 20;
+const VK_SIZE: usize = 20;
+
+/// The cell offset meaning "no cell".
+const NO_CELL: u32 = !0;
+/// The key name is stored as Latin-1 rather than UTF-16.
+const KEY_COMP_NAME: u16 = 0x20;
+/// The value name is stored as Latin-1 rather than UTF-16.
+const VALUE_COMP_NAME: u16 = 0x1;
+/// Set in a value's data size when the data is stored in its data offset.
+const DATA_INLINE: u32 = 0x8000_0000;
+/// Larger data needs big data cells, which are not supported.
+const MAX_DATA_SIZE: usize = 16344;
+
+const REG_SZ: u32 = 1;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+const REG_MULTI_SZ: u32 = 7;
+
+/// The security descriptor the offline registry library gives every key in a
+/// new hive: owned by Administrators, full access for SYSTEM and
+/// Administrators, and read access for Everyone and restricted code.
+const SECURITY_DESCRIPTOR: &[u8] = &[
+    0x01, 0x00, 0x04, 0x80, 0x70, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x14, 0x00, 0x00, 0x00, 0x02, 0x00, 0x5c, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x02, 0x14, 0x00,
+    0x3f, 0x00, 0x0f, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x12, 0x00, 0x00, 0x00,
+    0x00, 0x02, 0x18, 0x00, 0x3f, 0x00, 0x0f, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+    0x20, 0x00, 0x00, 0x00, 0x20, 0x02, 0x00, 0x00, 0x00, 0x02, 0x14, 0x00, 0x19, 0x00, 0x02, 0x00,
+    0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x14, 0x00,
+    0x19, 0x00, 0x02, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x0c, 0x00, 0x00, 0x00,
+    0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x20, 0x00, 0x00, 0x00, 0x20, 0x02, 0x00, 0x00,
+    0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x20, 0x00, 0x00, 0x00, 0x20, 0x02, 0x00, 0x00,
+];
+
+#[derive(Default)]
+struct Key<'a> {
+    name: &'a str,
+    subkeys: Vec<Key<'a>>,
+    values: Vec<(&'a str, &'a Value)>,
+}
+
+impl Key<'_> {
+    fn count(&self) -> u32 {
+        1 + self.subkeys.iter().map(Key::count).sum::<u32>()
+    }
+}
+
+/// Returns the contents of a hive file holding `entries`.
+pub fn write(entries: &[Entry]) -> anyhow::Result<Vec<u8>> {
+    let mut root = Key {
+        name: "ROOT",
+        ..Default::default()
+    };
+    for entry in entries {
+        let mut key = &mut root;
+        for &name in entry.key {
+            let index = match key
+                .subkeys
+                .iter()
+                .position(|k| k.name.eq_ignore_ascii_case(name))
+            {
+                Some(index) => index,
+                None => {
+                    key.subkeys.push(Key {
+                        name,
+                        ..Default::default()
+                    });
+                    key.subkeys.len() - 1
+                }
+            };
+            key = &mut key.subkeys[index];
+        }
+        match key
+            .values
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&entry.name))
+        {
+            Some((_, value)) => *value = &entry.value,
+            None => key.values.push((&entry.name, &entry.value)),
+        }
+    }
+
+    let mut bin = Bin {
+        data: vec![0; BIN_HEADER_SIZE],
+    };
+    let root_cell = bin.key(&root, NO_CELL, None, root.count())?;
+
+    // Fill the rest of the bin with a free cell.
+    let bin_size = bin.data.len().next_multiple_of(0x1000);
+    let free = bin_size - bin.data.len();
+    if free != 0 {
+        bin.data.extend((free as i32).to_le_bytes());
+        bin.data.resize(bin_size, 0);
+    }
+    bin.data[..4].copy_from_slice(b"hbin");
+    bin.data[8..12].copy_from_slice(&(bin_size as u32).to_le_bytes());
+
+    let mut hive = vec![0; BASE_BLOCK_SIZE];
+    let mut put = |offset: usize, data: &[u8]| hive[offset..][..data.len()].copy_from_slice(data);
+    put(0, b"regf");
+    put(4, &1u32.to_le_bytes()); // primary sequence number
+    put(8, &1u32.to_le_bytes()); // secondary sequence number
+    put(0x14, &1u32.to_le_bytes()); // major version
+    put(0x18, &5u32.to_le_bytes()); // minor version
+    put(0x20, &1u32.to_le_bytes()); // file format: direct memory load
+    put(0x24, &root_cell.to_le_bytes());
+    put(0x28, &(bin_size as u32).to_le_bytes());
+    put(0x2c, &1u32.to_le_bytes()); // clustering factor
+    let checksum = checksum(&hive);
+    hive[0x1fc..0x200].copy_from_slice(&checksum.to_le_bytes());
+
+    hive.extend(bin.data);
+    Ok(hive)
+}
+
+/// Computes the base block checksum, the XOR of its first 127 dwords.
+fn checksum(base_block: &[u8]) -> u32 {
+    let checksum = base_block[..0x1fc]
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        .fold(0, |a, b| a ^ b);
+    match checksum {
+        0 => 1,
+        u32::MAX => u32::MAX - 1,
+        checksum => checksum,
+    }
+}
+
+/// Computes the hash stored in `lh` subkey lists.
+fn name_hash(name: &str) -> u32 {
+    name.bytes().fold(0u32, |h, c| {
+        h.wrapping_mul(37)
+            .wrapping_add(c.to_ascii_uppercase().into())
+    })
+}
+
+/// The hive bin being built. Cell offsets are relative to its start.
+struct Bin {
+    data: Vec<u8>,
+}
+
+impl Bin {
+    /// Allocates a zeroed cell with room for `len` bytes.
+    fn alloc(&mut self, len: usize) -> u32 {
+        let offset = self.data.len();
+        let size = (len + 4).next_multiple_of(8);
+        self.data.extend((-(size as i32)).to_le_bytes());
+        self.data.resize(offset + size, 0);
+        offset as u32
+    }
+
+    /// Writes `data` to the start of cell `cell`.
+    fn set(&mut self, cell: u32, data: &[u8]) {
+        self.data[cell as usize + 4..][..data.len()].copy_from_slice(data);
+    }
+
+    fn push(&mut self, data: &[u8]) -> u32 {
+        let cell = self.alloc(data.len());
+        self.set(cell, data);
+        cell
+    }
+
+    /// Writes `key` and everything under it, sharing one security cell,
+    /// which is allocated with the root key.
+    fn key(
+        &mut self,
+        key: &Key<'_>,
+        parent: u32,
+        security: Option<u32>,
+        key_count: u32,
+    ) -> anyhow::Result<u32> {
+        check_name(key.name)?;
+        let nk = self.alloc(NK_SIZE + key.name.len());
+        let security = match security {
+            Some(security) => security,
+            None => {
+                let sk = self.alloc(SK_SIZE + SECURITY_DESCRIPTOR.len());
+                let mut cell = Vec::new();
+                cell.extend(b"sk");
+                cell.extend(0u16.to_le_bytes());
+                cell.extend(sk.to_le_bytes()); // next security cell
+                cell.extend(sk.to_le_bytes()); // previous security cell
+                cell.extend(key_count.to_le_bytes()); // reference count
+                cell.extend((SECURITY_DESCRIPTOR.len() as u32).to_le_bytes());
+                cell.extend(SECURITY_DESCRIPTOR);
+                self.set(sk, &cell);
+                sk
+            }
+        };
+
+        let mut max_value_name = 0;
+        let mut max_value_data = 0;
+        let value_list = if key.values.is_empty() {
+            NO_CELL
+        } else {
+            let list = self.alloc(4 * key.values.len());
+            let mut cells = Vec::new();
+            for &(name, value) in &key.values {
+                let (vk, data_len) = self.value(name, value)?;
+                cells.extend(vk.to_le_bytes());
+                max_value_name = max_value_name.max(name.len() as u32 * 2);
+                max_value_data = max_value_data.max(data_len);
+            }
+            self.set(list, &cells);
+            list
+        };
+
+        let mut subkeys = key.subkeys.iter().collect::<Vec<_>>();
+        subkeys.sort_by_key(|k| k.name.to_ascii_uppercase());
+        let subkey_list = if subkeys.is_empty() {
+            NO_CELL
+        } else {
+            let list = self.alloc(4 + 8 * subkeys.len());
+            let mut cell = Vec::new();
+            cell.extend(b"lh");
+            cell.extend((subkeys.len() as u16).to_le_bytes());
+            for subkey in &subkeys {
+                let child = self.key(subkey, nk, Some(security), key_count)?;
+                cell.extend(child.to_le_bytes());
+                cell.extend(name_hash(subkey.name).to_le_bytes());
+            }
+            self.set(list, &cell);
+            list
+        };
+        let max_subkey_name = subkeys
+            .iter()
+            .map(|k| k.name.len() as u32 * 2)
+            .max()
+            .unwrap_or(0);
+
+        let mut cell = Vec::with_capacity(NK_SIZE + key.name.len());
+        cell.extend(b"nk");
+        cell.extend(KEY_COMP_NAME.to_le_bytes());
+        cell.extend(0u64.to_le_bytes()); // last written timestamp
+        cell.extend(0u32.to_le_bytes()); // access bits
+        cell.extend(parent.to_le_bytes());
+        cell.extend((subkeys.len() as u32).to_le_bytes());
+        cell.extend(0u32.to_le_bytes()); // volatile subkey count
+        cell.extend(subkey_list.to_le_bytes());
+        cell.extend(NO_CELL.to_le_bytes()); // volatile subkey list
+        cell.extend((key.values.len() as u32).to_le_bytes());
+        cell.extend(value_list.to_le_bytes());
+        cell.extend(security.to_le_bytes());
+        cell.extend(NO_CELL.to_le_bytes()); // class name
+        cell.extend(max_subkey_name.to_le_bytes());
+        cell.extend(0u32.to_le_bytes()); // largest subkey class name
+        cell.extend(max_value_name.to_le_bytes());
+        cell.extend(max_value_data.to_le_bytes());
+        cell.extend(0u32.to_le_bytes()); // work var
+        cell.extend((key.name.len() as u16).to_le_bytes());
+        cell.extend(0u16.to_le_bytes()); // class name length
+        cell.extend(key.name.as_bytes());
+        self.set(nk, &cell);
+        Ok(nk)
+    }
+
+    /// Writes a value, returning its cell and data length.
+    fn value(&mut self, name: &str, value: &Value) -> anyhow::Result<(u32, u32)> {
+        check_name(name)?;
+        let utf16 = |s: &str| s.encode_utf16().chain([0]).collect::<Vec<_>>();
+        let (ty, data) = match value {
+            Value::Dword(v) => (REG_DWORD, v.to_le_bytes().to_vec()),
+            Value::Sz(v) => (REG_SZ, utf16_bytes(&utf16(v))),
+            Value::MultiSz(v) => (
+                REG_MULTI_SZ,
+                utf16_bytes(
+                    &v.iter()
+                        .flat_map(|s| utf16(s))
+                        .chain([0])
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            Value::Binary(v) => (REG_BINARY, v.clone()),
+        };
+        if data.len() > MAX_DATA_SIZE {
+            anyhow::bail!("value {name:?} is too large");
+        }
+
+        let vk = self.alloc(VK_SIZE + name.len());
+        let (size, offset) = if data.len() <= 4 {
+            let mut inline = [0; 4];
+            inline[..data.len()].copy_from_slice(&data);
+            (data.len() as u32 | DATA_INLINE, u32::from_le_bytes(inline))
+        } else {
+            (data.len() as u32, self.push(&data))
+        };
+
+        let mut cell = Vec::with_capacity(VK_SIZE + name.len());
+        cell.extend(b"vk");
+        cell.extend((name.len() as u16).to_le_bytes());
+        cell.extend(size.to_le_bytes());
+        cell.extend(offset.to_le_bytes());
+        cell.extend(ty.to_le_bytes());
+        cell.extend(VALUE_COMP_NAME.to_le_bytes());
+        cell.extend(0u16.to_le_bytes()); // spare
+        cell.extend(name.as_bytes());
+        self.set(vk, &cell);
+        Ok((vk, data.len() as u32))
+    }
+}
+
+fn utf16_bytes(s: &[u16]) -> Vec<u8> {
+    s.iter().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+/// Names are stored in their compressed form, which this writer only
+/// supports for ASCII.
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if !name.is_ascii() || name.len() > 255 {
+        anyhow::bail!("unsupported registry name {name:?}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zeroes the key timestamps in `hive`'s bin, walking its cells.
+    fn strip_timestamps(hive: &mut [u8]) {
+        let mut offset = BASE_BLOCK_SIZE + BIN_HEADER_SIZE;
+        while offset < hive.len() {
+            let size = i32::from_le_bytes(hive[offset..][..4].try_into().unwrap());
+            if &hive[offset + 4..][..2] == b"nk" {
+                hive[offset + 8..][..8].fill(0);
+            }
+            offset += size.unsigned_abs() as usize;
+        }
+    }
+
+    /// `test_data/offreg.hiv` was made on Windows by the offline registry
+    /// library from these entries, before later changes to them.
+    fn offreg_entries() -> Vec<Entry> {
+        const PIPETTE: &[&str] = &["SYSTEM", "CurrentControlSet", "Services", "pipette"];
+        let entry = |key, name: &'static str, value| Entry {
+            key,
+            name: name.into(),
+            value,
+        };
+        vec![
+            entry(PIPETTE, "Type", Value::Dword(0x10)),
+            entry(PIPETTE, "Start", Value::Dword(2)),
+            entry(PIPETTE, "ErrorControl", Value::Dword(1)),
+            entry(
+                PIPETTE,
+                "ImagePath",
+                Value::Sz("D:\\pipette.exe --service".into()),
+            ),
+            entry(
+                PIPETTE,
+                "DisplayName",
+                Value::Sz("Petri pipette agent".into()),
+            ),
+            entry(PIPETTE, "ObjectName", Value::Sz("LocalSystem".into())),
+            entry(
+                PIPETTE,
+                "DependOnService",
+                Value::MultiSz(vec!["RpcSs".into()]),
+            ),
+            entry(&[], "Sequence", Value::Dword(2)),
+        ]
+    }
+
+    #[test]
+    fn matches_offreg() {
+        let mut expected = include_bytes!("../test_data/offreg.hiv").to_vec();
+        let mut actual = write(&offreg_entries()).unwrap();
+        assert_eq!(actual.len(), expected.len());
+
+        // The offline registry library records its own marker and
+        // timestamps, which are not part of the format.
+        assert_eq!(actual[..0x30], expected[..0x30]);
+        assert_eq!(
+            checksum(&expected[..BASE_BLOCK_SIZE]),
+            u32::from_le_bytes(expected[0x1fc..0x200].try_into().unwrap())
+        );
+        strip_timestamps(&mut expected);
+        strip_timestamps(&mut actual);
+        assert!(actual[BASE_BLOCK_SIZE..] == expected[BASE_BLOCK_SIZE..]);
+    }
+
+    #[test]
+    fn imc_entries() {
+        for startup in [
+            crate::hive::PipetteStartup::Service,
+            crate::hive::PipetteStartup::StartupTask,
+        ] {
+            let entries =
+                crate::hive::entries(startup, &["Tcpip"], Some("D:\\pipette.log"), 'D').unwrap();
+            let hive = write(&entries).unwrap();
+            assert_eq!(hive.len() % 0x1000, 0);
+            assert_eq!(
+                checksum(&hive[..BASE_BLOCK_SIZE]),
+                u32::from_le_bytes(hive[0x1fc..0x200].try_into().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn hash() {
+        // From the hive made by the offline registry library.
+        assert_eq!(name_hash("SYSTEM"), 0x6141d0f9);
+        assert_eq!(name_hash("CurrentControlSet"), 0x23b1b3f4);
+        assert_eq!(name_hash("pipette"), 0x0136d30b);
+    }
+
+    #[test]
+    fn unsupported() {
+        let entry = |name: &'static str, value| Entry {
+            key: &[],
+            name: name.into(),
+            value,
+        };
+        write(&[entry("Big", Value::Sz("x".repeat(MAX_DATA_SIZE)))]).unwrap_err();
+        write(&[entry("Caf\u{e9}", Value::Dword(0))]).unwrap_err();
+    }
+}
@@ -1,37 +1,132 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+//! Checks the hive writer against the Windows offline registry library.
+
 mod offreg;
 
 use self::offreg::Hive;
-use anyhow::Context;
+use crate::hive::Entry;
+use crate::hive::Value;
 
-pub(crate) fn main() -> anyhow::Result<()> {
-    let path = std::env::args_os().nth(1).context("missing path")?;
+/// Builds an in-memory hive holding `entries`.
+fn build(entries: Vec<Entry>) -> anyhow::Result<Hive> {
     let hive = Hive::create()?;
-    {
+    for entry in entries {
         let mut key;
         let mut parent = hive.as_ref();
-        for subkey in ["SYSTEM", "CurrentControlSet", "Services", "pipette"] {
+        for subkey in entry.key {
             let new_key = parent.create_key(subkey)?;
             key = new_key;
             parent = key.as_ref();
         }
 
-        parent.set_dword("Type", 0x10)?; // win32 service
-        parent.set_dword("Start", 2)?; // auto start
-        parent.set_dword("ErrorControl", 1)?; // normal
-        parent.set_sz("ImagePath", "D:\\pipette.exe --service")?;
-        parent.set_sz("DisplayName", "Petri pipette agent")?;
-        parent.set_sz("ObjectName", "LocalSystem")?;
-        parent.set_multi_sz("DependOnService", ["RpcSs"])?;
+        match entry.value {
+            Value::Dword(v) => parent.set_dword(&entry.name, v)?,
+            Value::Sz(v) => parent.set_sz(&entry.name, &v)?,
+            Value::MultiSz(v) => parent.set_multi_sz(&entry.name, v.iter().map(String::as_str))?,
+            Value::Binary(v) => parent.set_binary(&entry.name, &v)?,
+        }
+    }
+    Ok(hive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Entry;
+    use super::Hive;
+    use super::build;
+    use super::offreg::Key;
+    use super::offreg::OwnedKey;
+    use crate::hive::PipetteStartup;
+    use crate::hive::Value;
+    use crate::hive::entries;
+
+    /// Checks that every key in the hive built by the offline registry
+    /// library, and in the one written by [`crate::regf`] and loaded by the
+    /// library, holds exactly the expected values.
+    fn check(startup: PipetteStartup, depend_on: &[&str], log_file: Option<&str>) {
+        let expected = entries(startup, depend_on, log_file, 'D').unwrap();
+        check_hive(&build(expected.clone()).unwrap(), &expected);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("imc.hiv");
+        std::fs::write(&path, crate::regf::write(&expected).unwrap()).unwrap();
+        check_hive(&Hive::open(&path).unwrap(), &expected);
+    }
+
+    fn check_hive(hive: &Hive, expected: &[Entry]) {
+        let mut keys = expected.iter().map(|e| e.key).collect::<Vec<_>>();
+        keys.dedup();
+        for path in keys {
+            let mut values = if path.is_empty() {
+                hive.enum_values().unwrap()
+            } else {
+                hive.open_key(&path.join("\\"))
+                    .unwrap()
+                    .enum_values()
+                    .unwrap()
+            };
+            values.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut want = expected
+                .iter()
+                .filter(|e| e.key == path)
+                .map(|e| (e.name.to_string(), e.value.clone()))
+                .collect::<Vec<_>>();
+            want.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(values, want, "{path:?}");
+        }
+    }
+
+    fn service_key(key: &Key, name: &str) -> OwnedKey {
+        key.open_key(&format!("SYSTEM\\CurrentControlSet\\Services\\{name}"))
+            .unwrap()
     }
 
-    // Windows defaults to 1, so we need to set it to 2 to cause Windows to
-    // apply the IMC changes on first boot.
-    hive.set_dword("Sequence", 2)?;
+    #[test]
+    fn service() {
+        check(PipetteStartup::Service, &[], None);
+        check(
+            PipetteStartup::Service,
+            &["Tcpip", "Dhcp"],
+            Some("D:\\pipette.log"),
+        );
 
-    let _ = std::fs::remove_file(&path);
-    hive.save(path.as_ref())?;
-    Ok(())
+        let hive = build(entries(PipetteStartup::Service, &["Tcpip"], None, 'D').unwrap()).unwrap();
+        let key = service_key(&hive, "pipette");
+        assert_eq!(key.get_dword("Start").unwrap(), 2);
+        assert_eq!(
+            key.get_sz("ImagePath").unwrap(),
+            "D:\\pipette.exe --service"
+        );
+        key.get_sz("Start").unwrap_err();
+        key.get_dword("Missing").unwrap_err();
+        assert_eq!(hive.get_dword("Sequence").unwrap(), 2);
+        assert!(
+            hive.open_key("SYSTEM\\CurrentControlSet\\Services\\pipette-task")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn startup_task() {
+        check(PipetteStartup::StartupTask, &[], None);
+        check(
+            PipetteStartup::StartupTask,
+            &["Tcpip"],
+            Some("D:\\pipette.log"),
+        );
+
+        let hive = build(entries(PipetteStartup::StartupTask, &[], None, 'D').unwrap()).unwrap();
+        let key = service_key(&hive, "pipette-task");
+        assert!(
+            key.get_sz("ImagePath")
+                .unwrap()
+                .starts_with("cmd.exe /c schtasks.exe /Create")
+        );
+        assert!(key.enum_values().unwrap().contains(&(
+            "DependOnService".to_owned(),
+            Value::MultiSz(vec!["Schedule".into()])
+        )));
+    }
 }
@@ -6,6 +6,7 @@
 // UNSAFETY: needed for the FFI bindings.
 #![expect(unsafe_code)]
 
+use crate::hive::Value;
 use std::ops::Deref;
 use std::os::windows::ffi::OsStrExt;
 use std::path::Path;
@@ -15,9 +16,14 @@
 use windows_sys::Wdk::System::OfflineRegistry::ORCloseKey;
 use windows_sys::Wdk::System::OfflineRegistry::ORCreateHive;
 use windows_sys::Wdk::System::OfflineRegistry::ORCreateKey;
+use windows_sys::Wdk::System::OfflineRegistry::OREnumValue;
+use windows_sys::Wdk::System::OfflineRegistry::ORGetValue;
 use windows_sys::Wdk::System::OfflineRegistry::ORHKEY;
-use windows_sys::Wdk::System::OfflineRegistry::ORSaveHive;
+use windows_sys::Wdk::System::OfflineRegistry::OROpenHive;
+use windows_sys::Wdk::System::OfflineRegistry::OROpenKey;
 use windows_sys::Wdk::System::OfflineRegistry::ORSetValue;
+use windows_sys::Win32::Foundation::ERROR_NO_MORE_ITEMS;
+use windows_sys::Win32::System::Registry::REG_BINARY;
 use windows_sys::Win32::System::Registry::REG_DWORD;
 use windows_sys::Win32::System::Registry::REG_MULTI_SZ;
 use windows_sys::Win32::System::Registry::REG_SZ;
@@ -34,19 +40,18 @@ pub fn create() -> std::io::Result<Self> {
         Ok(Self(Key(key)))
     }
 
-    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
         let path16 = path
             .as_os_str()
             .encode_wide()
             .chain([0])
             .collect::<Vec<_>>();
-
-        // SAFETY: calling as documented with owned key and null-terminated
-        // path.
+        let mut key = null_mut();
+        // SAFETY: calling as documented with a null-terminated path.
         unsafe {
-            chk(ORSaveHive((self.0).0, path16.as_ptr(), 6, 1))?;
+            chk(OROpenHive(path16.as_ptr(), &mut key))?;
         }
-        Ok(())
+        Ok(Self(Key(key)))
     }
 }
 
@@ -120,6 +125,97 @@ pub fn create_key(&self, name: &str) -> anyhow::Result<OwnedKey> {
         Ok(OwnedKey(Key(new_key)))
     }
 
+    pub fn open_key(&self, name: &str) -> std::io::Result<OwnedKey> {
+        let mut key = null_mut();
+        let name16 = name.encode_utf16().chain([0]).collect::<Vec<_>>();
+        // SAFETY: calling as documented with owned key and null-terminated
+        // name.
+        unsafe {
+            chk(OROpenKey(self.0, name16.as_ptr(), &mut key))?;
+        }
+        Ok(OwnedKey(Key(key)))
+    }
+
+    /// Returns the type and raw data of value `name`.
+    fn get_value(&self, name: &str) -> std::io::Result<(u32, Vec<u8>)> {
+        let name16 = name.encode_utf16().chain([0]).collect::<Vec<_>>();
+        let mut ty = 0;
+        let mut len = 0;
+        // SAFETY: calling as documented with owned key and null-terminated
+        // name, querying just the type and size.
+        unsafe {
+            chk(ORGetValue(
+                self.0,
+                null(),
+                name16.as_ptr(),
+                &mut ty,
+                null_mut(),
+                &mut len,
+            ))?;
+        }
+        let mut data = vec![0u8; len as usize];
+        // SAFETY: calling as documented with owned key, null-terminated name,
+        // and a buffer of `len` bytes.
+        unsafe {
+            chk(ORGetValue(
+                self.0,
+                null(),
+                name16.as_ptr(),
+                &mut ty,
+                data.as_mut_ptr().cast(),
+                &mut len,
+            ))?;
+        }
+        data.truncate(len as usize);
+        Ok((ty, data))
+    }
+
+    pub fn get_dword(&self, name: &str) -> std::io::Result<u32> {
+        match decode(self.get_value(name)?)? {
+            Value::Dword(v) => Ok(v),
+            _ => Err(invalid_type(name)),
+        }
+    }
+
+    pub fn get_sz(&self, name: &str) -> std::io::Result<String> {
+        match decode(self.get_value(name)?)? {
+            Value::Sz(v) => Ok(v),
+            _ => Err(invalid_type(name)),
+        }
+    }
+
+    /// Returns all the values of this key, in the order the hive stores
+    /// them.
+    pub fn enum_values(&self) -> std::io::Result<Vec<(String, Value)>> {
+        let mut values = Vec::new();
+        for index in 0.. {
+            // Value names are limited to 16383 characters.
+            let mut name16 = vec![0u16; 16384];
+            let mut name_len = name16.len() as u32;
+            // SAFETY: calling as documented with owned key and a name buffer
+            // of `name_len` characters, skipping the data.
+            let err = unsafe {
+                OREnumValue(
+                    self.0,
+                    index,
+                    name16.as_mut_ptr(),
+                    &mut name_len,
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            if err == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            chk(err)?;
+            let name = String::from_utf16_lossy(&name16[..name_len as usize]);
+            let value = decode(self.get_value(&name)?)?;
+            values.push((name, value));
+        }
+        Ok(values)
+    }
+
     pub fn set_dword(&self, name: &str, dword: u32) -> std::io::Result<()> {
         let name16 = name.encode_utf16().chain([0]).collect::<Vec<_>>();
         // SAFETY: calling as documented with owned key and null-terminated
@@ -136,6 +232,22 @@ pub fn set_dword(&self, name: &str, dword: u32) -> std::io::Result<()> {
         Ok(())
     }
 
+    pub fn set_binary(&self, name: &str, value: &[u8]) -> std::io::Result<()> {
+        let name16 = name.encode_utf16().chain([0]).collect::<Vec<_>>();
+        // SAFETY: calling as documented with owned key and null-terminated
+        // name.
+        unsafe {
+            chk(ORSetValue(
+                self.0,
+                name16.as_ptr(),
+                REG_BINARY,
+                value.as_ptr(),
+                value.len() as u32,
+            ))?;
+        }
+        Ok(())
+    }
+
     pub fn set_sz(&self, name: &str, value: &str) -> std::io::Result<()> {
         let name16 = name.encode_utf16().chain([0]).collect::<Vec<_>>();
         let value16 = value.encode_utf16().chain([0]).collect::<Vec<_>>();
@@ -179,6 +291,53 @@ pub fn set_multi_sz<'a>(
     }
 }
 
+/// Decodes raw value data of the types written by this tool.
+fn decode((ty, data): (u32, Vec<u8>)) -> std::io::Result<Value> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid value data");
+    let utf16 = |data: &[u8]| {
+        if data.len() % 2 != 0 {
+            return Err(invalid());
+        }
+        Ok(data
+            .chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>())
+    };
+    let value = match ty {
+        REG_DWORD => Value::Dword(u32::from_ne_bytes(
+            data.as_slice().try_into().map_err(|_| invalid())?,
+        )),
+        REG_BINARY => Value::Binary(data),
+        REG_SZ => {
+            let s = utf16(&data)?;
+            let s = s.strip_suffix(&[0]).unwrap_or(&s);
+            Value::Sz(String::from_utf16(s).map_err(|_| invalid())?)
+        }
+        REG_MULTI_SZ => {
+            let s = utf16(&data)?;
+            let s = s.strip_suffix(&[0, 0]).unwrap_or(&s);
+            let s = s.strip_suffix(&[0]).unwrap_or(s);
+            Value::MultiSz(if s.is_empty() {
+                Vec::new()
+            } else {
+                s.split(|&c| c == 0)
+                    .map(String::from_utf16)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?
+            })
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+fn invalid_type(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("unexpected type for value {name:?}"),
+    )
+}
+
 fn chk(err: u32) -> std::io::Result<()> {
     if err != 0 {
         return Err(std::io::Error::from_raw_os_error(err as i32));
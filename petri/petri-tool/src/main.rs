@@ -28,6 +28,22 @@ enum Command {
         /// Path to the output disk image.
         output: std::path::PathBuf,
     },
+    /// Removes Hyper-V VMs left behind by previous test runs.
+    #[cfg(windows)]
+    CleanHyperv {
+        /// Only remove the test VMs whose names start with this prefix.
+        #[clap(long, default_value = "")]
+        vm_prefix: String,
+
+        /// Also remove the virtual switches and NAT rules whose names start
+        /// with this prefix.
+        #[clap(long)]
+        network_prefix: Option<String>,
+
+        /// Delete the configuration files of VMs that can't be removed.
+        #[clap(long)]
+        delete_config_files: bool,
+    },
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
@@ -58,6 +74,20 @@ fn main() -> anyhow::Result<()> {
 
             Ok(())
         }
+        #[cfg(windows)]
+        Command::CleanHyperv {
+            vm_prefix,
+            network_prefix,
+            delete_config_files,
+        } => pal_async::DefaultPool::run_with(async |driver| {
+            petri::hyperv::vm::remove_leftover_test_resources(
+                &driver,
+                &vm_prefix,
+                network_prefix.as_deref(),
+                delete_config_files,
+            )
+            .await
+        }),
     }
 }
 
@@ -14,6 +14,7 @@
 use pal_async::task::Spawn;
 use pal_async::timer::PolledTimer;
 use pipette_protocol::DiagnosticFile;
+use pipette_protocol::GuestOs;
 use pipette_protocol::PipetteBootstrap;
 use pipette_protocol::PipetteRequest;
 use socket2::Socket;
@@ -61,6 +62,11 @@ pub async fn new(driver: DefaultDriver) -> anyhow::Result<Self> {
             diag_file_recv,
             watch: watch_recv,
             log,
+            os: if cfg!(windows) {
+                GuestOs::Windows
+            } else {
+                GuestOs::Unix
+            },
         });
 
         Ok(Self {
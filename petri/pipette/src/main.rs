@@ -20,13 +20,26 @@ fn main() -> anyhow::Result<()> {
 
 #[cfg(any(target_os = "linux", target_os = "windows"))]
 fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1).peekable();
     #[cfg(windows)]
-    if std::env::args().nth(1).as_deref() == Some("--service") {
+    let service = args.next_if(|arg| arg == "--service").is_some();
+    match (args.next(), args.next()) {
+        (None, _) => {}
+        (Some(arg), Some(path)) if arg == "--log-file" => trace::set_log_file(path.as_ref())?,
+        _ => anyhow::bail!("usage: pipette [--service] [--log-file PATH]"),
+    }
+
+    #[cfg(windows)]
+    if service {
         return winsvc::start_service();
     }
 
-    pal_async::DefaultPool::run_with(async |driver| {
+    let r = pal_async::DefaultPool::run_with(async |driver| {
         let agent = agent::Agent::new(driver).await?;
         agent.run().await
-    })
+    });
+    if let Err(e) = &r {
+        trace::log_to_file(format_args!("pipette failed: {e:#}"));
+    }
+    r
 }
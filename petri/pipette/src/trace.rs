@@ -5,12 +5,41 @@
 
 #![cfg(any(target_os = "linux", target_os = "windows"))]
 
+use anyhow::Context;
+use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::OnceLock;
 use tracing_subscriber::filter::Targets;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+/// A guest file that pipette's own logs are copied to, for diagnosing
+/// failures before the host connects.
+static LOG_FILE: OnceLock<std::fs::File> = OnceLock::new();
+
+/// Copy pipette's logs, including startup failures, to the file at `path`.
+pub fn set_log_file(path: &Path) -> anyhow::Result<()> {
+    let file = fs_err::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .into_parts()
+        .0;
+    LOG_FILE.set(file).ok().context("log file already set")?;
+    log_to_file(format_args!("pipette {} starting", std::process::id()));
+    Ok(())
+}
+
+/// Write a line to the log file, if there is one. Used for messages logged
+/// before tracing is initialized.
+pub fn log_to_file(message: std::fmt::Arguments<'_>) {
+    if let Some(mut file) = LOG_FILE.get() {
+        let _ = writeln!(file, "{message}");
+    }
+}
+
 /// Initialize tracing, returning a mesh pipe to read logs from.
 pub fn init_tracing() -> mesh::pipe::ReadPipe {
     let (log_read, log_write) = mesh::pipe::pipe();
@@ -38,6 +67,9 @@ pub fn init_tracing() -> mesh::pipe::ReadPipe {
 
 impl std::io::Write for &TracingWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(mut file) = LOG_FILE.get() {
+            let _ = file.write_all(buf);
+        }
         // Note that this will fail if the pipe fills up. This is probably fine
         // for this use case.
         self.0.write_nonblocking(buf)
@@ -21,7 +21,9 @@
 pub fn start_service() -> anyhow::Result<()> {
     // TODO: retarget stderr somewhere that the host can see (serial port?)
     define_windows_service!(ffi_service_main, service_main);
-    service_dispatcher::start(SERVICE_NAME, ffi_service_main).context("failed to start service")?;
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("failed to start service")
+        .inspect_err(|e| crate::trace::log_to_file(format_args!("{e:#}")))?;
     Ok(())
 }
 
@@ -29,6 +31,7 @@ fn service_main(_args: Vec<OsString>) {
     DefaultPool::run_with(async |driver| {
         if let Err(e) = service_main_inner(driver).await {
             eprintln!("service_main failed: {:#}", e);
+            crate::trace::log_to_file(format_args!("service_main failed: {e:#}"));
         }
     })
 }
@@ -9,6 +9,8 @@
 mod send;
 pub mod shell;
 
+pub use pipette_protocol::GuestOs;
+
 pub use pipette_protocol::PIPETTE_VSOCK_PORT;
 
 use crate::send::PipetteSender;
@@ -21,12 +23,13 @@
 use futures::StreamExt;
 use futures::io::BufReader;
 use futures_concurrency::future::TryJoin;
+use mesh::CancelContext;
 use mesh::payload::Timestamp;
-use mesh::rpc::RpcError;
 use mesh_remote::PointToPointMesh;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
 use pipette_protocol::DiagnosticFile;
+use pipette_protocol::EnvPair;
 use pipette_protocol::PipetteBootstrap;
 use pipette_protocol::PipetteRequest;
 use pipette_protocol::ReadFileRequest;
@@ -35,14 +38,34 @@
 use shell::WindowsShell;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 /// A client to a running `pipette` instance inside a VM.
 pub struct PipetteClient {
     send: PipetteSender,
     watch: mesh::OneshotReceiver<()>,
+    output_dir: PathBuf,
+    os: GuestOs,
+    env: Mutex<Vec<EnvPair>>,
     _mesh: PointToPointMesh,
     _log_task: Task<()>,
     _diag_task: Task<()>,
+    keepalive: Option<(Arc<KeepaliveState>, Task<()>)>,
+}
+
+/// The state of the background keepalive started by
+/// [`PipetteClient::with_keepalive`].
+#[derive(Default)]
+struct KeepaliveState {
+    /// The number of successful pings.
+    pings: AtomicU64,
+    /// Set once a ping fails, after which the keepalive stops.
+    lost: AtomicBool,
 }
 
 impl PipetteClient {
@@ -64,6 +87,7 @@ pub async fn new(
             diag_file_recv,
             watch,
             log,
+            os,
         } = bootstrap;
 
         let log_task = spawner.spawn("pipette-log", replay_logs(log));
@@ -75,15 +99,69 @@ pub async fn new(
         Ok(Self {
             send: PipetteSender::new(requests),
             watch,
+            output_dir: output_dir.to_owned(),
+            os,
+            env: Mutex::new(Vec::new()),
             _mesh: mesh,
             _log_task: log_task,
             _diag_task: diag_task,
+            keepalive: None,
         })
     }
 
-    /// Pings the agent to check if it's alive.
-    pub async fn ping(&self) -> Result<(), RpcError> {
-        self.send.call(PipetteRequest::Ping, ()).await
+    /// Pings the agent to check if it's alive, returning the round-trip time.
+    ///
+    /// Fails if the agent does not respond in time, which likely means that
+    /// it or the guest is hung.
+    pub async fn ping(&self) -> anyhow::Result<Duration> {
+        self.check_connection()?;
+        ping(&self.send).await
+    }
+
+    /// Fails if the keepalive has lost the connection to the agent, so that
+    /// requests fail immediately rather than waiting on a dead connection.
+    fn check_connection(&self) -> anyhow::Result<()> {
+        if self
+            .keepalive
+            .as_ref()
+            .is_some_and(|(state, _)| state.lost.load(Ordering::Relaxed))
+        {
+            return Err(anyhow::anyhow!(
+                "keepalive ping failed; the client does not reconnect"
+            ))
+            .context("lost connection to the agent");
+        }
+        Ok(())
+    }
+
+    /// Pings the agent every `interval` in the background for as long as
+    /// this client is alive, so that idle connections are not dropped by the
+    /// transport.
+    ///
+    /// The client does not reconnect. Once a keepalive ping fails, the
+    /// keepalive stops and all requests to the agent fail immediately; wait
+    /// for the agent again to get a new client.
+    pub fn with_keepalive(mut self, spawner: impl Spawn, interval: Duration) -> Self {
+        let state = Arc::new(KeepaliveState::default());
+        let task = spawner.spawn(
+            "pipette-keepalive",
+            keepalive(self.send.clone(), state.clone(), interval),
+        );
+        self.keepalive = Some((state, task));
+        self
+    }
+
+    /// Returns how many keepalive pings have succeeded, or `None` if the
+    /// keepalive was not started with [`with_keepalive`](Self::with_keepalive).
+    pub fn keepalive_pings(&self) -> Option<u64> {
+        self.keepalive
+            .as_ref()
+            .map(|(state, _)| state.pings.load(Ordering::Relaxed))
+    }
+
+    /// Returns the operating system family of the guest.
+    pub fn guest_os(&self) -> GuestOs {
+        self.os
     }
 
     /// Return a shell object to interact with a Windows guest.
@@ -105,36 +183,76 @@ pub fn command(&self, program: impl AsRef<str>) -> process::Command<'_> {
         process::Command::new(self, program)
     }
 
+    /// Sets an environment variable for all commands subsequently launched
+    /// with [`Self::command`], including via [`Self::run_script`] and the
+    /// shells.
+    ///
+    /// Individual commands can still override or remove it.
+    pub fn set_env(&self, name: impl AsRef<str>, value: impl AsRef<str>) {
+        let name = name.as_ref();
+        let mut env = self.env.lock().unwrap();
+        env.retain(|pair| pair.name != name);
+        env.push(EnvPair {
+            name: name.to_owned(),
+            value: Some(value.as_ref().to_owned()),
+        });
+    }
+
     /// Sends a request to the guest to power off.
+    ///
+    /// Before powering off, any well-known guest logs (see
+    /// [`GUEST_LOG_FILES`]) are copied into the output directory.
     pub async fn power_off(&self) -> anyhow::Result<()> {
+        self.collect_guest_logs().await;
         self.shutdown(pipette_protocol::ShutdownType::PowerOff)
             .await
     }
 
     /// Sends a request to the guest to reboot.
+    ///
+    /// This returns once the guest has accepted the request (or the
+    /// connection has been lost because the guest is already going down). The
+    /// client is not usable afterwards; call `wait_for_agent` on the VM to get
+    /// a fresh connection once the guest has restarted.
     pub async fn reboot(&self) -> anyhow::Result<()> {
         self.shutdown(pipette_protocol::ShutdownType::Reboot).await
     }
 
     async fn shutdown(&self, shutdown_type: pipette_protocol::ShutdownType) -> anyhow::Result<()> {
+        // The agent responds before it starts shutting down, but the response
+        // can be lost if the guest goes down first and the transport does not
+        // notice. Don't wait forever for it.
+        const SHUTDOWN_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
         let r = self.send.call(
             PipetteRequest::Shutdown,
             pipette_protocol::ShutdownRequest { shutdown_type },
         );
-        match r.await {
-            Ok(r) => r
+        match CancelContext::new()
+            .with_timeout(SHUTDOWN_RESPONSE_TIMEOUT)
+            .until_cancelled(r)
+            .await
+        {
+            Ok(Ok(r)) => r
                 .map_err(anyhow::Error::from)
                 .context("failed to shut down")?,
-            Err(_) => {
+            Ok(Err(_)) => {
                 // Presumably this is an expected error due to the agent exiting
                 // or the guest powering off.
             }
+            Err(_) => {
+                tracing::warn!(
+                    ?shutdown_type,
+                    "timed out waiting for shutdown response, assuming the guest is going down"
+                );
+            }
         }
         Ok(())
     }
 
     /// Reads the full contents of a file.
     pub async fn read_file(&self, path: impl AsRef<str>) -> anyhow::Result<Vec<u8>> {
+        self.check_connection()?;
         let (recv_pipe, send_pipe) = mesh::pipe::pipe();
         let req = ReadFileRequest {
             path: path.as_ref().to_string(),
@@ -161,6 +279,75 @@ pub async fn read_file(&self, path: impl AsRef<str>) -> anyhow::Result<Vec<u8>>
         Ok(contents)
     }
 
+    /// Streams a file from the guest into `local` on the host, returning the
+    /// number of bytes copied.
+    ///
+    /// Unlike [`Self::read_file`], the contents are not buffered in memory, so
+    /// this is suitable for large files such as logs. If the transfer fails
+    /// (e.g., because the guest file does not exist), `local` is removed.
+    #[doc(alias = "read_file_to")]
+    pub async fn get_file(&self, remote: impl AsRef<str>, local: &Path) -> anyhow::Result<u64> {
+        self.check_connection()?;
+        let remote = remote.as_ref();
+        let (recv_pipe, send_pipe) = mesh::pipe::pipe();
+        let req = ReadFileRequest {
+            path: remote.to_string(),
+            sender: send_pipe,
+        };
+
+        let file = fs_err::File::create(local)?;
+
+        let request_future = self.send.call_failable(PipetteRequest::ReadFile, req);
+        let transfer_future =
+            async { futures::io::copy(recv_pipe, &mut futures::io::AllowStdIo::new(file)).await };
+
+        tracing::debug!(remote, local = %local.display(), "beginning file get transfer");
+        let result = async {
+            let (bytes_read, io_result) = (request_future, transfer_future.map(Ok))
+                .try_join()
+                .await
+                .with_context(|| format!("failed to get file {remote}"))?;
+            let bytes_copied = io_result.context("io failure")?;
+            if bytes_read != bytes_copied {
+                anyhow::bail!("file truncated");
+            }
+            Ok(bytes_copied)
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = fs_err::remove_file(local);
+        } else {
+            tracing::debug!("file get complete");
+        }
+        result
+    }
+
+    /// Copies the well-known guest log files in [`GUEST_LOG_FILES`] into the
+    /// output directory and attaches them to the test results.
+    ///
+    /// Files that do not exist in the guest (e.g., Linux logs on a Windows
+    /// guest) are skipped.
+    pub async fn collect_guest_logs(&self) {
+        for remote in GUEST_LOG_FILES {
+            let name = remote
+                .rsplit(['/', '\\'])
+                .next()
+                .expect("rsplit always yields an item");
+            let local = self.output_dir.join(format!("guest_{name}"));
+            match self.get_file(remote, &local).await {
+                Ok(_) => print_attachment(&local),
+                Err(err) => {
+                    tracing::debug!(
+                        remote,
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "did not collect guest log"
+                    );
+                }
+            }
+        }
+    }
+
     /// Writes a file to the guest.
     /// Note: This may transfer the file in chunks. It is likely not suitable
     /// for writing to files that require all content to be written at once,
@@ -170,6 +357,7 @@ pub async fn write_file(
         path: impl AsRef<str>,
         contents: impl AsyncRead,
     ) -> anyhow::Result<()> {
+        self.check_connection()?;
         let (recv_pipe, mut send_pipe) = mesh::pipe::pipe();
         let req = WriteFileRequest {
             path: path.as_ref().to_string(),
@@ -197,6 +385,168 @@ pub async fn write_file(
         Ok(())
     }
 
+    /// Runs a multi-line script in the guest, returning its exit status and
+    /// output.
+    ///
+    /// The script is written to a temporary file in the guest and run with
+    /// `sh` on Unix guests or PowerShell on Windows guests. If the script does
+    /// not complete within `timeout`, it is killed (along with any processes
+    /// it started) and an error is returned.
+    pub async fn run_script(
+        &self,
+        script: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<process::Output> {
+        static NEXT_SCRIPT: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT_SCRIPT.fetch_add(1, Ordering::Relaxed);
+
+        let (path, mut command) = match self.os {
+            GuestOs::Unix => {
+                let path = format!("/tmp/pipette-script-{n}.sh");
+                // Run the script in a new session, so that it and everything
+                // it starts can be killed as a process group.
+                let mut command = self.command("setsid");
+                command.args(["sh", path.as_str()]);
+                (path, command)
+            }
+            GuestOs::Windows => {
+                let path = format!("C:\\Windows\\Temp\\pipette-script-{n}.ps1");
+                let mut command = self.command("powershell.exe");
+                command.args([
+                    "-NoProfile",
+                    "-NonInteractive",
+                    "-ExecutionPolicy",
+                    "Bypass",
+                    "-File",
+                    path.as_str(),
+                ]);
+                (path, command)
+            }
+        };
+
+        // Remove the script on every exit path, including a timeout.
+        let output: anyhow::Result<process::Output> = async {
+            self.write_file(&path, script.as_bytes())
+                .await
+                .context("failed to write script")?;
+
+            let child = command
+                .stdin(process::Stdio::null())
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped())
+                .spawn()
+                .await
+                .context("failed to start script")?;
+            let pid = child.id();
+
+            let result = CancelContext::new()
+                .with_timeout(timeout)
+                .until_cancelled(child.wait_with_output())
+                .await;
+
+            match result {
+                Ok(output) => output,
+                Err(_) => {
+                    let pid = pid.to_string();
+                    let kill = match self.os {
+                        GuestOs::Unix => {
+                            let group = format!("-{pid}");
+                            self.command("kill")
+                                .args(["-KILL", "--", group.as_str()])
+                                .output()
+                                .await
+                        }
+                        GuestOs::Windows => {
+                            self.command("taskkill.exe")
+                                .args(["/F", "/T", "/PID", pid.as_str()])
+                                .output()
+                                .await
+                        }
+                    };
+                    if let Err(err) = kill {
+                        tracing::warn!(%pid, error = ?err, "failed to kill timed out script");
+                    }
+                    anyhow::bail!("script timed out after {timeout:?}");
+                }
+            }
+        }
+        .await;
+
+        let remove = match self.os {
+            GuestOs::Unix => {
+                self.command("rm")
+                    .args(["-f", path.as_str()])
+                    .output()
+                    .await
+            }
+            GuestOs::Windows => {
+                self.command("cmd.exe")
+                    .args(["/c", "del", "/f", path.as_str()])
+                    .output()
+                    .await
+            }
+        };
+        if let Err(err) = remove {
+            tracing::warn!(%path, error = ?err, "failed to remove script");
+        }
+
+        output
+    }
+
+    /// Waits for a process named `name` to be running in the guest, polling the
+    /// guest's process list until it appears or `timeout` elapses.
+    ///
+    /// On Unix guests, `name` must exactly match the process name, as with
+    /// `pgrep -x`. The kernel truncates process names to 15 bytes, so longer
+    /// names are rejected rather than never matching. On Windows guests, it
+    /// is the name passed to `Get-Process`, without the `.exe` extension.
+    pub async fn wait_for_process(&self, name: &str, timeout: Duration) -> anyhow::Result<()> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(500);
+        // TASK_COMM_LEN, less the nul terminator.
+        const MAX_UNIX_PROCESS_NAME_LEN: usize = 15;
+
+        if self.os == GuestOs::Unix && name.len() > MAX_UNIX_PROCESS_NAME_LEN {
+            anyhow::bail!(
+                "process name {name} is longer than {MAX_UNIX_PROCESS_NAME_LEN} bytes, so it can never match"
+            );
+        }
+
+        let start = std::time::Instant::now();
+        while !self.process_running(name).await? {
+            if start.elapsed() >= timeout {
+                anyhow::bail!("timed out after {timeout:?} waiting for process {name}");
+            }
+            let mut c = CancelContext::new().with_timeout(POLL_INTERVAL);
+            let _ = c.cancelled().await;
+        }
+        Ok(())
+    }
+
+    async fn process_running(&self, name: &str) -> anyhow::Result<bool> {
+        let output = match self.os {
+            GuestOs::Unix => self.command("pgrep").args(["-x", name]).output().await,
+            GuestOs::Windows => {
+                let script = format!(
+                    "if (Get-Process -Name '{}' -ErrorAction SilentlyContinue) {{ exit 0 }} else {{ exit 1 }}",
+                    name.replace('\'', "''")
+                );
+                self.command("powershell.exe")
+                    .args(["-NoProfile", "-NonInteractive", "-Command", script.as_str()])
+                    .output()
+                    .await
+            }
+        }
+        .context("failed to list guest processes")?;
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => anyhow::bail!(
+                "failed to list guest processes: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }
+    }
+
     /// Waits for the agent to exit.
     pub async fn wait(self) -> Result<(), mesh::RecvError> {
         self.watch.await
@@ -204,6 +554,7 @@ pub async fn wait(self) -> Result<(), mesh::RecvError> {
 
     /// Returns the current time in the guest.
     pub async fn get_time(&self) -> anyhow::Result<Timestamp> {
+        self.check_connection()?;
         self.send
             .call(PipetteRequest::GetTime, ())
             .await
@@ -211,6 +562,43 @@ pub async fn get_time(&self) -> anyhow::Result<Timestamp> {
     }
 }
 
+/// Guest log files that are collected by [`PipetteClient::collect_guest_logs`].
+pub const GUEST_LOG_FILES: &[&str] = &["/var/log/cloud-init.log", "/var/log/cloud-init-output.log"];
+
+async fn ping(send: &PipetteSender) -> anyhow::Result<Duration> {
+    const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+    let start = std::time::Instant::now();
+    CancelContext::new()
+        .with_timeout(PING_TIMEOUT)
+        .until_cancelled(send.call(PipetteRequest::Ping, ()))
+        .await
+        .context("timed out waiting for ping response")?
+        .context("failed to ping agent")?;
+    Ok(start.elapsed())
+}
+
+async fn keepalive(send: PipetteSender, state: Arc<KeepaliveState>, interval: Duration) {
+    loop {
+        let mut c = CancelContext::new().with_timeout(interval);
+        let _ = c.cancelled().await;
+        match ping(&send).await {
+            Ok(latency) => {
+                state.pings.fetch_add(1, Ordering::Relaxed);
+                tracing::trace!(?latency, "pipette keepalive");
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "pipette keepalive failed, not reconnecting"
+                );
+                state.lost.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+}
+
 async fn replay_logs(log: mesh::pipe::ReadPipe) {
     let mut lines = BufReader::new(log).lines();
     while let Some(line) = lines.next().await {
@@ -237,15 +625,18 @@ async fn recv_diag_files(output_dir: PathBuf, mut diag_file_recv: mesh::Receiver
             .await
             .expect("failed to write diagnostic file");
         tracing::debug!(name, "diagnostic file transfer complete");
-
-        #[expect(
-            clippy::disallowed_methods,
-            reason = "ATTACHMENT is most reliable when using true canonicalized paths"
-        )]
-        let canonical_path = path
-            .canonicalize()
-            .expect("failed to canonicalize attachment path");
-        // Use the inline junit syntax to attach the file to the test result.
-        println!("[[ATTACHMENT|{}]]", canonical_path.display());
+        print_attachment(&path);
     }
 }
+
+fn print_attachment(path: &Path) {
+    #[expect(
+        clippy::disallowed_methods,
+        reason = "ATTACHMENT is most reliable when using true canonicalized paths"
+    )]
+    let canonical_path = path
+        .canonicalize()
+        .expect("failed to canonicalize attachment path");
+    // Use the inline junit syntax to attach the file to the test result.
+    println!("[[ATTACHMENT|{}]]", canonical_path.display());
+}
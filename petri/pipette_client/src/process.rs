@@ -40,7 +40,7 @@ pub(super) fn new(client: &'a PipetteClient, program: impl AsRef<str>) -> Self {
             stdin: None,
             stdout: None,
             stderr: None,
-            env: Vec::new(),
+            env: client.env.lock().unwrap().clone(),
             clear_env: false,
         }
     }
@@ -128,6 +128,7 @@ async fn spawn_inner(
         default_stdio: &StdioInner,
         default_stdin: bool,
     ) -> anyhow::Result<Child> {
+        self.client.check_connection()?;
         let (stdin_read, stdin_write) = self
             .stdin
             .as_ref()
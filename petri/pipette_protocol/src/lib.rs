@@ -27,6 +27,17 @@ pub struct PipetteBootstrap {
     pub watch: mesh::OneshotReceiver<()>,
     /// The log channel.
     pub log: ReadPipe,
+    /// The operating system the agent is running on.
+    pub os: GuestOs,
+}
+
+/// The operating system family of the guest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, MeshPayload)]
+pub enum GuestOs {
+    /// A Unix-like guest, such as Linux.
+    Unix,
+    /// A Windows guest.
+    Windows,
 }
 
 /// A request to the agent.
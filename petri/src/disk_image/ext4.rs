@@ -0,0 +1,324 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal ext4 filesystem writer, for building Linux guest disk images.
+//!
+//! The filesystem has a root directory containing only regular files, each
+//! stored contiguously and described by extents in its inode. All block group
+//! metadata lives at the start of the volume (`flex_bg`), and there are no
+//! backup superblocks (`sparse_super2`), journal, or metadata checksums.
+
+use super::PathOrBinary;
+use anyhow::Context;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+const BLOCK_SIZE: u64 = 4096;
+const BLOCKS_PER_GROUP: u64 = BLOCK_SIZE * 8;
+const INODE_SIZE: u64 = 128;
+const INODES_PER_GROUP: u32 = 256;
+const INODE_TABLE_BLOCKS: u64 = INODES_PER_GROUP as u64 * INODE_SIZE / BLOCK_SIZE;
+const GROUP_DESC_SIZE: u64 = 32;
+
+const ROOT_INO: u32 = 2;
+const LOST_AND_FOUND_INO: u32 = 11;
+const FIRST_FILE_INO: u32 = 12;
+
+/// The most blocks a single initialized extent can cover.
+const MAX_EXTENT_LEN: u64 = 32768;
+/// The number of extents that fit in the inode itself.
+const INLINE_EXTENTS: usize = 4;
+
+const COMPAT_SPARSE_SUPER2: u32 = 0x200;
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_EXTENTS: u32 = 0x40;
+const INCOMPAT_FLEX_BG: u32 = 0x200;
+const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+
+const S_IFREG: u16 = 0o100000;
+const S_IFDIR: u16 = 0o040000;
+const EXT4_EXTENTS_FL: u32 = 0x80000;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+
+/// Formats the `len`-byte volume at the start of `file` as ext4 with the
+/// given volume label, containing `files` in the root directory.
+///
+/// `file` must already be zeroed.
+pub(super) fn build_ext4(
+    file: &mut (impl Write + Seek),
+    len: u64,
+    volume_label: &str,
+    files: &[(&str, PathOrBinary<'_>)],
+) -> anyhow::Result<()> {
+    if volume_label.len() > 16 {
+        anyhow::bail!("volume label {volume_label:?} is too long");
+    }
+    let first_free_ino = FIRST_FILE_INO as usize + files.len();
+    if first_free_ino > INODES_PER_GROUP as usize + 1 {
+        anyhow::bail!("too many files for an ext4 image");
+    }
+
+    let total_blocks = len / BLOCK_SIZE;
+    let total_blocks_u32 =
+        u32::try_from(total_blocks).context("volume is too large for an ext4 image")?;
+    let groups = total_blocks.div_ceil(BLOCKS_PER_GROUP);
+    let gdt_blocks = (groups * GROUP_DESC_SIZE).div_ceil(BLOCK_SIZE);
+
+    // Lay out the metadata for every group at the start of the volume: the
+    // superblock, the group descriptors, then the bitmaps and inode tables.
+    let block_bitmaps = 1 + gdt_blocks;
+    let inode_bitmaps = block_bitmaps + groups;
+    let inode_tables = inode_bitmaps + groups;
+    let mut next_block = inode_tables + groups * INODE_TABLE_BLOCKS;
+
+    let mut root_entries = vec![
+        (ROOT_INO, FT_DIR, "."),
+        (ROOT_INO, FT_DIR, ".."),
+        (LOST_AND_FOUND_INO, FT_DIR, "lost+found"),
+    ];
+    for (i, (name, _)) in files.iter().enumerate() {
+        if name.is_empty() || name.len() > 255 || name.contains(['/', '\0']) || *name == "." {
+            anyhow::bail!("invalid file name {name:?}");
+        }
+        root_entries.push((FIRST_FILE_INO + i as u32, FT_REG_FILE, name));
+    }
+    let root_dir = directory_blocks(&root_entries);
+    let root_dir_start = next_block;
+    next_block += root_dir.len() as u64 / BLOCK_SIZE;
+
+    let lost_and_found_dir =
+        directory_blocks(&[(LOST_AND_FOUND_INO, FT_DIR, "."), (ROOT_INO, FT_DIR, "..")]);
+    let lost_and_found_start = next_block;
+    next_block += 1;
+
+    let mut inodes = vec![0; INODES_PER_GROUP as usize * INODE_SIZE as usize];
+    let mut write_inode = |ino: u32, inode: Inode| {
+        let offset = (ino as usize - 1) * INODE_SIZE as usize;
+        inode.write(&mut inodes[offset..][..INODE_SIZE as usize]);
+    };
+    write_inode(
+        ROOT_INO,
+        Inode::directory(0o755, 3, root_dir_start, root_dir.len() as u64),
+    );
+    write_inode(
+        LOST_AND_FOUND_INO,
+        Inode::directory(0o700, 2, lost_and_found_start, BLOCK_SIZE),
+    );
+
+    let mut file_starts = Vec::new();
+    for (i, (name, src)) in files.iter().enumerate() {
+        let size = match *src {
+            PathOrBinary::Path(path) => fs_err::metadata(path)?.len(),
+            PathOrBinary::Binary(data) => data.len() as u64,
+        };
+        let blocks = size.div_ceil(BLOCK_SIZE);
+        if blocks > MAX_EXTENT_LEN * INLINE_EXTENTS as u64 {
+            anyhow::bail!("{name} ({size} bytes) is too large for an ext4 image");
+        }
+        let start = next_block;
+        next_block += blocks;
+        if next_block > total_blocks {
+            anyhow::bail!("{name} ({size} bytes) does not fit in the {len:#x}-byte disk image");
+        }
+        write_inode(FIRST_FILE_INO + i as u32, Inode::file(start, size));
+        file_starts.push(start);
+    }
+    let used_blocks = next_block;
+    if used_blocks > total_blocks {
+        anyhow::bail!("disk image size {len:#x} is too small for an ext4 volume");
+    }
+
+    // Blocks are allocated contiguously from the start of the volume, and
+    // inodes from the start of the first group.
+    let mut metadata = vec![0; (inode_tables * BLOCK_SIZE) as usize];
+    let mut free_blocks = 0;
+    for group in 0..groups {
+        let first = group * BLOCKS_PER_GROUP;
+        let group_blocks = (total_blocks - first).min(BLOCKS_PER_GROUP);
+        let group_used = used_blocks.saturating_sub(first).min(group_blocks);
+        let group_free = group_blocks - group_used;
+        free_blocks += group_free;
+
+        // Blocks past the end of the volume are marked in use.
+        let bitmap =
+            &mut metadata[((block_bitmaps + group) * BLOCK_SIZE) as usize..][..BLOCK_SIZE as usize];
+        set_bits(bitmap, 0..group_used);
+        set_bits(bitmap, group_blocks..BLOCKS_PER_GROUP);
+
+        // As are inodes past the end of the group.
+        let used_inodes = if group == 0 {
+            first_free_ino as u64 - 1
+        } else {
+            0
+        };
+        let bitmap =
+            &mut metadata[((inode_bitmaps + group) * BLOCK_SIZE) as usize..][..BLOCK_SIZE as usize];
+        set_bits(bitmap, 0..used_inodes);
+        set_bits(bitmap, INODES_PER_GROUP as u64..BLOCK_SIZE * 8);
+
+        let desc = &mut metadata[(BLOCK_SIZE + group * GROUP_DESC_SIZE) as usize..]
+            [..GROUP_DESC_SIZE as usize];
+        put_u32(desc, 0, (block_bitmaps + group) as u32);
+        put_u32(desc, 4, (inode_bitmaps + group) as u32);
+        put_u32(desc, 8, (inode_tables + group * INODE_TABLE_BLOCKS) as u32);
+        put_u16(desc, 12, group_free as u16);
+        put_u16(desc, 14, (INODES_PER_GROUP as u64 - used_inodes) as u16);
+        put_u16(desc, 16, if group == 0 { 2 } else { 0 });
+    }
+
+    let free_inodes = groups as u32 * INODES_PER_GROUP - (first_free_ino as u32 - 1);
+    let sb = &mut metadata[1024..2048];
+    put_u32(sb, 0x0, groups as u32 * INODES_PER_GROUP); // s_inodes_count
+    put_u32(sb, 0x4, total_blocks_u32); // s_blocks_count_lo
+    put_u32(sb, 0xc, free_blocks as u32); // s_free_blocks_count_lo
+    put_u32(sb, 0x10, free_inodes); // s_free_inodes_count
+    put_u32(sb, 0x18, 2); // s_log_block_size: 1024 << 2
+    put_u32(sb, 0x1c, 2); // s_log_cluster_size
+    put_u32(sb, 0x20, BLOCKS_PER_GROUP as u32); // s_blocks_per_group
+    put_u32(sb, 0x24, BLOCKS_PER_GROUP as u32); // s_clusters_per_group
+    put_u32(sb, 0x28, INODES_PER_GROUP); // s_inodes_per_group
+    put_u16(sb, 0x36, 0xffff); // s_max_mnt_count: no forced checks
+    put_u16(sb, 0x38, 0xef53); // s_magic
+    put_u16(sb, 0x3a, 1); // s_state: clean
+    put_u16(sb, 0x3c, 1); // s_errors: continue
+    put_u32(sb, 0x4c, 1); // s_rev_level: dynamic
+    put_u32(sb, 0x54, LOST_AND_FOUND_INO); // s_first_ino
+    put_u16(sb, 0x58, INODE_SIZE as u16); // s_inode_size
+    put_u32(sb, 0x5c, COMPAT_SPARSE_SUPER2);
+    put_u32(
+        sb,
+        0x60,
+        INCOMPAT_FILETYPE | INCOMPAT_EXTENTS | INCOMPAT_FLEX_BG,
+    );
+    put_u32(sb, 0x64, RO_COMPAT_LARGE_FILE);
+    sb[0x68..0x78].copy_from_slice(&guid::Guid::new_random().as_bytes()); // s_uuid
+    sb[0x78..][..volume_label.len()].copy_from_slice(volume_label.as_bytes());
+    // s_log_groups_per_flex: put every group in one flex group.
+    sb[0x174] = groups.next_power_of_two().trailing_zeros() as u8;
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&metadata)?;
+    // Only the first group's inode table has any inodes in use.
+    file.seek(SeekFrom::Start(inode_tables * BLOCK_SIZE))?;
+    file.write_all(&inodes)?;
+    file.seek(SeekFrom::Start(root_dir_start * BLOCK_SIZE))?;
+    file.write_all(&root_dir)?;
+    file.seek(SeekFrom::Start(lost_and_found_start * BLOCK_SIZE))?;
+    file.write_all(&lost_and_found_dir)?;
+    for ((name, src), start) in files.iter().zip(file_starts) {
+        file.seek(SeekFrom::Start(start * BLOCK_SIZE))?;
+        match *src {
+            PathOrBinary::Path(src_path) => {
+                let mut src = fs_err::File::open(src_path)?;
+                std::io::copy(&mut src, file).with_context(|| format!("failed to copy {name}"))?;
+            }
+            PathOrBinary::Binary(src_data) => {
+                file.write_all(src_data)
+                    .with_context(|| format!("failed to write {name}"))?;
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// The fields of an inode that differ between the inodes this writer
+/// creates.
+struct Inode {
+    mode: u16,
+    links: u16,
+    size: u64,
+    start: u64,
+}
+
+impl Inode {
+    fn directory(permissions: u16, links: u16, start: u64, size: u64) -> Self {
+        Self {
+            mode: S_IFDIR | permissions,
+            links,
+            size,
+            start,
+        }
+    }
+
+    fn file(start: u64, size: u64) -> Self {
+        Self {
+            mode: S_IFREG | 0o755,
+            links: 1,
+            size,
+            start,
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        let blocks = self.size.div_ceil(BLOCK_SIZE);
+        put_u16(buf, 0x0, self.mode);
+        put_u32(buf, 0x4, self.size as u32); // i_size_lo
+        put_u16(buf, 0x1a, self.links);
+        put_u32(buf, 0x1c, (blocks * (BLOCK_SIZE / 512)) as u32); // i_blocks_lo
+        put_u32(buf, 0x20, EXT4_EXTENTS_FL);
+        put_u32(buf, 0x6c, (self.size >> 32) as u32); // i_size_high
+
+        // The extent tree, stored in i_block.
+        let i_block = &mut buf[0x28..0x64];
+        put_u16(i_block, 0, 0xf30a); // eh_magic
+        put_u16(i_block, 2, blocks.div_ceil(MAX_EXTENT_LEN) as u16); // eh_entries
+        put_u16(i_block, 4, INLINE_EXTENTS as u16); // eh_max
+        for (i, logical) in (0..blocks).step_by(MAX_EXTENT_LEN as usize).enumerate() {
+            let extent = &mut i_block[12 + i * 12..][..12];
+            let physical = self.start + logical;
+            put_u32(extent, 0, logical as u32); // ee_block
+            put_u16(extent, 4, (blocks - logical).min(MAX_EXTENT_LEN) as u16); // ee_len
+            put_u16(extent, 6, (physical >> 32) as u16); // ee_start_hi
+            put_u32(extent, 8, physical as u32); // ee_start_lo
+        }
+    }
+}
+
+/// Builds the blocks of a linear directory containing `entries` of inode
+/// number, file type, and name.
+fn directory_blocks(entries: &[(u32, u8, &str)]) -> Vec<u8> {
+    let mut blocks = Vec::new();
+    let mut block = Vec::new();
+    let mut last = 0;
+    for &(ino, file_type, name) in entries {
+        let rec_len = (8 + name.len()).next_multiple_of(4);
+        if block.len() + rec_len > BLOCK_SIZE as usize {
+            finish_directory_block(&mut blocks, &mut block, last);
+        }
+        last = block.len();
+        block.extend(ino.to_le_bytes());
+        block.extend((rec_len as u16).to_le_bytes());
+        block.push(name.len() as u8);
+        block.push(file_type);
+        block.extend(name.as_bytes());
+        block.resize(last + rec_len, 0);
+    }
+    finish_directory_block(&mut blocks, &mut block, last);
+    blocks
+}
+
+/// Extends the last entry of a directory block, at offset `last`, to the end
+/// of the block, and appends it to `blocks`.
+fn finish_directory_block(blocks: &mut Vec<u8>, block: &mut Vec<u8>, last: usize) {
+    let rec_len = BLOCK_SIZE as usize - last;
+    put_u16(block, last + 4, rec_len as u16);
+    block.resize(BLOCK_SIZE as usize, 0);
+    blocks.append(block);
+}
+
+fn set_bits(bitmap: &mut [u8], bits: std::ops::Range<u64>) {
+    for bit in bits {
+        bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+    }
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -10,15 +10,19 @@
 
 pub mod disk_image;
 mod linux_direct_serial_agent;
+mod matrix;
 // TODO: Add docs and maybe a trait interface for this, or maybe this can
 // remain crate-local somehow without violating interface privacy.
 #[expect(missing_docs)]
 pub mod openhcl_diag;
+mod serial_login;
 mod test;
 mod tracing;
 mod vm;
 mod worker;
 
+pub use matrix::BackendMatrixTest;
+pub use matrix::host_isolation_types;
 pub use petri_artifacts_core::ArtifactHandle;
 pub use petri_artifacts_core::ArtifactResolver;
 pub use petri_artifacts_core::AsArtifactHandle;
@@ -29,6 +33,10 @@
 pub use petri_artifacts_core::TestArtifactRequirements;
 pub use petri_artifacts_core::TestArtifacts;
 pub use pipette_client as pipette;
+pub use serial_login::DEFAULT_PROMPT_TIMEOUT;
+pub use serial_login::GuestCredentials;
+pub use serial_login::SerialConsole;
+pub use serial_login::serial_login;
 pub use test::PetriTestParams;
 pub use test::RunTest;
 pub use test::SimpleTest;
@@ -0,0 +1,367 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Infrastructure for running one test scenario against each VMM backend.
+
+use crate::Firmware;
+use crate::FirmwareVariant;
+use crate::IsolationType;
+use crate::PetriTestParams;
+use crate::PetriVmArtifacts;
+use crate::PetriVmBuilder;
+use crate::PetriVmmBackend;
+use crate::RunTest;
+use crate::TestCase;
+use crate::firmware_matrix;
+use petri_artifacts_common::tags::IsTestVhd;
+use petri_artifacts_common::tags::MachineArch;
+use petri_artifacts_core::ArtifactHandle;
+use petri_artifacts_core::ArtifactResolver;
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// Defines a set of tests running the backend-generic function `$f` against
+/// each VMM backend available on the host, named `$f::openvmm` and
+/// `$f::hyperv`.
+///
+/// `$firmware` is either a function taking an [`ArtifactResolver`] and
+/// returning the [`Firmware`] to boot, or `vhd(HANDLE)` to boot the VHD
+/// artifact with each [`FirmwareVariant`] it supports, named
+/// `$f::<variant>::<backend>`.
+///
+/// `$arch` may also be `arches(ARCH, ...)`, in which case `$firmware` takes
+/// the architecture as a second argument and the tests are named
+/// `$f::<arch>::<backend>`. Tests for an architecture the host cannot run are
+/// left out at runtime.
+#[macro_export]
+macro_rules! backend_matrix_test {
+    ($f:ident, arches($($arch:expr),+ $(,)?), $firmware:expr) => {
+        $crate::multitest!(
+            $crate::BackendMatrixTest::for_arches(stringify!($f), &[$($arch),+], $firmware)
+                .into_iter()
+                .flat_map(|matrix| $crate::backend_matrix_test!(@backends $f, matrix))
+                .collect()
+        );
+    };
+    ($f:ident, $arch:expr, vhd($guest:expr)) => {
+        $crate::multitest!(
+            $crate::BackendMatrixTest::for_firmware_matrix(
+                stringify!($f),
+                $arch,
+                $guest,
+                &$crate::host_isolation_types(),
+            )
+                .into_iter()
+                .flat_map(|matrix| $crate::backend_matrix_test!(@backends $f, matrix))
+                .collect()
+        );
+    };
+    ($f:ident, $arch:expr, $firmware:expr) => {
+        $crate::multitest!({
+            let matrix = $crate::BackendMatrixTest::new(stringify!($f), $arch, $firmware);
+            $crate::backend_matrix_test!(@backends $f, matrix)
+        });
+    };
+    (@backends $f:ident, $matrix:expr) => {{
+        let matrix = $matrix.backend("openvmm", $f::<$crate::openvmm::OpenVmmPetriBackend>);
+        #[cfg(windows)]
+        let matrix = matrix.backend("hyperv", $f::<$crate::hyperv::HyperVPetriBackend>);
+        matrix.into_tests()
+    }};
+}
+
+/// A single scenario registered against multiple VMM backends.
+///
+/// Each backend's test is named `<name>::<backend name>`, and is left out of
+/// the test list if the backend does not support the firmware and
+/// architecture on this host.
+pub struct BackendMatrixTest<F> {
+    name: String,
+    arch: MachineArch,
+    firmware: F,
+    tests: Vec<TestCase>,
+}
+
+impl<F> BackendMatrixTest<F>
+where
+    F: 'static + Send + Clone + Fn(&ArtifactResolver<'_>) -> Firmware,
+{
+    /// Returns a new, empty matrix for the scenario `name`.
+    pub fn new(name: impl Into<String>, arch: MachineArch, firmware: F) -> Self {
+        Self {
+            name: name.into(),
+            arch,
+            firmware,
+            tests: Vec::new(),
+        }
+    }
+
+    /// Adds a test running `scenario` against backend `T`.
+    pub fn backend<T, S, Fut>(mut self, backend_name: &str, scenario: S) -> Self
+    where
+        T: 'static + PetriVmmBackend,
+        S: 'static + Send + Fn(PetriVmBuilder<T>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        self.tests.push(TestCase::new(BackendTest {
+            leaf_name: format!("{}::{backend_name}", self.name),
+            arch: self.arch,
+            firmware: self.firmware.clone(),
+            scenario,
+            _backend: PhantomData::<fn() -> T>,
+        }));
+        self
+    }
+
+    /// Returns the tests, for registration with [`multitest!`](crate::multitest).
+    pub fn into_tests(self) -> Vec<TestCase> {
+        self.tests
+    }
+}
+
+impl BackendMatrixTest<()> {
+    /// Returns a matrix for each [`FirmwareVariant`] that can boot the VHD
+    /// `guest` on `arch`, named `<name>::<variant>`.
+    ///
+    /// `host_isolation` lists the isolation types supported by the host.
+    pub fn for_firmware_matrix<A: IsTestVhd + Copy + Send>(
+        name: &str,
+        arch: MachineArch,
+        guest: ArtifactHandle<A>,
+        host_isolation: &[IsolationType],
+    ) -> Vec<BackendMatrixTest<impl 'static + Send + Clone + Fn(&ArtifactResolver<'_>) -> Firmware>>
+    {
+        firmware_matrix(A::OS_FLAVOR, arch, host_isolation)
+            .into_iter()
+            .map(|variant| {
+                BackendMatrixTest::new(
+                    format!("{name}::{}", variant.name()),
+                    arch,
+                    move |resolver: &ArtifactResolver<'_>| variant.firmware(resolver, arch, guest),
+                )
+            })
+            .collect()
+    }
+}
+
+impl BackendMatrixTest<()> {
+    /// Returns a matrix for each of `arches`, named `<name>::<arch>`, booting
+    /// the firmware returned by `firmware` for that architecture.
+    ///
+    /// This allows one test binary to cover several guest architectures. The
+    /// tests for an architecture the host cannot run (see
+    /// [`host_supports_guest_arch`](crate::host_supports_guest_arch)) are left
+    /// out of the test list.
+    pub fn for_arches<F>(
+        name: &str,
+        arches: &[MachineArch],
+        firmware: F,
+    ) -> Vec<BackendMatrixTest<impl 'static + Send + Clone + Fn(&ArtifactResolver<'_>) -> Firmware>>
+    where
+        F: 'static + Send + Clone + Fn(&ArtifactResolver<'_>, MachineArch) -> Firmware,
+    {
+        arches
+            .iter()
+            .map(|&arch| {
+                let firmware = firmware.clone();
+                BackendMatrixTest::new(
+                    format!("{name}::{}", arch_name(arch)),
+                    arch,
+                    move |resolver: &ArtifactResolver<'_>| firmware(resolver, arch),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Returns the isolation types that OpenHCL VMs can be started with by any
+/// VMM backend available on this host, as reported by each backend's
+/// [capabilities](crate::PetriVmmBackend::capabilities).
+///
+/// These are derived from the host: OpenVMM supports VBS on Windows hosts,
+/// and Hyper-V supports VBS plus the hardware isolation type of the host's
+/// processor. Tests for an isolation type that a particular backend does not
+/// support are left out of the test list.
+pub fn host_isolation_types() -> Vec<IsolationType> {
+    let mut isolation = crate::openvmm::OpenVmmPetriBackend::capabilities().isolation;
+    #[cfg(windows)]
+    for hyperv in crate::hyperv::HyperVPetriBackend::capabilities().isolation {
+        if !isolation.contains(&hyperv) {
+            isolation.push(hyperv);
+        }
+    }
+    isolation
+}
+
+/// The name of `arch` as used in test names.
+fn arch_name(arch: MachineArch) -> &'static str {
+    match arch {
+        MachineArch::X86_64 => "x64",
+        MachineArch::Aarch64 => "aarch64",
+    }
+}
+
+struct BackendTest<T, F, S> {
+    leaf_name: String,
+    arch: MachineArch,
+    firmware: F,
+    scenario: S,
+    _backend: PhantomData<fn() -> T>,
+}
+
+impl<T, F, S, Fut> RunTest for BackendTest<T, F, S>
+where
+    T: 'static + PetriVmmBackend,
+    F: 'static + Send + Fn(&ArtifactResolver<'_>) -> Firmware,
+    S: 'static + Send + Fn(PetriVmBuilder<T>) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    type Artifacts = PetriVmArtifacts<T>;
+
+    fn leaf_name(&self) -> &str {
+        &self.leaf_name
+    }
+
+    fn resolve(&self, resolver: &ArtifactResolver<'_>) -> Option<Self::Artifacts> {
+        PetriVmArtifacts::new(resolver, (self.firmware)(resolver), self.arch)
+    }
+
+    fn run(&self, params: PetriTestParams<'_>, artifacts: Self::Artifacts) -> anyhow::Result<()> {
+        pal_async::DefaultPool::run_with(async |driver| {
+            let config = PetriVmBuilder::new(&params, artifacts, &driver)?;
+            (self.scenario)(config).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openvmm::OpenVmmPetriBackend;
+
+    async fn scenario<T: PetriVmmBackend>(_config: PetriVmBuilder<T>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn one_trial_per_backend() {
+        let tests = BackendMatrixTest::new("scenario", MachineArch::X86_64, |resolver| {
+            Firmware::linux_direct(resolver, MachineArch::X86_64)
+        })
+        .backend("openvmm", scenario::<OpenVmmPetriBackend>)
+        .backend("hyperv", scenario::<crate::hyperv::HyperVPetriBackend>)
+        .into_tests();
+
+        let names = tests.iter().map(|t| t.leaf_name()).collect::<Vec<_>>();
+        assert_eq!(names, ["scenario::openvmm", "scenario::hyperv"]);
+        // Hyper-V cannot boot Linux directly, so its test is left out.
+        assert!(!tests[1].is_supported());
+    }
+
+    #[test]
+    fn one_matrix_per_firmware_variant() {
+        use petri_artifacts_vmm_test::artifacts::test_vhd::UBUNTU_2204_SERVER_X64;
+
+        let tests = BackendMatrixTest::for_firmware_matrix(
+            "scenario",
+            MachineArch::X86_64,
+            UBUNTU_2204_SERVER_X64,
+            &[],
+        )
+        .into_iter()
+        .flat_map(|matrix| {
+            matrix
+                .backend("openvmm", scenario::<OpenVmmPetriBackend>)
+                .into_tests()
+        })
+        .collect::<Vec<_>>();
+
+        let names = tests.iter().map(|t| t.leaf_name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "scenario::uefi::openvmm",
+                "scenario::pcat::openvmm",
+                "scenario::openhcl_uefi::openvmm",
+            ]
+        );
+    }
+
+    #[test]
+    fn host_isolation_variants() {
+        use petri_artifacts_vmm_test::artifacts::test_vhd::GEN2_WINDOWS_DATA_CENTER_CORE2022_X64;
+
+        let tests = BackendMatrixTest::for_firmware_matrix(
+            "scenario",
+            MachineArch::X86_64,
+            GEN2_WINDOWS_DATA_CENTER_CORE2022_X64,
+            &host_isolation_types(),
+        )
+        .into_iter()
+        .flat_map(|matrix| {
+            matrix
+                .backend("openvmm", scenario::<OpenVmmPetriBackend>)
+                .into_tests()
+        })
+        .collect::<Vec<_>>();
+
+        let names = tests.iter().map(|t| t.leaf_name()).collect::<Vec<_>>();
+        let isolation = host_isolation_types();
+        let mut expected = vec![
+            "scenario::uefi::openvmm".to_owned(),
+            "scenario::pcat::openvmm".to_owned(),
+            "scenario::openhcl_uefi::openvmm".to_owned(),
+        ];
+        expected.extend(isolation.iter().map(|&isolation| {
+            format!(
+                "scenario::{}::openvmm",
+                FirmwareVariant::OpenhclUefi(Some(isolation)).name()
+            )
+        }));
+        assert_eq!(names, expected);
+        if cfg!(windows) {
+            assert!(isolation.contains(&IsolationType::Vbs));
+        } else {
+            assert!(isolation.is_empty());
+        }
+
+        // OpenVMM's hardware isolated variants are left out of the test list.
+        for (test, isolation) in tests[3..].iter().zip(isolation) {
+            assert_eq!(
+                test.is_supported(),
+                isolation == IsolationType::Vbs,
+                "{}",
+                test.leaf_name()
+            );
+        }
+    }
+
+    #[test]
+    fn foreign_arch_is_skipped() {
+        let tests = BackendMatrixTest::for_arches(
+            "scenario",
+            &[MachineArch::X86_64, MachineArch::Aarch64],
+            Firmware::linux_direct,
+        )
+        .into_iter()
+        .flat_map(|matrix| {
+            matrix
+                .backend("openvmm", scenario::<OpenVmmPetriBackend>)
+                .into_tests()
+        })
+        .collect::<Vec<_>>();
+
+        let names = tests.iter().map(|t| t.leaf_name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["scenario::x64::openvmm", "scenario::aarch64::openvmm"]
+        );
+
+        // Only the host's architecture can run, e.g. the aarch64 test is
+        // skipped on an x64 host.
+        let host = MachineArch::host();
+        assert_eq!(tests[0].is_supported(), host == MachineArch::X86_64);
+        assert_eq!(tests[1].is_supported(), host == MachineArch::Aarch64);
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Logging in to a guest over its serial console, as a fallback interaction
+//! channel when pipette is unavailable.
+
+use anyhow::Context as _;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use mesh::CancelContext;
+use parking_lot::Mutex;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+
+/// Credentials used to log in to the guest.
+#[derive(Debug, Clone)]
+pub struct GuestCredentials {
+    /// The user name
+    pub username: String,
+    /// The password
+    pub password: String,
+}
+
+impl Default for GuestCredentials {
+    /// The user created by the default cloud-init configuration.
+    fn default() -> Self {
+        Self {
+            username: "petri".into(),
+            password: "petri".into(),
+        }
+    }
+}
+
+const LOGIN_PROMPT: &str = "login: ";
+const PASSWORD_PROMPT: &str = "Password: ";
+const SHELL_PROMPTS: &[&str] = &["$ ", "# "];
+
+/// The default time to wait for each prompt when logging in.
+pub const DEFAULT_PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Drives a guest login prompt on a serial console, returning once a shell
+/// prompt is detected.
+///
+/// `read` should be the guest's console output, and `write` its input. Fails
+/// if any prompt does not appear within `timeout`.
+pub async fn serial_login(
+    mut read: impl AsyncRead + Unpin,
+    mut write: impl AsyncWrite + Unpin,
+    credentials: &GuestCredentials,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    // Start with a newline to get a fresh login prompt, in case the existing
+    // one has already scrolled past.
+    write.write_all(b"\n").await?;
+
+    wait_for_prompt(&mut read, &[LOGIN_PROMPT], timeout)
+        .await
+        .context("failed waiting for login prompt")?;
+    write
+        .write_all(format!("{}\n", credentials.username).as_bytes())
+        .await?;
+
+    wait_for_prompt(&mut read, &[PASSWORD_PROMPT], timeout)
+        .await
+        .context("failed waiting for password prompt")?;
+    write
+        .write_all(format!("{}\n", credentials.password).as_bytes())
+        .await?;
+
+    wait_for_prompt(&mut read, SHELL_PROMPTS, timeout)
+        .await
+        .context("failed waiting for shell prompt, credentials may be incorrect")?;
+
+    Ok(())
+}
+
+/// Reads from `read` until the output ends with one of `prompts`, for up to
+/// `timeout`.
+async fn wait_for_prompt(
+    read: &mut (impl AsyncRead + Unpin),
+    prompts: &[&str],
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut output = Vec::new();
+    let wait = async {
+        let mut buf = [0u8; 1];
+        loop {
+            if read.read(&mut buf).await? == 0 {
+                anyhow::bail!("console closed");
+            }
+            output.push(buf[0]);
+            if output.ends_with(b"Login incorrect") {
+                anyhow::bail!("login incorrect");
+            }
+            if prompts.iter().any(|p| output.ends_with(p.as_bytes())) {
+                return Ok(());
+            }
+        }
+    };
+    let result = match CancelContext::new()
+        .with_timeout(timeout)
+        .until_cancelled(wait)
+        .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timed out after {timeout:?}")),
+    };
+    result.with_context(|| {
+        format!(
+            "last output: {:?}",
+            String::from_utf8_lossy(&output[output.len().saturating_sub(256)..])
+        )
+    })
+}
+
+/// The host side of a guest serial console, from which [`SerialConsole`]s are
+/// opened.
+pub(crate) struct SerialConsoleHost {
+    output: Arc<Mutex<Option<mesh::Sender<Vec<u8>>>>>,
+    input: Option<Arc<Mutex<dyn AsyncWrite + Unpin + Send>>>,
+}
+
+impl SerialConsoleHost {
+    pub(crate) fn new() -> Self {
+        Self {
+            output: Default::default(),
+            input: None,
+        }
+    }
+
+    /// Wraps the console's output `reader`, copying everything read from it
+    /// to the most recently opened [`SerialConsole`].
+    pub(crate) fn tee<R>(&self, reader: R) -> ConsoleTee<R> {
+        ConsoleTee {
+            reader,
+            output: self.output.clone(),
+        }
+    }
+
+    /// Sets the console's input. Without it, consoles cannot be opened.
+    pub(crate) fn set_input(&mut self, input: impl AsyncWrite + Unpin + Send + 'static) {
+        self.input = Some(Arc::new(Mutex::new(input)));
+    }
+
+    /// Opens the console. Any previously opened console stops receiving
+    /// output.
+    pub(crate) fn open(&self) -> anyhow::Result<SerialConsole> {
+        let input = self
+            .input
+            .clone()
+            .context("the serial console's input is not available")?;
+        let (send, recv) = mesh::channel();
+        *self.output.lock() = Some(send);
+        Ok(SerialConsole {
+            output: recv,
+            pending: Vec::new(),
+            input,
+        })
+    }
+}
+
+/// A console output reader that also sends its output to a [`SerialConsole`].
+pub(crate) struct ConsoleTee<R> {
+    reader: R,
+    output: Arc<Mutex<Option<mesh::Sender<Vec<u8>>>>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ConsoleTee<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.reader).poll_read(cx, buf))?;
+        if let Some(output) = this.output.lock().as_ref().filter(|_| n != 0) {
+            output.send(buf[..n].to_vec());
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A guest serial console, for interacting with the guest when pipette is
+/// unavailable.
+///
+/// Reads return the guest's console output from when the console was opened,
+/// which is still logged as usual. Writes are sent to the guest as console
+/// input.
+pub struct SerialConsole {
+    output: mesh::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    input: Arc<Mutex<dyn AsyncWrite + Unpin + Send>>,
+}
+
+impl SerialConsole {
+    /// Logs in to the guest with `credentials` via [`serial_login`].
+    pub async fn login(
+        &mut self,
+        credentials: &GuestCredentials,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let (read, write) = AsyncReadExt::split(self);
+        serial_login(read, write, credentials, timeout).await
+    }
+}
+
+impl AsyncRead for SerialConsole {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.pending.is_empty() {
+            match ready!(this.output.poll_recv(cx)) {
+                Ok(data) => this.pending = data,
+                // The console's output is gone, so report EOF.
+                Err(_) => return Poll::Ready(Ok(0)),
+            }
+        }
+        let n = buf.len().min(this.pending.len());
+        buf[..n].copy_from_slice(&this.pending[..n]);
+        this.pending.drain(..n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for SerialConsole {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.input.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.input.lock()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.input.lock()).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestCredentials;
+    use super::SerialConsoleHost;
+    use super::serial_login;
+    use futures::AsyncReadExt;
+    use futures::AsyncWriteExt;
+    use futures::TryStreamExt;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn login_sends_credentials() {
+        let console = b"\r\nubuntu login: Password: \r\nWelcome to Ubuntu\r\npetri@ubuntu:~$ ";
+        let mut input = Vec::new();
+        let credentials = GuestCredentials {
+            username: "user".into(),
+            password: "hunter2".into(),
+        };
+        pal_async::DefaultPool::run_with(async |_| {
+            serial_login(&console[..], &mut input, &credentials, TIMEOUT).await
+        })
+        .unwrap();
+        assert_eq!(input, b"\nuser\nhunter2\n");
+    }
+
+    #[test]
+    fn login_reports_bad_credentials() {
+        let console = b"ubuntu login: Password: \r\nLogin incorrect\r\nubuntu login: ";
+        let err = pal_async::DefaultPool::run_with(async |_| {
+            serial_login(
+                &console[..],
+                &mut Vec::new(),
+                &GuestCredentials::default(),
+                TIMEOUT,
+            )
+            .await
+        })
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("login incorrect"), "{err:#}");
+    }
+
+    #[test]
+    fn login_times_out() {
+        // The console stops producing output after the login prompt.
+        let stalled = futures::stream::pending::<std::io::Result<&[u8]>>().into_async_read();
+        let console = (&b"ubuntu login: "[..]).chain(stalled);
+        let err = pal_async::DefaultPool::run_with(async |_| {
+            serial_login(
+                console,
+                &mut Vec::new(),
+                &GuestCredentials::default(),
+                Duration::from_millis(100),
+            )
+            .await
+        })
+        .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("password prompt"), "{err}");
+        assert!(err.contains("timed out"), "{err}");
+    }
+
+    #[test]
+    fn serial_console() {
+        let mut host = SerialConsoleHost::new();
+        let mut buf = Vec::new();
+        pal_async::DefaultPool::run_with(async |_| {
+            // Output from before the console is opened is not seen.
+            host.tee(&b"before"[..])
+                .read_to_end(&mut buf)
+                .await
+                .unwrap();
+            host.open().unwrap_err();
+            host.set_input(Vec::new());
+            let mut console = host.open().unwrap();
+            host.tee(&b"after"[..]).read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"beforeafter");
+            // Closing the host ends the console's output.
+            drop(host);
+
+            let mut output = Vec::new();
+            console.read_to_end(&mut output).await.unwrap();
+            assert_eq!(output, b"after");
+            console.write_all(b"input").await.unwrap();
+        });
+    }
+}
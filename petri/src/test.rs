@@ -55,6 +55,16 @@ impl TestCase {
     pub fn new(test: impl 'static + RunTest) -> Self {
         Self(Box::new(test))
     }
+
+    #[cfg(test)]
+    pub(crate) fn leaf_name(&self) -> &str {
+        self.0.leaf_name()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_supported(&self) -> bool {
+        self.0.requirements().is_some()
+    }
 }
 
 impl<T: 'static + RunTest> From<T> for TestCase {
@@ -97,6 +107,23 @@ fn name(&self) -> String {
         }
     }
 
+    /// Checks that the artifacts required by the test can be resolved.
+    ///
+    /// The log directory is skipped, since resolving it creates the test's
+    /// output directory.
+    fn check_artifacts(
+        &self,
+        resolve: fn(&str, TestArtifactRequirements) -> anyhow::Result<TestArtifacts>,
+    ) -> anyhow::Result<()> {
+        let mut requirements = TestArtifactRequirements::new();
+        for artifact in self.requirements.required_artifacts() {
+            if petri_artifacts_common::artifacts::TEST_LOG_DIRECTORY != artifact {
+                requirements.require(artifact);
+            }
+        }
+        resolve(&self.name(), requirements).map(|_| ())
+    }
+
     fn run(
         &self,
         resolve: fn(&str, TestArtifactRequirements) -> anyhow::Result<TestArtifacts>,
@@ -269,6 +296,47 @@ fn run(&self, params: PetriTestParams<'_>, artifacts: Self::Artifacts) -> anyhow
     }
 }
 
+/// Returns whether libtest would run the test named `name` given `args`.
+fn is_selected(args: &libtest_mimic::Arguments, name: &str) -> bool {
+    // Petri tests are never marked as ignored, so `--ignored` runs none of
+    // them.
+    if args.ignored {
+        return false;
+    }
+    let matches = |filter: &String| {
+        if args.exact {
+            name == filter
+        } else {
+            name.contains(filter.as_str())
+        }
+    };
+    args.filter.as_ref().is_none_or(matches) && !args.skip.iter().any(matches)
+}
+
+/// Checks that the required artifacts of all selected tests can be resolved,
+/// reporting all the failures at once.
+fn check_artifacts(
+    args: &libtest_mimic::Arguments,
+    tests: &[Test],
+    resolve: fn(&str, TestArtifactRequirements) -> anyhow::Result<TestArtifacts>,
+) -> anyhow::Result<()> {
+    let mut failed = String::new();
+    for test in tests {
+        let name = test.name();
+        if !is_selected(args, &name) {
+            continue;
+        }
+        if let Err(err) = test.check_artifacts(resolve) {
+            failed.push_str(&format!("{name}: {err:#}\n"));
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!("missing artifacts for selected tests:\n{failed}");
+    }
+    Ok(())
+}
+
 #[derive(clap::Parser)]
 struct Options {
     /// Lists the required artifacts for all tests.
@@ -307,6 +375,113 @@ pub fn test_main(
     }
     args.inner.test_threads = Some(1);
 
-    let trials = Test::all().map(|test| test.trial(resolve)).collect();
+    let tests = Test::all().collect::<Vec<_>>();
+
+    // Fail fast, before running any tests, if artifacts are missing.
+    if !args.inner.list {
+        if let Err(err) = check_artifacts(&args.inner, &tests, resolve) {
+            eprintln!("error: {err:#}");
+            std::process::exit(1);
+        }
+    }
+
+    let trials = tests.into_iter().map(|test| test.trial(resolve)).collect();
     libtest_mimic::run(&args.inner, trials).exit()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArtifactHandle;
+    use crate::ErasedArtifactHandle;
+    use crate::ResolveTestArtifact;
+    use petri_artifacts_core::ArtifactId;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+
+    mod artifacts {
+        petri_artifacts_core::declare_artifacts! {
+            PRESENT,
+            MISSING,
+        }
+    }
+
+    struct Resolver;
+
+    impl ResolveTestArtifact for Resolver {
+        fn resolve(&self, id: ErasedArtifactHandle) -> anyhow::Result<PathBuf> {
+            if artifacts::MISSING == id {
+                anyhow::bail!("not found");
+            }
+            Ok(PathBuf::from("artifact"))
+        }
+    }
+
+    fn resolve(
+        _name: &str,
+        requirements: TestArtifactRequirements,
+    ) -> anyhow::Result<TestArtifacts> {
+        requirements.resolve(Resolver)
+    }
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    fn test<A: ArtifactId + Copy + Send>(
+        leaf_name: &'static str,
+        artifact: ArtifactHandle<A>,
+    ) -> Test {
+        let test = TestCase::new(SimpleTest::new(
+            leaf_name,
+            move |resolver: &ArtifactResolver<'_>| {
+                resolver.require(artifact);
+                Some(())
+            },
+            |_, ()| {
+                RAN.store(true, Ordering::SeqCst);
+                anyhow::Ok(())
+            },
+        ));
+        let mut requirements = test.0.requirements().unwrap();
+        requirements.require(petri_artifacts_common::artifacts::TEST_LOG_DIRECTORY);
+        Test {
+            module: "petri::test",
+            test,
+            requirements,
+        }
+    }
+
+    #[test]
+    fn missing_artifact_reported_up_front() {
+        let tests = [
+            test("present", artifacts::PRESENT),
+            test("missing", artifacts::MISSING),
+        ];
+        let args = libtest_mimic::Arguments::default();
+
+        let err = check_artifacts(&args, &tests, resolve).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("test::missing"), "{msg}");
+        assert!(!msg.contains("test::present"), "{msg}");
+        assert!(!RAN.load(Ordering::SeqCst));
+
+        // Tests that are filtered out are not checked.
+        let args = libtest_mimic::Arguments {
+            filter: Some("present".into()),
+            ..Default::default()
+        };
+        check_artifacts(&args, &tests, resolve).unwrap();
+
+        // Only ignored tests are run with `--ignored`, and none are ignored.
+        let args = libtest_mimic::Arguments {
+            ignored: true,
+            ..Default::default()
+        };
+        check_artifacts(&args, &tests, resolve).unwrap();
+        let args = libtest_mimic::Arguments {
+            include_ignored: true,
+            ..Default::default()
+        };
+        check_artifacts(&args, &tests, resolve).unwrap_err();
+    }
+}
@@ -13,6 +13,7 @@
 use kmsg::KmsgParsedEntry;
 use parking_lot::Mutex;
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::io::Write as _;
 use std::path::Path;
 use std::path::PathBuf;
@@ -54,9 +55,10 @@ pub fn log_file(&self, name: &str) -> anyhow::Result<PetriLogFile> {
                 // cross-OS .txt file accesses extremely slow.
                 path.set_extension("log");
                 let file = File::create(&path)?;
-                // Write the path to the file in junit attachment syntax to
-                // stdout to ensure the file is attached to the test result.
-                println!("[[ATTACHMENT|{}]]", path.display());
+                // Attach the file to the test result, so that logs captured
+                // from external sources (e.g. guest serial ports) are
+                // published with it.
+                self.trace_attachment(&path);
                 vacant_entry
                     .insert(PetriLogFile(Arc::new(LogFileInner {
                         file,
@@ -240,6 +242,18 @@ macro_rules! log {
     };
 }
 
+fn new_log_source(root_path: &Path) -> anyhow::Result<PetriLogSource> {
+    // Canonicalize so that printed attachment paths are most likely to work.
+    let root_path = root_path.fs_err_canonicalize()?;
+    let jsonl = File::create(root_path.join("petri.jsonl"))?;
+    Ok(PetriLogSource(Arc::new(LogSourceInner {
+        json_log: JsonLog(Arc::new(jsonl)),
+        root_path,
+        log_files: Default::default(),
+        attachments: Default::default(),
+    })))
+}
+
 /// Initialize Petri tracing with the given output path for log files.
 ///
 /// Events go to three places:
@@ -256,16 +270,7 @@ pub fn try_init_tracing(root_path: &Path) -> anyhow::Result<PetriLogSource> {
             Targets::new().with_default(LevelFilter::DEBUG)
         };
 
-    // Canonicalize so that printed attachment paths are most likely to work.
-    let root_path = root_path.fs_err_canonicalize()?;
-    let jsonl = File::create(root_path.join("petri.jsonl"))?;
-    let logger = PetriLogSource(Arc::new(LogSourceInner {
-        json_log: JsonLog(Arc::new(jsonl)),
-        root_path,
-        log_files: Default::default(),
-        attachments: Default::default(),
-    }));
-
+    let logger = new_log_source(root_path)?;
     let petri_log = logger.log_file("petri")?;
 
     tracing_subscriber::fmt()
@@ -308,6 +313,17 @@ fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
 pub async fn log_stream(
     log_file: PetriLogFile,
     reader: impl AsyncRead + Unpin + Send + 'static,
+) -> anyhow::Result<()> {
+    guest_log_stream(log_file, None, reader).await
+}
+
+/// Logs lines from a guest console `reader` into `log_file`, additionally
+/// writing any guest panic output to a dedicated file if `panic_capture` is
+/// provided.
+pub async fn guest_log_stream(
+    log_file: PetriLogFile,
+    mut panic_capture: Option<GuestPanicCapture>,
+    reader: impl AsyncRead + Unpin + Send + 'static,
 ) -> anyhow::Result<()> {
     let mut buf = Vec::new();
     let mut reader = BufReader::new(reader);
@@ -321,10 +337,65 @@ pub async fn log_stream(
         let string_buf = String::from_utf8_lossy(&buf);
         let string_buf_trimmed = string_buf.trim_end();
         log_file.write_entry(string_buf_trimmed);
+        if let Some(panic_capture) = &mut panic_capture {
+            panic_capture.observe(string_buf_trimmed)?;
+        }
     }
     Ok(())
 }
 
+/// Watches guest console output for a kernel panic, and writes the panic
+/// output to `guest_panic.log` in a dedicated directory.
+pub struct GuestPanicCapture {
+    dir: PathBuf,
+    recent: VecDeque<String>,
+    file: Option<File>,
+}
+
+impl GuestPanicCapture {
+    /// Console output that indicates the guest has panicked.
+    const MARKERS: &[&str] = &["Kernel panic - not syncing", "sysrq: Trigger a crash"];
+    /// The number of lines preceding the panic to include for context.
+    const CONTEXT_LINES: usize = 32;
+    /// The name of the file the panic output is written to.
+    pub const FILE_NAME: &str = "guest_panic.log";
+
+    /// Creates a new capture that writes panic output to `dir`. The directory
+    /// is only created if a panic is observed.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            recent: VecDeque::new(),
+            file: None,
+        }
+    }
+
+    fn observe(&mut self, line: &str) -> anyhow::Result<()> {
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{line}")?;
+            return Ok(());
+        }
+
+        if Self::MARKERS.iter().any(|m| line.contains(m)) {
+            fs_err::create_dir_all(&self.dir)?;
+            let path = self.dir.join(Self::FILE_NAME);
+            tracing::error!(path = %path.display(), "guest panic detected");
+            let mut file = File::create(&path)?;
+            for recent in self.recent.drain(..) {
+                writeln!(file, "{recent}")?;
+            }
+            writeln!(file, "{line}")?;
+            self.file = Some(file);
+        } else {
+            if self.recent.len() == Self::CONTEXT_LINES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(line.to_owned());
+        }
+        Ok(())
+    }
+}
+
 /// Maps kernel log levels to tracing levels.
 fn kernel_level_to_tracing_level(kernel_level: u8) -> Level {
     match kernel_level {
@@ -384,4 +455,47 @@ fn test_kernel_level_to_tracing_level() {
         assert_eq!(kernel_level_to_tracing_level(8), Level::INFO);
         assert_eq!(kernel_level_to_tracing_level(255), Level::INFO);
     }
+
+    #[test]
+    fn test_guest_panic_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        let panic_dir = dir.path().join("panic");
+        let mut capture = GuestPanicCapture::new(&panic_dir);
+
+        capture.observe("booting").unwrap();
+        assert!(!panic_dir.exists());
+
+        capture.observe("sysrq: Trigger a crash").unwrap();
+        capture
+            .observe("Kernel panic - not syncing: sysrq triggered crash")
+            .unwrap();
+        capture.observe("CPU: 0 PID: 1").unwrap();
+
+        let output = fs_err::read_to_string(panic_dir.join(GuestPanicCapture::FILE_NAME)).unwrap();
+        assert_eq!(
+            output,
+            "booting\nsysrq: Trigger a crash\nKernel panic - not syncing: sysrq triggered crash\nCPU: 0 PID: 1\n"
+        );
+    }
+
+    #[test]
+    fn serial_log_is_attached() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = new_log_source(dir.path()).unwrap();
+        logger
+            .log_file("uefi")
+            .unwrap()
+            .write_entry("serial output");
+
+        let serial_log = fs_err::read_to_string(dir.path().join("uefi.log")).unwrap();
+        assert_eq!(serial_log, "serial output\n");
+
+        let json_log = fs_err::read_to_string(dir.path().join("petri.jsonl")).unwrap();
+        let attachments = json_log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter_map(|entry| entry["attachment"].as_str().map(str::to_owned))
+            .collect::<Vec<_>>();
+        assert_eq!(attachments, ["uefi.log"]);
+    }
 }
@@ -0,0 +1,121 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracking of how long each phase of a guest boot took.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Environment variable that, when set, turns boot budget failures into
+/// warnings, for environments too noisy for timing assertions.
+pub const BOOT_BUDGET_WARN_ONLY_ENV: &str = "PETRI_BOOT_BUDGET_WARN_ONLY";
+
+/// The times at which a VM reached each boot milestone, measured from when
+/// the VM was started.
+#[derive(Debug, Clone)]
+pub struct BootTimeline {
+    start: Instant,
+    milestones: Vec<(&'static str, Instant)>,
+}
+
+impl BootTimeline {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            milestones: Vec::new(),
+        }
+    }
+
+    /// Records that the milestone `name` was reached now. Only the first
+    /// time each milestone is reached is kept, so later reboots do not
+    /// extend the timeline.
+    pub(crate) fn record(&mut self, name: &'static str) {
+        self.record_at(name, Instant::now());
+    }
+
+    fn record_at(&mut self, name: &'static str, time: Instant) {
+        if !self.milestones.iter().any(|&(n, _)| n == name) {
+            self.milestones.push((name, time));
+        }
+    }
+
+    /// Returns the duration of each phase, ending at the named milestone.
+    pub fn phases(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        let mut last = self.start;
+        self.milestones.iter().map(move |&(name, time)| {
+            let phase = time.saturating_duration_since(last);
+            last = time;
+            (name, phase)
+        })
+    }
+
+    /// Returns the time from starting the VM to the last recorded milestone.
+    pub fn total(&self) -> Option<Duration> {
+        self.milestones
+            .last()
+            .map(|&(_, time)| time.saturating_duration_since(self.start))
+    }
+
+    /// Fails if the boot took longer than `budget`, or just warns if
+    /// `warn_only` is set.
+    pub(crate) fn check_budget(&self, budget: Duration, warn_only: bool) -> anyhow::Result<()> {
+        let Some(total) = self.total() else {
+            anyhow::bail!("no boot milestones recorded, has the VM booted?");
+        };
+        if total <= budget {
+            return Ok(());
+        }
+
+        let mut breakdown = String::new();
+        for (name, phase) in self.phases() {
+            writeln!(breakdown, "  {name}: {phase:.2?}").unwrap();
+        }
+        let msg = format!("boot took {total:.2?}, over the budget of {budget:.2?}:\n{breakdown}");
+        if warn_only {
+            tracing::warn!("{msg}");
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(msg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline() -> BootTimeline {
+        let mut timeline = BootTimeline::new();
+        let start = timeline.start;
+        timeline.record_at("boot event", start + Duration::from_secs(3));
+        timeline.record_at("pipette", start + Duration::from_secs(5));
+        // Later milestones of the same name are ignored.
+        timeline.record_at("pipette", start + Duration::from_secs(60));
+        timeline
+    }
+
+    #[test]
+    fn boot_under_budget() {
+        let timeline = timeline();
+        assert_eq!(timeline.total(), Some(Duration::from_secs(5)));
+        timeline
+            .check_budget(Duration::from_secs(600), false)
+            .unwrap();
+    }
+
+    #[test]
+    fn boot_over_budget() {
+        let timeline = timeline();
+        let err = timeline
+            .check_budget(Duration::from_millis(1), false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("boot event: 3.00s"), "{err}");
+        assert!(err.contains("pipette: 2.00s"), "{err}");
+
+        timeline
+            .check_budget(Duration::from_millis(1), true)
+            .unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Detection of unclean guest shutdowns from what the guest logs on the
+//! following boot.
+
+use anyhow::Context;
+
+/// Kernel log messages emitted when mounting a filesystem that was not
+/// cleanly unmounted.
+const LINUX_RECOVERY_MESSAGES: &[&str] = &[
+    // ext4 journal replay
+    "recovery complete",
+    "orphan cleanup",
+    // xfs log replay
+    "Starting recovery",
+    // fat
+    "was not properly unmounted",
+];
+
+/// Returns the lines of the Linux kernel log `dmesg` that indicate the
+/// filesystem had to be recovered at boot.
+pub(crate) fn linux_recovery_lines(dmesg: &str) -> Vec<&str> {
+    dmesg
+        .lines()
+        .filter(|line| LINUX_RECOVERY_MESSAGES.iter().any(|m| line.contains(m)))
+        .map(str::trim)
+        .collect()
+}
+
+/// Windows System log events, by ID and provider, logged at boot when the
+/// previous shutdown was unexpected.
+const WINDOWS_UNCLEAN_SHUTDOWN_EVENTS: &[(u32, &str)] = &[
+    // "The system has rebooted without cleanly shutting down first."
+    (41, "Microsoft-Windows-Kernel-Power"),
+    // "The previous system shutdown ... was unexpected."
+    (6008, "EventLog"),
+];
+
+/// Lists the System log events since boot that may indicate an unclean
+/// shutdown, one per line, in the format read by
+/// [`windows_unclean_shutdown_events`].
+pub(crate) const WINDOWS_SHUTDOWN_EVENTS_SCRIPT: &str = "\
+    $boot = (Get-CimInstance Win32_OperatingSystem).LastBootUpTime; \
+    Get-WinEvent -FilterHashtable @{ LogName = 'System'; Id = 41, 6008; StartTime = $boot } -ErrorAction SilentlyContinue | \
+    ForEach-Object { '{0} {1}: {2}' -f $_.Id, $_.ProviderName, ($_.Message -split [char]10)[0].Trim() }";
+
+/// Returns the lines of the output of [`WINDOWS_SHUTDOWN_EVENTS_SCRIPT`] that
+/// report an unclean shutdown.
+pub(crate) fn windows_unclean_shutdown_events(output: &str) -> anyhow::Result<Vec<&str>> {
+    let mut events = Vec::new();
+    for line in output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (id, provider) = line
+            .split_once(':')
+            .and_then(|(event, _)| event.split_once(' '))
+            .and_then(|(id, provider)| Some((id.parse::<u32>().ok()?, provider)))
+            .with_context(|| format!("unexpected event output: {line}"))?;
+        if WINDOWS_UNCLEAN_SHUTDOWN_EVENTS.contains(&(id, provider)) {
+            events.push(line);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::linux_recovery_lines;
+    use super::windows_unclean_shutdown_events;
+
+    #[test]
+    fn clean_boot() {
+        let dmesg = "[    1.234] EXT4-fs (sda1): mounted filesystem with ordered data mode.\n\
+                     [    1.456] EXT4-fs (sda1): re-mounted. Opts: discard,errors=remount-ro\n";
+        assert!(linux_recovery_lines(dmesg).is_empty());
+        assert!(windows_unclean_shutdown_events("").unwrap().is_empty());
+        // Other providers also log events with ID 41, so the provider must
+        // match too.
+        assert!(
+            windows_unclean_shutdown_events("41 Microsoft-Windows-Other: Something else.\r\n")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn unclean_boot() {
+        let dmesg = "[    1.100] EXT4-fs (sda1): INFO: recovery required on readonly filesystem\n\
+                     [    1.200] EXT4-fs (sda1): recovery complete\n\
+                     [    1.234] EXT4-fs (sda1): mounted filesystem with ordered data mode.\n";
+        assert_eq!(
+            linux_recovery_lines(dmesg),
+            ["[    1.200] EXT4-fs (sda1): recovery complete"]
+        );
+        let events = "41 Microsoft-Windows-Kernel-Power: The system has rebooted without cleanly shutting down first.\r\n\
+                      6008 EventLog: The previous system shutdown at 1:23:45 AM on 1/2/2025 was unexpected.\r\n";
+        assert_eq!(
+            windows_unclean_shutdown_events(events).unwrap(),
+            [
+                "41 Microsoft-Windows-Kernel-Power: The system has rebooted without cleanly shutting down first.",
+                "6008 EventLog: The previous system shutdown at 1:23:45 AM on 1/2/2025 was unexpected."
+            ]
+        );
+        windows_unclean_shutdown_events("Access is denied.").unwrap_err();
+    }
+}
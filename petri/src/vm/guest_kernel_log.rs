@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Snapshots of a Linux guest's kernel log, for checking that an operation
+//! such as servicing did not disrupt the guest.
+
+use std::collections::HashSet;
+
+/// The most severe log level that is not an error (`KERN_WARNING`). Levels
+/// below this (`KERN_EMERG` through `KERN_ERR`) are errors.
+const KERN_WARNING: u8 = 4;
+
+/// A snapshot of a Linux guest's kernel log, as captured by
+/// [`PetriVm::capture_guest_kernel_log`](super::PetriVm::capture_guest_kernel_log).
+#[derive(Debug, Clone)]
+pub struct GuestKernelLog {
+    /// The lines of `dmesg -r`, each prefixed with its `<priority>`.
+    lines: Vec<String>,
+}
+
+impl GuestKernelLog {
+    /// Parses the raw output of `dmesg -r`.
+    pub(crate) fn from_raw(raw: &str) -> Self {
+        Self {
+            lines: raw.lines().map(|line| line.trim_end().to_owned()).collect(),
+        }
+    }
+
+    /// Returns the error-level lines in this log that are not in `earlier`,
+    /// without their priority prefixes.
+    pub fn new_errors_since(&self, earlier: &GuestKernelLog) -> Vec<&str> {
+        let earlier = earlier.lines.iter().collect::<HashSet<_>>();
+        self.lines
+            .iter()
+            .filter(|line| !earlier.contains(line))
+            .filter_map(|line| {
+                let (level, message) = parse_line(line)?;
+                (level < KERN_WARNING).then_some(message)
+            })
+            .collect()
+    }
+
+    /// Fails with the new error-level lines if there are any in this log
+    /// that are not in `earlier`.
+    pub fn assert_no_new_errors_since(&self, earlier: &GuestKernelLog) -> anyhow::Result<()> {
+        let errors = self.new_errors_since(earlier);
+        if !errors.is_empty() {
+            anyhow::bail!("new errors in guest kernel log:\n{}", errors.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+/// Splits a `dmesg -r` line into its log level and message. The priority
+/// also encodes the facility, which is ignored.
+fn parse_line(line: &str) -> Option<(u8, &str)> {
+    let (priority, message) = line.strip_prefix('<')?.split_once('>')?;
+    let priority: u32 = priority.parse().ok()?;
+    Some(((priority & 7) as u8, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestKernelLog;
+
+    const BEFORE: &str = "<6>[    0.000000] Linux version 6.8.0\n\
+                          <3>[    1.000000] ACPI Error: boot-time error\n";
+
+    #[test]
+    fn no_new_errors() {
+        let before = GuestKernelLog::from_raw(BEFORE);
+        let after = GuestKernelLog::from_raw(&format!(
+            "{BEFORE}<4>[   10.000000] hv_vmbus: warning\n<6>[   11.000000] hv_vmbus: info\n"
+        ));
+        assert!(after.new_errors_since(&before).is_empty());
+        after.assert_no_new_errors_since(&before).unwrap();
+    }
+
+    #[test]
+    fn new_errors() {
+        let before = GuestKernelLog::from_raw(BEFORE);
+        let after = GuestKernelLog::from_raw(&format!(
+            "{BEFORE}<3>[   10.000000] hv_storvsc: error\n<12>[   11.000000] user warning\n\
+             <11>[   12.000000] user error\n"
+        ));
+        assert_eq!(
+            after.new_errors_since(&before),
+            [
+                "[   10.000000] hv_storvsc: error",
+                "[   12.000000] user error"
+            ]
+        );
+        after.assert_no_new_errors_since(&before).unwrap_err();
+    }
+}
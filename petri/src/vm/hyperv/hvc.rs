@@ -27,8 +27,20 @@ pub fn hvc_reset(vmid: &Guid) -> Result<(), CommandError> {
     hvc_output(|cmd| cmd.arg("reset").arg(vmid.to_string())).map(|_| ())
 }
 
+pub fn hvc_save(vmid: &Guid) -> Result<(), CommandError> {
+    hvc_output(|cmd| cmd.arg("save").arg(vmid.to_string())).map(|_| ())
+}
+
+pub fn hvc_pause(vmid: &Guid) -> Result<(), CommandError> {
+    hvc_output(|cmd| cmd.arg("pause").arg(vmid.to_string())).map(|_| ())
+}
+
+pub fn hvc_resume(vmid: &Guid) -> Result<(), CommandError> {
+    hvc_output(|cmd| cmd.arg("resume").arg(vmid.to_string())).map(|_| ())
+}
+
 /// HyperV VM state as reported by hvc
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum VmState {
     /// The VM is powered off.
     Off,
@@ -50,27 +62,33 @@ pub enum VmState {
     Pausing,
     /// The VM is resuming.
     Resuming,
+    /// A state not known to petri, such as a new transient state.
+    Unknown(String),
 }
 
 pub fn hvc_state(vmid: &Guid) -> anyhow::Result<VmState> {
-    Ok(
-        match hvc_output(|cmd| cmd.arg("state").arg(vmid.to_string()))
-            .context("hvc_state")?
-            .as_str()
-        {
-            "off" => VmState::Off,
-            "running" => VmState::Running,
-            "starting" => VmState::Starting,
-            "stopping" => VmState::Stopping,
-            "saved" => VmState::Saved,
-            "paused" => VmState::Paused,
-            "resetting" => VmState::Resetting,
-            "saving" => VmState::Saving,
-            "pausing" => VmState::Pausing,
-            "resuming" => VmState::Resuming,
-            s => anyhow::bail!("unknown vm state: {s}"),
-        },
-    )
+    Ok(parse_vm_state(
+        &hvc_output(|cmd| cmd.arg("state").arg(vmid.to_string())).context("hvc_state")?,
+    ))
+}
+
+fn parse_vm_state(state: &str) -> VmState {
+    match state {
+        "off" => VmState::Off,
+        "running" => VmState::Running,
+        "starting" => VmState::Starting,
+        "stopping" => VmState::Stopping,
+        "saved" => VmState::Saved,
+        "paused" => VmState::Paused,
+        "resetting" => VmState::Resetting,
+        "saving" => VmState::Saving,
+        "pausing" => VmState::Pausing,
+        "resuming" => VmState::Resuming,
+        s => {
+            tracing::warn!(state = s, "unknown vm state");
+            VmState::Unknown(s.to_owned())
+        }
+    }
 }
 
 pub fn hvc_ensure_off(vmid: &Guid) -> anyhow::Result<()> {
@@ -96,3 +114,17 @@ fn hvc_output(
 
     super::vm::run_cmd(cmd)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_state_is_preserved() {
+        assert_eq!(parse_vm_state("running"), VmState::Running);
+        assert_eq!(
+            parse_vm_state("fast-saving"),
+            VmState::Unknown("fast-saving".into())
+        );
+    }
+}
@@ -4,14 +4,17 @@
 mod hvc;
 pub mod powershell;
 pub mod vm;
+pub use hvc::VmState;
 use vmsocket::VmAddress;
 use vmsocket::VmSocket;
 
-use super::ProcessorTopology;
+use crate::BackendCapabilities;
+use crate::ControllerType;
 use crate::Firmware;
 use crate::IsolationType;
 use crate::OpenHclConfig;
 use crate::OpenHclServicingFlags;
+use crate::PetriVmBuilder;
 use crate::PetriVmConfig;
 use crate::PetriVmResources;
 use crate::PetriVmRuntime;
@@ -21,6 +24,7 @@
 use crate::UefiConfig;
 use crate::hyperv::powershell::HyperVSecureBootTemplate;
 use crate::openhcl_diag::OpenHclDiagHandler;
+use crate::vm::EXTRA_IDE_DISK_LOCATIONS;
 use crate::vm::append_cmdline;
 use anyhow::Context;
 use async_trait::async_trait;
@@ -41,12 +45,21 @@
 use std::fs;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::time::Duration;
 use vm::HyperVVM;
+use vm::Watchdog;
 use vmm_core_defs::HaltReason;
 
 /// The Hyper-V Petri backend
-pub struct HyperVPetriBackend {}
+pub struct HyperVPetriBackend {
+    keep_default_devices: bool,
+    vm_group: Option<String>,
+    existing_vm: Option<String>,
+    nic_switch: Option<String>,
+    watchdog: Option<Watchdog>,
+    merge_disks: bool,
+}
 
 /// Resources needed at runtime for a Hyper-V Petri VM
 pub struct HyperVPetriRuntime {
@@ -55,6 +68,93 @@ pub struct HyperVPetriRuntime {
     temp_dir: tempfile::TempDir,
     openhcl_diag_handler: Option<OpenHclDiagHandler>,
     driver: DefaultDriver,
+    guest_disks: Vec<GuestDiffDisk>,
+    disk_resets: usize,
+    /// Where to save the merged guest disks at teardown, if requested.
+    merged_disks_dir: Option<PathBuf>,
+}
+
+/// A differencing disk layered over a guest VHD artifact.
+struct GuestDiffDisk {
+    parent: PathBuf,
+    path: PathBuf,
+    controller_type: powershell::ControllerType,
+    controller_number: u32,
+    controller_location: u32,
+}
+
+impl GuestDiffDisk {
+    /// Merges the differencing disk into a copy of its parent in `dir`, and
+    /// returns the path to the copy. The parent itself is never modified,
+    /// since it is shared with other tests.
+    fn merge_into(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let path = dir.join(self.merged_file_name()?);
+        fs_err::copy(&self.parent, &path)?;
+        // Guest VHD artifacts may be read-only, and the copy inherits that.
+        let mut permissions = fs_err::metadata(&path)?.permissions();
+        #[expect(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs_err::set_permissions(&path, permissions)?;
+        // The copy has the same ID as the parent, so the differencing disk can
+        // be pointed at it and then merged into it.
+        powershell::run_set_vhd_parent(&self.path, &path)?;
+        powershell::run_merge_vhd(&self.path, &path)?;
+        Ok(path)
+    }
+
+    fn merged_file_name(&self) -> anyhow::Result<String> {
+        Ok(format!(
+            "merged_{}_{}_{}",
+            self.controller_number,
+            self.controller_location,
+            self.parent
+                .file_name()
+                .context("path has no filename")?
+                .to_string_lossy()
+        ))
+    }
+}
+
+/// Returns the isolation types the host supports for Hyper-V VMs. VBS only
+/// needs the hypervisor, while SNP and TDX need processor support.
+fn host_isolation() -> Vec<IsolationType> {
+    std::iter::once(IsolationType::Vbs)
+        .chain(hardware_isolation())
+        .collect()
+}
+
+/// Returns the hardware isolation type the host's processor supports: SNP
+/// for an AMD processor with SEV-SNP, and TDX for an Intel processor.
+///
+/// The firmware can still leave SNP or TDX disabled, in which case starting
+/// such a VM fails.
+#[cfg(target_arch = "x86_64")]
+fn hardware_isolation() -> Option<IsolationType> {
+    use x86defs::cpuid::CpuidFunction;
+    use x86defs::cpuid::ExtendedSevFeaturesEax;
+    use x86defs::cpuid::Vendor;
+
+    let result = safe_intrinsics::cpuid(CpuidFunction::VendorAndMaxFunction.0, 0);
+    let vendor = Vendor::from_ebx_ecx_edx(result.ebx, result.ecx, result.edx);
+    if vendor.is_amd_compatible() {
+        let max_extended = safe_intrinsics::cpuid(CpuidFunction::ExtendedMaxFunction.0, 0).eax;
+        let sev = (max_extended >= CpuidFunction::ExtendedSevFeatures.0).then(|| {
+            ExtendedSevFeaturesEax::from(
+                safe_intrinsics::cpuid(CpuidFunction::ExtendedSevFeatures.0, 0).eax,
+            )
+        });
+        sev.is_some_and(|sev| sev.sev_snp())
+            .then_some(IsolationType::Snp)
+    } else if vendor.is_intel_compatible() {
+        Some(IsolationType::Tdx)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn hardware_isolation() -> Option<IsolationType> {
+    None
 }
 
 #[async_trait]
@@ -63,13 +163,32 @@ impl PetriVmmBackend for HyperVPetriBackend {
     type VmRuntime = HyperVPetriRuntime;
 
     fn check_compat(firmware: &Firmware, arch: MachineArch) -> bool {
-        arch == MachineArch::host()
+        crate::host_supports_guest_arch(arch)
             && !firmware.is_linux_direct()
             && !(firmware.is_pcat() && arch == MachineArch::Aarch64)
+            && !firmware.is_vtl2_nvme_boot()
+    }
+
+    fn capabilities() -> BackendCapabilities {
+        BackendCapabilities {
+            isolation: host_isolation(),
+            linux_direct_boot: false,
+            vtl2_nvme_boot: false,
+            dynamic_memory: false,
+            modify_vmm_config: false,
+            inspect: false,
+        }
     }
 
     fn new(_resolver: &ArtifactResolver<'_>) -> Self {
-        HyperVPetriBackend {}
+        HyperVPetriBackend {
+            keep_default_devices: false,
+            vm_group: None,
+            existing_vm: None,
+            nic_switch: None,
+            watchdog: None,
+            merge_disks: false,
+        }
     }
 
     async fn run(
@@ -91,16 +210,51 @@ async fn run(
             agent_image,
             openhcl_agent_image,
             vmgs: _, // TODO
+            guest_crash_dump_dir,
+            guest_credentials: _,
+            disks,
         } = &config;
 
         let PetriVmResources {
             driver,
-            output_dir: _,
+            output_dir,
             log_source,
         } = resources;
 
+        if memory.dynamic_memory_range.is_some() {
+            anyhow::bail!("dynamic memory not supported in petri's Hyper-V backend");
+        }
+
         let temp_dir = tempfile::tempdir()?;
 
+        let watchdog = match self.watchdog {
+            Some(watchdog) => watchdog,
+            None => Watchdog::from_env()?,
+        };
+
+        if let Some(existing_vm) = &self.existing_vm {
+            let mut vm = HyperVVM::attach(
+                existing_vm,
+                log_source.log_file("hyperv")?,
+                firmware.expected_boot_event(),
+                driver.clone(),
+            )?;
+            vm.set_watchdog(watchdog);
+            if vm.state()? == VmState::Off {
+                vm.start()?;
+            }
+            return Ok(HyperVPetriRuntime {
+                vm,
+                log_tasks: Vec::new(),
+                temp_dir,
+                openhcl_diag_handler: None,
+                driver: driver.clone(),
+                guest_disks: Vec::new(),
+                disk_resets: 0,
+                merged_disks_dir: None,
+            });
+        }
+
         let (
             guest_state_isolation_type,
             generation,
@@ -166,49 +320,61 @@ async fn run(
             ),
         };
 
-        let vhd_paths = guest_artifact
-            .map(|artifact| vec![vec![artifact.get()]])
+        // The disks to attach, with the IDE controller number and location
+        // of each IDE disk. Each SCSI disk gets a new SCSI controller.
+        let mut vhd_paths = guest_artifact
+            .map(|artifact| {
+                let ide_location = match generation {
+                    powershell::HyperVGeneration::One => Some((0, 0)),
+                    powershell::HyperVGeneration::Two => None,
+                };
+                vec![(artifact.get(), ide_location)]
+            })
             .unwrap_or_default();
+        let mut ide_locations = EXTRA_IDE_DISK_LOCATIONS
+            .into_iter()
+            .map(|(channel, drive)| (channel.into(), drive.into()));
+        for (path, controller) in disks {
+            let ide_location = match controller {
+                ControllerType::Scsi => None,
+                ControllerType::Ide => {
+                    if generation != powershell::HyperVGeneration::One {
+                        anyhow::bail!("IDE disks are only supported for generation 1 VMs");
+                    }
+                    Some(
+                        ide_locations
+                            .next()
+                            .context("at most two IDE disks can be added")?,
+                    )
+                }
+            };
+            vhd_paths.push((path.as_path(), ide_location));
+        }
 
         let mut log_tasks = Vec::new();
+        let mut guest_disks = Vec::new();
 
         let mut vm = HyperVVM::new(
-            name,
-            generation,
-            guest_state_isolation_type,
-            memory.startup_bytes,
+            vm::InitialVmConfig {
+                name,
+                arch: *arch,
+                generation,
+                guest_state_isolation_type,
+                memory: memory.startup_bytes,
+                keep_default_devices: self.keep_default_devices,
+                group: self.vm_group.as_deref(),
+            },
             log_source.log_file("hyperv")?,
             firmware.expected_boot_event(),
             driver.clone(),
-        )?;
+        )
+        .await?;
 
-        {
-            let ProcessorTopology {
-                vp_count,
-                vps_per_socket,
-                enable_smt,
-                apic_mode,
-            } = proc_topology;
-            // TODO: fix this mapping, and/or update petri to better match
-            // Hyper-V's capabilities.
-            let apic_mode = apic_mode
-                .map(|m| match m {
-                    super::ApicMode::Xapic => powershell::HyperVApicMode::Legacy,
-                    super::ApicMode::X2apicSupported => powershell::HyperVApicMode::X2Apic,
-                    super::ApicMode::X2apicEnabled => powershell::HyperVApicMode::X2Apic,
-                })
-                .or((*arch == MachineArch::X86_64
-                    && generation == powershell::HyperVGeneration::Two)
-                    .then_some({
-                        // This is necessary for some tests to pass. TODO: fix.
-                        powershell::HyperVApicMode::X2Apic
-                    }));
-            vm.set_processor(&powershell::HyperVSetVMProcessorArgs {
-                count: Some(*vp_count),
-                apic_mode,
-                hw_thread_count_per_core: enable_smt.map(|smt| if smt { 2 } else { 1 }),
-                maximum_count_per_numa_node: *vps_per_socket,
-            })?;
+        vm.set_watchdog(watchdog);
+        vm.set_processor(proc_topology)?;
+
+        if let Some(switch_name) = &self.nic_switch {
+            vm.add_network_adapter(None, Some(switch_name.as_str()))?;
         }
 
         if let Some(UefiConfig {
@@ -237,31 +403,42 @@ async fn run(
             }
         }
 
-        for (i, vhds) in vhd_paths.iter().enumerate() {
-            let (controller_type, controller_number) = match generation {
-                powershell::HyperVGeneration::One => (powershell::ControllerType::Ide, i as u32),
-                powershell::HyperVGeneration::Two => {
-                    (powershell::ControllerType::Scsi, vm.add_scsi_controller(0)?)
-                }
-            };
-            for (controller_location, vhd) in vhds.iter().enumerate() {
-                let diff_disk_path = temp_dir.path().join(format!(
-                    "{}_{}_{}",
+        for (vhd, ide_location) in vhd_paths {
+            let (controller_type, controller_number, controller_location) = match ide_location {
+                Some((controller_number, controller_location)) => (
+                    powershell::ControllerType::Ide,
                     controller_number,
                     controller_location,
-                    vhd.file_name()
-                        .context("path has no filename")?
-                        .to_string_lossy()
-                ));
-
-                powershell::create_child_vhd(&diff_disk_path, vhd)?;
-                vm.add_vhd(
-                    &diff_disk_path,
-                    controller_type,
-                    Some(controller_location as u32),
-                    Some(controller_number),
-                )?;
-            }
+                ),
+                None => (
+                    powershell::ControllerType::Scsi,
+                    vm.add_scsi_controller(0)?,
+                    0,
+                ),
+            };
+            let diff_disk_path = temp_dir.path().join(format!(
+                "{}_{}_{}",
+                controller_number,
+                controller_location,
+                vhd.file_name()
+                    .context("path has no filename")?
+                    .to_string_lossy()
+            ));
+
+            powershell::create_child_vhd(&diff_disk_path, vhd)?;
+            vm.add_vhd(
+                &diff_disk_path,
+                controller_type,
+                Some(controller_location),
+                Some(controller_number),
+            )?;
+            guest_disks.push(GuestDiffDisk {
+                parent: vhd.to_path_buf(),
+                path: diff_disk_path,
+                controller_type,
+                controller_number,
+                controller_location,
+            });
         }
 
         if let Some(agent_image) = agent_image {
@@ -275,13 +452,14 @@ async fn run(
             }
 
             if matches!(firmware.os_flavor(), OsFlavor::Windows) {
-                // Make a file for the IMC hive. It's not guaranteed to be at a fixed
-                // location at runtime.
+                // Make a file for the IMC hive, which also pins the agent
+                // disk's drive letter.
+                let imc_hive_data = agent_image.build_imc_hive()?;
                 let imc_hive = temp_dir.path().join("imc.hiv");
                 {
                     let mut imc_hive_file = fs::File::create_new(&imc_hive)?;
                     imc_hive_file
-                        .write_all(include_bytes!("../../../guest-bootstrap/imc.hiv"))
+                        .write_all(&imc_hive_data)
                         .context("failed to write imc hive")?;
                 }
 
@@ -301,12 +479,20 @@ async fn run(
         let openhcl_diag_handler = if let Some((
             src_igvm_file,
             OpenHclConfig {
-                vtl2_nvme_boot: _, // TODO, see #1649.
+                vtl2_nvme_boot,
                 vmbus_redirect,
                 command_line,
             },
         )) = &openhcl_config
         {
+            // Hyper-V cannot attach a VHD through an emulated NVMe
+            // controller, so booting from NVMe would need a physical device
+            // assigned to VTL2. Fail rather than silently booting from SCSI.
+            // Tracked by #1649.
+            if *vtl2_nvme_boot {
+                anyhow::bail!("vtl2_nvme_boot is not yet supported for HyperV VMs");
+            }
+
             // Copy the IGVM file locally, since it may not be accessible by
             // Hyper-V (e.g., if it is in a WSL filesystem).
             let igvm_file = temp_dir.path().join("igvm.bin");
@@ -378,6 +564,9 @@ async fn run(
 
         let serial_pipe_path = vm.set_vm_com_port(1)?;
         let serial_log_file = log_source.log_file("guest")?;
+        let panic_capture = guest_crash_dump_dir
+            .as_deref()
+            .map(crate::GuestPanicCapture::new);
         log_tasks.push(driver.spawn("guest-log", {
             let driver = driver.clone();
             async move {
@@ -386,7 +575,12 @@ async fn run(
                     diag_client::hyperv::ComPortAccessInfo::PortPipePath(&serial_pipe_path),
                 )
                 .await?;
-                crate::log_stream(serial_log_file, PolledPipe::new(&driver, serial)?).await
+                crate::guest_log_stream(
+                    serial_log_file,
+                    panic_capture,
+                    PolledPipe::new(&driver, serial)?,
+                )
+                .await
             }
         }));
 
@@ -398,22 +592,181 @@ async fn run(
             temp_dir,
             openhcl_diag_handler,
             driver: driver.clone(),
+            guest_disks,
+            disk_resets: 0,
+            merged_disks_dir: self.merge_disks.then(|| output_dir.clone()),
         })
     }
 }
 
+impl PetriVmBuilder<HyperVPetriBackend> {
+    /// Keep the network adapter and SCSI controller that Hyper-V adds to new
+    /// VMs, instead of removing them before configuring the VM.
+    pub fn with_default_devices(mut self) -> Self {
+        self.backend.keep_default_devices = true;
+        self
+    }
+
+    /// Add the VM to the named VM group, creating the group if needed, so
+    /// that leftover VMs can be cleaned up in bulk with
+    /// [`vm::remove_vm_group`].
+    pub fn with_vm_group(mut self, group: impl Into<String>) -> Self {
+        self.backend.vm_group = Some(group.into());
+        self
+    }
+
+    /// Attach to an existing VM, specified by name or VM ID, instead of
+    /// creating one.
+    ///
+    /// The VM is used as it is configured: no disks, devices, or firmware
+    /// settings are added, and the VM is started if it is off. The VM is not
+    /// removed when the test finishes. This is intended for debugging and
+    /// for special setups that petri cannot create itself.
+    pub fn with_existing_vm(mut self, name_or_id: impl Into<String>) -> Self {
+        self.backend.existing_vm = Some(name_or_id.into());
+        self
+    }
+
+    /// Add a network adapter connected to the named virtual switch, which
+    /// must already exist on the host (typically an external switch, so that
+    /// the guest can reach the network).
+    pub fn with_nic(mut self, switch_name: impl Into<String>) -> Self {
+        self.backend.nic_switch = Some(switch_name.into());
+        self
+    }
+
+    /// Set how long to wait for each VM operation (booting, turning off,
+    /// reporting a heartbeat, and so on) to complete and how often to check.
+    ///
+    /// If not set, the timeout defaults to 240 seconds and can be overridden
+    /// with the `PETRI_HYPERV_TIMEOUT_SECS` environment variable.
+    pub fn with_watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.backend.watchdog = Some(watchdog);
+        self
+    }
+
+    /// When the VM is torn down after a successful test, merge each guest
+    /// differencing disk into a copy of its parent VHD and save it to the
+    /// test output directory, so that the resulting disk state can be
+    /// inspected. The shared parent VHDs are never modified.
+    ///
+    /// A test that fails before tearing down the VM drops it instead, so its
+    /// disks are not merged.
+    pub fn with_merged_disks(mut self) -> Self {
+        self.backend.merge_disks = true;
+        self
+    }
+}
+
+impl HyperVPetriRuntime {
+    /// Get the underlying Hyper-V VM
+    pub fn vm(&self) -> &HyperVVM {
+        &self.vm
+    }
+
+    /// Get the paths to the parent VHDs of the guest differencing disks.
+    pub fn guest_disk_parents(&self) -> impl Iterator<Item = &Path> {
+        self.guest_disks.iter().map(|disk| disk.parent.as_path())
+    }
+
+    /// Get the paths to the guest differencing disks attached to the VM,
+    /// each with the path to its parent VHD.
+    pub fn guest_disks(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.guest_disks
+            .iter()
+            .map(|disk| (disk.path.as_path(), disk.parent.as_path()))
+    }
+
+    /// Get the current power state of the VM
+    pub fn power_state(&self) -> anyhow::Result<VmState> {
+        self.vm.state()
+    }
+
+    /// Shut down the VM and start it again, keeping its disks.
+    ///
+    /// If `force` is set, the VM is turned off without notifying the guest,
+    /// simulating a power loss. Otherwise the guest is asked to shut down
+    /// gracefully via the shutdown IC.
+    pub async fn power_cycle(&mut self, force: bool) -> anyhow::Result<()> {
+        if force {
+            self.vm.kill()?;
+        } else {
+            self.vm.stop().await?;
+        }
+        self.vm.wait_for_off().await?;
+
+        // The shutdown was requested by the test, not initiated by the guest.
+        self.vm.clear_halt_events();
+        self.vm.start()
+    }
+
+    /// Turn off the VM, replace each guest differencing disk with a fresh one
+    /// from the same parent, and start the VM again.
+    ///
+    /// This discards all changes the guest has made to its disks, so that a
+    /// test can run several iterations against a pristine disk without
+    /// recreating the VM.
+    pub async fn reset_disk(&mut self) -> anyhow::Result<()> {
+        self.vm.kill()?;
+        self.vm.wait_for_off().await?;
+
+        // Attach a new file rather than recreating the old one in place, so
+        // that Hyper-V grants the VM access to it.
+        self.disk_resets += 1;
+        for disk in &mut self.guest_disks {
+            let file_name = disk.path.file_name().context("path has no filename")?;
+            let new_path = self
+                .temp_dir
+                .path()
+                .join(format!("reset{}", self.disk_resets))
+                .join(file_name);
+            fs_err::create_dir_all(new_path.parent().unwrap())?;
+            powershell::create_child_vhd(&new_path, &disk.parent)?;
+            self.vm.set_vhd(
+                &new_path,
+                disk.controller_type,
+                disk.controller_location,
+                disk.controller_number,
+            )?;
+            fs_err::remove_file(&disk.path)?;
+            disk.path = new_path;
+        }
+
+        // The forced power off is not a guest-initiated halt.
+        self.vm.clear_halt_events();
+        self.vm.start()
+    }
+}
+
 #[async_trait]
 impl PetriVmRuntime for HyperVPetriRuntime {
     async fn teardown(self) -> anyhow::Result<()> {
         for t in self.log_tasks {
             _ = t.cancel();
         }
-        self.vm.remove()
+        // The VM must be removed before its disks can be merged.
+        self.vm.remove()?;
+        // The merged disks are only diagnostics, so a failure to merge one
+        // does not fail the test.
+        if let Some(dir) = &self.merged_disks_dir {
+            for disk in &self.guest_disks {
+                match disk.merge_into(dir) {
+                    Ok(path) => {
+                        tracing::info!(path = %path.display(), "saved merged guest disk")
+                    }
+                    Err(err) => tracing::error!(
+                        disk = %disk.path.display(),
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "failed to merge guest disk"
+                    ),
+                }
+            }
+        }
+        Ok(())
     }
 
     async fn wait_for_halt(&mut self) -> anyhow::Result<HaltReason> {
-        self.vm.wait_for_halt().await?;
-        Ok(HaltReason::PowerOff) // TODO: Get actual halt reason
+        self.vm.wait_for_halt().await
     }
 
     async fn wait_for_agent(&mut self, set_high_vtl: bool) -> anyhow::Result<PipetteClient> {
@@ -448,9 +801,15 @@ async fn wait_for_agent(&mut self, set_high_vtl: bool) -> anyhow::Result<Pipette
                 .await;
         }
 
-        PipetteClient::new(&self.driver, socket, self.temp_dir.path())
+        let client = PipetteClient::new(&self.driver, socket, self.temp_dir.path())
             .await
-            .context("failed to connect to pipette")
+            .context("failed to connect to pipette")?;
+
+        // A fresh agent connection means any earlier guest-initiated restart
+        // has already been observed by the caller.
+        self.vm.clear_halt_events();
+
+        Ok(client)
     }
 
     fn openhcl_diag(&self) -> Option<&OpenHclDiagHandler> {
@@ -465,14 +824,18 @@ async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent> {
         self.vm.wait_for_boot_event().await
     }
 
+    async fn wait_for_heartbeat(&mut self) -> anyhow::Result<()> {
+        self.vm.wait_for_heartbeat().await
+    }
+
     async fn wait_for_enlightened_shutdown_ready(&mut self) -> anyhow::Result<()> {
         self.vm.wait_for_enlightened_shutdown_ready().await
     }
 
     async fn send_enlightened_shutdown(&mut self, kind: ShutdownKind) -> anyhow::Result<()> {
         match kind {
-            ShutdownKind::Shutdown => self.vm.stop()?,
-            ShutdownKind::Reboot => self.vm.restart()?,
+            ShutdownKind::Shutdown => self.vm.stop().await?,
+            ShutdownKind::Reboot => self.vm.restart().await?,
         }
 
         Ok(())
@@ -509,3 +872,21 @@ fn acl_read_for_vm(path: &Path, id: Option<guid::Guid>) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::GuestDiffDisk;
+    use super::powershell::ControllerType;
+
+    #[test]
+    fn merged_file_name() {
+        let disk = GuestDiffDisk {
+            parent: r"C:\images\ubuntu.vhdx".into(),
+            path: r"C:\temp\1_0_ubuntu.vhdx".into(),
+            controller_type: ControllerType::Scsi,
+            controller_number: 1,
+            controller_location: 0,
+        };
+        assert_eq!(disk.merged_file_name().unwrap(), "merged_1_0_ubuntu.vhdx");
+    }
+}
@@ -11,11 +11,14 @@
 use guid::Guid;
 use jiff::Timestamp;
 use powershell_builder as ps;
+use powershell_builder::JsonCommand;
 use powershell_builder::PowerShellBuilder;
 use serde::Deserialize;
 use serde::Serialize;
 use std::ffi::OsStr;
 use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
 use std::str::FromStr;
 
 /// Hyper-V VM Generation
@@ -147,7 +150,145 @@ pub fn run_remove_vm(vmid: &Guid) -> anyhow::Result<()> {
     .context("remove_vm")
 }
 
+/// Creates the VM group `group` if no group with that name exists.
+pub fn run_ensure_vm_group(group: &str) -> anyhow::Result<()> {
+    let count = run_count_cmd(count_objects(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VMGroup")
+            .arg("Name", group)
+            .arg("ErrorAction", ps::RawVal::new("SilentlyContinue"))
+            .pipeline(),
+    ))
+    .context("get_vm_group")?;
+    if count == 0 {
+        run_cmd(new_vm_group_cmd(group))
+            .map(|_| ())
+            .context("new_vm_group")?;
+    }
+    Ok(())
+}
+
+fn new_vm_group_cmd(group: &str) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("New-VMGroup")
+        .arg("Name", group)
+        .arg("GroupType", ps::RawVal::new("VMCollectionType"))
+        .finish()
+        .build()
+}
+
+/// Runs Add-VMGroupMember to add the VM to the VM group `group`.
+pub fn run_add_vm_group_member(vmid: &Guid, group: &str) -> anyhow::Result<()> {
+    run_cmd(add_vm_group_member_cmd(vmid, group))
+        .map(|_| ())
+        .context("add_vm_group_member")
+}
+
+fn add_vm_group_member_cmd(vmid: &Guid, group: &str) -> Command {
+    let vm = ps::Variable::new("vm");
+    PowerShellBuilder::new()
+        .cmdlet_to_var("Get-VM", &vm)
+        .arg("Id", vmid)
+        .next()
+        .cmdlet("Get-VMGroup")
+        .arg("Name", group)
+        .pipeline()
+        .cmdlet("Select-Object")
+        .arg("First", 1u32)
+        .pipeline()
+        .cmdlet("Add-VMGroupMember")
+        .arg("VM", &vm)
+        .finish()
+        .build()
+}
+
+/// Gets the IDs of the VMs in the VM group `group`.
+pub fn vm_group_member_ids(group: &str) -> anyhow::Result<Vec<Guid>> {
+    let output = run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VMGroup")
+            .arg("Name", group)
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "VMMembers")
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "Id")
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "Guid")
+            .finish()
+            .build(),
+    )
+    .context("vm_group_member_ids")?;
+    let mut vmids = Vec::new();
+    for s in output.lines() {
+        vmids.push(Guid::from_str(s)?);
+    }
+    Ok(vmids)
+}
+
+/// Runs Remove-VMGroup to remove all VM groups named `group`. The member
+/// VMs are not removed.
+pub fn run_remove_vm_group(group: &str) -> anyhow::Result<()> {
+    run_cmd(remove_vm_group_cmd(group))
+        .map(|_| ())
+        .context("remove_vm_group")
+}
+
+fn remove_vm_group_cmd(group: &str) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VMGroup")
+        .arg("Name", group)
+        .pipeline()
+        .cmdlet("Remove-VMGroup")
+        .flag("Force")
+        .finish()
+        .build()
+}
+
+/// Runs Remove-VMSwitch to remove all the virtual switches whose names start
+/// with `prefix`.
+pub fn run_remove_vm_switches(prefix: &str) -> anyhow::Result<()> {
+    run_cmd(remove_vm_switches_cmd(prefix))
+        .map(|_| ())
+        .context("remove_vm_switches")
+}
+
+fn remove_vm_switches_cmd(prefix: &str) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VMSwitch")
+        .arg("Name", format!("{prefix}*"))
+        .arg("ErrorAction", "SilentlyContinue")
+        .pipeline()
+        .cmdlet("Remove-VMSwitch")
+        .flag("Force")
+        .finish()
+        .build()
+}
+
+/// Runs Remove-NetNat to remove all the NAT rules whose names start with
+/// `prefix`.
+pub fn run_remove_net_nats(prefix: &str) -> anyhow::Result<()> {
+    run_cmd(remove_net_nats_cmd(prefix))
+        .map(|_| ())
+        .context("remove_net_nats")
+}
+
+fn remove_net_nats_cmd(prefix: &str) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-NetNat")
+        .arg("Name", format!("{prefix}*"))
+        .arg("ErrorAction", "SilentlyContinue")
+        .pipeline()
+        .cmdlet("Remove-NetNat")
+        .flag("Confirm:$false")
+        .finish()
+        .build()
+}
+
 /// Arguments for the Set-VMProcessor powershell cmdlet
+#[derive(Default)]
 pub struct HyperVSetVMProcessorArgs {
     /// Specifies the number of virtual processors to assign to the virtual
     /// machine. If not specified, the number of virtual processors is not
@@ -161,6 +302,9 @@ pub struct HyperVSetVMProcessorArgs {
     /// The maximum number of virtual processors that can be assigned to a
     /// NUMA node.
     pub maximum_count_per_numa_node: Option<u32>,
+    /// Specifies whether the hypervisor should expose the virtualization
+    /// extensions to the virtual machine, allowing nested virtualization.
+    pub expose_virtualization_extensions: Option<bool>,
 }
 
 /// The Hyper-V APIC mode
@@ -187,21 +331,27 @@ fn as_val(&self) -> impl '_ + AsRef<OsStr> {
 
 /// Runs Set-VMProcessor with the given arguments.
 pub fn run_set_vm_processor(vmid: &Guid, args: &HyperVSetVMProcessorArgs) -> anyhow::Result<()> {
-    run_cmd(
-        PowerShellBuilder::new()
-            .cmdlet("Get-VM")
-            .arg("Id", vmid)
-            .pipeline()
-            .cmdlet("Set-VMProcessor")
-            .arg_opt("Count", args.count)
-            .arg_opt("ApicMode", args.apic_mode)
-            .arg_opt("HwThreadCountPerCore", args.hw_thread_count_per_core)
-            .arg_opt("MaximumCountPerNumaNode", args.maximum_count_per_numa_node)
-            .finish()
-            .build(),
-    )
-    .map(|_| ())
-    .context("set_vm_processor")
+    run_cmd(set_vm_processor_cmd(vmid, args))
+        .map(|_| ())
+        .context("set_vm_processor")
+}
+
+fn set_vm_processor_cmd(vmid: &Guid, args: &HyperVSetVMProcessorArgs) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Set-VMProcessor")
+        .arg_opt("Count", args.count)
+        .arg_opt("ApicMode", args.apic_mode)
+        .arg_opt("HwThreadCountPerCore", args.hw_thread_count_per_core)
+        .arg_opt("MaximumCountPerNumaNode", args.maximum_count_per_numa_node)
+        .arg_opt(
+            "ExposeVirtualizationExtensions",
+            args.expose_virtualization_extensions,
+        )
+        .finish()
+        .build()
 }
 
 /// Arguments for the Set-VMMemory powershell cmdlet.
@@ -222,21 +372,112 @@ pub struct HyperVSetVMMemoryArgs {
 
 /// Runs Set-VMMemory with the given arguments.
 pub fn run_set_vm_memory(vmid: &Guid, args: &HyperVSetVMMemoryArgs) -> anyhow::Result<()> {
+    run_cmd(set_vm_memory_cmd(vmid, args))
+        .map(|_| ())
+        .context("set_vm_memory")
+}
+
+fn set_vm_memory_cmd(vmid: &Guid, args: &HyperVSetVMMemoryArgs) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Set-VMMemory")
+        .arg_opt("DynamicMemoryEnabled", args.dynamic_memory_enabled)
+        .arg_opt("MaximumBytes", args.maximum_bytes)
+        .arg_opt("MinimumBytes", args.minimum_bytes)
+        .arg_opt("StartupBytes", args.startup_bytes)
+        .finish()
+        .build()
+}
+
+/// The kind of checkpoint Hyper-V takes of a VM
+#[derive(Clone, Copy, Debug)]
+pub enum HyperVCheckpointType {
+    /// Checkpoints are disabled
+    Disabled,
+    /// Production checkpoints, which use backup technology in the guest,
+    /// falling back to standard checkpoints if that fails
+    Production,
+    /// Production checkpoints, failing instead of falling back to standard
+    /// checkpoints
+    ProductionOnly,
+    /// Standard checkpoints, which capture the full VM state, including
+    /// memory
+    Standard,
+}
+
+impl ps::AsVal for HyperVCheckpointType {
+    fn as_val(&self) -> impl '_ + AsRef<OsStr> {
+        match self {
+            HyperVCheckpointType::Disabled => "Disabled",
+            HyperVCheckpointType::Production => "Production",
+            HyperVCheckpointType::ProductionOnly => "ProductionOnly",
+            HyperVCheckpointType::Standard => "Standard",
+        }
+    }
+}
+
+/// Sets the kind of checkpoint taken of the VM.
+pub fn run_set_vm_checkpoint_type(
+    vmid: &Guid,
+    checkpoint_type: HyperVCheckpointType,
+) -> anyhow::Result<()> {
     run_cmd(
         PowerShellBuilder::new()
             .cmdlet("Get-VM")
             .arg("Id", vmid)
             .pipeline()
-            .cmdlet("Set-VMMemory")
-            .arg_opt("DynamicMemoryEnabled", args.dynamic_memory_enabled)
-            .arg_opt("MaximumBytes", args.maximum_bytes)
-            .arg_opt("MinimumBytes", args.minimum_bytes)
-            .arg_opt("StartupBytes", args.startup_bytes)
+            .cmdlet("Set-VM")
+            .arg("CheckpointType", checkpoint_type)
             .finish()
             .build(),
     )
     .map(|_| ())
-    .context("set_vm_memory")
+    .context("set_vm_checkpoint_type")
+}
+
+/// Runs Checkpoint-VM to take a checkpoint of the VM named `name`.
+pub fn run_checkpoint_vm(vmid: &Guid, name: &str) -> anyhow::Result<()> {
+    run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Checkpoint-VM")
+            .arg("SnapshotName", name)
+            .finish()
+            .build(),
+    )
+    .map(|_| ())
+    .context("checkpoint_vm")
+}
+
+/// Runs Restore-VMSnapshot to restore the VM to the checkpoint named `name`.
+pub fn run_restore_vm_checkpoint(vmid: &Guid, name: &str) -> anyhow::Result<()> {
+    // Get-VMSnapshot does not fail the command when no checkpoint matches,
+    // so count the checkpoints that were restored.
+    let count =
+        run_count_cmd(restore_vm_checkpoint_cmd(vmid, name)).context("restore_vm_checkpoint")?;
+    anyhow::ensure!(count != 0, "no checkpoint named {name}");
+    Ok(())
+}
+
+fn restore_vm_checkpoint_cmd(vmid: &Guid, name: &str) -> Command {
+    count_objects(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Get-VMSnapshot")
+            .arg("Name", name)
+            .arg("ErrorAction", "SilentlyContinue")
+            .pipeline()
+            .cmdlet("Restore-VMSnapshot")
+            .flag("Confirm:$false")
+            .flag("Passthru")
+            .pipeline(),
+    )
 }
 
 /// Arguments for the Add-VMHardDiskDrive powershell cmdlet
@@ -259,6 +500,10 @@ pub struct HyperVAddVMHardDiskDriveArgs<'a> {
     pub controller_number: Option<u32>,
     /// Specifies the full path of the hard disk drive file to be added.
     pub path: Option<&'a Path>,
+    /// Specifies the disk number of the offline physical disk to be
+    /// connected as a pass-through disk. Requires `PETRI_ALLOW_PHYSICAL_DISKS`
+    /// to be set.
+    pub disk_number: Option<u32>,
 }
 
 /// The type of controller to which a hard disk drive is to be added.
@@ -284,21 +529,135 @@ fn as_val(&self) -> impl '_ + AsRef<OsStr> {
 
 /// Runs Add-VMHardDiskDrive with the given arguments.
 pub fn run_add_vm_hard_disk_drive(args: HyperVAddVMHardDiskDriveArgs<'_>) -> anyhow::Result<()> {
+    if let Some(disk_number) = args.disk_number {
+        if !physical_disks_allowed() {
+            anyhow::bail!(
+                "refusing to attach physical disk {disk_number}: set PETRI_ALLOW_PHYSICAL_DISKS=1 to opt in"
+            );
+        }
+        if !disk_is_offline(disk_number)? {
+            anyhow::bail!("physical disk {disk_number} must be offline to be passed through");
+        }
+    }
+
+    run_cmd(add_vm_hard_disk_drive_cmd(&args)?)
+        .map(|_| ())
+        .context("add_vm_hard_disk_drive")
+}
+
+fn add_vm_hard_disk_drive_cmd(args: &HyperVAddVMHardDiskDriveArgs<'_>) -> anyhow::Result<Command> {
+    if args.path.is_some() && args.disk_number.is_some() {
+        anyhow::bail!("only one of path and disk number may be specified");
+    }
+
+    Ok(PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", args.vmid)
+        .pipeline()
+        .cmdlet("Add-VMHardDiskDrive")
+        .arg("ControllerType", args.controller_type)
+        .arg_opt("ControllerLocation", args.controller_location)
+        .arg_opt("ControllerNumber", args.controller_number)
+        .arg_opt("Path", args.path)
+        .arg_opt("DiskNumber", args.disk_number)
+        .finish()
+        .build())
+}
+
+/// Runs Set-VMHardDiskDrive to attach a different VHD to an existing hard
+/// disk drive.
+pub fn run_set_vm_hard_disk_drive_path(
+    vmid: &Guid,
+    controller_type: ControllerType,
+    controller_number: u32,
+    controller_location: u32,
+    path: &Path,
+) -> anyhow::Result<()> {
     run_cmd(
         PowerShellBuilder::new()
             .cmdlet("Get-VM")
-            .arg("Id", args.vmid)
+            .arg("Id", vmid)
             .pipeline()
-            .cmdlet("Add-VMHardDiskDrive")
-            .arg("ControllerType", args.controller_type)
-            .arg_opt("ControllerLocation", args.controller_location)
-            .arg_opt("ControllerNumber", args.controller_number)
-            .arg_opt("Path", args.path)
+            .cmdlet("Get-VMHardDiskDrive")
+            .arg("ControllerType", controller_type)
+            .arg("ControllerNumber", controller_number)
+            .arg("ControllerLocation", controller_location)
+            .pipeline()
+            .cmdlet("Set-VMHardDiskDrive")
+            .arg("Path", path)
             .finish()
             .build(),
     )
     .map(|_| ())
-    .context("add_vm_hard_disk_drive")
+    .context("set_vm_hard_disk_drive_path")
+}
+
+/// Runs Remove-VMHardDiskDrive to remove the hard disk drive at the given
+/// controller location, failing if there is no such drive.
+pub fn run_remove_vm_hard_disk_drive(
+    vmid: &Guid,
+    controller_type: ControllerType,
+    controller_number: u32,
+    controller_location: u32,
+) -> anyhow::Result<()> {
+    // Get-VMHardDiskDrive returns nothing rather than failing when no drive
+    // matches, so count the drives that were removed.
+    let count = run_count_cmd(remove_vm_hard_disk_drive_cmd(
+        vmid,
+        controller_type,
+        controller_number,
+        controller_location,
+    ))
+    .context("remove_vm_hard_disk_drive")?;
+    anyhow::ensure!(
+        count != 0,
+        "no {controller_type:?} hard disk drive at controller {controller_number} location {controller_location}"
+    );
+    Ok(())
+}
+
+fn remove_vm_hard_disk_drive_cmd(
+    vmid: &Guid,
+    controller_type: ControllerType,
+    controller_number: u32,
+    controller_location: u32,
+) -> Command {
+    count_objects(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Get-VMHardDiskDrive")
+            .arg("ControllerType", controller_type)
+            .arg("ControllerNumber", controller_number)
+            .arg("ControllerLocation", controller_location)
+            .pipeline()
+            .cmdlet("Remove-VMHardDiskDrive")
+            .flag("Passthru")
+            .pipeline(),
+    )
+}
+
+fn physical_disks_allowed() -> bool {
+    std::env::var("PETRI_ALLOW_PHYSICAL_DISKS")
+        .ok()
+        .is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Check whether the host physical disk is offline
+fn disk_is_offline(disk_number: u32) -> anyhow::Result<bool> {
+    let output = run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-Disk")
+            .arg("Number", disk_number)
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "IsOffline")
+            .finish()
+            .build(),
+    )
+    .context("disk_is_offline")?;
+    Ok(output == "True")
 }
 
 /// Arguments for the Add-VMDvdDrive powershell cmdlet
@@ -336,6 +695,79 @@ pub fn run_add_vm_dvd_drive(args: HyperVAddVMDvdDriveArgs<'_>) -> anyhow::Result
     .context("add_vm_dvd_drive")
 }
 
+/// Runs Set-VMDvdDrive to insert the given ISO file or physical drive into
+/// the DVD drive at the given controller location, or to eject the current
+/// media if `path` is `None`. Fails if there is no such drive.
+pub fn run_set_vm_dvd_drive(
+    vmid: &Guid,
+    controller_number: u32,
+    controller_location: u32,
+    path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let count = run_count_cmd(set_vm_dvd_drive_cmd(
+        vmid,
+        controller_number,
+        controller_location,
+        path,
+    ))
+    .context("set_vm_dvd_drive")?;
+    anyhow::ensure!(
+        count != 0,
+        "no DVD drive at controller {controller_number} location {controller_location}"
+    );
+    Ok(())
+}
+
+fn set_vm_dvd_drive_cmd(
+    vmid: &Guid,
+    controller_number: u32,
+    controller_location: u32,
+    path: Option<&Path>,
+) -> Command {
+    let builder = PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Get-VMDvdDrive")
+        .arg("ControllerNumber", controller_number)
+        .arg("ControllerLocation", controller_location)
+        .pipeline()
+        .cmdlet("Set-VMDvdDrive");
+    let builder = match path {
+        Some(path) => builder.arg("Path", path),
+        None => builder.arg("Path", ps::RawVal::new("$null")),
+    };
+    count_objects(builder.flag("Passthru").pipeline())
+}
+
+/// Runs Remove-VMDvdDrive to remove the DVD drive at the given controller
+/// location, failing if there is no such drive.
+pub fn run_remove_vm_dvd_drive(
+    vmid: &Guid,
+    controller_number: u32,
+    controller_location: u32,
+) -> anyhow::Result<()> {
+    let count = run_count_cmd(count_objects(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Get-VMDvdDrive")
+            .arg("ControllerNumber", controller_number)
+            .arg("ControllerLocation", controller_location)
+            .pipeline()
+            .cmdlet("Remove-VMDvdDrive")
+            .flag("Passthru")
+            .pipeline(),
+    ))
+    .context("remove_vm_dvd_drive")?;
+    anyhow::ensure!(
+        count != 0,
+        "no DVD drive at controller {controller_number} location {controller_location}"
+    );
+    Ok(())
+}
+
 /// Runs Add-VMScsiController with the given arguments.
 ///
 /// Returns the controller number.
@@ -397,6 +829,149 @@ pub fn create_child_vhd(path: &Path, parent_path: &Path) -> anyhow::Result<()> {
     .context("create_child_vhd")
 }
 
+/// Runs Set-VHD to change the parent of a differencing VHD.
+pub fn run_set_vhd_parent(path: &Path, parent_path: &Path) -> anyhow::Result<()> {
+    run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Set-VHD")
+            .arg("Path", path)
+            .arg("ParentPath", parent_path)
+            .finish()
+            .build(),
+    )
+    .map(|_| ())
+    .context("set_vhd_parent")
+}
+
+/// The type of a virtual hard disk, as reported by Get-VHD.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdType {
+    /// A fixed-size disk, whose file is allocated up front.
+    Fixed,
+    /// A dynamically expanding disk, whose file grows as it is written.
+    Dynamic,
+    /// A differencing disk, which stores changes to a parent disk.
+    Differencing,
+}
+
+/// Metadata about a virtual hard disk, as retrieved by [`run_get_vhd`].
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct VhdInfo {
+    /// The type of the disk.
+    pub vhd_type: VhdType,
+    /// The path of the parent disk, for differencing disks.
+    pub parent_path: Option<PathBuf>,
+    /// The size of the disk file on the host, in bytes.
+    pub file_size: u64,
+    /// The virtual size of the disk, in bytes.
+    pub size: u64,
+}
+
+/// Runs Get-VHD to get metadata about the VHD at `path`.
+pub fn run_get_vhd(path: &Path) -> anyhow::Result<VhdInfo> {
+    let output = run_cmd(get_vhd_cmd(path)).context("get_vhd")?;
+    parse_vhd_info(&output)
+}
+
+fn get_vhd_cmd(path: &Path) -> Command {
+    // ConvertTo-Json outputs enums as integers, so convert the type to its
+    // name first.
+    let props = ps::Array::new([
+        ps::Value::new(ps::HashTable::new([
+            ("label", ps::Value::new("VhdType")),
+            (
+                "expression",
+                ps::Value::new(ps::Script::new(r#""$($_.VhdType)""#)),
+            ),
+        ])),
+        ps::Value::new("ParentPath"),
+        ps::Value::new("FileSize"),
+        ps::Value::new("Size"),
+    ]);
+    PowerShellBuilder::new()
+        .cmdlet("Get-VHD")
+        .arg("Path", path)
+        .pipeline()
+        .cmdlet("Select-Object")
+        .positional(props)
+        .pipeline()
+        .output_json()
+        .build()
+}
+
+fn parse_vhd_info(output: &str) -> anyhow::Result<VhdInfo> {
+    let mut info: VhdInfo = serde_json::from_str(output)
+        .with_context(|| format!("invalid Get-VHD output: {output}"))?;
+    // Get-VHD reports an empty parent path for disks without a parent.
+    info.parent_path = info.parent_path.filter(|path| !path.as_os_str().is_empty());
+    Ok(info)
+}
+
+/// Runs Resize-VHD to change the virtual size of the VHD at `path` to
+/// `size_bytes`.
+///
+/// Hyper-V cannot resize differencing disks, so this fails for them.
+pub fn run_resize_vhd(path: &Path, size_bytes: u64) -> anyhow::Result<()> {
+    resize_vhd(path, Some(size_bytes)).context("resize_vhd")
+}
+
+/// Runs Resize-VHD to shrink the VHD at `path` to its minimum size.
+///
+/// Hyper-V cannot resize differencing disks, so this fails for them.
+pub fn run_resize_vhd_to_minimum_size(path: &Path) -> anyhow::Result<()> {
+    resize_vhd(path, None).context("resize_vhd_to_minimum_size")
+}
+
+fn resize_vhd(path: &Path, size_bytes: Option<u64>) -> anyhow::Result<()> {
+    check_resizable(path, &run_get_vhd(path)?)?;
+    run_cmd(resize_vhd_cmd(path, size_bytes))?;
+    Ok(())
+}
+
+fn check_resizable(path: &Path, info: &VhdInfo) -> anyhow::Result<()> {
+    if info.vhd_type == VhdType::Differencing {
+        anyhow::bail!(
+            "cannot resize differencing disk {}; resize its parent {} instead",
+            path.display(),
+            info.parent_path
+                .as_deref()
+                .unwrap_or(Path::new("<unknown>"))
+                .display()
+        );
+    }
+    Ok(())
+}
+
+/// Builds a Resize-VHD command, resizing to `size_bytes` or, if `None`, to
+/// the minimum size.
+fn resize_vhd_cmd(path: &Path, size_bytes: Option<u64>) -> Command {
+    let builder = PowerShellBuilder::new()
+        .cmdlet("Resize-VHD")
+        .arg("Path", path);
+    match size_bytes {
+        Some(size_bytes) => builder.arg("SizeBytes", size_bytes),
+        None => builder.flag("ToMinimumSize"),
+    }
+    .finish()
+    .build()
+}
+
+/// Runs Merge-VHD to merge a differencing VHD into its ancestor at
+/// `destination_path`.
+pub fn run_merge_vhd(path: &Path, destination_path: &Path) -> anyhow::Result<()> {
+    run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Merge-VHD")
+            .arg("Path", path)
+            .arg("DestinationPath", destination_path)
+            .finish()
+            .build(),
+    )
+    .map(|_| ())
+    .context("merge_vhd")
+}
+
 /// Runs Dismount-VHD with the given arguments.
 pub fn run_dismount_vhd(path: &Path) -> anyhow::Result<()> {
     run_cmd(
@@ -421,32 +996,295 @@ pub struct HyperVSetVMFirmwareArgs<'a> {
     /// enabled, you must have a valid secure boot template for the guest
     /// operating system to start.
     pub secure_boot_template: Option<HyperVSecureBootTemplate>,
+    /// Specifies the order in which the UEFI firmware tries to boot from each
+    /// device. The firmware boots from the first entry that succeeds, falling
+    /// back to the next entry when one fails. This replaces the whole boot
+    /// order, so devices that are left out are never booted from.
+    pub boot_order: Option<&'a [HyperVFirmwareBootDevice]>,
+}
+
+/// A UEFI boot device of a generation 2 VM, identifying one of the VM's
+/// devices or one of the firmware's existing boot entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HyperVFirmwareBootDevice {
+    /// The hard disk drive at the given controller location
+    HardDiskDrive {
+        /// The type of controller the drive is attached to
+        controller_type: ControllerType,
+        /// The number of the controller
+        controller_number: u32,
+        /// The location of the drive on the controller
+        controller_location: u32,
+    },
+    /// The DVD drive at the given SCSI controller location
+    DvdDrive {
+        /// The number of the controller
+        controller_number: u32,
+        /// The location of the drive on the controller
+        controller_location: u32,
+    },
+    /// The network adapter with the given name (PXE)
+    NetworkAdapter(String),
+    /// The existing boot entry for a file on a disk, such as
+    /// `"Windows Boot Manager"`, with the given description as reported by
+    /// Get-VMFirmware
+    File(String),
+}
+
+impl HyperVFirmwareBootDevice {
+    /// Starts a command that looks up the device's boot source in `vm`,
+    /// storing it in `var` if one is given. The lookup outputs nothing if the
+    /// VM has no such device.
+    fn lookup(
+        &self,
+        builder: PowerShellBuilder,
+        vm: &ps::Variable,
+        var: Option<&ps::Variable>,
+    ) -> ps::PowerShellCmdletBuilder {
+        let cmdlet = match self {
+            HyperVFirmwareBootDevice::HardDiskDrive { .. } => "Get-VMHardDiskDrive",
+            HyperVFirmwareBootDevice::DvdDrive { .. } => "Get-VMDvdDrive",
+            HyperVFirmwareBootDevice::NetworkAdapter(_) => "Get-VMNetworkAdapter",
+            HyperVFirmwareBootDevice::File(_) => "Get-VMFirmware",
+        };
+        let builder = match var {
+            Some(var) => builder.cmdlet_to_var(cmdlet, var),
+            None => builder.cmdlet(cmdlet),
+        }
+        .arg("VM", vm);
+        match self {
+            HyperVFirmwareBootDevice::HardDiskDrive {
+                controller_type,
+                controller_number,
+                controller_location,
+            } => builder
+                .arg("ControllerType", controller_type)
+                .arg("ControllerNumber", controller_number)
+                .arg("ControllerLocation", controller_location),
+            HyperVFirmwareBootDevice::DvdDrive {
+                controller_number,
+                controller_location,
+            } => builder
+                .arg("ControllerNumber", controller_number)
+                .arg("ControllerLocation", controller_location),
+            HyperVFirmwareBootDevice::NetworkAdapter(name) => builder.arg("Name", name),
+            HyperVFirmwareBootDevice::File(description) => builder
+                .pipeline()
+                .cmdlet("Select-Object")
+                .arg("ExpandProperty", "BootOrder")
+                .pipeline()
+                .cmdlet("Where-Object")
+                .arg("Property", "Description")
+                .flag("EQ")
+                .arg("Value", description),
+        }
+    }
 }
 
 /// Runs Set-VMFirmware with the given arguments.
 pub fn run_set_vm_firmware(args: HyperVSetVMFirmwareArgs<'_>) -> anyhow::Result<()> {
-    run_cmd(
-        PowerShellBuilder::new()
-            .cmdlet("Get-VM")
-            .arg("Id", args.vmid)
+    // Set-VMFirmware accepts a missing boot device as a `$null` boot order
+    // entry, so check that each device exists first to report it by name.
+    if let Some(order) = args.boot_order {
+        let counts =
+            run_cmd(boot_device_counts_cmd(args.vmid, order)).context("get_boot_device_counts")?;
+        check_boot_device_counts(order, &counts)?;
+    }
+    run_cmd(set_vm_firmware_cmd(&args))
+        .map(|_| ())
+        .context("set_vm_firmware")
+}
+
+/// Builds a command that outputs the number of devices matching each boot
+/// device, one per line.
+fn boot_device_counts_cmd(vmid: &Guid, order: &[HyperVFirmwareBootDevice]) -> Command {
+    let vm = ps::Variable::new("vm");
+    let mut builder = PowerShellBuilder::new()
+        .cmdlet_to_var("Get-VM", &vm)
+        .arg("Id", vmid)
+        .next();
+    for device in order {
+        builder = device
+            .lookup(builder, &vm, None)
             .pipeline()
-            .cmdlet("Set-VMFirmware")
-            .arg_opt(
-                "EnableSecureBoot",
-                args.secure_boot_enabled.map(|enabled| {
-                    if enabled {
-                        ps::RawVal::new("On")
-                    } else {
-                        ps::RawVal::new("Off")
-                    }
-                }),
-            )
-            .arg_opt("SecureBootTemplate", args.secure_boot_template)
-            .finish()
-            .build(),
-    )
-    .map(|_| ())
-    .context("set_vm_firmware")
+            .cmdlet("Measure-Object")
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "Count")
+            .next();
+    }
+    builder.build()
+}
+
+/// Checks the output of [`boot_device_counts_cmd`], failing unless each boot
+/// device matched exactly one of the VM's devices.
+fn check_boot_device_counts(
+    order: &[HyperVFirmwareBootDevice],
+    counts: &str,
+) -> anyhow::Result<()> {
+    let counts = counts
+        .lines()
+        .map(|count| count.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse boot device counts: {counts:?}"))?;
+    if counts.len() != order.len() {
+        anyhow::bail!(
+            "expected {} boot device counts, got {}",
+            order.len(),
+            counts.len()
+        );
+    }
+    for (device, count) in order.iter().zip(counts) {
+        match count {
+            0 => anyhow::bail!("boot device not found: {device:?}"),
+            1 => {}
+            n => anyhow::bail!("boot device {device:?} matched {n} devices"),
+        }
+    }
+    Ok(())
+}
+
+fn set_vm_firmware_cmd(args: &HyperVSetVMFirmwareArgs<'_>) -> Command {
+    let vm = ps::Variable::new("vm");
+    let mut builder = PowerShellBuilder::new()
+        .cmdlet_to_var("Get-VM", &vm)
+        .arg("Id", args.vmid)
+        .next();
+    // Look up each boot device's boot source first, since Set-VMFirmware
+    // takes the objects rather than identifiers.
+    let mut boot_order = None;
+    if let Some(order) = args.boot_order {
+        let mut vars = Vec::new();
+        for (i, device) in order.iter().enumerate() {
+            let var = ps::Variable::new(format!("boot{i}"));
+            builder = device.lookup(builder, &vm, Some(&var)).next();
+            vars.push(var);
+        }
+        boot_order = Some(vars);
+    }
+    builder
+        .cmdlet("Set-VMFirmware")
+        .arg("VM", &vm)
+        .arg_opt(
+            "EnableSecureBoot",
+            args.secure_boot_enabled.map(|enabled| {
+                if enabled {
+                    ps::RawVal::new("On")
+                } else {
+                    ps::RawVal::new("Off")
+                }
+            }),
+        )
+        .arg_opt("SecureBootTemplate", args.secure_boot_template)
+        .arg_opt("BootOrder", boot_order.map(ps::Array::new))
+        .finish()
+        .build()
+}
+
+/// A PCAT BIOS boot device for generation 1 VMs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HyperVBootDevice {
+    /// DVD drive
+    Cd,
+    /// IDE hard disk
+    Ide,
+    /// Legacy network adapter (PXE)
+    LegacyNetworkAdapter,
+    /// Floppy drive
+    Floppy,
+}
+
+impl HyperVBootDevice {
+    /// All the boot devices, each of which must appear exactly once in the
+    /// startup order.
+    pub const ALL: [Self; 4] = [
+        Self::Cd,
+        Self::Ide,
+        Self::LegacyNetworkAdapter,
+        Self::Floppy,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            HyperVBootDevice::Cd => "CD",
+            HyperVBootDevice::Ide => "IDE",
+            HyperVBootDevice::LegacyNetworkAdapter => "LegacyNetworkAdapter",
+            HyperVBootDevice::Floppy => "Floppy",
+        }
+    }
+}
+
+/// Arguments for the Set-VMBios powershell cmdlet
+pub struct HyperVSetVMBiosArgs<'a> {
+    /// Specifies the ID of the generation 1 virtual machine for which you
+    /// want to modify the BIOS configuration.
+    pub vmid: &'a Guid,
+    /// Specifies the order in which the BIOS attempts to boot from each
+    /// device. Must contain each boot device exactly once.
+    pub startup_order: Option<&'a [HyperVBootDevice]>,
+    /// Whether NumLock is enabled at startup
+    pub num_lock: Option<bool>,
+}
+
+/// Runs Set-VMBios with the given arguments.
+pub fn run_set_vm_bios(args: HyperVSetVMBiosArgs<'_>) -> anyhow::Result<()> {
+    run_cmd(set_vm_bios_cmd(&args)?)
+        .map(|_| ())
+        .context("set_vm_bios")
+}
+
+fn set_vm_bios_cmd(args: &HyperVSetVMBiosArgs<'_>) -> anyhow::Result<Command> {
+    if let Some(order) = args.startup_order {
+        for device in HyperVBootDevice::ALL {
+            let count = order.iter().filter(|&&d| d == device).count();
+            if count != 1 {
+                anyhow::bail!(
+                    "startup order must contain {device:?} exactly once, found {count} in {order:?}"
+                );
+            }
+        }
+    }
+
+    Ok(PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", args.vmid)
+        .pipeline()
+        .cmdlet("Set-VMBios")
+        .arg_opt(
+            "StartupOrder",
+            args.startup_order
+                .map(|order| ps::Array::new(order.iter().map(|d| d.name()))),
+        )
+        .flag_opt(args.num_lock.map(|enabled| {
+            if enabled {
+                "EnableNumLock"
+            } else {
+                "DisableNumLock"
+            }
+        }))
+        .finish()
+        .build())
+}
+
+/// Runs Set-VMFloppyDiskDrive to insert the given virtual floppy disk into a
+/// generation 1 VM, or to eject the current one if `path` is `None`.
+pub fn run_set_vm_floppy_disk_drive(vmid: &Guid, path: Option<&Path>) -> anyhow::Result<()> {
+    run_cmd(set_vm_floppy_disk_drive_cmd(vmid, path))
+        .map(|_| ())
+        .context("set_vm_floppy_disk_drive")
+}
+
+fn set_vm_floppy_disk_drive_cmd(vmid: &Guid, path: Option<&Path>) -> Command {
+    let builder = PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Set-VMFloppyDiskDrive");
+    let builder = match path {
+        Some(path) => builder.arg("Path", path),
+        None => builder.arg("Path", ps::RawVal::new("$null")),
+    };
+    builder.finish().build()
 }
 
 /// Runs Set-OpenHCLFirmware with the given arguments.
@@ -627,10 +1465,8 @@ pub fn run_get_winevent(
     }
     let filter = ps::HashTable::new(filter);
 
-    let output_var = ps::Variable::new("events");
-
     let mut builder = PowerShellBuilder::new()
-        .cmdlet_to_var("Get-WinEvent", &output_var)
+        .cmdlet("Get-WinEvent")
         .flag("Oldest")
         .arg("FilterHashtable", filter)
         .pipeline();
@@ -661,15 +1497,13 @@ pub fn run_get_winevent(
         builder
             .cmdlet("Select-Object")
             .positional(props)
-            .next()
-            .cmdlet("ConvertTo-Json")
-            .arg("InputObject", ps::Array::new([&output_var]))
-            .finish()
+            .pipeline()
+            .output_json()
             .build(),
     );
 
     match output {
-        Ok(logs) => serde_json::from_str(&logs).context("parsing winevents"),
+        Ok(logs) => JsonCommand::parse::<WinEvent>(&logs).context("parsing winevents"),
         Err(e) => match e {
             CommandError::Command(_, err_output)
                 if err_output.contains(
@@ -730,32 +1564,146 @@ pub fn hyperv_boot_events(vmid: &Guid, start_time: &Timestamp) -> anyhow::Result
     )
 }
 
-/// Get the IDs of the VM(s) with the specified name
-pub fn vm_id_from_name(name: &str) -> anyhow::Result<Vec<Guid>> {
+/// VM was turned off
+pub const EVENT_ID_TURNED_OFF: u32 = 18502;
+/// VM was shut down by the guest operating system
+pub const EVENT_ID_GUEST_SHUTDOWN: u32 = 18504;
+/// VM was reset by the guest operating system
+pub const EVENT_ID_GUEST_RESET: u32 = 18514;
+/// VM was reset because a virtual processor triple faulted
+pub const EVENT_ID_TRIPLE_FAULT: u32 = 18560;
+
+const HALT_EVENT_IDS: [u32; 4] = [
+    EVENT_ID_TURNED_OFF,
+    EVENT_ID_GUEST_SHUTDOWN,
+    EVENT_ID_GUEST_RESET,
+    EVENT_ID_TRIPLE_FAULT,
+];
+
+/// Get Hyper-V events describing why a VM halted or restarted
+pub fn hyperv_halt_events(vmid: &Guid, start_time: &Timestamp) -> anyhow::Result<Vec<WinEvent>> {
+    let vmid = vmid.to_string();
+    run_get_winevent(
+        &[HYPERV_WORKER_TABLE],
+        Some(start_time),
+        Some(&vmid),
+        &HALT_EVENT_IDS,
+    )
+}
+
+/// Get the directory containing the VM's configuration files
+pub fn vm_configuration_location(vmid: &Guid) -> anyhow::Result<PathBuf> {
     let output = run_cmd(
         PowerShellBuilder::new()
             .cmdlet("Get-VM")
-            .arg("Name", name)
+            .arg("Id", vmid)
             .pipeline()
             .cmdlet("Select-Object")
-            .arg("ExpandProperty", "Id")
+            .arg("ExpandProperty", "ConfigurationLocation")
+            .finish()
+            .build(),
+    )
+    .context("vm_configuration_location")?;
+    Ok(PathBuf::from(output))
+}
+
+/// Get the IDs of the VM(s) with the specified name
+pub fn vm_id_from_name(name: &str) -> anyhow::Result<Vec<Guid>> {
+    // Get-VM -Name treats the name as a wildcard pattern, so match the name
+    // exactly here instead.
+    Ok(vm_list()
+        .context("vm_id_from_name")?
+        .into_iter()
+        .filter(|vm| vm.name == name)
+        .map(|vm| vm.vmid)
+        .collect())
+}
+
+/// A VM listed by [`vm_list`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct VmListEntry {
+    /// The VM ID.
+    pub vmid: Guid,
+    /// The VM name.
+    pub name: String,
+}
+
+/// Lists the VMs on the host.
+pub fn vm_list() -> anyhow::Result<Vec<VmListEntry>> {
+    let output = run_cmd(vm_list_cmd()).context("vm_list")?;
+    parse_vm_list(&output)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VmListRow {
+    name: String,
+    id: String,
+}
+
+fn vm_list_cmd() -> Command {
+    // Output the ID as a plain string rather than relying on how
+    // ConvertTo-Json serializes a Guid.
+    let props = ps::Array::new([
+        ps::Value::new("Name"),
+        ps::Value::new(ps::HashTable::new([
+            ("label", ps::Value::new("Id")),
+            ("expression", ps::Value::new(ps::Script::new("$_.Id.Guid"))),
+        ])),
+    ]);
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .pipeline()
+        .cmdlet("Select-Object")
+        .positional(props)
+        .pipeline()
+        .output_json()
+        .build()
+}
+
+fn parse_vm_list(output: &str) -> anyhow::Result<Vec<VmListEntry>> {
+    JsonCommand::parse::<VmListRow>(output)
+        .with_context(|| format!("invalid Get-VM output: {output}"))?
+        .into_iter()
+        .map(|row| {
+            Ok(VmListEntry {
+                vmid: row
+                    .id
+                    .parse()
+                    .with_context(|| format!("invalid ID for VM {:?}: {}", row.name, row.id))?,
+                name: row.name,
+            })
+        })
+        .collect()
+}
+
+/// Get the name and generation of the VM with the specified ID
+pub fn vm_name_and_generation(vmid: &Guid) -> anyhow::Result<(String, HyperVGeneration)> {
+    let output = run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
             .pipeline()
-            .cmdlet("Select-Object")
-            .arg("ExpandProperty", "Guid")
+            .cmdlet("ForEach-Object")
+            .positional(ps::Script::new(r#""$($_.Generation) $($_.Name)""#))
             .finish()
             .build(),
     )
-    .context("vm_id_from_name")?;
-    let mut vmids = Vec::new();
-    for s in output.lines() {
-        vmids.push(Guid::from_str(s)?);
-    }
-    Ok(vmids)
+    .context("vm_name_and_generation")?;
+    let (generation, name) = output
+        .split_once(' ')
+        .with_context(|| format!("unexpected output: {output}"))?;
+    let generation = match generation {
+        "1" => HyperVGeneration::One,
+        "2" => HyperVGeneration::Two,
+        _ => anyhow::bail!("unknown VM generation {generation}"),
+    };
+    Ok((name.to_owned(), generation))
 }
 
-/// Hyper-V VM Shutdown Integration Component Status
+/// Hyper-V VM Integration Component Status
 #[derive(Clone, Copy, PartialEq, Debug)]
-pub enum VmShutdownIcStatus {
+pub enum VmIcStatus {
     /// The VM is off
     Off,
     /// The component is operating normally.
@@ -772,31 +1720,167 @@ pub enum VmShutdownIcStatus {
 }
 
 /// Get the VM's shutdown IC status
-pub fn vm_shutdown_ic_status(vmid: &Guid) -> anyhow::Result<VmShutdownIcStatus> {
-    let status = run_cmd(
+pub fn vm_shutdown_ic_status(vmid: &Guid) -> anyhow::Result<VmIcStatus> {
+    vm_ic_status(vmid, "Shutdown").context("vm_shutdown_ic_status")
+}
+
+/// Runs Enable-VMIntegrationService to enable the integration service
+/// `name`, such as `"Heartbeat"` or `"Time Synchronization"`.
+pub fn run_enable_vm_integration_service(vmid: &Guid, name: &str) -> anyhow::Result<()> {
+    run_cmd(vm_integration_service_cmd(vmid, name, true))
+        .map(|_| ())
+        .with_context(|| format!("enable_vm_integration_service {name}"))
+}
+
+/// Runs Disable-VMIntegrationService to disable the integration service
+/// `name`, such as `"Heartbeat"` or `"Time Synchronization"`.
+pub fn run_disable_vm_integration_service(vmid: &Guid, name: &str) -> anyhow::Result<()> {
+    run_cmd(vm_integration_service_cmd(vmid, name, false))
+        .map(|_| ())
+        .with_context(|| format!("disable_vm_integration_service {name}"))
+}
+
+fn vm_integration_service_cmd(vmid: &Guid, name: &str, enable: bool) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet(if enable {
+            "Enable-VMIntegrationService"
+        } else {
+            "Disable-VMIntegrationService"
+        })
+        .arg("Name", name)
+        .finish()
+        .build()
+}
+
+/// Resource usage of a VM since metering was enabled, as reported by
+/// Measure-VM. Fields are `None` when Hyper-V has no data for them yet.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct VmMetrics {
+    /// Average CPU usage, in MHz (`AvgCPU`).
+    pub average_processor_usage: Option<u64>,
+    /// Average memory usage, in MB (`AvgRAM`).
+    pub average_memory_usage: Option<u64>,
+    /// Minimum memory usage, in MB.
+    pub minimum_memory_usage: Option<u64>,
+    /// Maximum memory usage, in MB.
+    pub maximum_memory_usage: Option<u64>,
+    /// Total disk space allocated to the VM, in MB.
+    pub total_disk_allocation: Option<u64>,
+    /// Average normalized IOPS across all of the VM's disks.
+    #[serde(rename = "AggregatedAverageNormalizedIOPS")]
+    pub aggregated_average_normalized_iops: Option<u64>,
+    /// Average disk latency across all of the VM's disks, in microseconds.
+    pub aggregated_average_latency: Option<u64>,
+    /// Data read from all of the VM's disks, in MB.
+    pub aggregated_disk_data_read: Option<u64>,
+    /// Data written to all of the VM's disks, in MB.
+    pub aggregated_disk_data_written: Option<u64>,
+}
+
+/// Runs Enable-VMResourceMetering to start collecting the VM's resource
+/// usage. Does nothing if metering is already enabled.
+pub fn run_enable_vm_resource_metering(vmid: &Guid) -> anyhow::Result<()> {
+    run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Enable-VMResourceMetering")
+            .finish()
+            .build(),
+    )
+    .map(|_| ())
+    .context("enable_vm_resource_metering")
+}
+
+/// Runs Measure-VM to get the VM's resource usage, enabling metering first
+/// if needed.
+pub fn run_measure_vm(vmid: &Guid) -> anyhow::Result<VmMetrics> {
+    let output = run_cmd(measure_vm_cmd(vmid)).context("measure_vm")?;
+    serde_json::from_str(&output).with_context(|| format!("invalid Measure-VM output: {output}"))
+}
+
+fn measure_vm_cmd(vmid: &Guid) -> Command {
+    // Only select the aggregate metrics, leaving out the per-disk and
+    // per-network adapter reports.
+    let props = ps::Array::new([
+        "AverageProcessorUsage",
+        "AverageMemoryUsage",
+        "MinimumMemoryUsage",
+        "MaximumMemoryUsage",
+        "TotalDiskAllocation",
+        "AggregatedAverageNormalizedIOPS",
+        "AggregatedAverageLatency",
+        "AggregatedDiskDataRead",
+        "AggregatedDiskDataWritten",
+    ]);
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Enable-VMResourceMetering")
+        .next()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Measure-VM")
+        .pipeline()
+        .cmdlet("Select-Object")
+        .positional(props)
+        .pipeline()
+        .output_json()
+        .build()
+}
+
+/// Get the VM's heartbeat IC status
+pub fn vm_heartbeat_ic_status(vmid: &Guid) -> anyhow::Result<VmIcStatus> {
+    vm_ic_status(vmid, "Heartbeat").context("vm_heartbeat_ic_status")
+}
+
+fn vm_ic_status(vmid: &Guid, name: &str) -> anyhow::Result<VmIcStatus> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct IntegrationService {
+        primary_status_description: Option<String>,
+    }
+
+    let output = run_cmd(
         PowerShellBuilder::new()
             .cmdlet("Get-VM")
             .arg("Id", vmid)
             .pipeline()
             .cmdlet("Get-VMIntegrationService")
-            .arg("Name", "Shutdown")
+            .arg("Name", name)
             .pipeline()
             .cmdlet("Select-Object")
-            .arg("ExpandProperty", "PrimaryStatusDescription")
-            .finish()
+            .positional("PrimaryStatusDescription")
+            .pipeline()
+            .output_json()
             .build(),
-    )
-    .context("vm_shutdown_ic_status")?;
+    )?;
+    let services = JsonCommand::parse::<IntegrationService>(&output)?;
+    let [service] = services.as_slice() else {
+        anyhow::bail!(
+            "expected one {name} integration service, found {}",
+            services.len()
+        );
+    };
 
-    Ok(match status.as_str() {
-        "" => VmShutdownIcStatus::Off,
-        "OK" => VmShutdownIcStatus::Ok,
-        "Degraded" => VmShutdownIcStatus::Degraded,
-        "Non-Recoverable Error" => VmShutdownIcStatus::NonRecoverableError,
-        "No Contact" => VmShutdownIcStatus::NoContact,
-        "Lost Communication" => VmShutdownIcStatus::LostCommunication,
-        s => anyhow::bail!("Unknown VM shutdown status: {s}"),
-    })
+    Ok(
+        match service.primary_status_description.as_deref().unwrap_or("") {
+            "" => VmIcStatus::Off,
+            "OK" => VmIcStatus::Ok,
+            "Degraded" => VmIcStatus::Degraded,
+            "Non-Recoverable Error" => VmIcStatus::NonRecoverableError,
+            "No Contact" => VmIcStatus::NoContact,
+            "Lost Communication" => VmIcStatus::LostCommunication,
+            s => anyhow::bail!("Unknown VM {name} IC status: {s}"),
+        },
+    )
 }
 
 /// Runs Remove-VmNetworkAdapter to remove all network adapters from a VM.
@@ -814,6 +1898,69 @@ pub fn run_remove_vm_network_adapter(vmid: &Guid) -> anyhow::Result<()> {
     .context("remove_vm_network_adapters")
 }
 
+/// Runs Add-VMNetworkAdapter to add a network adapter to a VM, optionally
+/// naming it and connecting it to the virtual switch `switch_name`.
+pub fn run_add_vm_network_adapter(
+    vmid: &Guid,
+    name: Option<&str>,
+    switch_name: Option<&str>,
+) -> anyhow::Result<()> {
+    run_cmd(add_vm_network_adapter_cmd(vmid, name, switch_name))
+        .map(|_| ())
+        .context("add_vm_network_adapter")
+}
+
+fn add_vm_network_adapter_cmd(
+    vmid: &Guid,
+    name: Option<&str>,
+    switch_name: Option<&str>,
+) -> Command {
+    PowerShellBuilder::new()
+        .cmdlet("Get-VM")
+        .arg("Id", vmid)
+        .pipeline()
+        .cmdlet("Add-VMNetworkAdapter")
+        .arg_opt("Name", name)
+        .arg_opt("SwitchName", switch_name)
+        .finish()
+        .build()
+}
+
+/// Get whether dynamic memory is enabled for the VM
+pub fn vm_dynamic_memory_enabled(vmid: &Guid) -> anyhow::Result<bool> {
+    let enabled = run_cmd(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Get-VMMemory")
+            .pipeline()
+            .cmdlet("Select-Object")
+            .arg("ExpandProperty", "DynamicMemoryEnabled")
+            .finish()
+            .build(),
+    )
+    .context("vm_dynamic_memory_enabled")?;
+    match enabled.as_str() {
+        "True" => Ok(true),
+        "False" => Ok(false),
+        _ => anyhow::bail!("unexpected DynamicMemoryEnabled value: {enabled}"),
+    }
+}
+
+/// Get the number of SCSI controllers attached to the VM
+pub fn vm_scsi_controller_count(vmid: &Guid) -> anyhow::Result<u32> {
+    run_count_cmd(count_objects(
+        PowerShellBuilder::new()
+            .cmdlet("Get-VM")
+            .arg("Id", vmid)
+            .pipeline()
+            .cmdlet("Get-VMScsiController")
+            .pipeline(),
+    ))
+    .context("vm_scsi_controller_count")
+}
+
 /// Runs Remove-VMScsiController with the given arguments.
 pub fn run_remove_vm_scsi_controller(vmid: &Guid, controller_number: u32) -> anyhow::Result<()> {
     run_cmd(
@@ -831,3 +1978,594 @@ pub fn run_remove_vm_scsi_controller(vmid: &Guid, controller_number: u32) -> any
     .map(|_| ())
     .context("remove_vm_scsi_controller")
 }
+
+/// Finishes a pipeline, which must end with a pipe, by counting its output
+/// objects for [`run_count_cmd`].
+fn count_objects(builder: PowerShellBuilder) -> Command {
+    builder
+        .cmdlet("Measure-Object")
+        .pipeline()
+        .cmdlet("Select-Object")
+        .arg("ExpandProperty", "Count")
+        .finish()
+        .build()
+}
+
+/// Runs a pipeline finished by [`count_objects`], returning the count.
+///
+/// Cmdlets such as Get-VMDvdDrive output nothing rather than failing when no
+/// object matches, so this is how to tell whether a pipeline did anything.
+fn run_count_cmd(cmd: Command) -> anyhow::Result<u32> {
+    let count = run_cmd(cmd)?;
+    count
+        .parse()
+        .with_context(|| format!("invalid object count: {count}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn add_physical_disk_args() {
+        let vmid = Guid::new_random();
+        let cmd = add_vm_hard_disk_drive_cmd(&HyperVAddVMHardDiskDriveArgs {
+            vmid: &vmid,
+            controller_type: ControllerType::Scsi,
+            controller_location: Some(1),
+            controller_number: None,
+            path: None,
+            disk_number: Some(3),
+        })
+        .unwrap();
+        let args = args(&cmd);
+        let pos = args.iter().position(|a| a == "-DiskNumber").unwrap();
+        assert_eq!(args[pos + 1], "3");
+        assert!(!args.iter().any(|a| a == "-Path"));
+    }
+
+    #[test]
+    fn set_processor_args() {
+        let vmid = Guid::new_random();
+        let four = args(&set_vm_processor_cmd(
+            &vmid,
+            &HyperVSetVMProcessorArgs {
+                count: Some(4),
+                apic_mode: None,
+                hw_thread_count_per_core: Some(2),
+                maximum_count_per_numa_node: None,
+                expose_virtualization_extensions: None,
+            },
+        ));
+        let pos = four.iter().position(|a| a == "-Count").unwrap();
+        assert_eq!(four[pos + 1], "4");
+        let pos = four
+            .iter()
+            .position(|a| a == "-HwThreadCountPerCore")
+            .unwrap();
+        assert_eq!(four[pos + 1], "2");
+        assert!(!four.iter().any(|a| a == "-ApicMode"));
+
+        let one = args(&set_vm_processor_cmd(
+            &vmid,
+            &HyperVSetVMProcessorArgs {
+                count: Some(1),
+                apic_mode: None,
+                hw_thread_count_per_core: None,
+                maximum_count_per_numa_node: None,
+                expose_virtualization_extensions: None,
+            },
+        ));
+        assert!(!one.iter().any(|a| a == "-HwThreadCountPerCore"));
+        assert!(!one.iter().any(|a| a == "-ExposeVirtualizationExtensions"));
+
+        let nested = args(&set_vm_processor_cmd(
+            &vmid,
+            &HyperVSetVMProcessorArgs {
+                expose_virtualization_extensions: Some(true),
+                ..Default::default()
+            },
+        ));
+        let pos = nested
+            .iter()
+            .position(|a| a == "-ExposeVirtualizationExtensions")
+            .unwrap();
+        assert_eq!(nested[pos + 1], "$true");
+        assert!(!nested.iter().any(|a| a == "-Count"));
+    }
+
+    #[test]
+    fn set_memory_args() {
+        let vmid = Guid::new_random();
+        let args = args(&set_vm_memory_cmd(
+            &vmid,
+            &HyperVSetVMMemoryArgs {
+                dynamic_memory_enabled: Some(true),
+                minimum_bytes: Some(512 * 1024 * 1024),
+                maximum_bytes: Some(4 * 1024 * 1024 * 1024),
+                ..Default::default()
+            },
+        ));
+        let pos = args.iter().position(|a| a == "-MinimumBytes").unwrap();
+        assert_eq!(args[pos + 1], "536870912");
+        let pos = args.iter().position(|a| a == "-MaximumBytes").unwrap();
+        assert_eq!(args[pos + 1], "4294967296");
+        assert!(args.iter().any(|a| a == "-DynamicMemoryEnabled"));
+        assert!(!args.iter().any(|a| a == "-StartupBytes"));
+    }
+
+    #[test]
+    fn restore_checkpoint_args() {
+        let vmid = Guid::new_random();
+        let args = args(&restore_vm_checkpoint_cmd(&vmid, "before"));
+        let pos = args.iter().position(|a| a == "-Name").unwrap();
+        assert_eq!(args[pos + 1], "\"before\"");
+        let restore = args.iter().position(|a| a == "Restore-VMSnapshot").unwrap();
+        assert_eq!(args[restore + 1], "-Confirm:$false");
+        assert!(args[restore..].iter().any(|a| a == "-Passthru"));
+    }
+
+    #[test]
+    fn set_dvd_drive_args() {
+        let vmid = Guid::new_random();
+        let insert = args(&set_vm_dvd_drive_cmd(
+            &vmid,
+            1,
+            0,
+            Some(Path::new(r"C:\media\install.iso")),
+        ));
+        let set = insert.iter().position(|a| a == "Set-VMDvdDrive").unwrap();
+        assert_eq!(
+            insert[set + 1..set + 3],
+            ["-Path", r#""C:\media\install.iso""#]
+        );
+        let pos = insert
+            .iter()
+            .position(|a| a == "-ControllerNumber")
+            .unwrap();
+        assert_eq!(insert[pos + 1], "1");
+
+        // Ejecting must pass an unquoted $null, not the string "$null".
+        let eject = args(&set_vm_dvd_drive_cmd(&vmid, 1, 0, None));
+        let set = eject.iter().position(|a| a == "Set-VMDvdDrive").unwrap();
+        assert_eq!(eject[set + 1..set + 3], ["-Path", "$null"]);
+    }
+
+    #[test]
+    fn get_vhd() {
+        let args = args(&get_vhd_cmd(Path::new(r"C:\vhds\child.vhdx")));
+        let pos = args.iter().position(|a| a == "-Path").unwrap();
+        assert_eq!(args[pos + 1], r#""C:\vhds\child.vhdx""#);
+        assert!(args.iter().any(|a| a == "ConvertTo-Json"));
+
+        let info = parse_vhd_info(
+            r#"{"VhdType":"Differencing","ParentPath":"C:\\vhds\\base.vhdx","FileSize":4194304,"Size":34359738368}"#,
+        )
+        .unwrap();
+        assert_eq!(info.vhd_type, VhdType::Differencing);
+        assert_eq!(
+            info.parent_path.as_deref(),
+            Some(Path::new(r"C:\vhds\base.vhdx"))
+        );
+        assert_eq!(info.file_size, 4 * 1024 * 1024);
+        assert_eq!(info.size, 32 * 1024 * 1024 * 1024);
+
+        let info = parse_vhd_info(
+            r#"{"VhdType":"Dynamic","ParentPath":"","FileSize":4194304,"Size":1073741824}"#,
+        )
+        .unwrap();
+        assert_eq!(info.vhd_type, VhdType::Dynamic);
+        assert_eq!(info.parent_path, None);
+        let info = parse_vhd_info(
+            r#"{"VhdType":"Fixed","ParentPath":null,"FileSize":1073745920,"Size":1073741824}"#,
+        )
+        .unwrap();
+        assert_eq!(info.parent_path, None);
+        parse_vhd_info(r#"{"VhdType":3,"ParentPath":"","FileSize":0,"Size":0}"#).unwrap_err();
+    }
+
+    #[test]
+    fn resize_vhd_args() {
+        let path = Path::new(r"C:\vhds\disk.vhdx");
+        let grow = args(&resize_vhd_cmd(path, Some(2 * 1024 * 1024 * 1024)));
+        let pos = grow.iter().position(|a| a == "-SizeBytes").unwrap();
+        assert_eq!(grow[pos + 1], "2147483648");
+        assert!(!grow.iter().any(|a| a == "-ToMinimumSize"));
+
+        let shrink = args(&resize_vhd_cmd(path, None));
+        assert!(shrink.iter().any(|a| a == "-ToMinimumSize"));
+        assert!(!shrink.iter().any(|a| a == "-SizeBytes"));
+
+        let mut info = VhdInfo {
+            vhd_type: VhdType::Dynamic,
+            parent_path: None,
+            file_size: 0,
+            size: 0,
+        };
+        check_resizable(path, &info).unwrap();
+        info.vhd_type = VhdType::Differencing;
+        info.parent_path = Some(r"C:\vhds\base.vhdx".into());
+        let err = check_resizable(path, &info).unwrap_err();
+        assert!(err.to_string().contains("differencing"), "{err}");
+    }
+
+    #[test]
+    fn measure_vm() {
+        let vmid = Guid::new_random();
+        let args = args(&measure_vm_cmd(&vmid));
+        let enable = args
+            .iter()
+            .position(|a| a == "Enable-VMResourceMetering")
+            .unwrap();
+        let measure = args.iter().position(|a| a == "Measure-VM").unwrap();
+        assert!(enable < measure);
+        assert!(args.iter().any(|a| a == "ConvertTo-Json"));
+
+        let metrics: VmMetrics = serde_json::from_str(
+            r#"{"AverageProcessorUsage":245,"AverageMemoryUsage":4096,"MinimumMemoryUsage":4096,"MaximumMemoryUsage":4096,"TotalDiskAllocation":32768,"AggregatedAverageNormalizedIOPS":120,"AggregatedAverageLatency":850,"AggregatedDiskDataRead":512,"AggregatedDiskDataWritten":64}"#,
+        )
+        .unwrap();
+        assert_eq!(metrics.average_processor_usage, Some(245));
+        assert_eq!(metrics.total_disk_allocation, Some(32768));
+        assert_eq!(metrics.aggregated_average_normalized_iops, Some(120));
+
+        let metrics: VmMetrics = serde_json::from_str(
+            r#"{"AverageProcessorUsage":null,"AverageMemoryUsage":null,"MinimumMemoryUsage":null,"MaximumMemoryUsage":null,"TotalDiskAllocation":0,"AggregatedAverageNormalizedIOPS":null,"AggregatedAverageLatency":null,"AggregatedDiskDataRead":null,"AggregatedDiskDataWritten":null}"#,
+        )
+        .unwrap();
+        assert_eq!(metrics.average_processor_usage, None);
+        assert_eq!(metrics.total_disk_allocation, Some(0));
+    }
+
+    #[test]
+    fn integration_service_args() {
+        let vmid = Guid::new_random();
+        let enable = args(&vm_integration_service_cmd(
+            &vmid,
+            "Time Synchronization",
+            true,
+        ));
+        assert!(enable.iter().any(|a| a == "Enable-VMIntegrationService"));
+        let pos = enable.iter().position(|a| a == "-Name").unwrap();
+        assert_eq!(enable[pos + 1], "\"Time Synchronization\"");
+
+        let disable = args(&vm_integration_service_cmd(&vmid, "Shutdown", false));
+        assert!(disable.iter().any(|a| a == "Disable-VMIntegrationService"));
+        assert!(!disable.iter().any(|a| a == "Enable-VMIntegrationService"));
+    }
+
+    #[test]
+    fn json_list() {
+        const EVENT: &str = r#"{"TimeCreated":"2025-01-02T03:04:05.6789012-08:00","ProviderName":"Microsoft-Windows-Hyper-V-Worker","Level":4,"Id":18601,"Message":"started"}"#;
+
+        let events = JsonCommand::parse::<WinEvent>(EVENT).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, 18601);
+        assert_eq!(events[0].message, "started");
+
+        let events = JsonCommand::parse::<WinEvent>(&format!("[{EVENT},{EVENT}]")).unwrap();
+        assert_eq!(events.len(), 2);
+
+        assert!(JsonCommand::parse::<WinEvent>("").unwrap().is_empty());
+        assert!(JsonCommand::parse::<WinEvent>("[]").unwrap().is_empty());
+        JsonCommand::parse::<WinEvent>(r#"{"Id":1}"#).unwrap_err();
+    }
+
+    #[test]
+    fn vm_list_output() {
+        let output = concat!(
+            r#"[{"Name":"petri test vm","Id":"3f2504e0-4f89-11d3-9a0c-0305e82c3301"},"#,
+            r#"{"Name":"other  vm","Id":"b9e8e5a1-1c2d-4e5f-8a9b-0c1d2e3f4a5b"}]"#
+        );
+        assert_eq!(
+            parse_vm_list(output).unwrap(),
+            [
+                VmListEntry {
+                    vmid: guid::guid!("3f2504e0-4f89-11d3-9a0c-0305e82c3301"),
+                    name: "petri test vm".into(),
+                },
+                VmListEntry {
+                    vmid: guid::guid!("b9e8e5a1-1c2d-4e5f-8a9b-0c1d2e3f4a5b"),
+                    name: "other  vm".into(),
+                },
+            ]
+        );
+        assert!(parse_vm_list("").unwrap().is_empty());
+        assert!(parse_vm_list(r#"{"Name":"petri test vm","Id":"not-a-guid"}"#).is_err());
+    }
+
+    #[test]
+    fn set_firmware_boot_order_args() {
+        let vmid = Guid::new_random();
+        let order = args(&set_vm_firmware_cmd(&HyperVSetVMFirmwareArgs {
+            vmid: &vmid,
+            secure_boot_enabled: None,
+            secure_boot_template: None,
+            boot_order: Some(&[
+                HyperVFirmwareBootDevice::NetworkAdapter("Network Adapter".into()),
+                HyperVFirmwareBootDevice::HardDiskDrive {
+                    controller_type: ControllerType::Scsi,
+                    controller_number: 0,
+                    controller_location: 1,
+                },
+                HyperVFirmwareBootDevice::File("Windows Boot Manager".into()),
+            ]),
+        }));
+        let nic = order
+            .iter()
+            .position(|a| a == "Get-VMNetworkAdapter")
+            .unwrap();
+        assert_eq!(order[nic - 2], "$boot0");
+        assert_eq!(order[nic + 3..nic + 5], ["-Name", "\"Network Adapter\""]);
+        let disk = order
+            .iter()
+            .position(|a| a == "Get-VMHardDiskDrive")
+            .unwrap();
+        assert_eq!(order[disk - 2], "$boot1");
+        let file = order.iter().position(|a| a == "Get-VMFirmware").unwrap();
+        assert_eq!(order[file - 2], "$boot2");
+        let value = order.iter().position(|a| a == "-Value").unwrap();
+        assert_eq!(order[value + 1], "\"Windows Boot Manager\"");
+        let pos = order.iter().position(|a| a == "-BootOrder").unwrap();
+        assert_eq!(order[pos + 1], "@($boot0; $boot1; $boot2)");
+        assert!(!order.iter().any(|a| a == "-EnableSecureBoot"));
+
+        let secure_boot = args(&set_vm_firmware_cmd(&HyperVSetVMFirmwareArgs {
+            vmid: &vmid,
+            secure_boot_enabled: Some(false),
+            secure_boot_template: None,
+            boot_order: None,
+        }));
+        assert!(!secure_boot.iter().any(|a| a == "-BootOrder"));
+        let pos = secure_boot
+            .iter()
+            .position(|a| a == "-EnableSecureBoot")
+            .unwrap();
+        assert_eq!(secure_boot[pos + 1], "Off");
+    }
+
+    #[test]
+    fn boot_device_counts() {
+        let vmid = Guid::new_random();
+        let order = [
+            HyperVFirmwareBootDevice::NetworkAdapter("Network Adapter".into()),
+            HyperVFirmwareBootDevice::File("Windows Boot Manager".into()),
+        ];
+        let cmd = args(&boot_device_counts_cmd(&vmid, &order));
+        // Each lookup is counted, without storing the device.
+        let nic = cmd
+            .iter()
+            .position(|a| a == "Get-VMNetworkAdapter")
+            .unwrap();
+        assert_ne!(cmd[nic - 1], "=");
+        let file = cmd.iter().position(|a| a == "Get-VMFirmware").unwrap();
+        assert_eq!(
+            cmd.iter().filter(|a| *a == "Measure-Object").count(),
+            order.len()
+        );
+        let count = cmd.iter().position(|a| a == "Measure-Object").unwrap();
+        assert!(nic < count && count < file);
+
+        check_boot_device_counts(&order, "1\r\n1").unwrap();
+        let err = check_boot_device_counts(&order, "1\n0").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("boot device not found: File(\"Windows Boot Manager\")"),
+            "{err}"
+        );
+        let err = check_boot_device_counts(&order, "2\n1").unwrap_err();
+        assert!(err.to_string().contains("matched 2 devices"), "{err}");
+        check_boot_device_counts(&order, "1").unwrap_err();
+        check_boot_device_counts(&order, "1\nerror").unwrap_err();
+    }
+
+    #[test]
+    fn set_bios_startup_order_args() {
+        let vmid = Guid::new_random();
+        let cmd = set_vm_bios_cmd(&HyperVSetVMBiosArgs {
+            vmid: &vmid,
+            startup_order: Some(&[
+                HyperVBootDevice::Floppy,
+                HyperVBootDevice::Ide,
+                HyperVBootDevice::Cd,
+                HyperVBootDevice::LegacyNetworkAdapter,
+            ]),
+            num_lock: None,
+        })
+        .unwrap();
+        let args = args(&cmd);
+        let pos = args.iter().position(|a| a == "-StartupOrder").unwrap();
+        assert_eq!(
+            args[pos + 1],
+            r#"@("Floppy"; "IDE"; "CD"; "LegacyNetworkAdapter")"#
+        );
+        assert!(!args.iter().any(|a| a.ends_with("NumLock")));
+
+        // Each device must be listed exactly once.
+        set_vm_bios_cmd(&HyperVSetVMBiosArgs {
+            vmid: &vmid,
+            startup_order: Some(&[HyperVBootDevice::Floppy, HyperVBootDevice::Floppy]),
+            num_lock: None,
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn set_floppy_args() {
+        let vmid = Guid::new_random();
+        let vmid_arg = format!("\"{vmid}\"");
+        let insert = args(&set_vm_floppy_disk_drive_cmd(
+            &vmid,
+            Some(Path::new(r"C:\disks\boot.vfd")),
+        ));
+        assert_eq!(
+            insert[1..],
+            [
+                "Get-VM",
+                "-Id",
+                vmid_arg.as_str(),
+                "|",
+                "Set-VMFloppyDiskDrive",
+                "-Path",
+                r#""C:\disks\boot.vfd""#,
+            ]
+        );
+
+        let eject = args(&set_vm_floppy_disk_drive_cmd(&vmid, None));
+        assert_eq!(eject[eject.len() - 2..], ["-Path", "$null"]);
+    }
+
+    #[test]
+    fn vm_group_args() {
+        let vmid = Guid::new_random();
+        let vmid_arg = format!("\"{vmid}\"");
+        // Skip the leading -NoProfile.
+        assert_eq!(
+            args(&new_vm_group_cmd("petri"))[1..],
+            [
+                "New-VMGroup",
+                "-Name",
+                "\"petri\"",
+                "-GroupType",
+                "VMCollectionType"
+            ]
+        );
+        assert_eq!(
+            args(&add_vm_group_member_cmd(&vmid, "petri"))[1..],
+            [
+                "$vm",
+                "=",
+                "Get-VM",
+                "-Id",
+                vmid_arg.as_str(),
+                ";",
+                "Get-VMGroup",
+                "-Name",
+                "\"petri\"",
+                "|",
+                "Select-Object",
+                "-First",
+                "1",
+                "|",
+                "Add-VMGroupMember",
+                "-VM",
+                "$vm"
+            ]
+        );
+        assert_eq!(
+            args(&remove_vm_group_cmd("petri"))[1..],
+            [
+                "Get-VMGroup",
+                "-Name",
+                "\"petri\"",
+                "|",
+                "Remove-VMGroup",
+                "-Force"
+            ]
+        );
+        assert_eq!(
+            args(&remove_vm_switches_cmd("petri"))[1..],
+            [
+                "Get-VMSwitch",
+                "-Name",
+                "\"petri*\"",
+                "-ErrorAction",
+                "\"SilentlyContinue\"",
+                "|",
+                "Remove-VMSwitch",
+                "-Force"
+            ]
+        );
+        assert_eq!(
+            args(&remove_net_nats_cmd("petri"))[1..],
+            [
+                "Get-NetNat",
+                "-Name",
+                "\"petri*\"",
+                "-ErrorAction",
+                "\"SilentlyContinue\"",
+                "|",
+                "Remove-NetNat",
+                "-Confirm:$false"
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_hard_disk_drive_args() {
+        let vmid = Guid::new_random();
+        let vmid_arg = format!("\"{vmid}\"");
+        assert_eq!(
+            args(&remove_vm_hard_disk_drive_cmd(
+                &vmid,
+                ControllerType::Scsi,
+                1,
+                2
+            ))[1..],
+            [
+                "Get-VM",
+                "-Id",
+                vmid_arg.as_str(),
+                "|",
+                "Get-VMHardDiskDrive",
+                "-ControllerType",
+                "SCSI",
+                "-ControllerNumber",
+                "1",
+                "-ControllerLocation",
+                "2",
+                "|",
+                "Remove-VMHardDiskDrive",
+                "-Passthru",
+                "|",
+                "Measure-Object",
+                "|",
+                "Select-Object",
+                "-ExpandProperty",
+                "Count"
+            ]
+        );
+    }
+
+    #[test]
+    fn add_network_adapter_args() {
+        let vmid = Guid::new_random();
+        let vmid_arg = format!("\"{vmid}\"");
+        assert_eq!(
+            args(&add_vm_network_adapter_cmd(&vmid, None, Some("External")))[1..],
+            [
+                "Get-VM",
+                "-Id",
+                vmid_arg.as_str(),
+                "|",
+                "Add-VMNetworkAdapter",
+                "-SwitchName",
+                "\"External\""
+            ]
+        );
+        let args = args(&add_vm_network_adapter_cmd(&vmid, Some("nic0"), None));
+        let pos = args.iter().position(|a| a == "-Name").unwrap();
+        assert_eq!(args[pos + 1], "\"nic0\"");
+        assert!(!args.iter().any(|a| a == "-SwitchName"));
+    }
+
+    #[test]
+    fn add_disk_rejects_path_and_disk_number() {
+        let vmid = Guid::new_random();
+        add_vm_hard_disk_drive_cmd(&HyperVAddVMHardDiskDriveArgs {
+            vmid: &vmid,
+            controller_type: ControllerType::Scsi,
+            controller_location: None,
+            controller_number: None,
+            path: Some(Path::new("disk.vhdx")),
+            disk_number: Some(3),
+        })
+        .unwrap_err();
+    }
+}
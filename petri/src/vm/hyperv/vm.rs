@@ -8,77 +8,168 @@
 use super::powershell;
 use crate::OpenHclServicingFlags;
 use crate::PetriLogFile;
+use crate::vm::ApicMode;
+use crate::vm::ProcessorTopology;
+use crate::vm::StagedDeadline;
 use anyhow::Context;
 use get_resources::ged::FirmwareEvent;
 use guid::Guid;
 use jiff::Timestamp;
-use jiff::ToSpan;
 use pal_async::DefaultDriver;
 use pal_async::timer::PolledTimer;
+use petri_artifacts_common::tags::MachineArch;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::Stdio;
 use std::time::Duration;
+use std::time::Instant;
 use tempfile::TempDir;
 use thiserror::Error;
 use tracing::Level;
+use vmm_core_defs::HaltReason;
 
 /// A Hyper-V VM
 pub struct HyperVVM {
     name: String,
     vmid: Guid,
+    arch: MachineArch,
+    generation: powershell::HyperVGeneration,
+    /// Whether the VM was created by petri, rather than attached to, and so
+    /// should be removed when it is no longer needed.
+    created: bool,
     destroyed: bool,
     _temp_dir: TempDir,
     ps_mod: PathBuf,
     create_time: Timestamp,
+    last_halt_time: Timestamp,
     log_file: PetriLogFile,
     expected_boot_event: Option<FirmwareEvent>,
     driver: DefaultDriver,
+    watchdog: Watchdog,
+}
+
+/// How long to wait for a Hyper-V VM to reach a state, such as booting or
+/// turning off, and how often to check it.
+///
+/// Each operation has one overall deadline, which is shared by all the
+/// states it waits for (saving a VM waits for it to be running and then
+/// saved, for example) and includes the time spent checking them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchdog {
+    /// How long to wait before giving up.
+    pub timeout: Duration,
+    /// How long to sleep between checks.
+    pub poll_interval: Duration,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(240),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The environment variable that overrides the default watchdog timeout, in
+/// seconds.
+const WATCHDOG_TIMEOUT_ENV: &str = "PETRI_HYPERV_TIMEOUT_SECS";
+
+impl Watchdog {
+    /// Returns the default watchdog, with the timeout overridden by the
+    /// `PETRI_HYPERV_TIMEOUT_SECS` environment variable if it is set. This
+    /// allows short timeouts for local runs and longer ones in CI.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut watchdog = Self::default();
+        if let Ok(timeout) = std::env::var(WATCHDOG_TIMEOUT_ENV) {
+            watchdog.timeout = parse_timeout_secs(&timeout)
+                .with_context(|| format!("invalid {WATCHDOG_TIMEOUT_ENV}"))?;
+        }
+        Ok(watchdog)
+    }
+
+    /// Returns the deadline of an operation starting now.
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Returns how long to sleep before checking again at `now`, or `None`
+    /// if `deadline` has passed.
+    fn next_poll(&self, now: Instant, deadline: Instant) -> Option<Duration> {
+        let remaining = deadline.checked_duration_since(now)?;
+        (!remaining.is_zero()).then(|| remaining.min(self.poll_interval))
+    }
+}
+
+fn parse_timeout_secs(s: &str) -> anyhow::Result<Duration> {
+    let secs = s.trim().parse::<u64>()?;
+    if secs == 0 {
+        anyhow::bail!("timeout must be nonzero");
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// The initial configuration of a new Hyper-V VM
+pub struct InitialVmConfig<'a> {
+    /// The name of the VM
+    pub name: &'a str,
+    /// The guest architecture
+    pub arch: MachineArch,
+    /// The VM generation
+    pub generation: powershell::HyperVGeneration,
+    /// The guest state isolation type
+    pub guest_state_isolation_type: powershell::HyperVGuestStateIsolationType,
+    /// The amount of memory, in bytes, to assign to the VM
+    pub memory: u64,
+    /// Keep the network adapter and SCSI controller that Hyper-V adds to new
+    /// VMs, rather than removing them
+    pub keep_default_devices: bool,
+    /// The VM group to add the VM to, which is created if needed
+    pub group: Option<&'a str>,
 }
 
 impl HyperVVM {
     /// Create a new Hyper-V VM
-    pub fn new(
-        name: &str,
-        generation: powershell::HyperVGeneration,
-        guest_state_isolation_type: powershell::HyperVGuestStateIsolationType,
-        memory: u64,
+    pub async fn new(
+        config: InitialVmConfig<'_>,
         log_file: PetriLogFile,
         expected_boot_event: Option<FirmwareEvent>,
         driver: DefaultDriver,
     ) -> anyhow::Result<Self> {
+        let InitialVmConfig {
+            name,
+            arch,
+            generation,
+            guest_state_isolation_type,
+            memory,
+            keep_default_devices,
+            group,
+        } = config;
         let create_time = Timestamp::now();
         let name = name.to_owned();
-        let temp_dir = tempfile::tempdir()?;
-        let ps_mod = temp_dir.path().join("hyperv.psm1");
-        {
-            let mut ps_mod_file = std::fs::File::create_new(&ps_mod)?;
-            ps_mod_file
-                .write_all(include_bytes!("hyperv.psm1"))
-                .context("failed to write hyperv helpers powershell module")?;
-        }
+        let (temp_dir, ps_mod) = write_ps_mod()?;
 
         // Delete the VM if it already exists
-        let cleanup = |vmid: &Guid| -> anyhow::Result<()> {
-            hvc::hvc_ensure_off(vmid)?;
-            powershell::run_remove_vm(vmid)
-        };
-
-        if let Ok(vmids) = powershell::vm_id_from_name(&name) {
-            for vmid in vmids {
-                match cleanup(&vmid) {
-                    Ok(_) => {
-                        tracing::info!("Successfully cleaned up VM from previous test run ({vmid})")
-                    }
-                    Err(e) => {
-                        tracing::warn!(
-                            "Failed to clean up VM from previous test run ({vmid}): {e:?}"
-                        )
+        match powershell::vm_id_from_name(&name) {
+            Ok(vmids) => {
+                for vmid in vmids {
+                    match force_remove_vm(&driver, &vmid, false).await {
+                        Ok(_) => {
+                            tracing::info!(
+                                "Successfully cleaned up VM from previous test run ({vmid})"
+                            )
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "Failed to clean up VM from previous test run ({vmid}): {e:?}"
+                            )
+                        }
                     }
                 }
             }
+            Err(e) => tracing::warn!("Failed to list VMs from previous test runs: {e:?}"),
         }
 
         let vmid = powershell::run_new_vm(powershell::HyperVNewVMArgs {
@@ -97,22 +188,39 @@ pub fn new(
         let this = Self {
             name,
             vmid,
+            arch,
+            generation,
+            created: true,
             destroyed: false,
             _temp_dir: temp_dir,
             ps_mod,
             create_time,
+            last_halt_time: create_time,
             log_file,
             expected_boot_event,
             driver,
+            watchdog: Watchdog::default(),
         };
 
-        // Remove the default network adapter
-        powershell::run_remove_vm_network_adapter(&vmid)
-            .context("remove default network adapter")?;
+        if let Some(group) = group {
+            powershell::run_ensure_vm_group(group)?;
+            powershell::run_add_vm_group_member(&vmid, group)
+                .with_context(|| format!("add VM to group {group}"))?;
+        }
+
+        if !keep_default_devices {
+            // Remove the default network adapter
+            powershell::run_remove_vm_network_adapter(&vmid)
+                .context("remove default network adapter")?;
 
-        // Remove the default SCSI controller
-        powershell::run_remove_vm_scsi_controller(&vmid, 0)
-            .context("remove default SCSI controller")?;
+            // Remove the default SCSI controller
+            powershell::run_remove_vm_scsi_controller(&vmid, 0)
+                .context("remove default SCSI controller")?;
+        }
+
+        // Meter the VM's resource usage for the whole run, so it can be
+        // reported at teardown.
+        powershell::run_enable_vm_resource_metering(&vmid)?;
 
         // Disable dynamic memory
         powershell::run_set_vm_memory(
@@ -129,12 +237,66 @@ pub fn new(
                 vmid: &vmid,
                 secure_boot_enabled: Some(false),
                 secure_boot_template: None,
+                boot_order: None,
             })?;
         }
 
         Ok(this)
     }
 
+    /// Attach to an existing Hyper-V VM, specified by name or VM ID, instead
+    /// of creating a new one.
+    ///
+    /// The VM's configuration is left as-is, and the VM is not removed when
+    /// this is dropped.
+    pub fn attach(
+        name_or_id: &str,
+        log_file: PetriLogFile,
+        expected_boot_event: Option<FirmwareEvent>,
+        driver: DefaultDriver,
+    ) -> anyhow::Result<Self> {
+        let id = name_or_id.parse::<Guid>().ok();
+        let vms = powershell::vm_list()?;
+        let mut matches = vms
+            .iter()
+            .filter(|vm| vm.name == name_or_id || Some(vm.vmid) == id);
+        let vmid = match (matches.next(), matches.next()) {
+            (Some(vm), None) => vm.vmid,
+            (None, _) => anyhow::bail!("no VM named {name_or_id}"),
+            (Some(_), Some(_)) => anyhow::bail!("more than one VM named {name_or_id}"),
+        };
+        let (name, generation) = powershell::vm_name_and_generation(&vmid)
+            .with_context(|| format!("failed to find VM {name_or_id}"))?;
+        let (temp_dir, ps_mod) = write_ps_mod()?;
+        let attach_time = Timestamp::now();
+
+        tracing::info!(name, vmid = vmid.to_string(), "Attached to Hyper-V VM");
+
+        Ok(Self {
+            name,
+            vmid,
+            // Hyper-V only runs guests of the host's architecture.
+            arch: MachineArch::host(),
+            generation,
+            created: false,
+            destroyed: false,
+            _temp_dir: temp_dir,
+            ps_mod,
+            create_time: attach_time,
+            last_halt_time: attach_time,
+            log_file,
+            expected_boot_event,
+            driver,
+            watchdog: Watchdog::default(),
+        })
+    }
+
+    /// Set how long to wait for each operation on the VM to complete, and how
+    /// often to check.
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = watchdog;
+    }
+
     /// Get the name of the VM
     pub fn name(&self) -> &str {
         &self.name
@@ -169,9 +331,13 @@ pub fn flush_logs(&self) -> anyhow::Result<()> {
     /// verifies that it is the expected success value.
     pub async fn wait_for_successful_boot_event(&mut self) -> anyhow::Result<()> {
         if let Some(expected_boot_event) = self.expected_boot_event {
-            self.wait_for(Self::boot_event, Some(expected_boot_event), 240.seconds())
-                .await
-                .context("wait_for_successful_boot_event")?;
+            self.wait_for(
+                Self::boot_event,
+                Some(expected_boot_event),
+                self.watchdog.deadline(),
+            )
+            .await
+            .context("wait_for_successful_boot_event")?;
         } else {
             tracing::warn!("Configured firmware does not emit a boot event, skipping");
         }
@@ -182,7 +348,8 @@ pub async fn wait_for_successful_boot_event(&mut self) -> anyhow::Result<()> {
     /// Waits for an event emitted by the firmware about its boot status, and
     /// returns that status.
     pub async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent> {
-        self.wait_for_some(Self::boot_event, 240.seconds()).await
+        self.wait_for_some(Self::boot_event, self.watchdog.deadline())
+            .await
     }
 
     fn boot_event(&self) -> anyhow::Result<Option<FirmwareEvent>> {
@@ -208,11 +375,65 @@ fn boot_event(&self) -> anyhow::Result<Option<FirmwareEvent>> {
     }
 
     /// Set the VM processor topology.
-    pub fn set_processor(
-        &mut self,
-        args: &powershell::HyperVSetVMProcessorArgs,
+    pub fn set_processor(&mut self, topology: &ProcessorTopology) -> anyhow::Result<()> {
+        powershell::run_set_vm_processor(
+            &self.vmid,
+            &set_processor_args(topology, self.arch, self.generation),
+        )
+    }
+
+    /// Expose the hardware virtualization extensions to the guest, so that it
+    /// can run its own hypervisor. The VM must be off.
+    ///
+    /// This is only supported for generation 2 x86_64 VMs without dynamic
+    /// memory.
+    pub fn enable_nested_virtualization(&mut self) -> anyhow::Result<()> {
+        let dynamic_memory = powershell::vm_dynamic_memory_enabled(&self.vmid)?;
+        check_nested_virtualization(self.arch, self.generation, dynamic_memory)?;
+        powershell::run_set_vm_processor(
+            &self.vmid,
+            &powershell::HyperVSetVMProcessorArgs {
+                expose_virtualization_extensions: Some(true),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Enable or disable the integration service `name`, such as
+    /// `"Shutdown"`, `"Heartbeat"`, or `"Time Synchronization"`.
+    pub fn set_integration_service(&self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        if enabled {
+            powershell::run_enable_vm_integration_service(&self.vmid, name)
+        } else {
+            powershell::run_disable_vm_integration_service(&self.vmid, name)
+        }
+    }
+
+    /// Configure the VM's memory, such as enabling dynamic memory or changing
+    /// its range. Hyper-V only allows some of these to change while the VM
+    /// is running.
+    pub fn set_memory(&self, args: &powershell::HyperVSetVMMemoryArgs) -> anyhow::Result<()> {
+        powershell::run_set_vm_memory(&self.vmid, args)
+    }
+
+    /// Set the kind of checkpoint taken by [`Self::checkpoint`].
+    pub fn set_checkpoint_type(
+        &self,
+        checkpoint_type: powershell::HyperVCheckpointType,
     ) -> anyhow::Result<()> {
-        powershell::run_set_vm_processor(&self.vmid, args)
+        powershell::run_set_vm_checkpoint_type(&self.vmid, checkpoint_type)
+    }
+
+    /// Take a checkpoint of the VM named `name`.
+    pub fn checkpoint(&self, name: &str) -> anyhow::Result<()> {
+        powershell::run_checkpoint_vm(&self.vmid, name)
+            .with_context(|| format!("failed to checkpoint VM {}", self.name))
+    }
+
+    /// Restore the VM to the checkpoint named `name`.
+    pub fn restore_checkpoint(&self, name: &str) -> anyhow::Result<()> {
+        powershell::run_restore_vm_checkpoint(&self.vmid, name)
+            .with_context(|| format!("failed to restore VM {} to checkpoint {name}", self.name))
     }
 
     /// Set the OpenHCL firmware file
@@ -239,6 +460,28 @@ pub fn set_secure_boot(
             vmid: &self.vmid,
             secure_boot_enabled: Some(secure_boot_enabled),
             secure_boot_template,
+            boot_order: None,
+        })
+    }
+
+    /// Set the order in which the UEFI firmware tries each boot device. Only
+    /// supported for generation 2 VMs.
+    ///
+    /// The firmware boots from the first device that succeeds, falling back
+    /// to the next one when a device fails to boot. Devices that are left out
+    /// are removed from the boot order.
+    pub fn set_boot_order(
+        &self,
+        order: &[powershell::HyperVFirmwareBootDevice],
+    ) -> anyhow::Result<()> {
+        if self.generation != powershell::HyperVGeneration::Two {
+            anyhow::bail!("UEFI boot order is only supported on generation 2 VMs");
+        }
+        powershell::run_set_vm_firmware(powershell::HyperVSetVMFirmwareArgs {
+            vmid: &self.vmid,
+            secure_boot_enabled: None,
+            secure_boot_template: None,
+            boot_order: Some(order),
         })
     }
 
@@ -256,6 +499,21 @@ pub fn add_scsi_controller(&mut self, target_vtl: u32) -> anyhow::Result<u32> {
         Ok(controller_number)
     }
 
+    /// Add a network adapter, optionally naming it and connecting it to the
+    /// virtual switch `switch_name`.
+    pub fn add_network_adapter(
+        &mut self,
+        name: Option<&str>,
+        switch_name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        powershell::run_add_vm_network_adapter(&self.vmid, name, switch_name)
+    }
+
+    /// Get the number of SCSI controllers attached to the VM
+    pub fn scsi_controller_count(&self) -> anyhow::Result<u32> {
+        powershell::vm_scsi_controller_count(&self.vmid)
+    }
+
     /// Add a VHD
     pub fn add_vhd(
         &mut self,
@@ -270,6 +528,59 @@ pub fn add_vhd(
             controller_location,
             controller_number,
             path: Some(path),
+            disk_number: None,
+        })
+    }
+
+    /// Replace the VHD attached at an existing controller location.
+    pub fn set_vhd(
+        &mut self,
+        path: &Path,
+        controller_type: powershell::ControllerType,
+        controller_location: u32,
+        controller_number: u32,
+    ) -> anyhow::Result<()> {
+        powershell::run_set_vm_hard_disk_drive_path(
+            &self.vmid,
+            controller_type,
+            controller_number,
+            controller_location,
+            path,
+        )
+    }
+
+    /// Remove the hard disk drive at an existing controller location, which
+    /// hot-unplugs it if the VM is running.
+    pub fn remove_vhd(
+        &mut self,
+        controller_type: powershell::ControllerType,
+        controller_number: u32,
+        controller_location: u32,
+    ) -> anyhow::Result<()> {
+        powershell::run_remove_vm_hard_disk_drive(
+            &self.vmid,
+            controller_type,
+            controller_number,
+            controller_location,
+        )
+    }
+
+    /// Pass through an offline physical disk. This must be explicitly
+    /// allowed by setting `PETRI_ALLOW_PHYSICAL_DISKS`.
+    pub fn add_physical_disk(
+        &mut self,
+        disk_number: u32,
+        controller_type: powershell::ControllerType,
+        controller_location: Option<u32>,
+        controller_number: Option<u32>,
+    ) -> anyhow::Result<()> {
+        powershell::run_add_vm_hard_disk_drive(powershell::HyperVAddVMHardDiskDriveArgs {
+            vmid: &self.vmid,
+            controller_type,
+            controller_location,
+            controller_number,
+            path: None,
+            disk_number: Some(disk_number),
         })
     }
 
@@ -278,7 +589,58 @@ pub fn set_imc(&mut self, imc_hive: &Path) -> anyhow::Result<()> {
         powershell::run_set_initial_machine_configuration(&self.vmid, &self.ps_mod, imc_hive)
     }
 
-    fn state(&self) -> anyhow::Result<VmState> {
+    /// Set the order in which the PCAT BIOS tries each boot device. Only
+    /// supported for generation 1 VMs, which must be off.
+    pub fn set_bios_startup_order(
+        &self,
+        order: &[powershell::HyperVBootDevice],
+    ) -> anyhow::Result<()> {
+        self.check_generation_one()?;
+        powershell::run_set_vm_bios(powershell::HyperVSetVMBiosArgs {
+            vmid: &self.vmid,
+            startup_order: Some(order),
+            num_lock: None,
+        })
+    }
+
+    /// Insert a virtual floppy disk (.vfd) into the VM's floppy drive, or
+    /// eject the current one if `path` is `None`. Only supported for
+    /// generation 1 VMs.
+    pub fn set_floppy(&self, path: Option<&Path>) -> anyhow::Result<()> {
+        self.check_generation_one()?;
+        powershell::run_set_vm_floppy_disk_drive(&self.vmid, path)
+    }
+
+    /// Insert an ISO file or physical drive into the DVD drive at an existing
+    /// controller location, or eject the current media if `path` is `None`.
+    /// This works while the VM is running, to simulate swapping discs.
+    pub fn set_dvd_media(
+        &mut self,
+        controller_number: u32,
+        controller_location: u32,
+        path: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        powershell::run_set_vm_dvd_drive(&self.vmid, controller_number, controller_location, path)
+    }
+
+    /// Remove the DVD drive at an existing controller location.
+    pub fn remove_dvd_drive(
+        &mut self,
+        controller_number: u32,
+        controller_location: u32,
+    ) -> anyhow::Result<()> {
+        powershell::run_remove_vm_dvd_drive(&self.vmid, controller_number, controller_location)
+    }
+
+    fn check_generation_one(&self) -> anyhow::Result<()> {
+        if self.generation != powershell::HyperVGeneration::One {
+            anyhow::bail!("legacy BIOS devices are only supported on generation 1 VMs");
+        }
+        Ok(())
+    }
+
+    /// Get the current state of the VM
+    pub fn state(&self) -> anyhow::Result<VmState> {
         hvc::hvc_state(&self.vmid)
     }
 
@@ -298,19 +660,33 @@ pub fn start(&self) -> anyhow::Result<()> {
     }
 
     /// Attempt to gracefully shut down the VM
-    pub fn stop(&self) -> anyhow::Result<()> {
-        self.check_shutdown_ic()?;
-        self.check_state(VmState::Running)?;
-        hvc::hvc_stop(&self.vmid)?;
-        Ok(())
+    pub async fn stop(&self) -> anyhow::Result<()> {
+        when_running(
+            &self.driver,
+            &self.watchdog,
+            || self.state(),
+            || {
+                self.check_shutdown_ic()?;
+                hvc::hvc_stop(&self.vmid)?;
+                Ok(())
+            },
+        )
+        .await
     }
 
     /// Attempt to gracefully restart the VM
-    pub fn restart(&self) -> anyhow::Result<()> {
-        self.check_shutdown_ic()?;
-        self.check_state(VmState::Running)?;
-        hvc::hvc_restart(&self.vmid)?;
-        Ok(())
+    pub async fn restart(&self) -> anyhow::Result<()> {
+        when_running(
+            &self.driver,
+            &self.watchdog,
+            || self.state(),
+            || {
+                self.check_shutdown_ic()?;
+                hvc::hvc_restart(&self.vmid)?;
+                Ok(())
+            },
+        )
+        .await
     }
 
     /// Kill the VM
@@ -318,11 +694,48 @@ pub fn kill(&self) -> anyhow::Result<()> {
         hvc::hvc_kill(&self.vmid).context("hvc_kill")
     }
 
+    /// Wait for the VM to be off
+    pub async fn wait_for_off(&self) -> anyhow::Result<()> {
+        self.wait_for_state(VmState::Off, self.watchdog.deadline())
+            .await
+    }
+
     /// Issue a hard reset to the VM
     pub fn reset(&self) -> anyhow::Result<()> {
         hvc::hvc_reset(&self.vmid).context("hvc_reset")
     }
 
+    /// Save the VM's state to disk, leaving it in the saved state
+    pub async fn save(&self) -> anyhow::Result<()> {
+        // Don't race a VM that is still starting
+        let deadline = self.watchdog.deadline();
+        self.wait_for_state(VmState::Running, deadline).await?;
+        hvc::hvc_save(&self.vmid).context("hvc_save")?;
+        self.wait_for_state(VmState::Saved, deadline).await
+    }
+
+    /// Pause the VM
+    pub async fn pause(&self) -> anyhow::Result<()> {
+        // Don't race a VM that is still starting
+        let deadline = self.watchdog.deadline();
+        self.wait_for_state(VmState::Running, deadline).await?;
+        hvc::hvc_pause(&self.vmid).context("hvc_pause")?;
+        self.wait_for_state(VmState::Paused, deadline).await
+    }
+
+    /// Resume a paused VM, or restore a saved one, and wait for it to be
+    /// running
+    pub async fn resume(&self) -> anyhow::Result<()> {
+        let deadline = self.watchdog.deadline();
+        match self.state()? {
+            VmState::Paused => hvc::hvc_resume(&self.vmid).context("hvc_resume")?,
+            // Hyper-V restores a saved VM when it is started.
+            VmState::Saved => hvc::hvc_start(&self.vmid).context("hvc_start")?,
+            state => anyhow::bail!("cannot resume VM in state {state:?}"),
+        }
+        self.wait_for_state(VmState::Running, deadline).await
+    }
+
     /// Enable serial output and return the named pipe path
     pub fn set_vm_com_port(&mut self, port: u8) -> anyhow::Result<String> {
         let pipe_path = format!(r#"\\.\pipe\{}-{}"#, self.vmid, port);
@@ -330,13 +743,56 @@ pub fn set_vm_com_port(&mut self, port: u8) -> anyhow::Result<String> {
         Ok(pipe_path)
     }
 
-    /// Wait for the VM to stop
-    pub async fn wait_for_halt(&self) -> anyhow::Result<()> {
-        self.wait_for_state(VmState::Off).await
+    /// Wait for the VM to halt or restart, returning the reason.
+    ///
+    /// Hyper-V restarts the VM on its own after a reset or triple fault, so
+    /// these are detected from the worker process event log rather than the
+    /// VM state.
+    pub async fn wait_for_halt(&mut self) -> anyhow::Result<HaltReason> {
+        let (halt_reason, time) = match self
+            .wait_for_some(Self::halt_event, self.watchdog.deadline())
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                let state = self.state()?;
+                return Err(e).context(format!(
+                    "VM did not halt, guest may be hung (state: {state:?})"
+                ));
+            }
+        };
+        self.last_halt_time = time;
+        Ok(halt_reason)
+    }
+
+    /// Ignore any halt events logged before now, for example because the
+    /// caller has already observed that the guest restarted.
+    pub fn clear_halt_events(&mut self) {
+        self.last_halt_time = Timestamp::now();
     }
 
-    async fn wait_for_state(&self, target: VmState) -> anyhow::Result<()> {
-        self.wait_for(Self::state, target, 240.seconds())
+    fn halt_event(&self) -> anyhow::Result<Option<(HaltReason, Timestamp)>> {
+        // Get-WinEvent returns the newest events first, but the halts must be
+        // reported in the order they happened.
+        let event = powershell::hyperv_halt_events(&self.vmid, &self.last_halt_time)?
+            .into_iter()
+            .filter(|e| e.time_created > self.last_halt_time)
+            .min_by_key(|e| e.time_created);
+
+        if let Some(event) = event {
+            return Ok(Some((halt_reason_from_event(&event)?, event.time_created)));
+        }
+
+        // The VM may have been turned off without an event being logged
+        if self.state()? == VmState::Off {
+            return Ok(Some((HaltReason::PowerOff, Timestamp::now())));
+        }
+
+        Ok(None)
+    }
+
+    async fn wait_for_state(&self, target: VmState, deadline: Instant) -> anyhow::Result<()> {
+        self.wait_for(Self::state, target, deadline)
             .await
             .context("wait_for_state")
     }
@@ -345,67 +801,85 @@ async fn wait_for_state(&self, target: VmState) -> anyhow::Result<()> {
     pub async fn wait_for_enlightened_shutdown_ready(&self) -> anyhow::Result<()> {
         self.wait_for(
             Self::shutdown_ic_status,
-            powershell::VmShutdownIcStatus::Ok,
-            240.seconds(),
+            powershell::VmIcStatus::Ok,
+            self.watchdog.deadline(),
         )
         .await
         .context("wait_for_enlightened_shutdown_ready")
     }
 
-    fn shutdown_ic_status(&self) -> anyhow::Result<powershell::VmShutdownIcStatus> {
+    /// Wait for the VM heartbeat ic to report that the guest is healthy
+    pub async fn wait_for_heartbeat(&self) -> anyhow::Result<()> {
+        self.wait_for(
+            Self::heartbeat_ic_status,
+            powershell::VmIcStatus::Ok,
+            self.watchdog.deadline(),
+        )
+        .await
+        .context("wait_for_heartbeat")
+    }
+
+    /// Waits for the firmware to report a successful boot and then for the
+    /// guest's heartbeat to be healthy, with both stages sharing `timeout`.
+    ///
+    /// Pipette readiness is not checked here, since the agent connection is
+    /// owned by the petri runtime; see
+    /// [`PetriVm::wait_for_successful_boot`](crate::PetriVm::wait_for_successful_boot).
+    pub async fn wait_for_successful_boot(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        let mut deadline = StagedDeadline::new(timeout);
+        deadline
+            .stage("boot event", self.wait_for_successful_boot_event())
+            .await?;
+        deadline
+            .stage("heartbeat", self.wait_for_heartbeat())
+            .await?;
+        Ok(())
+    }
+
+    fn heartbeat_ic_status(&self) -> anyhow::Result<powershell::VmIcStatus> {
+        powershell::vm_heartbeat_ic_status(&self.vmid)
+    }
+
+    fn shutdown_ic_status(&self) -> anyhow::Result<powershell::VmIcStatus> {
         powershell::vm_shutdown_ic_status(&self.vmid)
     }
 
     fn check_shutdown_ic(&self) -> anyhow::Result<()> {
         let status = self.shutdown_ic_status()?;
-        if status != powershell::VmShutdownIcStatus::Ok {
+        if status != powershell::VmIcStatus::Ok {
             anyhow::bail!("unexpected shutdown ic status {status:?}, should be Ok");
         }
         Ok(())
     }
 
-    // TODO: replace timeouts throughout the hyper-v petri infrastructure
-    // with a watchdog
+    /// Polls `f` until it returns `target`, giving up at `deadline`.
     async fn wait_for<T: std::fmt::Debug + PartialEq>(
         &self,
         f: fn(&Self) -> anyhow::Result<T>,
         target: T,
-        timeout: jiff::Span,
+        deadline: Instant,
     ) -> anyhow::Result<()> {
-        let start = Timestamp::now();
-        loop {
-            let state = f(self)?;
-            if state == target {
-                break;
-            }
-            if timeout.compare(Timestamp::now() - start)? == std::cmp::Ordering::Less {
-                anyhow::bail!("timed out waiting for {target:?}. current: {state:?}");
-            }
-            PolledTimer::new(&self.driver)
-                .sleep(Duration::from_secs(1))
-                .await;
-        }
-
-        Ok(())
+        poll_until(&self.driver, &self.watchdog, || f(self), target, deadline).await
     }
 
+    /// Polls `f` until it returns `Some`, giving up at `deadline`.
     async fn wait_for_some<T: std::fmt::Debug + PartialEq>(
         &self,
         f: fn(&Self) -> anyhow::Result<Option<T>>,
-        timeout: jiff::Span,
+        deadline: Instant,
     ) -> anyhow::Result<T> {
-        let start = Timestamp::now();
         loop {
             let state = f(self)?;
             if let Some(state) = state {
                 return Ok(state);
             }
-            if timeout.compare(Timestamp::now() - start)? == std::cmp::Ordering::Less {
-                anyhow::bail!("timed out waiting for Some");
-            }
-            PolledTimer::new(&self.driver)
-                .sleep(Duration::from_secs(1))
-                .await;
+            let Some(poll) = self.watchdog.next_poll(Instant::now(), deadline) else {
+                anyhow::bail!(
+                    "timed out after {:?} waiting for Some",
+                    self.watchdog.timeout
+                );
+            };
+            PolledTimer::new(&self.driver).sleep(poll).await;
         }
     }
 
@@ -414,8 +888,46 @@ pub fn remove(mut self) -> anyhow::Result<()> {
         self.remove_inner()
     }
 
+    /// Remove the VM, even if it is stuck in a transition state such as
+    /// `Stopping` or `Saving`.
+    pub async fn force_remove(mut self) -> anyhow::Result<()> {
+        if !self.created {
+            return self.remove_inner();
+        }
+        if !self.destroyed {
+            let res = force_remove_vm(&self.driver, &self.vmid, false).await;
+            self.flush_logs()?;
+            res?;
+            self.destroyed = true;
+        }
+        Ok(())
+    }
+
+    /// Get the VM's resource usage since metering was enabled.
+    pub fn metrics(&self) -> anyhow::Result<powershell::VmMetrics> {
+        powershell::run_measure_vm(&self.vmid)
+    }
+
+    fn log_metrics(&self) {
+        match self.metrics() {
+            Ok(metrics) => tracing::info!(?metrics, "Hyper-V VM resource usage"),
+            Err(err) => tracing::warn!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to measure Hyper-V VM resource usage"
+            ),
+        }
+    }
+
     fn remove_inner(&mut self) -> anyhow::Result<()> {
+        if !self.created && !self.destroyed {
+            // Leave VMs that petri attached to in place.
+            tracing::info!(name = %self.name, "Leaving attached Hyper-V VM in place");
+            self.destroyed = true;
+            return self.flush_logs();
+        }
         if !self.destroyed {
+            // Metrics are lost once the VM is removed.
+            self.log_metrics();
             let res_off = hvc::hvc_ensure_off(&self.vmid);
             let res_remove = powershell::run_remove_vm(&self.vmid);
 
@@ -456,6 +968,289 @@ fn drop(&mut self) {
     }
 }
 
+/// Write the Hyper-V helpers powershell module to a new temporary directory,
+/// returning the directory and the path to the module.
+fn write_ps_mod() -> anyhow::Result<(TempDir, PathBuf)> {
+    let temp_dir = tempfile::tempdir()?;
+    let ps_mod = temp_dir.path().join("hyperv.psm1");
+    {
+        let mut ps_mod_file = std::fs::File::create_new(&ps_mod)?;
+        ps_mod_file
+            .write_all(include_bytes!("hyperv.psm1"))
+            .context("failed to write hyperv helpers powershell module")?;
+    }
+    Ok((temp_dir, ps_mod))
+}
+
+/// Remove all the VMs in the VM group `group`, and then the group itself.
+///
+/// This is useful for reclaiming the resources of VMs that were not cleaned
+/// up, for example because the test process was killed. If
+/// `delete_config_files` is set, the configuration files of VMs that can't be
+/// removed are deleted as a last resort.
+pub async fn remove_vm_group(
+    driver: &DefaultDriver,
+    group: &str,
+    delete_config_files: bool,
+) -> anyhow::Result<()> {
+    let mut result = Ok(());
+    for vmid in powershell::vm_group_member_ids(group)? {
+        match force_remove_vm(driver, &vmid, delete_config_files).await {
+            Ok(()) => tracing::info!(group, %vmid, "removed VM in group"),
+            Err(e) => {
+                tracing::warn!(group, %vmid, "failed to remove VM in group: {e:?}");
+                result = result.and(Err(e));
+            }
+        }
+    }
+    result.context("failed to remove VMs in group")?;
+    powershell::run_remove_vm_group(group)
+}
+
+/// Petri names VMs after the test that created them, which always includes
+/// the test's module path.
+const TEST_VM_NAME_SEPARATOR: &str = "::";
+
+/// Returns whether `name` is the name of a VM created by petri that starts
+/// with `prefix`.
+fn is_test_vm_name(name: &str, prefix: &str) -> bool {
+    name.starts_with(prefix) && name.contains(TEST_VM_NAME_SEPARATOR)
+}
+
+/// Remove the VMs created by petri whose names start with `vm_prefix`, such
+/// as ones left behind by test runs that were killed. If `network_prefix` is
+/// set, also remove the virtual switches and NAT rules whose names start with
+/// it; these are named independently of the VMs. If `delete_config_files` is
+/// set, the configuration files of VMs that can't be removed are deleted as a
+/// last resort.
+pub async fn remove_leftover_test_resources(
+    driver: &DefaultDriver,
+    vm_prefix: &str,
+    network_prefix: Option<&str>,
+    delete_config_files: bool,
+) -> anyhow::Result<()> {
+    if network_prefix.is_some_and(|prefix| prefix.is_empty()) {
+        anyhow::bail!("a name prefix is required to remove switches and NAT rules");
+    }
+
+    let mut result = Ok(());
+    let vms = powershell::vm_list()?;
+    for vm in vms.iter().filter(|vm| is_test_vm_name(&vm.name, vm_prefix)) {
+        match force_remove_vm(driver, &vm.vmid, delete_config_files).await {
+            Ok(()) => tracing::info!(name = %vm.name, vmid = %vm.vmid, "removed leftover VM"),
+            Err(e) => {
+                tracing::warn!(
+                    name = %vm.name,
+                    vmid = %vm.vmid,
+                    "failed to remove leftover VM: {e:?}"
+                );
+                result = result.and(Err(e));
+            }
+        }
+    }
+    result.context("failed to remove leftover VMs")?;
+
+    if let Some(prefix) = network_prefix {
+        powershell::run_remove_vm_switches(prefix)?;
+        powershell::run_remove_net_nats(prefix)?;
+    }
+    Ok(())
+}
+
+/// Polls `f` until it returns `target`, sleeping between polls according to
+/// `watchdog` and giving up at `deadline`.
+async fn poll_until<T: std::fmt::Debug + PartialEq>(
+    driver: &DefaultDriver,
+    watchdog: &Watchdog,
+    mut f: impl FnMut() -> anyhow::Result<T>,
+    target: T,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    loop {
+        let state = f()?;
+        if state == target {
+            break;
+        }
+        let Some(poll) = watchdog.next_poll(Instant::now(), deadline) else {
+            anyhow::bail!(
+                "timed out after {:?} waiting for {target:?}. current: {state:?}",
+                watchdog.timeout
+            );
+        };
+        PolledTimer::new(driver).sleep(poll).await;
+    }
+
+    Ok(())
+}
+
+/// Waits for `state` to report that the VM is running, and then runs `f`, so
+/// that `f` does not race a VM that is still starting.
+async fn when_running<R>(
+    driver: &DefaultDriver,
+    watchdog: &Watchdog,
+    state: impl FnMut() -> anyhow::Result<VmState>,
+    f: impl FnOnce() -> anyhow::Result<R>,
+) -> anyhow::Result<R> {
+    poll_until(
+        driver,
+        watchdog,
+        state,
+        VmState::Running,
+        watchdog.deadline(),
+    )
+    .await
+    .context("wait_for_state")?;
+    f()
+}
+
+/// Kill and remove a VM, retrying with backoff, and then delete whatever
+/// Remove-VM left behind of its configuration and saved state. If the VM
+/// still can't be removed and `delete_config_files` is set, delete its
+/// configuration files as a last resort.
+async fn force_remove_vm(
+    driver: &DefaultDriver,
+    vmid: &Guid,
+    delete_config_files: bool,
+) -> anyhow::Result<()> {
+    // Get this up front since it can't be queried once things go wrong
+    let config_location = powershell::vm_configuration_location(vmid);
+
+    let res = retry_with_backoff(
+        driver,
+        FORCE_REMOVE_ATTEMPTS,
+        Duration::from_secs(1),
+        || {
+            if hvc::hvc_state(vmid)? != VmState::Off {
+                if let Err(e) = hvc::hvc_kill(vmid) {
+                    tracing::warn!("hvc_kill attempt failed: {e}")
+                }
+            }
+            powershell::run_remove_vm(vmid)
+        },
+    )
+    .await;
+
+    let removed = match res {
+        Ok(()) => true,
+        Err(e) if powershell::vm_list().is_ok_and(|vms| !vms.iter().any(|vm| vm.vmid == *vmid)) => {
+            tracing::info!("VM ({vmid}) was removed despite errors: {e:?}");
+            true
+        }
+        Err(e) if delete_config_files => {
+            tracing::warn!("failed to remove VM ({vmid}), deleting configuration files: {e:?}");
+            false
+        }
+        Err(e) => return Err(e),
+    };
+
+    let config_location = match config_location {
+        Ok(config_location) => config_location,
+        Err(e) if removed => {
+            // There is nothing to clean up without the location, but the VM
+            // itself is gone.
+            tracing::warn!("not deleting files of removed VM ({vmid}): {e:?}");
+            return Ok(());
+        }
+        Err(e) => return Err(e).context("failed to get VM configuration location"),
+    };
+    delete_vm_files(&config_location.join("Virtual Machines"), vmid)
+}
+
+/// Deletes the configuration file and state directory of the VM with ID
+/// `vmid` from `config_dir`, which Hyper-V names after the VM ID.
+fn delete_vm_files(config_dir: &Path, vmid: &Guid) -> anyhow::Result<()> {
+    let entries = match std::fs::read_dir(config_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read {}", config_dir.display()));
+        }
+    };
+    let vmid = vmid.to_string().to_uppercase();
+    for entry in entries {
+        let path = entry?.path();
+        let is_vm_file = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.to_uppercase().starts_with(&vmid));
+        if !is_vm_file {
+            continue;
+        }
+        tracing::info!(path = %path.display(), "deleting VM configuration file");
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        }
+        .with_context(|| format!("failed to delete {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+const FORCE_REMOVE_ATTEMPTS: u32 = 5;
+
+/// Run `f` until it succeeds, up to `attempts` times, doubling the delay
+/// between attempts each time. Returns the last error on failure.
+async fn retry_with_backoff<T>(
+    driver: &DefaultDriver,
+    attempts: u32,
+    initial_delay: Duration,
+    mut f: impl FnMut() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut timer = PolledTimer::new(driver);
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt >= attempts => {
+                return Err(e).context(format!("failed after {attempts} attempts"));
+            }
+            Err(e) => {
+                tracing::warn!(attempt, "attempt failed, retrying in {delay:?}: {e:?}");
+                timer.sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Map a Hyper-V worker halt event to the corresponding [`HaltReason`].
+fn halt_reason_from_event(event: &powershell::WinEvent) -> anyhow::Result<HaltReason> {
+    Ok(match event.id {
+        powershell::EVENT_ID_TURNED_OFF | powershell::EVENT_ID_GUEST_SHUTDOWN => {
+            HaltReason::PowerOff
+        }
+        powershell::EVENT_ID_GUEST_RESET => HaltReason::Reset,
+        powershell::EVENT_ID_TRIPLE_FAULT => {
+            let vp = triple_fault_vp(&event.message);
+            if vp.is_none() {
+                tracing::warn!(
+                    "triple fault event does not identify the virtual processor, assuming VP 0: {}",
+                    event.message
+                );
+            }
+            HaltReason::TripleFault {
+                vp: vp.unwrap_or(0),
+                registers: None,
+            }
+        }
+        id => anyhow::bail!("Unexpected halt event id: {id}"),
+    })
+}
+
+/// Parse the index of the faulting virtual processor from the message of a
+/// triple fault event, such as "... virtual processor 3 ...".
+fn triple_fault_vp(message: &str) -> Option<u32> {
+    let (_, rest) = message.split_once("virtual processor ")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
 /// Error running command
 #[derive(Error, Debug)]
 pub(crate) enum CommandError {
@@ -497,3 +1292,303 @@ pub(crate) fn run_cmd(mut cmd: Command) -> Result<String, CommandError> {
 
     Ok(String::from_utf8(output.stdout)?.trim().to_owned())
 }
+
+/// Maps a petri processor topology onto Set-VMProcessor arguments.
+fn set_processor_args(
+    topology: &ProcessorTopology,
+    arch: MachineArch,
+    generation: powershell::HyperVGeneration,
+) -> powershell::HyperVSetVMProcessorArgs {
+    let ProcessorTopology {
+        vp_count,
+        vps_per_socket,
+        enable_smt,
+        apic_mode,
+    } = *topology;
+    // TODO: fix this mapping, and/or update petri to better match
+    // Hyper-V's capabilities.
+    let apic_mode = apic_mode
+        .map(|m| match m {
+            ApicMode::Xapic => powershell::HyperVApicMode::Legacy,
+            ApicMode::X2apicSupported => powershell::HyperVApicMode::X2Apic,
+            ApicMode::X2apicEnabled => powershell::HyperVApicMode::X2Apic,
+        })
+        .or(
+            (arch == MachineArch::X86_64 && generation == powershell::HyperVGeneration::Two)
+                .then_some({
+                    // This is necessary for some tests to pass. TODO: fix.
+                    powershell::HyperVApicMode::X2Apic
+                }),
+        );
+    powershell::HyperVSetVMProcessorArgs {
+        count: Some(vp_count),
+        apic_mode,
+        hw_thread_count_per_core: enable_smt.map(|smt| if smt { 2 } else { 1 }),
+        maximum_count_per_numa_node: vps_per_socket,
+    }
+}
+
+/// Checks that a VM with the given configuration can use nested
+/// virtualization.
+fn check_nested_virtualization(
+    arch: MachineArch,
+    generation: powershell::HyperVGeneration,
+    dynamic_memory: bool,
+) -> anyhow::Result<()> {
+    if arch != MachineArch::X86_64 {
+        anyhow::bail!("nested virtualization is only supported for x86_64 VMs");
+    }
+    if generation != powershell::HyperVGeneration::Two {
+        anyhow::bail!("nested virtualization is only supported for generation 2 VMs");
+    }
+    if dynamic_memory {
+        anyhow::bail!("nested virtualization requires dynamic memory to be disabled");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+
+    #[test]
+    fn processor_args() {
+        let args = set_processor_args(
+            &ProcessorTopology {
+                vp_count: 4,
+                vps_per_socket: Some(2),
+                enable_smt: Some(true),
+                apic_mode: None,
+            },
+            MachineArch::X86_64,
+            powershell::HyperVGeneration::Two,
+        );
+        assert_eq!(args.count, Some(4));
+        assert_eq!(args.hw_thread_count_per_core, Some(2));
+        assert_eq!(args.maximum_count_per_numa_node, Some(2));
+        assert!(matches!(
+            args.apic_mode,
+            Some(powershell::HyperVApicMode::X2Apic)
+        ));
+
+        let args = set_processor_args(
+            &ProcessorTopology {
+                vp_count: 1,
+                ..Default::default()
+            },
+            MachineArch::Aarch64,
+            powershell::HyperVGeneration::Two,
+        );
+        assert_eq!(args.count, Some(1));
+        assert_eq!(args.hw_thread_count_per_core, None);
+        assert!(args.apic_mode.is_none());
+    }
+
+    #[test]
+    fn nested_virtualization() {
+        check_nested_virtualization(
+            MachineArch::X86_64,
+            powershell::HyperVGeneration::Two,
+            false,
+        )
+        .unwrap();
+        check_nested_virtualization(
+            MachineArch::Aarch64,
+            powershell::HyperVGeneration::Two,
+            false,
+        )
+        .unwrap_err();
+        check_nested_virtualization(
+            MachineArch::X86_64,
+            powershell::HyperVGeneration::One,
+            false,
+        )
+        .unwrap_err();
+        let err = check_nested_virtualization(
+            MachineArch::X86_64,
+            powershell::HyperVGeneration::Two,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("dynamic memory"), "{err}");
+    }
+
+    #[test]
+    fn test_vm_names() {
+        let name = "multiarch::hyperv_uefi_x64_ubuntu_2204_server_x64_boot";
+        assert!(is_test_vm_name(name, ""));
+        assert!(is_test_vm_name(name, "multiarch::"));
+        assert!(!is_test_vm_name(name, "x86_64::"));
+        // VMs not created by petri are left alone, whatever the prefix.
+        assert!(!is_test_vm_name("my-dev-vm", ""));
+        assert!(!is_test_vm_name("petri-switch", "petri"));
+    }
+
+    #[test]
+    fn watchdog() {
+        let watchdog = Watchdog {
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(100),
+        };
+        let now = Instant::now();
+        let deadline = now + watchdog.timeout;
+        assert_eq!(
+            watchdog.next_poll(now, deadline),
+            Some(Duration::from_millis(100))
+        );
+        // The last sleep is cut short at the deadline.
+        assert_eq!(
+            watchdog.next_poll(deadline - Duration::from_millis(30), deadline),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(watchdog.next_poll(deadline, deadline), None);
+        assert_eq!(
+            watchdog.next_poll(deadline + Duration::from_secs(1), deadline),
+            None
+        );
+
+        assert_eq!(parse_timeout_secs(" 30 ").unwrap(), Duration::from_secs(30));
+        parse_timeout_secs("0").unwrap_err();
+        parse_timeout_secs("-1").unwrap_err();
+        parse_timeout_secs("4m").unwrap_err();
+    }
+
+    #[test]
+    fn halt_reasons() {
+        let event = |id, message: &str| powershell::WinEvent {
+            time_created: Timestamp::UNIX_EPOCH,
+            provider_name: "Microsoft-Windows-Hyper-V-Worker".into(),
+            level: 4,
+            id,
+            message: message.into(),
+        };
+        for id in [
+            powershell::EVENT_ID_TURNED_OFF,
+            powershell::EVENT_ID_GUEST_SHUTDOWN,
+        ] {
+            assert!(matches!(
+                halt_reason_from_event(&event(id, "")).unwrap(),
+                HaltReason::PowerOff
+            ));
+        }
+        assert!(matches!(
+            halt_reason_from_event(&event(powershell::EVENT_ID_GUEST_RESET, "")).unwrap(),
+            HaltReason::Reset
+        ));
+        assert!(matches!(
+            halt_reason_from_event(&event(
+                powershell::EVENT_ID_TRIPLE_FAULT,
+                "'vm' was reset because virtual processor 3 encountered a triple fault."
+            ))
+            .unwrap(),
+            HaltReason::TripleFault { vp: 3, .. }
+        ));
+        assert!(matches!(
+            halt_reason_from_event(&event(powershell::EVENT_ID_TRIPLE_FAULT, "triple fault"))
+                .unwrap(),
+            HaltReason::TripleFault { vp: 0, .. }
+        ));
+        halt_reason_from_event(&event(powershell::EVENT_ID_BOOT_SUCCESS, "")).unwrap_err();
+    }
+
+    #[test]
+    fn triple_fault_vps() {
+        assert_eq!(triple_fault_vp("virtual processor 12 faulted"), Some(12));
+        assert_eq!(triple_fault_vp("on virtual processor 0."), Some(0));
+        assert_eq!(triple_fault_vp("a virtual processor that faulted"), None);
+    }
+
+    #[async_test]
+    async fn retry_recovers_from_stuck_state(driver: DefaultDriver) {
+        // Simulate a VM that stays in a transition state for a few attempts
+        let mut states = [VmState::Stopping, VmState::Stopping, VmState::Off].into_iter();
+        let mut calls = 0;
+        retry_with_backoff(&driver, FORCE_REMOVE_ATTEMPTS, Duration::ZERO, || {
+            calls += 1;
+            match states.next().unwrap() {
+                VmState::Off => Ok(()),
+                state => anyhow::bail!("VM is {state:?}"),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls, 3);
+    }
+
+    #[async_test]
+    async fn retry_gives_up_when_always_stuck(driver: DefaultDriver) {
+        let mut calls = 0;
+        let err = retry_with_backoff::<()>(&driver, FORCE_REMOVE_ATTEMPTS, Duration::ZERO, || {
+            calls += 1;
+            anyhow::bail!("VM is {:?}", VmState::Saving)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(calls, FORCE_REMOVE_ATTEMPTS);
+        assert!(format!("{err:?}").contains("Saving"));
+    }
+
+    #[async_test]
+    async fn waits_for_running(driver: DefaultDriver) {
+        let watchdog = Watchdog {
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(1),
+        };
+        let mut states = [VmState::Starting, VmState::Starting, VmState::Running].into_iter();
+        let polls = std::cell::Cell::new(0);
+        let state = || {
+            polls.set(polls.get() + 1);
+            Ok(states.next().unwrap())
+        };
+        // The action only runs once the VM has left the Starting state.
+        let polls_before_action = when_running(&driver, &watchdog, state, || Ok(polls.get()))
+            .await
+            .unwrap();
+        assert_eq!(polls_before_action, 3);
+    }
+
+    #[async_test]
+    async fn stuck_starting_times_out(driver: DefaultDriver) {
+        let watchdog = Watchdog {
+            timeout: Duration::from_millis(20),
+            poll_interval: Duration::from_millis(1),
+        };
+        let mut ran = false;
+        let err = when_running(
+            &driver,
+            &watchdog,
+            || Ok(VmState::Starting),
+            || {
+                ran = true;
+                Ok(())
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(!ran);
+        assert!(format!("{err:#}").contains("Starting"), "{err:#}");
+    }
+
+    #[test]
+    fn deletes_vm_files() {
+        let vmid = guid::guid!("3f2504e0-4f89-11d3-9a0c-0305e82c3301");
+        let dir = tempfile::tempdir().unwrap();
+        let upper = vmid.to_string().to_uppercase();
+        std::fs::write(dir.path().join(format!("{upper}.vmcx")), b"").unwrap();
+        std::fs::create_dir(dir.path().join(&upper)).unwrap();
+        std::fs::write(dir.path().join(&upper).join("state.vmrs"), b"").unwrap();
+        let other = "B9E8E5A1-1C2D-4E5F-8A9B-0C1D2E3F4A5B.vmcx";
+        std::fs::write(dir.path().join(other), b"").unwrap();
+
+        delete_vm_files(dir.path(), &vmid).unwrap();
+        let left = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(left, [other]);
+
+        // A missing directory has nothing to delete.
+        delete_vm_files(&dir.path().join("missing"), &vmid).unwrap();
+    }
+}
@@ -7,13 +7,27 @@
 /// OpenVMM VM management
 pub mod openvmm;
 
+mod boot_timeline;
+mod clean_shutdown;
+mod guest_kernel_log;
+
+pub use boot_timeline::BOOT_BUDGET_WARN_ONLY_ENV;
+pub use boot_timeline::BootTimeline;
+pub use guest_kernel_log::GuestKernelLog;
+
+use crate::GuestCredentials;
 use crate::PetriLogSource;
 use crate::PetriTestParams;
 use crate::ShutdownKind;
 use crate::disk_image::AgentImage;
+use crate::disk_image::FileSystemType;
 use crate::openhcl_diag::OpenHclDiagHandler;
+use anyhow::Context;
 use async_trait::async_trait;
 use get_resources::ged::FirmwareEvent;
+use jiff::SignedDuration;
+use jiff::Timestamp;
+use mesh::CancelContext;
 use pal_async::DefaultDriver;
 use pal_async::timer::PolledTimer;
 use petri_artifacts_common::tags::GuestQuirks;
@@ -21,10 +35,13 @@
 use petri_artifacts_common::tags::IsTestVmgs;
 use petri_artifacts_common::tags::MachineArch;
 use petri_artifacts_common::tags::OsFlavor;
+use petri_artifacts_core::ArtifactHandle;
 use petri_artifacts_core::ArtifactResolver;
 use petri_artifacts_core::ResolvedArtifact;
 use petri_artifacts_core::ResolvedOptionalArtifact;
 use pipette_client::PipetteClient;
+use std::future::Future;
+use std::path::Path;
 use std::path::PathBuf;
 use std::time::Duration;
 use vmm_core_defs::HaltReason;
@@ -47,13 +64,19 @@ pub struct PetriVmArtifacts<T: PetriVmmBackend> {
 impl<T: PetriVmmBackend> PetriVmArtifacts<T> {
     /// Resolves the artifacts needed to instantiate a [`PetriVmBuilder`].
     ///
-    /// Returns `None` if the supplied configuration is not supported on this platform.
+    /// Returns `None` if the supplied configuration is not supported on this
+    /// platform, including OpenHCL isolation types the backend does not
+    /// support.
     pub fn new(
         resolver: &ArtifactResolver<'_>,
         firmware: Firmware,
         arch: MachineArch,
     ) -> Option<Self> {
-        if !T::check_compat(&firmware, arch) {
+        if !T::check_compat(&firmware, arch)
+            || firmware
+                .isolation()
+                .is_some_and(|isolation| !T::capabilities().supports_isolation(isolation))
+        {
             return None;
         }
         Some(Self {
@@ -100,6 +123,39 @@ pub struct PetriVmConfig {
     pub openhcl_agent_image: Option<AgentImage>,
     /// VM guest state
     pub vmgs: PetriVmgsResource,
+    /// Directory to write guest crash output to, if any
+    pub guest_crash_dump_dir: Option<PathBuf>,
+    /// Credentials for logging in to the guest without pipette
+    pub guest_credentials: GuestCredentials,
+    /// Additional disks to attach to the VM, after the boot disk
+    pub disks: Vec<(PathBuf, ControllerType)>,
+}
+
+/// The features a VMM backend supports, for tests that need to branch on or
+/// skip unsupported functionality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    /// The isolation types that OpenHCL VMs can be started with on this
+    /// host.
+    pub isolation: Vec<IsolationType>,
+    /// Whether Linux Direct boot (without firmware) is supported.
+    pub linux_direct_boot: bool,
+    /// Whether OpenHCL can boot the guest from an emulated NVMe device.
+    pub vtl2_nvme_boot: bool,
+    /// Whether dynamic memory is supported.
+    pub dynamic_memory: bool,
+    /// Whether the VMM-specific configuration can be modified before the VM
+    /// starts.
+    pub modify_vmm_config: bool,
+    /// Whether the VMM's own state can be inspected.
+    pub inspect: bool,
+}
+
+impl BackendCapabilities {
+    /// Returns whether OpenHCL VMs can be started with `isolation`.
+    pub fn supports_isolation(&self, isolation: IsolationType) -> bool {
+        self.isolation.contains(&isolation)
+    }
 }
 
 /// Resources used by a Petri VM during contruction and runtime
@@ -122,6 +178,9 @@ pub trait PetriVmmBackend {
     /// supported on the VMM.
     fn check_compat(firmware: &Firmware, arch: MachineArch) -> bool;
 
+    /// The features supported by the VMM.
+    fn capabilities() -> BackendCapabilities;
+
     /// Resolve any artifacts needed to use this backend
     fn new(resolver: &ArtifactResolver<'_>) -> Self;
 
@@ -140,6 +199,9 @@ pub struct PetriVm<T: PetriVmmBackend> {
     resources: PetriVmResources,
     runtime: T::VmRuntime,
     quirks: GuestQuirks,
+    os_flavor: OsFlavor,
+    guest_credentials: GuestCredentials,
+    boot_timeline: BootTimeline,
 }
 
 impl<T: PetriVmmBackend> PetriVmBuilder<T> {
@@ -160,6 +222,9 @@ pub fn new(
                 agent_image: artifacts.agent_image,
                 openhcl_agent_image: artifacts.openhcl_agent_image,
                 vmgs: PetriVmgsResource::Ephemeral,
+                guest_crash_dump_dir: None,
+                guest_credentials: Default::default(),
+                disks: Vec::new(),
             },
             modify_vmm_config: None,
             resources: PetriVmResources {
@@ -194,18 +259,30 @@ pub async fn run(self) -> anyhow::Result<(PetriVm<T>, PipetteClient)> {
         Ok((vm, client))
     }
 
+    /// Returns the features supported by the VMM backend.
+    pub fn capabilities(&self) -> BackendCapabilities {
+        T::capabilities()
+    }
+
     async fn run_core(self) -> anyhow::Result<PetriVm<T>> {
         let arch = self.config.arch;
         let quirks = self.config.firmware.quirks();
+        let os_flavor = self.config.firmware.os_flavor();
+        let guest_credentials = self.config.guest_credentials.clone();
+        let mut boot_timeline = BootTimeline::new();
         let runtime = self
             .backend
             .run(self.config, self.modify_vmm_config, &self.resources)
             .await?;
+        boot_timeline.record("vm start");
         Ok(PetriVm {
             arch,
             resources: self.resources,
             runtime,
             quirks,
+            os_flavor,
+            guest_credentials,
+            boot_timeline,
         })
     }
 
@@ -253,12 +330,26 @@ pub fn with_processor_topology(mut self, topology: ProcessorTopology) -> Self {
         self
     }
 
-    /// Set the VM to use the specified processor topology.
+    /// Set the number of virtual processors, keeping the rest of the
+    /// processor topology.
+    pub fn with_processor_count(mut self, count: u32) -> Self {
+        self.config.proc_topology.vp_count = count;
+        self
+    }
+
+    /// Set the VM to use the specified memory configuration.
     pub fn with_memory(mut self, memory: MemoryConfig) -> Self {
         self.config.memory = memory;
         self
     }
 
+    /// Set the amount of memory, in bytes, to assign to the VM, keeping the
+    /// rest of the memory configuration.
+    pub fn with_memory_size(mut self, bytes: u64) -> Self {
+        self.config.memory.startup_bytes = bytes;
+        self
+    }
+
     /// Sets a custom OpenHCL IGVM file to use.
     pub fn with_custom_openhcl(mut self, artifact: ResolvedArtifact<impl IsOpenhclIgvm>) -> Self {
         match &mut self.config.firmware {
@@ -310,6 +401,116 @@ pub fn with_agent_file(mut self, name: &str, artifact: ResolvedArtifact) -> Self
         self
     }
 
+    /// Sets the size of the VM's pipette agent image, for when its files do
+    /// not fit in the default size.
+    pub fn with_agent_image_size(mut self, size_bytes: u64) -> Self {
+        self.config
+            .agent_image
+            .as_mut()
+            .expect("no guest pipette")
+            .set_disk_size(size_bytes);
+        self
+    }
+
+    /// Sets the filesystem of the VM's pipette agent image, which defaults to
+    /// FAT32. ext4 is only supported for Linux guests; other guests fail to
+    /// start.
+    pub fn with_agent_file_system(mut self, file_system: FileSystemType) -> Self {
+        self.config
+            .agent_image
+            .as_mut()
+            .expect("no guest pipette")
+            .set_file_system(file_system);
+        self
+    }
+
+    /// Replaces the default cloud-init `user-data` in the VM's pipette agent
+    /// image. The guest must use cloud-init, and the data must be a YAML
+    /// document starting with `#cloud-config`.
+    ///
+    /// The default configuration installs and starts pipette, so custom
+    /// user-data must do the same for the agent to be reachable.
+    pub fn with_cloud_init_user_data(mut self, user_data: impl Into<Vec<u8>>) -> Self {
+        self.config
+            .agent_image
+            .as_mut()
+            .expect("no guest pipette")
+            .set_cloud_init_user_data(user_data.into())
+            .expect("invalid cloud-init user-data");
+        self
+    }
+
+    /// Replaces the default cloud-init `meta-data` in the VM's pipette agent
+    /// image.
+    pub fn with_cloud_init_meta_data(mut self, meta_data: impl Into<Vec<u8>>) -> Self {
+        self.config
+            .agent_image
+            .as_mut()
+            .expect("no guest pipette")
+            .set_cloud_init_meta_data(meta_data.into())
+            .expect("invalid cloud-init meta-data");
+        self
+    }
+
+    /// Adds an unattend answer file to the VM's pipette agent image, which
+    /// Windows Setup consumes on first boot. Only supported for Windows
+    /// guests.
+    pub fn with_windows_unattend(mut self, unattend: impl Into<Vec<u8>>) -> Self {
+        self.config
+            .agent_image
+            .as_mut()
+            .expect("no guest pipette")
+            .set_windows_unattend(unattend.into())
+            .expect("invalid unattend file");
+        self
+    }
+
+    /// Attaches the disk at `path` to the VM.
+    ///
+    /// Each SCSI disk is attached to a new SCSI controller. IDE disks are
+    /// attached to channel 0 drive 1 and then channel 1 drive 1 on every
+    /// backend, leaving the boot disk and Hyper-V's default DVD drive in
+    /// place, so at most two can be added, and are only supported for PCAT
+    /// firmware. The file is not modified:
+    /// the guest's writes go to a differencing layer that is discarded when
+    /// the VM is torn down.
+    pub fn with_disk(mut self, path: impl Into<PathBuf>, controller: ControllerType) -> Self {
+        self.config.disks.push((path.into(), controller));
+        self
+    }
+
+    /// Captures guest crash output into `dir`.
+    ///
+    /// The guest's console output is watched for a kernel panic, and
+    /// everything from shortly before the panic onwards is written to
+    /// [`GuestPanicCapture::FILE_NAME`](crate::GuestPanicCapture::FILE_NAME)
+    /// in `dir`. This only captures console text; memory dumps (kdump or
+    /// Windows minidumps) are not collected.
+    pub fn with_guest_crash_dump(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.guest_crash_dump_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the credentials used to log in to the guest when pipette is not
+    /// available. The OpenVMM backend uses these to log in over the serial
+    /// console with
+    /// [`PetriVmOpenVmm::serial_login`](crate::openvmm::PetriVmOpenVmm::serial_login).
+    ///
+    /// These are not configured in the guest; they must match an account the
+    /// guest image already has. The default matches the user created by the
+    /// default cloud-init configuration.
+    pub fn with_guest_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.config.guest_credentials = GuestCredentials {
+            username: username.into(),
+            password: password.into(),
+        };
+        self
+    }
+
     /// Adds a file to the paravisor's pipette agent image.
     pub fn with_openhcl_agent_file(mut self, name: &str, artifact: ResolvedArtifact) -> Self {
         self.config
@@ -437,7 +638,160 @@ pub async fn wait_for_vtl2_ready(&mut self) -> anyhow::Result<()> {
     /// Wait for a connection from a pipette agent running in the guest.
     /// Useful if you've rebooted the vm or are otherwise expecting a fresh connection.
     pub async fn wait_for_agent(&mut self) -> anyhow::Result<PipetteClient> {
-        self.runtime.wait_for_agent(false).await
+        let client = self.runtime.wait_for_agent(false).await?;
+        self.boot_timeline.record("pipette");
+        Ok(client)
+    }
+
+    /// Check that cloud-init finished without errors in a Linux guest,
+    /// failing with cloud-init's detailed status otherwise.
+    pub async fn assert_cloud_init_ok(&self, agent: &PipetteClient) -> anyhow::Result<()> {
+        let sh = agent.unix_shell();
+        let output = pipette_client::cmd!(sh, "cloud-init status --wait --long")
+            .ignore_status()
+            .output()
+            .await
+            .context("failed to query cloud-init status")?;
+        // Exits with 1 on a fatal error and 2 on a recoverable one.
+        if !output.status.success() {
+            anyhow::bail!(
+                "cloud-init reported failure ({}):\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that the guest's previous shutdown was clean, failing if its
+    /// filesystem had to be recovered when it booted again.
+    ///
+    /// Call this after the guest has booted again following a power off, to
+    /// catch power management bugs that leave the filesystem dirty. Linux
+    /// guests are checked for journal recovery in the kernel log, and Windows
+    /// guests for the unexpected shutdown events (Kernel-Power 41 and
+    /// EventLog 6008) logged since boot.
+    pub async fn assert_clean_shutdown(&self, agent: &PipetteClient) -> anyhow::Result<()> {
+        match self.os_flavor {
+            OsFlavor::Linux => {
+                let sh = agent.unix_shell();
+                let dmesg = pipette_client::cmd!(sh, "dmesg")
+                    .read()
+                    .await
+                    .context("failed to read kernel log")?;
+                let recovery = clean_shutdown::linux_recovery_lines(&dmesg);
+                if !recovery.is_empty() {
+                    anyhow::bail!(
+                        "guest filesystem was recovered at boot, previous shutdown was unclean:\n{}",
+                        recovery.join("\n")
+                    );
+                }
+            }
+            OsFlavor::Windows => {
+                let sh = agent.windows_shell();
+                let script = clean_shutdown::WINDOWS_SHUTDOWN_EVENTS_SCRIPT;
+                let output =
+                    pipette_client::cmd!(sh, "powershell.exe -NoProfile -Command {script}")
+                        .read()
+                        .await
+                        .context("failed to read the system event log")?;
+                let events = clean_shutdown::windows_unclean_shutdown_events(&output)?;
+                if !events.is_empty() {
+                    anyhow::bail!(
+                        "guest logged an unexpected shutdown at boot, previous shutdown was unclean:\n{}",
+                        events.join("\n")
+                    );
+                }
+            }
+            flavor => anyhow::bail!("clean shutdown check not supported for {flavor:?} guests"),
+        }
+        Ok(())
+    }
+
+    /// Capture the Linux guest's kernel log, to compare against a later
+    /// capture with [`GuestKernelLog::assert_no_new_errors_since`]. For
+    /// example, snapshot the log before servicing to check that servicing
+    /// did not disrupt the guest.
+    pub async fn capture_guest_kernel_log(
+        &self,
+        agent: &PipetteClient,
+    ) -> anyhow::Result<GuestKernelLog> {
+        if !matches!(self.os_flavor, OsFlavor::Linux) {
+            anyhow::bail!(
+                "kernel log capture not supported for {:?} guests",
+                self.os_flavor
+            );
+        }
+        let sh = agent.unix_shell();
+        // Include the raw priority of each line, so that errors can be told
+        // apart.
+        let dmesg = pipette_client::cmd!(sh, "dmesg -r")
+            .read()
+            .await
+            .context("failed to read kernel log")?;
+        Ok(GuestKernelLog::from_raw(&dmesg))
+    }
+
+    /// Check that the guest can open a TCP connection to `host` on `port`
+    /// within `timeout`, failing with the guest's error output otherwise.
+    pub async fn assert_guest_can_reach(
+        &self,
+        agent: &PipetteClient,
+        host: &str,
+        port: u16,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        // `host` is spliced into the guest scripts below.
+        anyhow::ensure!(is_valid_host(host), "invalid host {host:?}");
+        let output = match self.os_flavor {
+            OsFlavor::Linux => {
+                let sh = agent.unix_shell();
+                let secs = timeout.as_secs().max(1).to_string();
+                let script = format!("echo > /dev/tcp/{host}/{port}");
+                pipette_client::cmd!(sh, "timeout {secs} bash -c {script}")
+                    .ignore_status()
+                    .output()
+                    .await
+            }
+            OsFlavor::Windows => {
+                let sh = agent.windows_shell();
+                let ms = timeout.as_millis().to_string();
+                let script = format!(
+                    "$c = New-Object Net.Sockets.TcpClient; if (-not $c.ConnectAsync('{host}', {port}).Wait({ms})) {{ throw 'timed out' }}"
+                );
+                pipette_client::cmd!(sh, "powershell.exe -NoProfile -Command {script}")
+                    .ignore_status()
+                    .output()
+                    .await
+            }
+            flavor => anyhow::bail!("reachability check not supported for {flavor:?} guests"),
+        }
+        .context("failed to run connection test in guest")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "guest could not connect to {host}:{port} within {timeout:?} ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Measure how far the guest clock drifts from the host clock over
+    /// `duration`, as the time that elapsed in the guest minus the time that
+    /// elapsed on the host. A positive drift means the guest clock ran fast.
+    pub async fn measure_clock_drift(
+        &self,
+        agent: &PipetteClient,
+        duration: Duration,
+    ) -> anyhow::Result<SignedDuration> {
+        let start = guest_clock_offset(agent).await?;
+        PolledTimer::new(&self.resources.driver)
+            .sleep(duration)
+            .await;
+        let end = guest_clock_offset(agent).await?;
+        Ok(end - start)
     }
 
     /// Wait for a connection from a pipette agent running in VTL 2.
@@ -456,13 +810,61 @@ pub async fn wait_for_vtl2_agent(&mut self) -> anyhow::Result<PipetteClient> {
     /// * PCAT guests may not emit an event depending on the PCAT version, this
     ///   method is best effort for them.
     pub async fn wait_for_successful_boot_event(&mut self) -> anyhow::Result<()> {
-        self.runtime.wait_for_successful_boot_event().await
+        self.runtime.wait_for_successful_boot_event().await?;
+        self.boot_timeline.record("boot event");
+        Ok(())
     }
 
     /// Waits for an event emitted by the firmware about its boot status, and
     /// returns that status.
     pub async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent> {
-        self.runtime.wait_for_boot_event().await
+        let event = self.runtime.wait_for_boot_event().await?;
+        self.boot_timeline.record("boot event");
+        Ok(event)
+    }
+
+    /// Waits for the guest to finish booting: the firmware reports a
+    /// successful boot, the guest's heartbeat is healthy, and pipette
+    /// connects.
+    ///
+    /// All stages share a single `timeout`, and the returned error names the
+    /// stage that failed or timed out.
+    pub async fn wait_for_successful_boot(
+        &mut self,
+        timeout: Duration,
+    ) -> anyhow::Result<PipetteClient> {
+        let mut deadline = StagedDeadline::new(timeout);
+        deadline
+            .stage("boot event", self.runtime.wait_for_successful_boot_event())
+            .await?;
+        self.boot_timeline.record("boot event");
+        deadline
+            .stage("heartbeat", self.runtime.wait_for_heartbeat())
+            .await?;
+        self.boot_timeline.record("heartbeat");
+        let client = deadline
+            .stage("pipette", self.runtime.wait_for_agent(false))
+            .await?;
+        self.boot_timeline.record("pipette");
+        Ok(client)
+    }
+
+    /// Returns the time taken to reach each boot milestone observed so far.
+    pub fn boot_timeline(&self) -> &BootTimeline {
+        &self.boot_timeline
+    }
+
+    /// Fails if the guest took longer than `budget` to boot, as measured from
+    /// starting the VM to the last boot milestone observed (typically the
+    /// pipette connection). The error includes the duration of each phase.
+    ///
+    /// If [`BOOT_BUDGET_WARN_ONLY_ENV`] is set, exceeding the budget is only
+    /// logged as a warning.
+    pub fn assert_boot_under(&self, budget: Duration) -> anyhow::Result<()> {
+        let warn_only = std::env::var(BOOT_BUDGET_WARN_ONLY_ENV)
+            .ok()
+            .is_some_and(|v| !v.is_empty() && v != "0");
+        self.boot_timeline.check_budget(budget, warn_only)
     }
 
     /// Wait for the Hyper-V shutdown IC to be ready and use it to instruct
@@ -500,6 +902,17 @@ pub fn arch(&self) -> MachineArch {
         self.arch
     }
 
+    /// Get the credentials for logging in to the guest without pipette
+    pub fn guest_credentials(&self) -> &GuestCredentials {
+        &self.guest_credentials
+    }
+
+    /// Get the directory this run of the test writes its logs, screenshots,
+    /// and other output to
+    pub fn output_dir(&self) -> &Path {
+        &self.resources.output_dir
+    }
+
     /// Get the inner runtime backend to make backend-specific calls
     pub fn backend(&mut self) -> &mut T::VmRuntime {
         &mut self.runtime
@@ -534,6 +947,53 @@ fn openhcl_diag(&self) -> anyhow::Result<&OpenHclDiagHandler> {
     }
 }
 
+/// Returns the guest clock minus the host clock. The host clock is read
+/// halfway through the request to cancel out its round trip.
+async fn guest_clock_offset(agent: &PipetteClient) -> anyhow::Result<SignedDuration> {
+    let before = Timestamp::now();
+    let guest = agent.get_time().await?;
+    let after = Timestamp::now();
+    let guest = Timestamp::new(guest.seconds, guest.nanos).context("guest time out of range")?;
+    let host = before + after.duration_since(before) / 2;
+    Ok(guest.duration_since(host))
+}
+
+/// Returns whether `host` is an IP address or a DNS name.
+fn is_valid_host(host: &str) -> bool {
+    host.parse::<std::net::IpAddr>().is_ok()
+        || host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// A single deadline shared by the stages of a multi-stage wait, so that a
+/// failure or timeout can be attributed to the stage that caused it.
+pub(crate) struct StagedDeadline {
+    ctx: CancelContext,
+}
+
+impl StagedDeadline {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            ctx: CancelContext::new().with_timeout(timeout),
+        }
+    }
+
+    /// Runs `fut` as the stage `name`, failing if the deadline passes first.
+    pub(crate) async fn stage<T>(
+        &mut self,
+        name: &str,
+        fut: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        match self.ctx.until_cancelled(fut).await {
+            Ok(res) => res.with_context(|| format!("boot stage '{name}' failed")),
+            Err(_) => anyhow::bail!("timed out waiting for boot stage '{name}'"),
+        }
+    }
+}
+
 /// A running VM that tests can interact with.
 #[async_trait]
 pub trait PetriVmRuntime {
@@ -555,6 +1015,10 @@ pub trait PetriVmRuntime {
     /// Waits for an event emitted by the firmware about its boot status, and
     /// returns that status.
     async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent>;
+    /// Waits for the guest's heartbeat IC to report that it is healthy.
+    ///
+    /// VMMs without a heartbeat IC return Ok immediately.
+    async fn wait_for_heartbeat(&mut self) -> anyhow::Result<()>;
     /// Waits for the Hyper-V shutdown IC to be ready
     // TODO: return a receiver that will be closed when it is no longer ready.
     async fn wait_for_enlightened_shutdown_ready(&mut self) -> anyhow::Result<()>;
@@ -610,7 +1074,9 @@ pub struct MemoryConfig {
     pub startup_bytes: u64,
     /// Specifies the minimum and maximum amount of dynamic memory, in bytes.
     ///
-    /// Dynamic memory will be disabled if this is `None`.
+    /// Dynamic memory will be disabled if this is `None`. Neither backend
+    /// supports dynamic memory yet, so VMs configured with a range fail to
+    /// start.
     pub dynamic_memory_range: Option<(u64, u64)>,
 }
 
@@ -797,6 +1263,19 @@ pub fn openhcl_uefi(
         }
     }
 
+    fn is_vtl2_nvme_boot(&self) -> bool {
+        matches!(
+            self,
+            Firmware::OpenhclUefi {
+                openhcl_config: OpenHclConfig {
+                    vtl2_nvme_boot: true,
+                    ..
+                },
+                ..
+            }
+        )
+    }
+
     fn is_openhcl(&self) -> bool {
         match self {
             Firmware::OpenhclLinuxDirect { .. }
@@ -961,6 +1440,91 @@ fn uefi_config_mut(&mut self) -> Option<&mut UefiConfig> {
     }
 }
 
+/// A firmware configuration a guest image can be booted with, as enumerated
+/// by [`firmware_matrix`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareVariant {
+    /// UEFI
+    Uefi,
+    /// PCAT BIOS
+    Pcat,
+    /// UEFI with OpenHCL in VTL2, with optional isolation
+    OpenhclUefi(Option<IsolationType>),
+}
+
+impl FirmwareVariant {
+    /// Constructs the [`Firmware`] for this variant, booting the VHD `guest`.
+    pub fn firmware<A: petri_artifacts_common::tags::IsTestVhd>(
+        self,
+        resolver: &ArtifactResolver<'_>,
+        arch: MachineArch,
+        guest: ArtifactHandle<A>,
+    ) -> Firmware {
+        let vhd = BootImageConfig::from_vhd(resolver.require(guest));
+        match self {
+            FirmwareVariant::Uefi => Firmware::uefi(resolver, arch, UefiGuest::Vhd(vhd)),
+            FirmwareVariant::Pcat => Firmware::pcat(resolver, PcatGuest::Vhd(vhd)),
+            FirmwareVariant::OpenhclUefi(isolation) => {
+                Firmware::openhcl_uefi(resolver, arch, UefiGuest::Vhd(vhd), isolation, false)
+            }
+        }
+    }
+
+    /// A short name for the variant, suitable for use in test names.
+    pub fn name(self) -> &'static str {
+        match self {
+            FirmwareVariant::Uefi => "uefi",
+            FirmwareVariant::Pcat => "pcat",
+            FirmwareVariant::OpenhclUefi(None) => "openhcl_uefi",
+            FirmwareVariant::OpenhclUefi(Some(IsolationType::Vbs)) => "openhcl_uefi_vbs",
+            FirmwareVariant::OpenhclUefi(Some(IsolationType::Snp)) => "openhcl_uefi_snp",
+            FirmwareVariant::OpenhclUefi(Some(IsolationType::Tdx)) => "openhcl_uefi_tdx",
+        }
+    }
+}
+
+/// Returns whether this host can run a guest of architecture `arch`.
+///
+/// Neither backend emulates a foreign architecture, so this is only the
+/// host's own architecture.
+pub fn host_supports_guest_arch(arch: MachineArch) -> bool {
+    arch == MachineArch::host()
+}
+
+/// Returns the firmware variants that a guest of `os_flavor` can be booted
+/// with on `arch`, including OpenHCL with each of the `host_isolation` types
+/// supported by the host.
+pub fn firmware_matrix(
+    os_flavor: OsFlavor,
+    arch: MachineArch,
+    host_isolation: &[IsolationType],
+) -> Vec<FirmwareVariant> {
+    let uefi = !matches!(os_flavor, OsFlavor::FreeBsd);
+    let pcat = arch == MachineArch::X86_64 && !matches!(os_flavor, OsFlavor::Uefi);
+    // Isolated VMs are only supported on x86_64.
+    let isolation = match arch {
+        MachineArch::X86_64 => host_isolation,
+        MachineArch::Aarch64 => &[],
+    };
+
+    let mut variants = Vec::new();
+    if uefi {
+        variants.push(FirmwareVariant::Uefi);
+    }
+    if pcat {
+        variants.push(FirmwareVariant::Pcat);
+    }
+    if uefi {
+        variants.push(FirmwareVariant::OpenhclUefi(None));
+        variants.extend(
+            isolation
+                .iter()
+                .map(|&isolation| FirmwareVariant::OpenhclUefi(Some(isolation))),
+        );
+    }
+    variants
+}
+
 /// The guest the VM will boot into. A boot drive with the chosen setup
 /// will be automatically configured.
 #[derive(Debug)]
@@ -1081,8 +1645,22 @@ pub fn from_iso<A>(artifact: ResolvedArtifact<A>) -> Self
     }
 }
 
+/// The IDE channels and drives of disks added with
+/// [`PetriVmBuilder::with_disk`], in order, on every backend. The boot disk is
+/// at 0:0, and Hyper-V gives generation 1 VMs a DVD drive at 1:0.
+pub(crate) const EXTRA_IDE_DISK_LOCATIONS: [(u8, u8); 2] = [(0, 1), (1, 1)];
+
+/// The type of storage controller to attach a disk to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerType {
+    /// Synthetic SCSI controller
+    Scsi,
+    /// Emulated IDE controller
+    Ide,
+}
+
 /// Isolation type
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IsolationType {
     /// VBS
     Vbs,
@@ -1147,3 +1725,102 @@ fn append_cmdline(cmd: &mut Option<String>, add_cmd: &str) {
         *cmd = Some(add_cmd.to_string());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_matrix_x64_linux_no_isolation() {
+        assert_eq!(
+            firmware_matrix(OsFlavor::Linux, MachineArch::X86_64, &[]),
+            [
+                FirmwareVariant::Uefi,
+                FirmwareVariant::Pcat,
+                FirmwareVariant::OpenhclUefi(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn firmware_matrix_isolation() {
+        let isolation = [IsolationType::Vbs, IsolationType::Snp];
+        assert_eq!(
+            firmware_matrix(OsFlavor::Windows, MachineArch::X86_64, &isolation),
+            [
+                FirmwareVariant::Uefi,
+                FirmwareVariant::Pcat,
+                FirmwareVariant::OpenhclUefi(None),
+                FirmwareVariant::OpenhclUefi(Some(IsolationType::Vbs)),
+                FirmwareVariant::OpenhclUefi(Some(IsolationType::Snp)),
+            ]
+        );
+        assert_eq!(
+            firmware_matrix(OsFlavor::Linux, MachineArch::Aarch64, &isolation),
+            [FirmwareVariant::Uefi, FirmwareVariant::OpenhclUefi(None)]
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn backend_capabilities() {
+        let openvmm = openvmm::OpenVmmPetriBackend::capabilities();
+        let hyperv = hyperv::HyperVPetriBackend::capabilities();
+
+        assert!(openvmm.linux_direct_boot && !hyperv.linux_direct_boot);
+        assert!(openvmm.vtl2_nvme_boot && !hyperv.vtl2_nvme_boot);
+        assert!(!openvmm.dynamic_memory && hyperv.dynamic_memory);
+        assert!(openvmm.modify_vmm_config && !hyperv.modify_vmm_config);
+        assert!(openvmm.inspect && !hyperv.inspect);
+        for isolation in [IsolationType::Vbs, IsolationType::Snp, IsolationType::Tdx] {
+            assert!(hyperv.supports_isolation(isolation));
+        }
+        assert!(openvmm.supports_isolation(IsolationType::Vbs));
+        assert!(!openvmm.supports_isolation(IsolationType::Snp));
+        assert!(!openvmm.supports_isolation(IsolationType::Tdx));
+    }
+
+    #[test]
+    fn valid_hosts() {
+        for host in [
+            "10.0.0.2",
+            "fe80::1",
+            "localhost",
+            "example-host.contoso.com",
+        ] {
+            assert!(is_valid_host(host), "{host}");
+        }
+        for host in ["", "a..b", "-a", "a b", "x'; rm -rf /", "a/b", "$(id)"] {
+            assert!(!is_valid_host(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn staged_deadline_reports_stuck_stage() {
+        let err = pal_async::DefaultPool::run_with(async |_| {
+            let mut deadline = StagedDeadline::new(Duration::from_millis(50));
+            deadline.stage("boot event", async { Ok(()) }).await?;
+            deadline
+                .stage("heartbeat", std::future::pending::<anyhow::Result<()>>())
+                .await?;
+            deadline.stage("pipette", async { Ok(()) }).await
+        })
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("'heartbeat'"), "{err:#}");
+    }
+
+    #[test]
+    fn staged_deadline_reports_failed_stage() {
+        let err = pal_async::DefaultPool::run_with(async |_| {
+            let mut deadline = StagedDeadline::new(Duration::from_secs(60));
+            deadline.stage("boot event", async { Ok(()) }).await?;
+            deadline
+                .stage::<()>("heartbeat", async { anyhow::bail!("lost communication") })
+                .await
+        })
+        .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("boot stage 'heartbeat' failed"), "{err}");
+        assert!(err.contains("lost communication"), "{err}");
+    }
+}
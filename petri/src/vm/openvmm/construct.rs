@@ -10,7 +10,9 @@
 use super::PetriVmConfigOpenVmm;
 use super::PetriVmResourcesOpenVmm;
 use super::SCSI_INSTANCE;
+use super::memdiff_disk;
 use super::memdiff_disk_from_artifact;
+use crate::ControllerType;
 use crate::Firmware;
 use crate::IsolationType;
 use crate::MemoryConfig;
@@ -28,6 +30,8 @@
 use crate::linux_direct_serial_agent::LinuxDirectSerialAgent;
 use crate::openhcl_diag::OpenHclDiagHandler;
 use crate::openvmm::memdiff_vmgs_from_artifact;
+use crate::serial_login::SerialConsoleHost;
+use crate::vm::EXTRA_IDE_DISK_LOCATIONS;
 use crate::vm::append_cmdline;
 use anyhow::Context;
 use framebuffer::FRAMEBUFFER_SIZE;
@@ -74,6 +78,8 @@
 use serial_core::resources::DisconnectedSerialBackendHandle;
 use serial_socket::net::OpenSocketSerialConfig;
 use sparse_mmap::alloc_shared_memory;
+use std::path::Path;
+use std::path::PathBuf;
 use storvsp_resources::ScsiControllerHandle;
 use storvsp_resources::ScsiDeviceAndPath;
 use storvsp_resources::ScsiPath;
@@ -107,6 +113,9 @@ pub fn new(
             agent_image: _,
             openhcl_agent_image: _,
             vmgs,
+            guest_crash_dump_dir,
+            guest_credentials,
+            disks,
         } = &petri_vm_config;
 
         let PetriVmResources {
@@ -121,6 +130,7 @@ pub fn new(
             driver,
             logger: log_source,
             vmgs,
+            guest_crash_dump_dir: guest_crash_dump_dir.as_deref(),
         };
 
         let mut chipset = VmManifestBuilder::new(
@@ -148,6 +158,7 @@ pub fn new(
             mut emulated_serial_config,
             serial_tasks: log_stream_tasks,
             linux_direct_serial_agent,
+            serial_console,
         } = setup.configure_serial(log_source)?;
 
         let (video_dev, framebuffer, framebuffer_access) = match setup.config_video()? {
@@ -216,6 +227,7 @@ pub fn new(
         };
 
         setup.load_boot_disk(&mut devices, vtl2_settings.as_mut())?;
+        setup.load_disks(&mut devices, disks)?;
         let expected_boot_event = firmware.expected_boot_event();
 
         // Configure the serial ports now that they have been updated by the
@@ -475,10 +487,13 @@ pub fn new(
                 kvp_ic_send,
                 expected_boot_event,
                 ged_send,
+                debugger_rpc_send: None,
                 pipette_listener,
                 vtl2_pipette_listener,
                 openhcl_diag_handler,
                 linux_direct_serial_agent,
+                serial_console,
+                guest_credentials: guest_credentials.clone(),
                 driver: driver.clone(),
                 output_dir: output_dir.to_owned(),
                 agent_image: petri_vm_config.agent_image,
@@ -491,6 +506,8 @@ pub fn new(
             },
 
             openvmm_log_file: log_source.log_file("openvmm")?,
+            openvmm_log_filter: None,
+            openvmm_log_copy: None,
 
             ged,
             framebuffer_access,
@@ -504,12 +521,14 @@ struct PetriVmConfigSetupCore<'a> {
     driver: &'a DefaultDriver,
     logger: &'a PetriLogSource,
     vmgs: &'a PetriVmgsResource,
+    guest_crash_dump_dir: Option<&'a Path>,
 }
 
 struct SerialData {
     emulated_serial_config: [Option<Resource<SerialBackendHandle>>; 4],
     serial_tasks: Vec<Task<anyhow::Result<()>>>,
     linux_direct_serial_agent: Option<LinuxDirectSerialAgent>,
+    serial_console: SerialConsoleHost,
 }
 
 enum Device {
@@ -537,9 +556,15 @@ fn configure_serial(&self, logger: &PetriLogSource) -> anyhow::Result<SerialData
             .create_serial_stream()
             .context("failed to create serial0 stream")?;
         let (serial0_read, serial0_write) = serial0_host.split();
+        let mut serial_console = SerialConsoleHost::new();
+        let serial0_read = serial_console.tee(serial0_read);
         let serial0_task = self.driver.spawn(
             "serial0-console",
-            crate::log_stream(serial0_log_file, serial0_read),
+            crate::guest_log_stream(
+                serial0_log_file,
+                self.guest_crash_dump_dir.map(crate::GuestPanicCapture::new),
+                serial0_read,
+            ),
         );
         serial_tasks.push(serial0_task);
 
@@ -562,16 +587,21 @@ fn configure_serial(&self, logger: &PetriLogSource) -> anyhow::Result<SerialData
             let (serial1_read, _serial1_write) = serial1_host.split();
             let linux_direct_serial_agent =
                 LinuxDirectSerialAgent::new(serial1_read, serial0_write);
+            // The serial agent owns the console's input, so the console
+            // cannot be opened.
             Ok(SerialData {
                 emulated_serial_config: [serial0, serial1, serial2, None],
                 serial_tasks,
                 linux_direct_serial_agent: Some(linux_direct_serial_agent),
+                serial_console,
             })
         } else {
+            serial_console.set_input(serial0_write);
             Ok(SerialData {
                 emulated_serial_config: [serial0, None, serial2, None],
                 serial_tasks,
                 linux_direct_serial_agent: None,
+                serial_console,
             })
         }
     }
@@ -615,6 +645,7 @@ fn load_firmware(&self) -> anyhow::Result<LoadMode> {
                     initrd: Some(initrd),
                     cmdline: "console=ttyS0 debug panic=-1 rdinit=/bin/sh".into(),
                     custom_dsdt: None,
+                    custom_acpi_tables: Vec::new(),
                     enable_serial: true,
                 }
             }
@@ -630,6 +661,7 @@ fn load_firmware(&self) -> anyhow::Result<LoadMode> {
                     initrd: Some(initrd),
                     cmdline: "console=ttyAMA0 earlycon debug panic=-1 rdinit=/bin/sh".into(),
                     custom_dsdt: None,
+                    custom_acpi_tables: Vec::new(),
                     enable_serial: true,
                 }
             }
@@ -745,6 +777,63 @@ fn load_firmware(&self) -> anyhow::Result<LoadMode> {
         })
     }
 
+    fn load_disks(
+        &self,
+        devices: &mut impl Extend<Device>,
+        disks: &[(PathBuf, ControllerType)],
+    ) -> anyhow::Result<()> {
+        let mut ide_locations = EXTRA_IDE_DISK_LOCATIONS.into_iter();
+        for (path, controller) in disks {
+            let disk = memdiff_disk(path)?;
+            match controller {
+                ControllerType::Scsi => {
+                    devices.extend([Device::Vmbus(
+                        DeviceVtl::Vtl0,
+                        ScsiControllerHandle {
+                            instance_id: Guid::new_random(),
+                            max_sub_channel_count: 1,
+                            io_queue_depth: None,
+                            devices: vec![ScsiDeviceAndPath {
+                                path: ScsiPath {
+                                    path: 0,
+                                    target: 0,
+                                    lun: 0,
+                                },
+                                device: SimpleScsiDiskHandle {
+                                    read_only: false,
+                                    parameters: Default::default(),
+                                    disk,
+                                }
+                                .into_resource(),
+                            }],
+                            requests: None,
+                        }
+                        .into_resource(),
+                    )]);
+                }
+                ControllerType::Ide => {
+                    if !matches!(self.firmware, Firmware::Pcat { .. }) {
+                        anyhow::bail!("IDE disks are only supported for PCAT firmware");
+                    }
+                    // Use the same locations as Hyper-V, so that the guest
+                    // sees the same layout on either backend.
+                    let (channel, drive) = ide_locations
+                        .next()
+                        .context("at most two IDE disks can be added")?;
+                    devices.extend([Device::Ide(IdeDeviceConfig {
+                        path: ide_resources::IdePath { channel, drive },
+                        guest_media: GuestMedia::Disk {
+                            read_only: false,
+                            disk_parameters: None,
+                            disk_type: disk,
+                        },
+                    })]);
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn load_boot_disk(
         &self,
         devices: &mut impl Extend<Device>,
@@ -0,0 +1,263 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Detecting and reporting crashes of the OpenVMM process itself, as opposed
+//! to the guest.
+
+use crate::PetriLogSource;
+use futures::AsyncRead;
+use mesh::CancelContext;
+use mesh::OneshotReceiver;
+use mesh::RecvError;
+use mesh_process::ProcessExit;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The number of bytes of the end of the OpenVMM process's stderr to keep
+/// for crash reports.
+const STDERR_TAIL_BYTES: usize = 16 * 1024;
+
+/// The OpenVMM process exited abnormally, rather than the VM halting.
+#[derive(Debug, Error)]
+#[error("VMM process crashed: {exit}")]
+pub struct VmmCrashError {
+    /// How the process exited.
+    pub exit: ProcessExit,
+    /// The last lines the process wrote to stderr.
+    pub stderr_tail: String,
+    /// Where the core dump was written, if the process dumped core.
+    pub core_dump: Option<String>,
+    /// The `openvmm_crash.log` test attachment holding the crash report, if
+    /// it could be written.
+    pub report: Option<PathBuf>,
+}
+
+impl VmmCrashError {
+    fn report(&self) -> String {
+        let mut report = format!("{self}\n");
+        if let Some(core_dump) = &self.core_dump {
+            writeln!(report, "core dump: {core_dump}").unwrap();
+        }
+        writeln!(report, "last stderr output:\n{}", self.stderr_tail).unwrap();
+        report
+    }
+}
+
+/// Watches the OpenVMM process, to tell a VMM crash apart from the VM halting.
+pub(super) struct VmmExitWatcher {
+    exit: Option<OneshotReceiver<ProcessExit>>,
+    stderr_tail: StderrTail,
+    log_source: PetriLogSource,
+}
+
+impl VmmExitWatcher {
+    pub(super) fn new(
+        exit: OneshotReceiver<ProcessExit>,
+        stderr_tail: StderrTail,
+        log_source: PetriLogSource,
+    ) -> Self {
+        Self {
+            exit: Some(exit),
+            stderr_tail,
+            log_source,
+        }
+    }
+
+    /// Explains why the VM's halt channel closed with `err`: if the VMM
+    /// process crashed, records the crash and returns a [`VmmCrashError`].
+    pub(super) async fn vm_disappeared(&mut self, err: RecvError) -> anyhow::Error {
+        match self.crashed_exit().await {
+            Some(exit) => self.record_crash(exit).into(),
+            None => anyhow::Error::new(err).context("VM disappeared"),
+        }
+    }
+
+    /// Returns how the VMM process exited, if it did so abnormally.
+    async fn crashed_exit(&mut self) -> Option<ProcessExit> {
+        // The process may not have been reaped yet when the channel closes.
+        let exit = CancelContext::new()
+            .with_timeout(Duration::from_secs(5))
+            .until_cancelled(self.exit.take()?)
+            .await;
+        match exit {
+            Ok(Ok(exit)) if !exit.success() => Some(exit),
+            _ => None,
+        }
+    }
+
+    fn record_crash(&self, exit: ProcessExit) -> VmmCrashError {
+        let mut crash = VmmCrashError {
+            core_dump: exit.core_dumped.then(|| core_dump_location(exit.pid)),
+            stderr_tail: self.stderr_tail.lines(),
+            exit,
+            report: None,
+        };
+        tracing::error!(
+            exit = %crash.exit,
+            core_dump = crash.core_dump.as_deref(),
+            "VMM process crashed"
+        );
+        match self
+            .log_source
+            .write_attachment("openvmm_crash.log", crash.report())
+        {
+            Ok(path) => crash.report = Some(path),
+            Err(err) => tracing::warn!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to write VMM crash report"
+            ),
+        }
+        crash
+    }
+}
+
+/// The end of the OpenVMM process's stderr.
+#[derive(Clone, Default)]
+pub(super) struct StderrTail(Arc<Mutex<VecDeque<u8>>>);
+
+impl StderrTail {
+    fn push(&self, data: &[u8]) {
+        let mut tail = self.0.lock();
+        tail.extend(data);
+        let excess = tail.len().saturating_sub(STDERR_TAIL_BYTES);
+        tail.drain(..excess);
+    }
+
+    /// Returns the complete lines in the tail.
+    fn lines(&self) -> String {
+        let tail = self.0.lock();
+        let (a, b) = tail.as_slices();
+        let data = [a, b].concat();
+        // Skip the first line if it was cut off.
+        let data = if tail.len() == STDERR_TAIL_BYTES {
+            data.iter()
+                .position(|&c| c == b'\n')
+                .map_or(&[][..], |i| &data[i + 1..])
+        } else {
+            &data[..]
+        };
+        String::from_utf8_lossy(data).into_owned()
+    }
+}
+
+/// A reader that keeps the end of everything read from `reader` in `tail`.
+pub(super) struct TailReader<R> {
+    pub(super) reader: R,
+    pub(super) tail: StderrTail,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TailReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = std::task::ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
+        self.tail.push(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Describes where the Linux kernel wrote the core dump of process `pid`.
+fn core_dump_location(pid: i32) -> String {
+    let pattern = fs_err::read_to_string("/proc/sys/kernel/core_pattern");
+    let uses_pid =
+        fs_err::read_to_string("/proc/sys/kernel/core_uses_pid").is_ok_and(|s| s.trim() == "1");
+    match pattern {
+        Ok(pattern) => core_dump_path(pattern.trim(), pid, uses_pid),
+        Err(err) => format!("unknown ({err})"),
+    }
+}
+
+/// Expands the kernel's `core_pattern` for process `pid`.
+fn core_dump_path(pattern: &str, pid: i32, uses_pid: bool) -> String {
+    if let Some(handler) = pattern.strip_prefix('|') {
+        return format!("piped to {handler} (try `coredumpctl info {pid}`)");
+    }
+    let mut path = String::new();
+    let mut has_pid = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('%', Some('p')) => {
+                chars.next();
+                has_pid = true;
+                write!(path, "{pid}").unwrap();
+            }
+            ('%', Some('%')) => {
+                chars.next();
+                path.push('%');
+            }
+            _ => path.push(c),
+        }
+    }
+    if uses_pid && !has_pid {
+        write!(path, ".{pid}").unwrap();
+    }
+    // Relative paths are relative to the VMM's working directory, which it
+    // inherits from this process.
+    match std::env::current_dir() {
+        Ok(dir) if !path.starts_with('/') => dir.join(path).display().to_string(),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stderr_tail() {
+        let tail = StderrTail::default();
+        tail.push(b"one\ntwo\n");
+        assert_eq!(tail.lines(), "one\ntwo\n");
+
+        let long = "x".repeat(STDERR_TAIL_BYTES);
+        tail.push(long.as_bytes());
+        tail.push(b"\nthree\n");
+        assert_eq!(tail.lines(), "three\n");
+    }
+
+    #[test]
+    fn core_dump_paths() {
+        assert_eq!(
+            core_dump_path("/var/crash/core.%e.%p", 42, false),
+            "/var/crash/core.%e.42"
+        );
+        assert_eq!(core_dump_path("/cores/core%%", 42, true), "/cores/core%.42");
+        assert!(std::path::Path::new(&core_dump_path("core", 42, false)).ends_with("core"));
+        assert!(
+            core_dump_path("|/usr/lib/systemd/systemd-coredump %P", 42, false)
+                .contains("coredumpctl info 42")
+        );
+    }
+
+    #[test]
+    fn crash_report() {
+        let crash = VmmCrashError {
+            exit: ProcessExit {
+                pid: 42,
+                code: None,
+                signal: Some(11),
+                core_dumped: true,
+            },
+            stderr_tail: "thread 'main' panicked\n".into(),
+            core_dump: Some("/cores/core.42".into()),
+            report: None,
+        };
+        assert_eq!(
+            crash.to_string(),
+            "VMM process crashed: signal 11 (core dumped)"
+        );
+        let report = crash.report();
+        assert!(report.contains("/cores/core.42"), "{report}");
+        assert!(report.contains("thread 'main' panicked"), "{report}");
+    }
+}
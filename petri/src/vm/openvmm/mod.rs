@@ -9,13 +9,21 @@
 //! * The VM is either shut down by the code in `runtime`, or gets dropped and cleaned up automatically.
 
 mod construct;
+mod crash;
 mod modify;
 mod runtime;
 mod start;
 
+pub use crash::VmmCrashError;
+pub use runtime::MemoryMap;
 pub use runtime::PetriVmOpenVmm;
+pub use runtime::VmbusChannelInfo;
+pub use runtime::VpRegister;
 
+use crate::BackendCapabilities;
 use crate::Firmware;
+use crate::GuestCredentials;
+use crate::IsolationType;
 use crate::PetriLogFile;
 use crate::PetriLogSource;
 use crate::PetriVmConfig;
@@ -25,6 +33,7 @@
 use crate::disk_image::AgentImage;
 use crate::linux_direct_serial_agent::LinuxDirectSerialAgent;
 use crate::openhcl_diag::OpenHclDiagHandler;
+use crate::serial_login::SerialConsoleHost;
 use anyhow::Context;
 use async_trait::async_trait;
 use disk_backend_resources::LayeredDiskHandle;
@@ -46,6 +55,7 @@
 use petri_artifacts_common::tags::OsFlavor;
 use petri_artifacts_core::ArtifactResolver;
 use petri_artifacts_core::ResolvedArtifact;
+use std::path::Path;
 use std::path::PathBuf;
 use tempfile::TempPath;
 use unix_socket::UnixListener;
@@ -73,6 +83,10 @@
 /// The MAC address used by the NIC assigned with [`PetriVmConfigOpenVmm::with_nic`].
 pub const NIC_MAC_ADDRESS: MacAddress = MacAddress::new([0x00, 0x15, 0x5D, 0x12, 0x12, 0x12]);
 
+/// The MAC address used by the NIC assigned with [`PetriVmConfigOpenVmm::with_net_queues`].
+pub const NET_QUEUES_MAC_ADDRESS: MacAddress =
+    MacAddress::new([0x00, 0x15, 0x5D, 0x12, 0x12, 0x13]);
+
 /// OpenVMM Petri Backend
 pub struct OpenVmmPetriBackend {
     openvmm_path: ResolvedArtifact,
@@ -84,11 +98,28 @@ impl PetriVmmBackend for OpenVmmPetriBackend {
     type VmRuntime = PetriVmOpenVmm;
 
     fn check_compat(firmware: &Firmware, arch: MachineArch) -> bool {
-        arch == MachineArch::host()
+        crate::host_supports_guest_arch(arch)
             && !(firmware.is_openhcl() && (!cfg!(windows) || arch == MachineArch::Aarch64))
             && !(firmware.is_pcat() && arch == MachineArch::Aarch64)
     }
 
+    fn capabilities() -> BackendCapabilities {
+        BackendCapabilities {
+            // OpenHCL only runs on Windows hosts, where WHP supports VBS
+            // isolation only.
+            isolation: if cfg!(windows) {
+                vec![IsolationType::Vbs]
+            } else {
+                Vec::new()
+            },
+            linux_direct_boot: true,
+            vtl2_nvme_boot: true,
+            dynamic_memory: false,
+            modify_vmm_config: true,
+            inspect: true,
+        }
+    }
+
     fn new(resolver: &ArtifactResolver<'_>) -> Self {
         OpenVmmPetriBackend {
             openvmm_path: resolver
@@ -125,6 +156,8 @@ pub struct PetriVmConfigOpenVmm {
 
     // Logging
     openvmm_log_file: PetriLogFile,
+    openvmm_log_filter: Option<String>,
+    openvmm_log_copy: Option<PathBuf>,
 
     // Resources that are only used during startup.
     ged: Option<get_resources::ged::GuestEmulationDeviceHandle>,
@@ -138,10 +171,13 @@ struct PetriVmResourcesOpenVmm {
     kvp_ic_send: Sender<hyperv_ic_resources::kvp::KvpConnectRpc>,
     expected_boot_event: Option<FirmwareEvent>,
     ged_send: Option<Sender<get_resources::ged::GuestEmulationRequest>>,
+    debugger_rpc_send: Option<Sender<vmm_core_defs::debug_rpc::DebugRequest>>,
     pipette_listener: PolledSocket<UnixListener>,
     vtl2_pipette_listener: Option<PolledSocket<UnixListener>>,
     openhcl_diag_handler: Option<OpenHclDiagHandler>,
     linux_direct_serial_agent: Option<LinuxDirectSerialAgent>,
+    serial_console: SerialConsoleHost,
+    guest_credentials: GuestCredentials,
 
     // Externally injected management stuff also needed at runtime.
     driver: DefaultDriver,
@@ -168,7 +204,10 @@ pub fn os_flavor(&self) -> OsFlavor {
 fn memdiff_disk_from_artifact(
     artifact: &ResolvedArtifact,
 ) -> anyhow::Result<Resource<DiskHandleKind>> {
-    let path = artifact.as_ref();
+    memdiff_disk(artifact.as_ref())
+}
+
+fn memdiff_disk(path: &Path) -> anyhow::Result<Resource<DiskHandleKind>> {
     let disk = open_disk_type(path, true)
         .with_context(|| format!("failed to open disk: {}", path.display()))?;
     Ok(LayeredDiskHandle {
@@ -8,6 +8,7 @@
 // `PetriVmConfig`, and add corresponding functions to `PetriVmBuilder`.
 
 use super::MANA_INSTANCE;
+use super::NET_QUEUES_MAC_ADDRESS;
 use super::NIC_MAC_ADDRESS;
 use super::PetriVmConfigOpenVmm;
 use chipset_resources::battery::BatteryDeviceHandleX64;
@@ -18,11 +19,21 @@
 use hvlite_defs::config::Config;
 use hvlite_defs::config::DeviceVtl;
 use hvlite_defs::config::LoadMode;
+use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VpciDeviceConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
+use petri_artifacts_common::tags::MachineArch;
+use std::path::Path;
+use std::path::PathBuf;
 use tpm_resources::TpmDeviceHandle;
 use tpm_resources::TpmRegisterLayout;
+use virtio_resources::VirtioPciDeviceHandle;
+use virtio_resources::p9::VirtioPlan9Handle;
 use vm_resource::IntoResource;
+use vm_resource::Resource;
+use vm_resource::kind::NetEndpointHandleKind;
+use vm_resource::kind::VirtioDeviceHandle;
+use vmbus_core::protocol::Version;
 use vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreHandle;
 use vmotherboard::ChipsetDeviceHandle;
 use vtl2_settings_proto::Vtl2Settings;
@@ -166,6 +177,139 @@ pub fn with_nic(mut self) -> Self {
         self
     }
 
+    /// Set the tracing filter for the OpenVMM process (in `OPENVMM_LOG`
+    /// syntax, e.g. `debug` or `virtio_net=trace`), and copy its log to
+    /// `path` in addition to the test's `openvmm.log`.
+    pub fn with_openvmm_log(mut self, path: PathBuf, filter: &str) -> Self {
+        self.openvmm_log_filter = Some(filter.to_owned());
+        self.openvmm_log_copy = Some(path);
+        self
+    }
+
+    /// Add a virtio-net NIC with `count` queue pairs, backed by `endpoint`, to
+    /// the VM. The NIC uses [`NET_QUEUES_MAC_ADDRESS`].
+    ///
+    /// `count` must be a power of two no larger than 0x4000, the most queue
+    /// pairs the virtio-net device can offer alongside its control queue. The
+    /// device offers no more queue pairs than the endpoint supports, so a
+    /// single-queue endpoint such as consomme limits the NIC to one pair.
+    pub fn with_net_queues(
+        mut self,
+        count: u16,
+        endpoint: Resource<NetEndpointHandleKind>,
+    ) -> Self {
+        const VIRTIO_NET_MAX_QUEUE_PAIRS: u16 = 0x4000;
+        assert!(
+            count.is_power_of_two() && count <= VIRTIO_NET_MAX_QUEUE_PAIRS,
+            "virtio-net queue count must be a power of two no larger than {VIRTIO_NET_MAX_QUEUE_PAIRS}, got {count}"
+        );
+
+        self.add_virtio_pci_device(
+            virtio_resources::net::VirtioNetHandle {
+                max_queues: Some(count),
+                mac_address: NET_QUEUES_MAC_ADDRESS,
+                endpoint,
+            }
+            .into_resource(),
+        );
+        self
+    }
+
+    /// Specifies whether the Hyper-V enlightenments should be offloaded to the
+    /// hypervisor's built-in implementation where available, which is the
+    /// default.
+    ///
+    /// When disabled, OpenVMM emulates the enlightenments (and the APIC)
+    /// itself, exercising its fallback paths. This currently only has an
+    /// effect on WHP.
+    pub fn with_offloaded_enlightenments(mut self, offload: bool) -> Self {
+        assert!(
+            self.config.hypervisor.with_hv,
+            "enlightenments require the hypervisor interface"
+        );
+        assert!(
+            offload || !self.config.hypervisor.user_mode_apic,
+            "user mode enlightenments already imply a user mode APIC"
+        );
+        self.config.hypervisor.user_mode_hv_enlightenments = !offload;
+        self
+    }
+
+    /// Emulate the APIC in OpenVMM rather than in the hypervisor. This
+    /// currently only has an effect on WHP.
+    pub fn with_user_mode_apic(mut self, enable: bool) -> Self {
+        assert!(
+            !self.config.hypervisor.user_mode_hv_enlightenments,
+            "user mode enlightenments already imply a user mode APIC"
+        );
+        self.config.hypervisor.user_mode_apic = enable;
+        self
+    }
+
+    /// Limit the VMBus protocol version offered to the guest to `version`, as
+    /// produced by [`vmbus_core::protocol::make_version`].
+    ///
+    /// The guest negotiates the highest version it supports at or below this
+    /// cap. For UEFI guests, the cap only takes effect after the firmware
+    /// disconnects from VMBus.
+    pub fn with_vmbus_max_version(mut self, version: u32) -> Self {
+        const KNOWN_VERSIONS: &[Version] = &[
+            Version::V1,
+            Version::Win7,
+            Version::Win8,
+            Version::Win8_1,
+            Version::Win10,
+            Version::Win10Rs3_0,
+            Version::Win10Rs3_1,
+            Version::Win10Rs4,
+            Version::Win10Rs5,
+            Version::Iron,
+            Version::Copper,
+        ];
+        assert!(
+            KNOWN_VERSIONS.iter().any(|&v| v as u32 == version),
+            "unknown vmbus version {}.{}",
+            version >> 16,
+            version & 0xffff
+        );
+        self.config
+            .vmbus
+            .as_mut()
+            .expect("vmbus is not enabled")
+            .vmbus_max_version = Some(version);
+        self
+    }
+
+    /// Share a host directory with the guest using virtio-9p.
+    ///
+    /// In a Linux guest, the share can be mounted with
+    /// `mount -t 9p -o trans=virtio <tag> <mount point>`.
+    pub fn with_shared_folder(mut self, host_path: impl AsRef<Path>, tag: &str) -> Self {
+        self.add_virtio_pci_device(
+            VirtioPlan9Handle {
+                tag: tag.to_owned(),
+                root_path: host_path.as_ref().display().to_string(),
+                debug: false,
+            }
+            .into_resource(),
+        );
+        self
+    }
+
+    /// Adds a virtio device on PCI, using VPCI when possible to match the
+    /// OpenVMM CLI (the KVM backend does not support it).
+    fn add_virtio_pci_device(&mut self, resource: Resource<VirtioDeviceHandle>) {
+        if cfg!(windows) || cfg!(target_os = "macos") {
+            self.config.vpci_devices.push(VpciDeviceConfig {
+                vtl: DeviceVtl::Vtl0,
+                instance_id: guid::Guid::new_random(),
+                resource: VirtioPciDeviceHandle(resource).into_resource(),
+            });
+        } else {
+            self.config.virtio_devices.push((VirtioBus::Pci, resource));
+        }
+    }
+
     /// Specifies whether the UEFI will always attempt a default boot
     pub fn with_default_boot_always_attempt(mut self, val: bool) -> Self {
         match self.config.load_mode {
@@ -206,6 +350,40 @@ pub fn with_custom_vtl2_settings(mut self, f: impl FnOnce(&mut Vtl2Settings)) ->
         self
     }
 
+    /// Add a custom ACPI table, such as an SSDT, to the VM.
+    ///
+    /// `blob` must be the complete table, including its header, and must
+    /// have a valid checksum. A `DSDT` replaces the default DSDT; any other
+    /// table is added to the XSDT. Only supported for x86_64 Linux direct
+    /// boot, since otherwise the firmware builds the ACPI tables.
+    pub fn with_custom_acpi_table(mut self, signature: [u8; 4], blob: Vec<u8>) -> Self {
+        if let Err(err) = validate_acpi_table(signature, &blob) {
+            panic!(
+                "invalid {} table: {err}",
+                String::from_utf8_lossy(&signature)
+            );
+        }
+        let LoadMode::Linux {
+            custom_dsdt,
+            custom_acpi_tables,
+            ..
+        } = &mut self.config.load_mode
+        else {
+            panic!("custom ACPI tables are only supported for Linux direct boot")
+        };
+        assert_eq!(
+            self.arch,
+            MachineArch::X86_64,
+            "custom ACPI tables are only supported on x86_64"
+        );
+        if &signature == b"DSDT" {
+            *custom_dsdt = Some(blob);
+        } else {
+            custom_acpi_tables.push(blob);
+        }
+        self
+    }
+
     /// Load with the specified VTL2 relocation mode.
     pub fn with_vtl2_relocation_mode(mut self, mode: Vtl2BaseAddressType) -> Self {
         let LoadMode::Igvm {
@@ -218,6 +396,38 @@ pub fn with_vtl2_relocation_mode(mut self, mode: Vtl2BaseAddressType) -> Self {
         self
     }
 
+    /// Set the size of VTL2's memory, overriding the size requested by the
+    /// IGVM file.
+    ///
+    /// VTL2 is placed using the memory layout rather than at a fixed address,
+    /// since a fixed placement may not fit the new size. `bytes` must be a
+    /// nonzero multiple of 2MB.
+    pub fn with_vtl2_memory_size(mut self, bytes: u64) -> Self {
+        const SIZE_2_MB: u64 = 2 * 1024 * 1024;
+        assert!(
+            bytes != 0 && bytes % SIZE_2_MB == 0,
+            "vtl2 memory size {bytes:#x} must be a nonzero multiple of 2MB"
+        );
+        let LoadMode::Igvm {
+            vtl2_base_address, ..
+        } = &mut self.config.load_mode
+        else {
+            panic!("vtl2 memory size is only supported for OpenHCL firmware")
+        };
+        *vtl2_base_address = match vtl2_base_address {
+            Vtl2BaseAddressType::Absolute(_) | Vtl2BaseAddressType::MemoryLayout { .. } => {
+                Vtl2BaseAddressType::MemoryLayout { size: Some(bytes) }
+            }
+            Vtl2BaseAddressType::Vtl2Allocate { .. } => {
+                Vtl2BaseAddressType::Vtl2Allocate { size: Some(bytes) }
+            }
+            Vtl2BaseAddressType::File => {
+                panic!("vtl2 memory size cannot be changed when loading at the file's address")
+            }
+        };
+        self
+    }
+
     /// This is intended for special one-off use cases. As soon as something
     /// is needed in multiple tests we should consider making it a supported
     /// pattern.
@@ -242,4 +452,44 @@ pub fn with_allow_early_vtl0_access(mut self, allow: bool) -> Self {
 
         self
     }
+
+    /// Enable reading and writing VP registers at runtime via
+    /// [`PetriVmOpenVmm::read_vp_register`](super::PetriVmOpenVmm::read_vp_register).
+    ///
+    /// This requires OpenVMM to be built with the `gdb` feature, which is on
+    /// by default.
+    pub fn with_vp_register_access(mut self) -> Self {
+        let (send, recv) = mesh::channel();
+        self.config.debugger_rpc = Some(recv);
+        self.resources.debugger_rpc_send = Some(send);
+        self
+    }
+}
+
+/// Checks that `table` is a complete ACPI table with the given signature and a
+/// valid checksum.
+fn validate_acpi_table(signature: [u8; 4], table: &[u8]) -> anyhow::Result<()> {
+    const HEADER_LEN: usize = 36;
+
+    if table.len() < HEADER_LEN {
+        anyhow::bail!("table is smaller than the {HEADER_LEN} byte header");
+    }
+    if table[..4] != signature {
+        anyhow::bail!(
+            "header signature is {}",
+            String::from_utf8_lossy(&table[..4])
+        );
+    }
+    let length = u32::from_le_bytes(table[4..8].try_into().unwrap());
+    if length as usize != table.len() {
+        anyhow::bail!(
+            "header length is {length}, but the table is {} bytes",
+            table.len()
+        );
+    }
+    let sum = table.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    if sum != 0 {
+        anyhow::bail!("checksum is off by {sum:#x}");
+    }
+    Ok(())
 }
@@ -4,8 +4,11 @@
 //! Methods to interact with a running [`PetriVmOpenVmm`].
 
 use super::PetriVmResourcesOpenVmm;
+use super::crash::VmmExitWatcher;
 use crate::OpenHclServicingFlags;
+use crate::PetriLogSource;
 use crate::PetriVmRuntime;
+use crate::SerialConsole;
 use crate::ShutdownKind;
 use crate::openhcl_diag::OpenHclDiagHandler;
 use crate::worker::Worker;
@@ -15,6 +18,7 @@
 use futures::FutureExt;
 use futures_concurrency::future::Race;
 use get_resources::ged::FirmwareEvent;
+use guid::Guid;
 use hvlite_defs::rpc::PulseSaveRestoreError;
 use hyperv_ic_resources::shutdown::ShutdownRpc;
 use mesh::CancelContext;
@@ -23,17 +27,22 @@
 use mesh::rpc::RpcError;
 use mesh::rpc::RpcSend;
 use mesh_process::Mesh;
+use mesh_process::ResourceUsage;
 use pal_async::DefaultDriver;
 use pal_async::socket::PolledSocket;
 use pal_async::task::Task;
 use petri_artifacts_core::ResolvedArtifact;
 use pipette_client::PipetteClient;
+use std::fmt;
 use std::future::Future;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use unix_socket::UnixListener;
 use vmm_core_defs::HaltReason;
+use vmm_core_defs::debug_rpc::DebugRequest;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
 use vtl2_settings_proto::Vtl2Settings;
 
 /// A running VM that tests can interact with.
@@ -51,6 +60,17 @@ async fn teardown(self) -> anyhow::Result<()> {
         tracing::info!("cancelling watchdogs");
         futures::future::join_all(self.inner.watchdog_tasks.into_iter().map(|t| t.cancel())).await;
 
+        // Record the resource usage of the VMM process while it is still
+        // running, since it can no longer be queried once it has exited and
+        // been reaped. This helps track down memory and CPU regressions.
+        match self.inner.mesh.resource_usage().await {
+            Ok(usage) => record_resource_usage(&self.inner.resources.log_source, usage),
+            Err(err) => tracing::warn!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to get VMM resource usage"
+            ),
+        }
+
         tracing::info!("Cancelled watchdogs, waiting for worker");
         let worker = Arc::into_inner(self.inner.worker)
             .expect("Watchdog task was cancelled, we should be the only ref left");
@@ -68,15 +88,21 @@ async fn teardown(self) -> anyhow::Result<()> {
     }
 
     async fn wait_for_halt(&mut self) -> anyhow::Result<HaltReason> {
-        let halt_reason = if let Some(already) = self.halt.already_received.take() {
-            already.map_err(anyhow::Error::from)
-        } else {
-            self.halt
-                .halt_notif
-                .recv()
-                .await
-                .context("Failed to get halt reason")
-        }?;
+        let halt_reason = match self.halt.already_received.take() {
+            Some(already) => already,
+            None => self.halt.halt_notif.recv().await,
+        };
+        let halt_reason = match halt_reason {
+            Ok(halt_reason) => halt_reason,
+            Err(err) => {
+                return Err(self
+                    .halt
+                    .vmm_exit
+                    .vm_disappeared(err)
+                    .await
+                    .context("Failed to get halt reason"));
+            }
+        };
 
         tracing::info!(?halt_reason, "Got halt reason");
         Ok(halt_reason)
@@ -98,6 +124,11 @@ async fn wait_for_boot_event(&mut self) -> anyhow::Result<FirmwareEvent> {
         Self::wait_for_boot_event(self).await
     }
 
+    async fn wait_for_heartbeat(&mut self) -> anyhow::Result<()> {
+        tracing::warn!("OpenVMM does not support the heartbeat IC, skipping");
+        Ok(())
+    }
+
     async fn wait_for_enlightened_shutdown_ready(&mut self) -> anyhow::Result<()> {
         Self::wait_for_enlightened_shutdown_ready(self)
             .await
@@ -117,16 +148,265 @@ async fn restart_openhcl(
     }
 }
 
+/// Logs the resource usage of the VMM process, and saves it to the
+/// `resource_usage.json` attachment, which is reported as properties of the
+/// test in the JUnit results.
+fn record_resource_usage(log_source: &PetriLogSource, usage: ResourceUsage) {
+    tracing::info!(
+        peak_memory = usage.peak_memory,
+        cpu_time = ?usage.cpu_time,
+        "VMM resource usage"
+    );
+    let json = serde_json::json!({
+        "peak_memory_bytes": usage.peak_memory,
+        "cpu_time_secs": usage.cpu_time.as_secs_f64(),
+    });
+    if let Err(err) = log_source.write_attachment("resource_usage.json", json.to_string()) {
+        tracing::warn!(
+            error = err.as_ref() as &dyn std::error::Error,
+            "failed to write resource usage"
+        );
+    }
+}
+
 pub(super) struct PetriVmInner {
     pub(super) resources: PetriVmResourcesOpenVmm,
     pub(super) mesh: Mesh,
     pub(super) worker: Arc<Worker>,
     pub(super) watchdog_tasks: Vec<Task<()>>,
+    pub(super) paused: bool,
 }
 
 struct PetriVmHaltReceiver {
     halt_notif: Receiver<HaltReason>,
     already_received: Option<Result<HaltReason, RecvError>>,
+    vmm_exit: VmmExitWatcher,
+}
+
+/// The guest memory map of a VM, as reported by the VM worker.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// The RAM ranges, in address order.
+    pub ram: Vec<Range<u64>>,
+    /// The MMIO gaps, in address order.
+    pub mmio: Vec<Range<u64>>,
+    /// The range reserved for VTL2, if it was allocated from the memory
+    /// layout.
+    pub vtl2: Option<Range<u64>>,
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (kind, ranges) in [("ram", &self.ram), ("mmio", &self.mmio)] {
+            for range in ranges {
+                writeln!(f, "{kind:>4}: {:#x}-{:#x}", range.start, range.end)?;
+            }
+        }
+        if let Some(range) = &self.vtl2 {
+            writeln!(f, "vtl2: {:#x}-{:#x}", range.start, range.end)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryMap {
+    /// Parses the memory layout from the worker's `memory_layout` inspect
+    /// node.
+    fn from_inspect(node: &inspect::Node) -> anyhow::Result<Self> {
+        let mut map = Self::default();
+        let inspect::Node::Dir(entries) = node else {
+            anyhow::bail!("expected a directory");
+        };
+        for entry in entries {
+            let ranges = match entry.name.as_str() {
+                "ram" => &mut map.ram,
+                "mmio" => &mut map.mmio,
+                "vtl2_range" => {
+                    let inspect::Node::Value(inspect::Value {
+                        kind: inspect::ValueKind::String(range),
+                        ..
+                    }) = &entry.node
+                    else {
+                        anyhow::bail!("expected a VTL2 range value");
+                    };
+                    map.vtl2 = Some(Self::parse_range(range)?);
+                    continue;
+                }
+                _ => continue,
+            };
+            let inspect::Node::Dir(range_entries) = &entry.node else {
+                anyhow::bail!("expected a directory of {} ranges", entry.name);
+            };
+            for range in range_entries {
+                ranges.push(Self::parse_range(&range.name)?);
+            }
+            ranges.sort_by_key(|range| range.start);
+        }
+        Ok(map)
+    }
+
+    /// Returns whether `range` is entirely within a single RAM range.
+    pub fn is_ram(&self, range: &Range<u64>) -> bool {
+        self.ram
+            .iter()
+            .any(|ram| ram.start <= range.start && range.end <= ram.end)
+    }
+
+    /// Parses a range in its `MemoryRange` display form, `start-end` in hex.
+    fn parse_range(range: &str) -> anyhow::Result<Range<u64>> {
+        let parse = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16);
+        let (start, end) = range
+            .split_once('-')
+            .and_then(|(start, end)| Some(parse(start).ok()?..parse(end).ok()?))
+            .with_context(|| format!("bad range {range}"))?;
+        Ok(start..end)
+    }
+}
+
+/// A VMBus channel offered to the guest by the VTL0 VMBus server, as reported
+/// by the VM worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmbusChannelInfo {
+    /// The channel's instance ID.
+    pub instance_id: Guid,
+    /// The channel's interface (device type) ID.
+    pub interface_id: Guid,
+    /// The device's name for the interface, such as `scsi` or `shutdown_ic`.
+    pub interface_name: String,
+    /// The channel state, such as `closed` or `open`.
+    pub state: String,
+}
+
+impl VmbusChannelInfo {
+    /// Parses the primary channels from the VMBus server's `channels`
+    /// inspect node, which has a child per channel keyed by instance ID.
+    fn from_inspect(node: &inspect::Node) -> anyhow::Result<Vec<Self>> {
+        let inspect::Node::Dir(entries) = node else {
+            anyhow::bail!("expected a directory");
+        };
+        entries
+            .iter()
+            .map(|entry| {
+                let inspect::Node::Dir(fields) = &entry.node else {
+                    anyhow::bail!("expected a directory for channel {}", entry.name);
+                };
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find_map(|field| match &field.node {
+                            inspect::Node::Value(inspect::Value {
+                                kind: inspect::ValueKind::String(value),
+                                ..
+                            }) if field.name == name => Some(value.clone()),
+                            _ => None,
+                        })
+                        .with_context(|| format!("channel {} is missing {name}", entry.name))
+                };
+                Ok(Self {
+                    instance_id: entry
+                        .name
+                        .parse()
+                        .with_context(|| format!("bad instance ID {}", entry.name))?,
+                    interface_id: field("interface_id")?.parse().context("bad interface ID")?,
+                    interface_name: field("interface_name")?,
+                    state: field("state")?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A VP register that can be accessed with
+/// [`PetriVmOpenVmm::read_vp_register`] and
+/// [`PetriVmOpenVmm::write_vp_register`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VpRegister {
+    /// An x86-64 general purpose register, by encoding index (rax, rcx, rdx,
+    /// rbx, rsp, rbp, rsi, rdi, r8-r15).
+    X86Gp(u8),
+    /// x86-64 RIP.
+    X86Rip,
+    /// x86-64 RFLAGS.
+    X86Rflags,
+    /// x86-64 CR0.
+    X86Cr0,
+    /// x86-64 CR2.
+    X86Cr2,
+    /// x86-64 CR3.
+    X86Cr3,
+    /// x86-64 CR4.
+    X86Cr4,
+    /// x86-64 CR8.
+    X86Cr8,
+    /// x86-64 EFER.
+    X86Efer,
+    /// The x86-64 CS selector, whose low two bits are the current privilege
+    /// level.
+    X86CsSelector,
+    /// An aarch64 general purpose register, x0-x30.
+    Aarch64X(u8),
+    /// aarch64 SP_EL0.
+    Aarch64SpEl0,
+    /// aarch64 SP_EL1.
+    Aarch64SpEl1,
+    /// aarch64 PC.
+    Aarch64Pc,
+    /// aarch64 CPSR.
+    Aarch64Cpsr,
+    /// aarch64 SCTLR_EL1.
+    Aarch64SctlrEl1,
+    /// aarch64 TCR_EL1.
+    Aarch64TcrEl1,
+    /// aarch64 TTBR0_EL1.
+    Aarch64Ttbr0El1,
+    /// aarch64 TTBR1_EL1.
+    Aarch64Ttbr1El1,
+}
+
+impl VpRegister {
+    fn read(self, state: &mut DebuggerVpState) -> anyhow::Result<u64> {
+        match (self, &*state) {
+            (Self::X86CsSelector, DebuggerVpState::X86_64(s)) => Ok(s.cs.selector.into()),
+            _ => Ok(*self.field(state)?),
+        }
+    }
+
+    fn write(self, state: &mut DebuggerVpState, value: u64) -> anyhow::Result<()> {
+        match (self, &mut *state) {
+            (Self::X86CsSelector, DebuggerVpState::X86_64(s)) => {
+                s.cs.selector = value
+                    .try_into()
+                    .with_context(|| format!("{value:#x} is not a valid selector"))?;
+            }
+            _ => *self.field(state)? = value,
+        }
+        Ok(())
+    }
+
+    fn field(self, state: &mut DebuggerVpState) -> anyhow::Result<&mut u64> {
+        let field = match (self, state) {
+            (Self::X86Gp(n), DebuggerVpState::X86_64(s)) => s.gp.get_mut(n as usize),
+            (Self::X86Rip, DebuggerVpState::X86_64(s)) => Some(&mut s.rip),
+            (Self::X86Rflags, DebuggerVpState::X86_64(s)) => Some(&mut s.rflags),
+            (Self::X86Cr0, DebuggerVpState::X86_64(s)) => Some(&mut s.cr0),
+            (Self::X86Cr2, DebuggerVpState::X86_64(s)) => Some(&mut s.cr2),
+            (Self::X86Cr3, DebuggerVpState::X86_64(s)) => Some(&mut s.cr3),
+            (Self::X86Cr4, DebuggerVpState::X86_64(s)) => Some(&mut s.cr4),
+            (Self::X86Cr8, DebuggerVpState::X86_64(s)) => Some(&mut s.cr8),
+            (Self::X86Efer, DebuggerVpState::X86_64(s)) => Some(&mut s.efer),
+            (Self::Aarch64X(n), DebuggerVpState::Aarch64(s)) => s.x.get_mut(n as usize),
+            (Self::Aarch64SpEl0, DebuggerVpState::Aarch64(s)) => Some(&mut s.sp_el0),
+            (Self::Aarch64SpEl1, DebuggerVpState::Aarch64(s)) => Some(&mut s.sp_el1),
+            (Self::Aarch64Pc, DebuggerVpState::Aarch64(s)) => Some(&mut s.pc),
+            (Self::Aarch64Cpsr, DebuggerVpState::Aarch64(s)) => Some(&mut s.cpsr),
+            (Self::Aarch64SctlrEl1, DebuggerVpState::Aarch64(s)) => Some(&mut s.sctlr_el1),
+            (Self::Aarch64TcrEl1, DebuggerVpState::Aarch64(s)) => Some(&mut s.tcr_el1),
+            (Self::Aarch64Ttbr0El1, DebuggerVpState::Aarch64(s)) => Some(&mut s.ttbr0_el1),
+            (Self::Aarch64Ttbr1El1, DebuggerVpState::Aarch64(s)) => Some(&mut s.ttbr1_el1),
+            _ => anyhow::bail!("{self:?} is not valid for this VM's architecture"),
+        };
+        field.with_context(|| format!("{self:?} is out of range"))
+    }
 }
 
 // Wrap a PetriVmInner function in [`PetriVmOpenVmm::wait_for_halt_or_internal`] to
@@ -143,12 +423,17 @@ macro_rules! petri_vm_fn {
 // TODO: Add all runtime functions that are not backend specific
 // to the `PetriVmRuntime` trait
 impl PetriVmOpenVmm {
-    pub(super) fn new(inner: PetriVmInner, halt_notif: Receiver<HaltReason>) -> Self {
+    pub(super) fn new(
+        inner: PetriVmInner,
+        halt_notif: Receiver<HaltReason>,
+        vmm_exit: VmmExitWatcher,
+    ) -> Self {
         Self {
             inner,
             halt: PetriVmHaltReceiver {
                 halt_notif,
                 already_received: None,
+                vmm_exit,
             },
         }
     }
@@ -172,6 +457,39 @@ pub async fn wait_for_teardown(mut self) -> anyhow::Result<HaltReason> {
         Ok(halt_reason)
     }
 
+    /// Opens the guest's serial console, for interacting with the guest
+    /// when pipette is unavailable.
+    ///
+    /// This is not available for Linux direct boot, whose console is used by
+    /// the serial agent. Opening a new console closes the previous one's
+    /// output.
+    pub fn serial_console(&self) -> anyhow::Result<SerialConsole> {
+        self.inner.resources.serial_console.open()
+    }
+
+    /// Opens the guest's serial console and logs in with the configured
+    /// [guest credentials](crate::PetriVmBuilder::with_guest_credentials).
+    pub async fn serial_login(&self) -> anyhow::Result<SerialConsole> {
+        let mut console = self.serial_console()?;
+        console
+            .login(
+                &self.inner.resources.guest_credentials,
+                crate::DEFAULT_PROMPT_TIMEOUT,
+            )
+            .await?;
+        Ok(console)
+    }
+
+    /// Crashes the OpenVMM process, to test how VMM crashes are reported.
+    /// Waiting for the VM afterwards fails with a
+    /// [`VmmCrashError`](super::VmmCrashError).
+    pub async fn crash_vmm(&mut self) -> anyhow::Result<()> {
+        let pid = self.inner.vmm_pid().await?;
+        tracing::info!(pid, "crashing the VMM process");
+        self.inner.mesh.crash(pid);
+        Ok(())
+    }
+
     petri_vm_fn!(
         /// Gets a live core dump of the OpenHCL process specified by 'name' and
         /// writes it to 'path'
@@ -225,6 +543,25 @@ pub async fn reset(&mut self) -> anyhow::Result<()>
         /// Test that we are able to inspect OpenHCL.
         pub async fn test_inspect_openhcl(&mut self) -> anyhow::Result<()>
     );
+    petri_vm_fn!(
+        /// Inspects the VM worker's state at `path` (e.g. `partition/vp`).
+        pub async fn inspect(&mut self, path: &str) -> anyhow::Result<inspect::Node>
+    );
+    petri_vm_fn!(
+        /// Gets the guest memory map (RAM ranges and MMIO gaps) from the VM
+        /// worker.
+        pub async fn memory_map(&mut self) -> anyhow::Result<MemoryMap>
+    );
+    petri_vm_fn!(
+        /// Checks that the guest memory map matches `expected`, failing with
+        /// both maps if it does not.
+        pub async fn assert_memory_map(&mut self, expected: &MemoryMap) -> anyhow::Result<()>
+    );
+    petri_vm_fn!(
+        /// Gets the VMBus channels offered to the guest by the VTL0 VMBus
+        /// server. Subchannels are not included.
+        pub async fn vmbus_channels(&mut self) -> anyhow::Result<Vec<VmbusChannelInfo>>
+    );
     petri_vm_fn!(
         /// Get the kmsg stream from OpenHCL.
         pub async fn kmsg(&mut self) -> anyhow::Result<KmsgStream>
@@ -241,15 +578,70 @@ pub async fn wait_for_vtl2_ready(&mut self) -> anyhow::Result<()>
         /// Wait for a connection from a pipette agent
         pub async fn wait_for_agent(&mut self, set_high_vtl: bool) -> anyhow::Result<PipetteClient>
     );
+    petri_vm_fn!(
+        /// Reads `len` bytes of guest physical memory at `gpa`, which must be
+        /// within a single RAM range of the guest memory map.
+        ///
+        /// The guest keeps running while the memory is read, so use
+        /// [`Self::with_paused`] to get a consistent view.
+        pub async fn read_gpa(&mut self, gpa: u64, len: usize) -> anyhow::Result<Vec<u8>>
+    );
+    petri_vm_fn!(
+        /// Writes `data` to guest physical memory at `gpa`, which must be
+        /// within a single RAM range of the guest memory map.
+        ///
+        /// The guest keeps running while the memory is written, so use
+        /// [`Self::with_paused`] to avoid racing with it.
+        pub async fn write_gpa(&mut self, gpa: u64, data: &[u8]) -> anyhow::Result<()>
+    );
+    petri_vm_fn!(
+        /// Reads register `reg` of VP `vp`.
+        ///
+        /// The VM must be paused, via [`Self::with_paused`], and configured
+        /// with
+        /// [`PetriVmConfigOpenVmm::with_vp_register_access`](super::PetriVmConfigOpenVmm::with_vp_register_access).
+        pub async fn read_vp_register(&mut self, vp: u32, reg: VpRegister) -> anyhow::Result<u64>
+    );
+    petri_vm_fn!(
+        /// Writes `value` to register `reg` of VP `vp`.
+        ///
+        /// The same requirements as [`Self::read_vp_register`] apply.
+        pub async fn write_vp_register(&mut self, vp: u32, reg: VpRegister, value: u64) -> anyhow::Result<()>
+    );
     petri_vm_fn!(
         /// Modifies OpenHCL VTL2 settings.
         pub async fn modify_vtl2_settings(&mut self, f: impl FnOnce(&mut Vtl2Settings)) -> anyhow::Result<()>
     );
 
+    petri_vm_fn!(pub(crate) async fn pause(&mut self) -> anyhow::Result<()>);
     petri_vm_fn!(pub(crate) async fn resume(&mut self) -> anyhow::Result<()>);
     petri_vm_fn!(pub(crate) async fn verify_save_restore(&mut self) -> anyhow::Result<()>);
     petri_vm_fn!(pub(crate) async fn launch_linux_direct_pipette(&mut self) -> anyhow::Result<()>);
 
+    /// Pauses the VM, runs `f`, and resumes the VM, so that `f` sees a
+    /// consistent snapshot of the VM's state, such as its memory or inspect
+    /// output.
+    ///
+    /// The VM is resumed even if `f` fails, but `f` must not wait on the
+    /// guest, since it will not make progress until `f` returns. If the VM
+    /// was already paused, it is left paused.
+    pub async fn with_paused<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut Self) -> R,
+    ) -> anyhow::Result<R> {
+        let was_paused = self.inner.paused;
+        if !was_paused {
+            tracing::info!("Pausing VM");
+            self.pause().await?;
+        }
+        let r = f(self).await;
+        if !was_paused {
+            tracing::info!("Resuming VM");
+            self.resume().await?;
+        }
+        Ok(r)
+    }
+
     /// Wrap the provided future in a race with the worker process's halt
     /// notification channel. This is useful for preventing a future from
     /// waiting indefinitely if the VM dies for any reason. If the worker
@@ -313,7 +705,7 @@ enum Either<T> {
                     }
                     Err(_cancel) => match halt_result {
                         Ok(halt_reason) => Err(anyhow::anyhow!("VM halted: {:x?}", halt_reason)),
-                        Err(e) => Err(e).context("VM disappeared"),
+                        Err(e) => Err(halt.vmm_exit.vm_disappeared(e).await),
                     },
                 }
             }
@@ -462,6 +854,123 @@ async fn test_inspect_openhcl(&self) -> anyhow::Result<()> {
         self.openhcl_diag()?.test_inspect().await
     }
 
+    async fn inspect(&self, path: &str) -> anyhow::Result<inspect::Node> {
+        match self.worker.inspect(path).await {
+            inspect::Node::Failed(err) => {
+                Err(err).with_context(|| format!("failed to inspect {path}"))
+            }
+            node => Ok(node),
+        }
+    }
+
+    /// Finds the OpenVMM process among the mesh's processes.
+    async fn vmm_pid(&self) -> anyhow::Result<i32> {
+        let mut inspection = inspect::inspect("hosts", &self.mesh);
+        inspection.resolve().await;
+        let inspect::Node::Dir(hosts) = inspection.results() else {
+            anyhow::bail!("failed to inspect the mesh hosts");
+        };
+        hosts
+            .iter()
+            .find_map(|host| {
+                let inspect::Node::Dir(fields) = &host.node else {
+                    return None;
+                };
+                fields
+                    .iter()
+                    .any(|field| {
+                        field.name == "name"
+                            && matches!(&field.node, inspect::Node::Value(inspect::Value {
+                                kind: inspect::ValueKind::String(name),
+                                ..
+                            }) if name == "vmm")
+                    })
+                    .then(|| host.name.parse().ok())
+                    .flatten()
+            })
+            .context("no VMM process in the mesh")
+    }
+
+    async fn memory_map(&self) -> anyhow::Result<MemoryMap> {
+        let node = self.inspect("memory_layout").await?;
+        MemoryMap::from_inspect(&node).context("failed to parse memory layout")
+    }
+
+    async fn read_gpa(&self, gpa: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        self.check_ram(gpa, len).await?;
+        self.worker
+            .read_memory(gpa, len)
+            .await
+            .with_context(|| format!("failed to read {len:#x} bytes at {gpa:#x}"))
+    }
+
+    async fn write_gpa(&self, gpa: u64, data: &[u8]) -> anyhow::Result<()> {
+        self.check_ram(gpa, data.len()).await?;
+        self.worker
+            .write_memory(gpa, data.to_vec())
+            .await
+            .with_context(|| format!("failed to write {:#x} bytes at {gpa:#x}", data.len()))
+    }
+
+    async fn check_ram(&self, gpa: u64, len: usize) -> anyhow::Result<()> {
+        let range = gpa..gpa
+            .checked_add(len as u64)
+            .with_context(|| format!("{len:#x} bytes at {gpa:#x} overflows"))?;
+        if !self.memory_map().await?.is_ram(&range) {
+            anyhow::bail!(
+                "{:#x}-{:#x} is not within guest RAM",
+                range.start,
+                range.end
+            );
+        }
+        Ok(())
+    }
+
+    async fn read_vp_register(&self, vp: u32, reg: VpRegister) -> anyhow::Result<u64> {
+        let mut state = self.get_vp_state(vp).await?;
+        reg.read(&mut state)
+    }
+
+    async fn write_vp_register(&self, vp: u32, reg: VpRegister, value: u64) -> anyhow::Result<()> {
+        let mut state = self.get_vp_state(vp).await?;
+        reg.write(&mut state, value)?;
+        self.debugger_rpc()?
+            .call(DebugRequest::SetVpState, (vp, state))
+            .await?
+            .with_context(|| format!("failed to set VP {vp} state"))
+    }
+
+    async fn get_vp_state(&self, vp: u32) -> anyhow::Result<Box<DebuggerVpState>> {
+        self.debugger_rpc()?
+            .call(DebugRequest::GetVpState, vp)
+            .await?
+            .with_context(|| format!("failed to get VP {vp} state"))
+    }
+
+    fn debugger_rpc(&self) -> anyhow::Result<&mesh::Sender<DebugRequest>> {
+        anyhow::ensure!(
+            self.paused,
+            "VP registers can only be accessed while the VM is paused"
+        );
+        self.resources
+            .debugger_rpc_send
+            .as_ref()
+            .context("VM is not configured with VP register access")
+    }
+
+    async fn assert_memory_map(&self, expected: &MemoryMap) -> anyhow::Result<()> {
+        let actual = self.memory_map().await?;
+        if actual != *expected {
+            anyhow::bail!("memory map mismatch\nexpected:\n{expected}actual:\n{actual}");
+        }
+        Ok(())
+    }
+
+    async fn vmbus_channels(&self) -> anyhow::Result<Vec<VmbusChannelInfo>> {
+        let node = self.inspect("vmbus/channels").await?;
+        VmbusChannelInfo::from_inspect(&node).context("failed to parse vmbus channels")
+    }
+
     async fn kmsg(&self) -> anyhow::Result<KmsgStream> {
         self.openhcl_diag()?.kmsg().await
     }
@@ -507,8 +1016,15 @@ async fn wait_for_agent_core(
         client
     }
 
-    async fn resume(&self) -> anyhow::Result<()> {
+    async fn pause(&mut self) -> anyhow::Result<()> {
+        self.worker.pause().await?;
+        self.paused = true;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> anyhow::Result<()> {
         self.worker.resume().await?;
+        self.paused = false;
         Ok(())
     }
 
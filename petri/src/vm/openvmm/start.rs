@@ -6,6 +6,9 @@
 use super::PetriVmConfigOpenVmm;
 use super::PetriVmOpenVmm;
 use super::PetriVmResourcesOpenVmm;
+use super::crash::StderrTail;
+use super::crash::TailReader;
+use super::crash::VmmExitWatcher;
 use crate::Firmware;
 use crate::PetriLogFile;
 use crate::PetriLogSource;
@@ -14,6 +17,8 @@
 use diag_client::DiagClient;
 use disk_backend_resources::FileDiskHandle;
 use framebuffer::FramebufferAccess;
+use futures::AsyncRead;
+use futures::AsyncWrite;
 use guid::Guid;
 use hvlite_defs::config::DeviceVtl;
 use image::ColorType;
@@ -30,7 +35,9 @@
 use scsidisk_resources::SimpleScsiDiskHandle;
 use std::io::Write;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::Poll;
 use std::time::Duration;
 use storvsp_resources::ScsiControllerHandle;
 use storvsp_resources::ScsiDeviceAndPath;
@@ -47,6 +54,8 @@ async fn run_core(self) -> anyhow::Result<PetriVmOpenVmm> {
             mut resources,
 
             openvmm_log_file,
+            openvmm_log_filter,
+            openvmm_log_copy,
 
             ged,
             framebuffer_access,
@@ -108,9 +117,15 @@ async fn run_core(self) -> anyhow::Result<PetriVmOpenVmm> {
 
         let mesh = Mesh::new("petri_mesh".to_string())?;
 
-        let host = Self::openvmm_host(&mut resources, &mesh, openvmm_log_file)
-            .await
-            .context("failed to create host process")?;
+        let (host, vmm_exit) = Self::openvmm_host(
+            &mut resources,
+            &mesh,
+            openvmm_log_file,
+            openvmm_log_filter,
+            openvmm_log_copy,
+        )
+        .await
+        .context("failed to create host process")?;
         let (worker, halt_notif) = Worker::launch(&host, config)
             .await
             .context("failed to launch vm worker")?;
@@ -130,8 +145,10 @@ async fn run_core(self) -> anyhow::Result<PetriVmOpenVmm> {
                 mesh,
                 worker,
                 watchdog_tasks,
+                paused: true,
             },
             halt_notif,
+            vmm_exit,
         );
 
         tracing::info!("Resuming VM");
@@ -190,12 +207,12 @@ pub async fn run(mut self) -> anyhow::Result<PetriVmOpenVmm> {
             ));
 
             if matches!(self.firmware.os_flavor(), OsFlavor::Windows) {
-                // Make a file for the IMC hive. It's not guaranteed to be at a fixed
-                // location at runtime.
+                // Make a file for the IMC hive, which also pins the agent
+                // disk's drive letter.
                 let mut imc_hive_file =
                     tempfile::tempfile().context("failed to create temp file")?;
                 imc_hive_file
-                    .write_all(include_bytes!("../../../guest-bootstrap/imc.hiv"))
+                    .write_all(&agent_image.build_imc_hive()?)
                     .context("failed to write imc hive")?;
 
                 // Add the IMC device.
@@ -356,28 +373,86 @@ async fn openvmm_host(
         resources: &mut PetriVmResourcesOpenVmm,
         mesh: &Mesh,
         log_file: PetriLogFile,
-    ) -> anyhow::Result<WorkerHost> {
+        log_filter: Option<String>,
+        log_copy: Option<PathBuf>,
+    ) -> anyhow::Result<(WorkerHost, VmmExitWatcher)> {
         // Copy the child's stderr to this process's, since internally this is
-        // wrapped by the test harness.
+        // wrapped by the test harness. Keep the end of it to report if the
+        // process crashes.
         let (stderr_read, stderr_write) = pal::pipe_pair()?;
-        let task = resources.driver.spawn(
-            "serial log",
-            crate::log_stream(
-                log_file,
-                PolledPipe::new(&resources.driver, stderr_read)
-                    .context("failed to create polled pipe")?,
-            ),
-        );
+        let stderr_tail = StderrTail::default();
+        let stderr_read = TailReader {
+            reader: PolledPipe::new(&resources.driver, stderr_read)
+                .context("failed to create polled pipe")?,
+            tail: stderr_tail.clone(),
+        };
+        let task = if let Some(path) = log_copy {
+            let copy = blocking::Unblock::new(fs_err::File::create(path)?);
+            resources.driver.spawn(
+                "serial log",
+                crate::log_stream(
+                    log_file,
+                    CopyingReader {
+                        reader: stderr_read,
+                        copy,
+                        pending: Vec::new(),
+                    },
+                ),
+            )
+        } else {
+            resources
+                .driver
+                .spawn("serial log", crate::log_stream(log_file, stderr_read))
+        };
         resources.log_stream_tasks.push(task);
 
+        let (exit_send, exit_recv) = mesh::oneshot();
+        let mut config = ProcessConfig::new("vmm")
+            .process_name(&resources.openvmm_path)
+            .stderr(Some(stderr_write))
+            .exit_notify(exit_send);
+        if let Some(filter) = log_filter {
+            config = config.env("OPENVMM_LOG", filter);
+        }
+
         let (host, runner) = mesh_worker::worker_host();
-        mesh.launch_host(
-            ProcessConfig::new("vmm")
-                .process_name(&resources.openvmm_path)
-                .stderr(Some(stderr_write)),
-            hvlite_defs::entrypoint::MeshHostParams { runner },
-        )
-        .await?;
-        Ok(host)
+        mesh.launch_host(config, hvlite_defs::entrypoint::MeshHostParams { runner })
+            .await?;
+        let vmm_exit = VmmExitWatcher::new(exit_recv, stderr_tail, resources.log_source.clone());
+        Ok((host, vmm_exit))
+    }
+}
+
+/// A reader that copies everything read from `reader` to the file `copy`.
+struct CopyingReader<R> {
+    reader: R,
+    copy: blocking::Unblock<fs_err::File>,
+    /// Data that has been read but not yet written to `copy`.
+    pending: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CopyingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        // Finish copying the previous read before reading more, so that the
+        // copy is not left behind.
+        while !this.pending.is_empty() {
+            let n = std::task::ready!(Pin::new(&mut this.copy).poll_write(cx, &this.pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            this.pending.drain(..n);
+        }
+        let n = std::task::ready!(Pin::new(&mut this.reader).poll_read(cx, buf))?;
+        if n == 0 {
+            // Make sure the copy is complete before reporting EOF.
+            std::task::ready!(Pin::new(&mut this.copy).poll_flush(cx))?;
+        }
+        this.pending.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
     }
 }
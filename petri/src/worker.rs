@@ -49,11 +49,24 @@ pub(crate) async fn resume(&self) -> Result<bool, RpcError> {
         self.rpc.call(VmRpc::Resume, ()).await
     }
 
+    pub(crate) async fn pause(&self) -> Result<bool, RpcError> {
+        self.rpc.call(VmRpc::Pause, ()).await
+    }
+
     pub(crate) async fn reset(&self) -> anyhow::Result<()> {
         self.rpc.call(VmRpc::Reset, ()).await??;
         Ok(())
     }
 
+    pub(crate) async fn read_memory(&self, gpa: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        Ok(self.rpc.call(VmRpc::ReadMemory, (gpa, len)).await??)
+    }
+
+    pub(crate) async fn write_memory(&self, gpa: u64, data: Vec<u8>) -> anyhow::Result<()> {
+        self.rpc.call(VmRpc::WriteMemory, (gpa, data)).await??;
+        Ok(())
+    }
+
     pub(crate) async fn pulse_save_restore(&self) -> Result<(), RpcError<PulseSaveRestoreError>> {
         self.rpc.call_failable(VmRpc::PulseSaveRestore, ()).await
     }
@@ -75,10 +88,14 @@ pub(crate) async fn restart_openhcl(
         .await
     }
 
-    pub(crate) async fn inspect_all(&self) -> String {
-        let mut inspection = inspect::inspect("", &self.handle);
+    pub(crate) async fn inspect(&self, path: &str) -> inspect::Node {
+        let mut inspection = inspect::inspect(path, &self.handle);
         inspection.resolve().await;
-        let results = inspection.results();
+        inspection.results()
+    }
+
+    pub(crate) async fn inspect_all(&self) -> String {
+        let results = self.inspect("").await;
         format!("{results:#}",)
     }
 
@@ -4,9 +4,13 @@
 //! Infrastructure to create a multi-process mesh and spawn child processes
 //! within it.
 
-// UNSAFETY: Needed to accept a raw Fd/Handle from our spawning process.
+// UNSAFETY: Needed to accept a raw Fd/Handle from our spawning process, and
+// to query the clock tick rate for process resource usage.
 #![expect(unsafe_code)]
 
+#[cfg(target_os = "linux")]
+mod procfs;
+
 use anyhow::Context;
 use base64::Engine;
 use debug_ptr::DebugPtr;
@@ -18,6 +22,7 @@
 use inspect::SensitivityLevel;
 use mesh::MeshPayload;
 use mesh::OneshotReceiver;
+use mesh::OneshotSender;
 use mesh::message::MeshField;
 use mesh::payload::Protobuf;
 use mesh::rpc::Rpc;
@@ -35,6 +40,7 @@
 use slab::Slab;
 use std::borrow::Cow;
 use std::ffi::OsString;
+use std::fmt;
 use std::fs::File;
 #[cfg(unix)]
 use std::os::unix::prelude::*;
@@ -42,6 +48,7 @@
 use std::os::windows::prelude::*;
 use std::path::PathBuf;
 use std::thread;
+use std::time::Duration;
 use tracing::Instrument;
 use tracing::instrument;
 use unicycle::FuturesUnordered;
@@ -268,8 +275,52 @@ pub struct ProcessConfig {
     process_name: Option<PathBuf>,
     process_args: Vec<OsString>,
     stderr: Option<File>,
+    env: Vec<(OsString, OsString)>,
     skip_worker_arg: bool,
     sandbox_profile: Option<Box<dyn SandboxProfile + Sync>>,
+    exit_notify: Option<OneshotSender<ProcessExit>>,
+}
+
+/// How a process launched by a [`Mesh`] exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessExit {
+    /// The process ID.
+    pub pid: i32,
+    /// The exit code, if the process exited normally.
+    pub code: Option<i32>,
+    /// The signal that terminated the process, on Unix.
+    pub signal: Option<i32>,
+    /// Whether the process dumped core, on Unix.
+    pub core_dumped: bool,
+}
+
+impl ProcessExit {
+    /// Returns true if the process exited with code 0.
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl fmt::Display for ProcessExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(code) = self.code {
+            // Windows crash codes are NTSTATUS values, which read better in
+            // hex.
+            if code < 0 {
+                write!(f, "exit code {code:#x}")?;
+            } else {
+                write!(f, "exit code {code}")?;
+            }
+        } else if let Some(signal) = self.signal {
+            write!(f, "signal {signal}")?;
+        } else {
+            f.write_str("unknown status")?;
+        }
+        if self.core_dumped {
+            f.write_str(" (core dumped)")?;
+        }
+        Ok(())
+    }
 }
 
 impl ProcessConfig {
@@ -281,8 +332,10 @@ pub fn new(name: impl Into<String>) -> Self {
             process_name: None,
             process_args: Vec::new(),
             stderr: None,
+            env: Vec::new(),
             skip_worker_arg: false,
             sandbox_profile: None,
+            exit_notify: None,
         }
     }
 
@@ -297,8 +350,10 @@ pub fn new_with_sandbox(
             process_name: None,
             process_args: Vec::new(),
             stderr: None,
+            env: Vec::new(),
             skip_worker_arg: false,
             sandbox_profile: Some(sandbox_profile),
+            exit_notify: None,
         }
     }
 
@@ -329,11 +384,25 @@ pub fn args<I>(mut self, args: I) -> Self
         self
     }
 
+    /// Sends how the process exited to `send` once it does, so that callers
+    /// can distinguish crashes from clean exits.
+    pub fn exit_notify(mut self, send: OneshotSender<ProcessExit>) -> Self {
+        self.exit_notify = Some(send);
+        self
+    }
+
     /// Sets the process's stderr to `file`.
     pub fn stderr(mut self, file: Option<File>) -> Self {
         self.stderr = file;
         self
     }
+
+    /// Sets an environment variable for the process, in addition to the ones
+    /// inherited from this process.
+    pub fn env(mut self, key: impl Into<OsString>, val: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), val.into()));
+        self
+    }
 }
 
 struct MeshInner {
@@ -362,6 +431,19 @@ enum MeshRequest {
     NewHost(Rpc<NewHostParams, anyhow::Result<()>>),
     Inspect(inspect::Deferred),
     Crash(i32),
+    ResourceUsage(Rpc<(), anyhow::Result<ResourceUsage>>),
+}
+
+/// The resource usage of the processes launched by a [`Mesh`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The peak memory usage of the largest process, in bytes.
+    ///
+    /// On Linux, this is the peak resident set size. On Windows, this is the
+    /// peak committed memory.
+    pub peak_memory: u64,
+    /// The total user and kernel CPU time used by the processes.
+    pub cpu_time: Duration,
 }
 
 struct NewHostParams {
@@ -476,6 +558,18 @@ pub async fn shutdown(self) {
     pub fn crash(&self, pid: i32) {
         self.request.send(MeshRequest::Crash(pid));
     }
+
+    /// Returns the resource usage of the processes launched by this mesh.
+    ///
+    /// On Linux, this only includes processes that are still running, so call
+    /// this before shutting down the mesh. On Windows, this includes every
+    /// process that has been launched.
+    pub async fn resource_usage(&self) -> anyhow::Result<ResourceUsage> {
+        self.request
+            .call(MeshRequest::ResourceUsage, ())
+            .await
+            .context("mesh failed")?
+    }
 }
 
 #[derive(MeshPayload)]
@@ -586,6 +680,7 @@ enum Event {
                             tracing::error!("failed to crash process, pid {pid} not found");
                         }
                     }
+                    MeshRequest::ResourceUsage(rpc) => rpc.handle_sync(|()| self.resource_usage()),
                 },
                 Event::Done(id) => {
                     self.hosts.remove(id);
@@ -594,6 +689,42 @@ enum Event {
         }
     }
 
+    #[cfg(windows)]
+    fn resource_usage(&self) -> anyhow::Result<ResourceUsage> {
+        Ok(ResourceUsage {
+            peak_memory: self
+                .job
+                .peak_process_memory_used()
+                .context("failed to query job memory usage")? as u64,
+            cpu_time: self
+                .job
+                .cpu_time()
+                .context("failed to query job cpu time")?,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn resource_usage(&self) -> anyhow::Result<ResourceUsage> {
+        // Exited processes are reaped and removed, so report that rather than
+        // zero usage.
+        if self.hosts.is_empty() {
+            anyhow::bail!("no running processes");
+        }
+        let mut usage = ResourceUsage::default();
+        for (_, host) in &self.hosts {
+            let host_usage = procfs::resource_usage(host.pid)
+                .with_context(|| format!("failed to get resource usage of pid {}", host.pid))?;
+            usage.peak_memory = usage.peak_memory.max(host_usage.peak_memory);
+            usage.cpu_time += host_usage.cpu_time;
+        }
+        Ok(usage)
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    fn resource_usage(&self) -> anyhow::Result<ResourceUsage> {
+        anyhow::bail!("resource usage is not supported on this platform")
+    }
+
     /// Spawns a new process with a mesh channel associated with this `Mesh` instance.
     #[instrument(name = "mesh_spawn_process", skip(self, params), fields(mesh_name = self.mesh_name.as_str(), pid = tracing::field::Empty))]
     async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
@@ -619,6 +750,7 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
         };
 
         let name = config.name.clone();
+        let exit_notify = config.exit_notify;
 
         #[cfg(windows)]
         let wait = {
@@ -653,6 +785,10 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                 .env(INVITATION_ENV_NAME, invitation_env)
                 .job(self.job.as_handle());
 
+            for (key, val) in &config.env {
+                builder.env(key, val);
+            }
+
             if let Some(log_file) = config.stderr.as_ref() {
                 builder.stderr(process::Stdio::Handle(log_file.as_handle()));
             }
@@ -674,6 +810,12 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                 } else {
                     tracing::error!(pid, name = name.as_str(), code, "mesh child abnormal exit");
                 }
+                ProcessExit {
+                    pid,
+                    code: Some(code as i32),
+                    signal: None,
+                    core_dumped: false,
+                }
             }
         };
         #[cfg(unix)]
@@ -707,6 +849,10 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                 .dup_fd(invitation.fd.as_fd(), IPC_FD)
                 .env(INVITATION_ENV_NAME, invitation_env);
 
+            for (key, val) in config.env {
+                command.env(key, val);
+            }
+
             if !config.skip_worker_arg {
                 command.arg(&name);
             }
@@ -734,6 +880,12 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
                         "mesh child abnormal exit"
                     );
                 }
+                ProcessExit {
+                    pid,
+                    code: exit_status.code(),
+                    signal: exit_status.signal(),
+                    core_dumped: exit_status.core_dumped(),
+                }
             }
         };
 
@@ -749,7 +901,10 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
         thread::Builder::new()
             .name(format!("wait-mesh-child-{}", pid))
             .spawn(move || {
-                wait();
+                let exit = wait();
+                if let Some(exit_notify) = exit_notify {
+                    exit_notify.send(exit);
+                }
                 wait_send.send(id);
             })
             .unwrap();
@@ -758,3 +913,29 @@ async fn spawn_process(&mut self, params: NewHostParams) -> anyhow::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_exit() {
+        let exit = |code, signal, core_dumped| ProcessExit {
+            pid: 1,
+            code,
+            signal,
+            core_dumped,
+        };
+        assert!(exit(Some(0), None, false).success());
+        assert!(!exit(Some(1), None, false).success());
+        assert_eq!(exit(Some(1), None, false).to_string(), "exit code 1");
+        assert_eq!(
+            exit(Some(0xc0000005_u32 as i32), None, false).to_string(),
+            "exit code 0xc0000005"
+        );
+        assert_eq!(
+            exit(None, Some(6), true).to_string(),
+            "signal 6 (core dumped)"
+        );
+    }
+}
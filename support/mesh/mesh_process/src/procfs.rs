@@ -0,0 +1,83 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource usage of Linux processes, read from procfs.
+
+use crate::ResourceUsage;
+use anyhow::Context;
+use std::time::Duration;
+
+/// Returns the resource usage of the running process with ID `pid`.
+pub(crate) fn resource_usage(pid: i32) -> anyhow::Result<ResourceUsage> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .context("failed to read process status")?;
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .context("failed to read process stat")?;
+    // SAFETY: `sysconf` has no safety requirements.
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks_per_sec = u32::try_from(ticks_per_sec)
+        .ok()
+        .filter(|&t| t != 0)
+        .context("invalid clock tick rate")?;
+    Ok(ResourceUsage {
+        peak_memory: peak_rss(&status)?,
+        cpu_time: Duration::from_secs(cpu_ticks(&stat)?) / ticks_per_sec,
+    })
+}
+
+/// Parses the peak resident set size, in bytes, from the contents of
+/// `/proc/<pid>/status`.
+fn peak_rss(status: &str) -> anyhow::Result<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .context("missing VmHWM")?;
+    let kb = line
+        .trim()
+        .strip_suffix("kB")
+        .context("unexpected VmHWM units")?
+        .trim()
+        .parse::<u64>()
+        .context("invalid VmHWM")?;
+    Ok(kb * 1024)
+}
+
+/// Parses the total user and system CPU time, in clock ticks, from the
+/// contents of `/proc/<pid>/stat`.
+fn cpu_ticks(stat: &str) -> anyhow::Result<u64> {
+    // The process name (field 2) is in parentheses and may contain spaces, so
+    // start after it. The next field is the state (field 3), and utime and
+    // stime are fields 14 and 15.
+    let (_, rest) = stat.rsplit_once(')').context("invalid stat")?;
+    let mut fields = rest.split_whitespace().skip(11);
+    let mut next = || -> anyhow::Result<u64> {
+        fields
+            .next()
+            .context("missing cpu time")?
+            .parse()
+            .context("invalid cpu time")
+    };
+    Ok(next()? + next()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let status = "Name:\tcat\nVmPeak:\t  9000 kB\nVmHWM:\t     1024 kB\nVmRSS:\t  512 kB\n";
+        assert_eq!(peak_rss(status).unwrap(), 1024 * 1024);
+        peak_rss("Name:\tcat\n").unwrap_err();
+
+        let stat = "1234 (a (weird) name) S 1 1234 1234 0 -1 4194304 100 0 0 0 25 17 0 0 20 0 1 0";
+        assert_eq!(cpu_ticks(stat).unwrap(), 42);
+        cpu_ticks("1234 (cat) S 1").unwrap_err();
+    }
+
+    #[test]
+    fn current_process() {
+        let usage = resource_usage(std::process::id() as i32).unwrap();
+        assert_ne!(usage.peak_memory, 0);
+    }
+}
@@ -12,6 +12,7 @@
 use std::os::windows::io::OwnedHandle;
 use std::ptr::null;
 use std::ptr::null_mut;
+use std::time::Duration;
 
 /// A Windows job object.
 pub struct Job(OwnedHandle);
@@ -56,6 +57,55 @@ pub fn set_terminate_on_close(&self) -> io::Result<()> {
         }
         Ok(())
     }
+
+    /// Returns the peak committed memory of any process that has been
+    /// attached to the job, in bytes.
+    pub fn peak_process_memory_used(&self) -> io::Result<usize> {
+        // SAFETY: It is safe to initialize this C structure using `zeroed`.
+        let mut info: winapi::um::winnt::JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { zeroed() };
+        // SAFETY: `QueryInformationJobObject` is safe to call with a valid
+        // handle and a buffer of the size of the requested class.
+        let r = unsafe {
+            winapi::um::jobapi2::QueryInformationJobObject(
+                self.0.as_raw_handle(),
+                winapi::um::winnt::JobObjectExtendedLimitInformation,
+                std::ptr::from_mut(&mut info).cast(),
+                size_of_val(&info) as u32,
+                null_mut(),
+            )
+        };
+        if r == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info.PeakProcessMemoryUsed)
+    }
+
+    /// Returns the total user and kernel CPU time used by all the processes
+    /// that have been attached to the job.
+    pub fn cpu_time(&self) -> io::Result<Duration> {
+        // SAFETY: It is safe to initialize this C structure using `zeroed`.
+        let mut info: winapi::um::winnt::JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+            unsafe { zeroed() };
+        // SAFETY: `QueryInformationJobObject` is safe to call with a valid
+        // handle and a buffer of the size of the requested class.
+        let r = unsafe {
+            winapi::um::jobapi2::QueryInformationJobObject(
+                self.0.as_raw_handle(),
+                winapi::um::winnt::JobObjectBasicAccountingInformation,
+                std::ptr::from_mut(&mut info).cast(),
+                size_of_val(&info) as u32,
+                null_mut(),
+            )
+        };
+        if r == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `LARGE_INTEGER` is a union of integer views of the same
+        // value, so any view is valid.
+        let ticks = unsafe { *info.TotalUserTime.QuadPart() + *info.TotalKernelTime.QuadPart() };
+        // The times are in 100ns units.
+        Ok(Duration::from_nanos(ticks as u64 * 100))
+    }
 }
 
 impl AsHandle for Job {
@@ -75,3 +125,26 @@ fn from(job: Job) -> Self {
         job.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Job;
+    use crate::windows::process::Builder;
+    use std::os::windows::io::AsHandle;
+    use std::path::Path;
+
+    #[test]
+    fn resource_usage() {
+        let job = Job::new().unwrap();
+        let cmd = Path::new(&std::env::var_os("SystemRoot").unwrap()).join(r"System32\cmd.exe");
+        let mut builder = Builder::from_args(&cmd, ["/c", "exit"]);
+        builder.job(job.as_handle());
+        let process = builder.spawn().unwrap();
+        process.wait();
+        assert_eq!(process.exit_code(), 0);
+
+        // The usage of exited processes is still reported.
+        assert_ne!(job.peak_process_memory_used().unwrap(), 0);
+        job.cpu_time().unwrap();
+    }
+}
@@ -9,6 +9,7 @@
 #![cfg(windows)]
 #![forbid(unsafe_code)]
 
+use serde::de::DeserializeOwned;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::path::Path;
@@ -48,6 +49,51 @@ pub fn cmdlet_to_var<S: AsRef<str>>(
     pub fn build(self) -> Command {
         self.0
     }
+
+    /// Finish building the powershell script, which must end with a pipe, by
+    /// converting its output objects to JSON that can be parsed as a list
+    /// with [`JsonCommand::parse`]
+    pub fn output_json(self) -> JsonCommand {
+        JsonCommand(
+            self.cmdlet("ConvertTo-Json")
+                .arg("Depth", JSON_DEPTH)
+                .flag("Compress")
+                .finish()
+                .build(),
+        )
+    }
+}
+
+/// The maximum depth of objects converted by
+/// [`PowerShellBuilder::output_json`]. ConvertTo-Json defaults to 2 and
+/// silently truncates deeper objects.
+const JSON_DEPTH: u32 = 5;
+
+/// A PowerShell command that outputs a list of objects as JSON
+pub struct JsonCommand(Command);
+
+impl JsonCommand {
+    /// Return the inner `Command`
+    pub fn build(self) -> Command {
+        self.0
+    }
+
+    /// Parse the output of the command as a list of `T`.
+    ///
+    /// PowerShell unrolls pipeline output, so ConvertTo-Json outputs a bare
+    /// object rather than an array when the pipeline outputs exactly one
+    /// object, and nothing at all when it outputs none.
+    pub fn parse<T: DeserializeOwned>(output: &str) -> serde_json::Result<Vec<T>> {
+        if output.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let value: serde_json::Value = serde_json::from_str(output)?;
+        if value.is_array() {
+            serde_json::from_value(value)
+        } else {
+            Ok(vec![serde_json::from_value(value)?])
+        }
+    }
 }
 
 /// A PowerShell Cmdlet builder
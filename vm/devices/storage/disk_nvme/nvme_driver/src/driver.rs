@@ -179,13 +179,70 @@ pub async fn new(
         cpu_count: u32,
         device: T,
         bounce_buffer: bool,
+    ) -> anyhow::Result<Self> {
+        Self::new_inner(
+            driver_source,
+            cpu_count,
+            cpu_count.try_into().unwrap_or(u16::MAX),
+            device,
+            bounce_buffer,
+        )
+        .await
+    }
+
+    /// Initializes the driver, creating at most `io_queue_count` IO queues.
+    ///
+    /// CPUs beyond the queue count share an existing queue. Fails if
+    /// `io_queue_count` is zero, exceeds `cpu_count`, or exceeds the number of
+    /// interrupts the device supports.
+    pub async fn new_with_queue_count(
+        driver_source: &VmTaskDriverSource,
+        cpu_count: u32,
+        io_queue_count: u16,
+        device: T,
+        bounce_buffer: bool,
+    ) -> anyhow::Result<Self> {
+        if io_queue_count == 0 {
+            anyhow::bail!("io queue count must be nonzero");
+        }
+        if io_queue_count as u32 > cpu_count {
+            anyhow::bail!(
+                "io queue count {} exceeds cpu count {}",
+                io_queue_count,
+                cpu_count
+            );
+        }
+        let max_interrupt_count = device.max_interrupt_count();
+        if io_queue_count as u32 > max_interrupt_count {
+            anyhow::bail!(
+                "io queue count {} exceeds device interrupt count {}",
+                io_queue_count,
+                max_interrupt_count
+            );
+        }
+        Self::new_inner(
+            driver_source,
+            cpu_count,
+            io_queue_count,
+            device,
+            bounce_buffer,
+        )
+        .await
+    }
+
+    async fn new_inner(
+        driver_source: &VmTaskDriverSource,
+        cpu_count: u32,
+        io_queue_count: u16,
+        device: T,
+        bounce_buffer: bool,
     ) -> anyhow::Result<Self> {
         let pci_id = device.id().to_owned();
         let mut this = Self::new_disabled(driver_source, cpu_count, device, bounce_buffer)
             .instrument(tracing::info_span!("nvme_new_disabled", pci_id))
             .await?;
         match this
-            .enable(cpu_count as u16)
+            .enable(io_queue_count)
             .instrument(tracing::info_span!("nvme_enable", pci_id))
             .await
         {
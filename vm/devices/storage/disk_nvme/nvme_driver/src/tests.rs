@@ -27,7 +27,6 @@
 use user_driver_emulated_mock::Mapping;
 use vmcore::vm_task::SingleDriverBackend;
 use vmcore::vm_task::VmTaskDriverSource;
-use zerocopy::IntoBytes;
 
 #[async_test]
 async fn test_nvme_driver_direct_dma(driver: DefaultDriver) {
@@ -118,6 +117,166 @@ async fn test_nvme_ioqueue_invalid_mqes(driver: DefaultDriver) {
     assert!(driver.is_err());
 }
 
+#[async_test]
+async fn test_nvme_driver_queue_count_cap(driver: DefaultDriver) {
+    const MSIX_COUNT: u16 = 64;
+    const IO_QUEUE_COUNT: u16 = 64;
+    const CPU_COUNT: u32 = 8;
+    const QUEUE_CAP: u16 = 2;
+
+    let pages = 1024;
+    let device_test_memory =
+        DeviceTestMemory::new(pages * 2, false, "test_nvme_driver_queue_count_cap");
+    let guest_mem = device_test_memory.guest_memory();
+    let dma_client = device_test_memory.dma_client();
+    let payload_mem = device_test_memory.payload_mem();
+
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+    let mut msi_set = MsiInterruptSet::new();
+    let nvme = nvme::NvmeController::new(
+        &driver_source,
+        guest_mem,
+        &mut msi_set,
+        &mut ExternallyManagedMmioIntercepts,
+        NvmeControllerCaps {
+            msix_count: MSIX_COUNT,
+            max_io_queues: IO_QUEUE_COUNT,
+            subsystem_id: Guid::new_random(),
+        },
+    );
+    nvme.client()
+        .add_namespace(1, disklayer_ram::ram_disk(2 << 20, false).unwrap())
+        .await
+        .unwrap();
+
+    let device = NvmeTestEmulatedDevice::new(nvme, msi_set, dma_client.clone());
+    let driver =
+        NvmeDriver::new_with_queue_count(&driver_source, CPU_COUNT, QUEUE_CAP, device, false)
+            .await
+            .unwrap();
+    let namespace = driver.namespace(1).await.unwrap();
+
+    // Issue IO from every CPU so that each one requests a queue.
+    let buf_range = OwnedRequestBuffers::linear(0, 4096, true);
+    for cpu in 0..CPU_COUNT {
+        namespace
+            .read(
+                cpu,
+                0,
+                8,
+                &payload_mem,
+                buf_range.buffer(&payload_mem).range(),
+            )
+            .await
+            .unwrap();
+    }
+
+    // Only QUEUE_CAP CPUs get their own queue; the rest share one.
+    assert_eq!(
+        driver.fallback_cpu_count(),
+        (CPU_COUNT - QUEUE_CAP as u32) as usize
+    );
+
+    driver.shutdown().await;
+}
+
+#[async_test]
+async fn test_nvme_driver_queue_count_above_cpu_count(driver: DefaultDriver) {
+    const MSIX_COUNT: u16 = 64;
+    const IO_QUEUE_COUNT: u16 = 64;
+    const CPU_COUNT: u32 = 4;
+
+    let pages = 1000;
+    let device_test_memory =
+        DeviceTestMemory::new(pages, false, "test_nvme_driver_queue_count_above_cpu_count");
+    let guest_mem = device_test_memory.guest_memory();
+    let dma_client = device_test_memory.dma_client();
+
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+    let mut msi_set = MsiInterruptSet::new();
+    let nvme = nvme::NvmeController::new(
+        &driver_source,
+        guest_mem,
+        &mut msi_set,
+        &mut ExternallyManagedMmioIntercepts,
+        NvmeControllerCaps {
+            msix_count: MSIX_COUNT,
+            max_io_queues: IO_QUEUE_COUNT,
+            subsystem_id: Guid::new_random(),
+        },
+    );
+
+    let device = NvmeTestEmulatedDevice::new(nvme, msi_set, dma_client.clone());
+    let driver = NvmeDriver::new_with_queue_count(
+        &driver_source,
+        CPU_COUNT,
+        CPU_COUNT as u16 + 1,
+        device,
+        false,
+    )
+    .await;
+    assert!(driver.is_err());
+}
+
+#[async_test]
+async fn test_nvme_namespace_resize(driver: DefaultDriver) {
+    const MSIX_COUNT: u16 = 2;
+    const IO_QUEUE_COUNT: u16 = 64;
+    const CPU_COUNT: u32 = 64;
+    const DISK_SIZE: u64 = 2 << 20;
+
+    let pages = 1000;
+    let device_test_memory = DeviceTestMemory::new(pages, false, "test_nvme_namespace_resize");
+    let guest_mem = device_test_memory.guest_memory();
+    let dma_client = device_test_memory.dma_client();
+
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+    let mut msi_set = MsiInterruptSet::new();
+    let nvme = nvme::NvmeController::new(
+        &driver_source,
+        guest_mem,
+        &mut msi_set,
+        &mut ExternallyManagedMmioIntercepts,
+        NvmeControllerCaps {
+            msix_count: MSIX_COUNT,
+            max_io_queues: IO_QUEUE_COUNT,
+            subsystem_id: Guid::new_random(),
+        },
+    );
+
+    let disk = disklayer_ram::ram_disk(DISK_SIZE, false).unwrap();
+    nvme.client().add_namespace(1, disk.clone()).await.unwrap();
+
+    let device = NvmeTestEmulatedDevice::new(nvme, msi_set, dma_client.clone());
+    let driver = NvmeDriver::new(&driver_source, CPU_COUNT, device, false)
+        .await
+        .unwrap();
+    let namespace = driver.namespace(1).await.unwrap();
+    let old_block_count = namespace.block_count();
+    assert_eq!(old_block_count, DISK_SIZE / namespace.block_size() as u64);
+
+    // Grow the backing disk. The controller raises a changed namespace
+    // asynchronous event, which causes the driver to rescan the namespace.
+    let new_sector_count = disk.sector_count() * 2;
+    inspect::update(
+        "disk/layers/0/backing/sector_count",
+        &new_sector_count.to_string(),
+        &disk,
+    )
+    .await
+    .unwrap();
+
+    let new_block_count = namespace.wait_resize(old_block_count).await;
+    assert_eq!(new_block_count, old_block_count * 2);
+
+    // A fresh query of the namespace sees the new capacity too.
+    drop(namespace);
+    let namespace = driver.namespace(1).await.unwrap();
+    assert_eq!(namespace.block_count(), new_block_count);
+
+    driver.shutdown().await;
+}
+
 async fn test_nvme_driver(driver: DefaultDriver, allow_dma: bool) {
     const MSIX_COUNT: u16 = 2;
     const IO_QUEUE_COUNT: u16 = 64;
@@ -240,15 +399,17 @@ async fn test_nvme_save_restore_inner(driver: DefaultDriver) {
 
     // Memory setup
     let pages = 1000;
-    let device_test_memory = DeviceTestMemory::new(pages, false, "test_nvme_save_restore_inner");
+    let mut device_test_memory =
+        DeviceTestMemory::new(pages, false, "test_nvme_save_restore_inner");
     let guest_mem = device_test_memory.guest_memory();
     let dma_client = device_test_memory.dma_client();
+    let payload_mem = device_test_memory.payload_mem();
 
-    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
     let mut msi_x = MsiInterruptSet::new();
     let nvme_ctrl = nvme::NvmeController::new(
         &driver_source,
-        guest_mem.clone(),
+        guest_mem,
         &mut msi_x,
         &mut ExternallyManagedMmioIntercepts,
         NvmeControllerCaps {
@@ -266,47 +427,70 @@ async fn test_nvme_save_restore_inner(driver: DefaultDriver) {
         .unwrap();
 
     let device = NvmeTestEmulatedDevice::new(nvme_ctrl, msi_x, dma_client.clone());
+    // Keep a handle to the controller so that it stays alive across servicing,
+    // as it would with keep-alive.
+    let kept_alive_device = device.reattach(dma_client);
     let mut nvme_driver = NvmeDriver::new(&driver_source, CPU_COUNT, device, false)
         .await
         .unwrap();
-    let _ns1 = nvme_driver.namespace(1).await.unwrap();
+    let ns1 = nvme_driver.namespace(1).await.unwrap();
+
+    // Write 1024 bytes of data to disk starting at LBA 1.
+    let buf_range = OwnedRequestBuffers::linear(0, 16384, true);
+    payload_mem.write_at(0, &[0xcc; 1024]).unwrap();
+    ns1.write(
+        0,
+        1,
+        2,
+        false,
+        &payload_mem,
+        buf_range.buffer(&payload_mem).range(),
+    )
+    .await
+    .unwrap();
+
     let saved_state = nvme_driver.save().await.unwrap();
     // As of today we do not save namespace data to avoid possible conflict
     // when namespace has changed during servicing.
     // TODO: Review and re-enable in future.
     assert_eq!(saved_state.namespaces.len(), 0);
 
-    // Create a second set of devices since the ownership has been moved.
-    let mut new_msi_x = MsiInterruptSet::new();
-    let mut new_nvme_ctrl = nvme::NvmeController::new(
-        &driver_source,
-        guest_mem.clone(),
-        &mut new_msi_x,
-        &mut ExternallyManagedMmioIntercepts,
-        NvmeControllerCaps {
-            msix_count: MSIX_COUNT,
-            max_io_queues: IO_QUEUE_COUNT,
-            subsystem_id: Guid::new_random(),
-        },
-    );
+    // Hand the DMA memory over to the new driver. This must happen while the
+    // old driver still owns its allocations, which it frees when dropped. The
+    // old driver goes away without resetting the controller.
+    device_test_memory.save_restore().unwrap();
+    drop(ns1);
+    drop(nvme_driver);
+
+    let new_device = kept_alive_device.reattach(device_test_memory.dma_client());
+    drop(kept_alive_device);
+    let mut new_nvme_driver =
+        NvmeDriver::restore(&driver_source, CPU_COUNT, new_device, &saved_state, false)
+            .await
+            .unwrap();
+
+    // Read back the data written before servicing, using the restored I/O
+    // queue.
+    payload_mem.write_at(0, &[0; 4096]).unwrap();
+    let ns1 = new_nvme_driver.namespace(1).await.unwrap();
+    ns1.read(
+        0,
+        0,
+        8,
+        &payload_mem,
+        buf_range.buffer(&payload_mem).range(),
+    )
+    .await
+    .unwrap();
+    let mut v = [0; 4096];
+    payload_mem.read_at(0, &mut v).unwrap();
+    assert_eq!(&v[..512], &[0; 512]);
+    assert_eq!(&v[512..1536], &[0xcc; 1024]);
+    assert!(v[1536..].iter().all(|&x| x == 0));
 
-    let mut backoff = user_driver::backoff::Backoff::new(&driver);
-
-    // Enable the controller for keep-alive test.
-    let mut dword = 0u32;
-    // Read Register::CC.
-    new_nvme_ctrl.read_bar0(0x14, dword.as_mut_bytes()).unwrap();
-    // Set CC.EN.
-    dword |= 1;
-    new_nvme_ctrl.write_bar0(0x14, dword.as_bytes()).unwrap();
-    // Wait for CSTS.RDY to set.
-    backoff.back_off().await;
-
-    let _new_device = NvmeTestEmulatedDevice::new(new_nvme_ctrl, new_msi_x, dma_client.clone());
-    // TODO: Memory restore is disabled for emulated DMA, uncomment once fixed.
-    // let _new_nvme_driver = NvmeDriver::restore(&driver_source, CPU_COUNT, new_device, &saved_state)
-    //     .await
-    //     .unwrap();
+    drop(ns1);
+    new_nvme_driver.update_servicing_flags(false);
+    new_nvme_driver.shutdown().await;
 }
 
 #[derive(Inspect)]
@@ -337,6 +521,16 @@ pub fn new(device: T, msi_set: MsiInterruptSet, dma_client: Arc<U>) -> Self {
         }
     }
 
+    /// Returns a new handle to the same underlying device, using `dma_client`
+    /// for DMA allocations. See [`EmulatedDevice::reattach`].
+    pub fn reattach<V: DmaClient>(&self, dma_client: Arc<V>) -> NvmeTestEmulatedDevice<T, V> {
+        NvmeTestEmulatedDevice {
+            device: self.device.reattach(dma_client),
+            mocked_response_u32: Arc::new(Mutex::new(None)),
+            mocked_response_u64: Arc::new(Mutex::new(None)),
+        }
+    }
+
     // TODO: set_mock_response_u32 is intentionally not implemented to avoid dead code.
     pub fn set_mock_response_u64(&mut self, mapping: Option<(usize, u64)>) {
         let mut mock_response = self.mocked_response_u64.lock();
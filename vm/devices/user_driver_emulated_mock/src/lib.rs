@@ -33,6 +33,7 @@
 use user_driver::interrupt::DeviceInterruptSource;
 use user_driver::memory::PAGE_SIZE;
 use user_driver::memory::PAGE_SIZE64;
+use vmcore::save_restore::SaveRestore;
 
 /// A wrapper around any user_driver device T. It provides device emulation by providing access to the memory shared with the device and thus
 /// allowing the user to control device behaviour to a certain extent. Can be used with devices such as the `NvmeController`
@@ -49,6 +50,7 @@ fn inspect(&self, req: inspect::Request<'_>) {
     }
 }
 
+#[derive(Clone)]
 struct MsiController {
     events: Arc<[DeviceInterruptSource]>,
 }
@@ -117,6 +119,18 @@ pub fn new(mut device: T, msi_set: MsiInterruptSet, dma_client: Arc<U>) -> Self
             bar0_len,
         }
     }
+
+    /// Returns a new handle to the same underlying device and interrupts, using
+    /// `dma_client` for DMA allocations. This can be used to simulate attaching
+    /// a new driver to a device that was kept alive across servicing.
+    pub fn reattach<V: DmaClient>(&self, dma_client: Arc<V>) -> EmulatedDevice<T, V> {
+        EmulatedDevice {
+            device: self.device.clone(),
+            controller: self.controller.clone(),
+            dma_client,
+            bar0_len: self.bar0_len,
+        }
+    }
 }
 
 /// A memory mapping for an [`EmulatedDevice`].
@@ -218,7 +232,10 @@ fn write_u64(&self, offset: usize, data: u64) {
 pub struct DeviceTestMemory {
     guest_mem: GuestMemory,
     payload_mem: GuestMemory,
-    _pool: PagePool,
+    test_mapper: TestMapper,
+    pool_range: MemoryRange,
+    pool_name: String,
+    pool: PagePool,
     allocator: Arc<PagePoolAllocator>,
 }
 
@@ -232,11 +249,8 @@ pub fn new(num_pages: u64, allow_dma: bool, pool_name: &str) -> Self {
         let test_mapper = TestMapper::new(num_pages).unwrap();
         let sparse_mmap = test_mapper.sparse_mapping();
         let guest_mem = GuestMemoryAccessWrapper::create_test_guest_memory(sparse_mmap, allow_dma);
-        let pool = PagePool::new(
-            &[MemoryRange::from_4k_gpn_range(0..num_pages / 2)],
-            test_mapper,
-        )
-        .unwrap();
+        let pool_range = MemoryRange::from_4k_gpn_range(0..num_pages / 2);
+        let pool = PagePool::new(&[pool_range], test_mapper.try_clone().unwrap()).unwrap();
 
         // Save page pool so that it is not dropped.
         let allocator = pool.allocator(pool_name.into()).unwrap();
@@ -244,11 +258,30 @@ pub fn new(num_pages: u64, allow_dma: bool, pool_name: &str) -> Self {
         Self {
             guest_mem: guest_mem.clone(),
             payload_mem: guest_mem.subrange(range_half, range_half, false).unwrap(),
-            _pool: pool,
+            test_mapper,
+            pool_range,
+            pool_name: pool_name.into(),
+            pool,
             allocator: Arc::new(allocator),
         }
     }
 
+    /// Simulates servicing by saving the state of the DMA page pool and
+    /// restoring it into a new pool over the same memory. Allocations made
+    /// before the save can then be reclaimed with
+    /// [`DmaClient::attach_pending_buffers`] on the new [`Self::dma_client`].
+    ///
+    /// Clients returned by [`Self::dma_client`] before this call keep using the
+    /// old pool, so they should be dropped along with their devices.
+    pub fn save_restore(&mut self) -> anyhow::Result<()> {
+        let state = self.pool.save().context("failed to save page pool")?;
+        let mut pool = PagePool::new(&[self.pool_range], self.test_mapper.try_clone()?)?;
+        pool.restore(state).context("failed to restore page pool")?;
+        self.allocator = Arc::new(pool.allocator(self.pool_name.clone())?);
+        self.pool = pool;
+        Ok(())
+    }
+
     /// Returns [`GuestMemory`] accessor to the underlying memory. Reports base_iova as 0 if `allow_dma` switch is enabled.
     pub fn guest_memory(&self) -> GuestMemory {
         self.guest_mem.clone()
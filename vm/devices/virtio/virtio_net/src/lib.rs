@@ -39,6 +39,7 @@
 use thiserror::Error;
 use virtio::DeviceTraits;
 use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
 use virtio::Resources;
 use virtio::VirtioDevice;
 use virtio::VirtioQueue;
@@ -105,9 +106,16 @@ struct NetStatus {
 
 const DEFAULT_MTU: u16 = 1514;
 
-#[expect(dead_code)]
 const VIRTIO_NET_MAX_QUEUES: u16 = 0x8000;
 
+// Control queue classes and commands (VIRTIO_NET_CTRL_*).
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+
+// Control queue command status (VIRTIO_NET_OK and VIRTIO_NET_ERR).
+const VIRTIO_NET_OK: u8 = 0;
+const VIRTIO_NET_ERR: u8 = 1;
+
 #[repr(C)]
 struct NetConfig {
     pub mac: [u8; 6],
@@ -207,10 +215,17 @@ fn drop(&mut self) {}
 impl VirtioDevice for Device {
     fn traits(&self) -> DeviceTraits {
         // TODO: Add network features based on endpoint capabilities (NetworkFeatures::VIRTIO_NET_F_*)
+        // Multiple queue pairs are configured through the control queue,
+        // which follows the last queue pair.
+        let multiqueue = self.registers.max_virtqueue_pairs > 1;
         DeviceTraits {
             device_id: 1,
-            device_features: NetworkFeatures::new().with_mac(true).into(),
-            max_queues: 2 * self.registers.max_virtqueue_pairs,
+            device_features: NetworkFeatures::new()
+                .with_mac(true)
+                .with_ctrl_vq(multiqueue)
+                .with_mq(multiqueue)
+                .into(),
+            max_queues: 2 * self.registers.max_virtqueue_pairs + multiqueue as u16,
             device_register_length: size_of::<NetConfig>() as u32,
             shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
         }
@@ -239,6 +254,16 @@ fn write_registers_u32(&mut self, _offset: u16, _val: u32) {}
 
     fn enable(&mut self, resources: Resources) {
         let mut queue_resources: Vec<_> = resources.queues.into_iter().collect();
+        let (queue_pairs, control_index) = queue_layout(
+            resources.features.into(),
+            self.registers.max_virtqueue_pairs,
+            queue_resources.len(),
+        );
+        let control_queue = control_index.and_then(|i| {
+            let control_resources = queue_resources.remove(i);
+            self.control_queue(resources.features, control_resources)
+        });
+        queue_resources.truncate(2 * queue_pairs as usize);
         let mut workers = Vec::with_capacity(queue_resources.len() / 2);
         while queue_resources.len() > 1 {
             let mut next = queue_resources.drain(..2);
@@ -305,7 +330,7 @@ fn enable(&mut self, resources: Resources) {
 
         let (tx, rx) = mesh::channel();
         self.coordinator_send = Some(tx);
-        self.insert_coordinator(rx, workers.len() as u16);
+        self.insert_coordinator(rx, workers.len() as u16, control_queue);
         for (i, virtio_state) in workers.into_iter().enumerate() {
             self.insert_worker(virtio_state, i);
         }
@@ -428,10 +453,12 @@ pub fn build(
         endpoint: Box<dyn Endpoint>,
         mac_address: MacAddress,
     ) -> Device {
-        // TODO: Implement VIRTIO_NET_F_MQ and VIRTIO_NET_F_RSS logic based on mulitqueue support.
-        // let multiqueue = endpoint.multiqueue_support();
-        // let max_queues = self.max_queues.clamp(1, multiqueue.max_queues.min(VIRTIO_NET_MAX_QUEUES));
-        let max_queues = 1;
+        // TODO: Implement VIRTIO_NET_F_RSS logic based on multiqueue support.
+        // Leave room for the control queue in the 16-bit queue count.
+        let multiqueue = endpoint.multiqueue_support();
+        let max_queues = self
+            .max_queues
+            .clamp(1, multiqueue.max_queues.min(VIRTIO_NET_MAX_QUEUES - 1));
 
         let driver = driver_source.simple();
         let adapter = Arc::new(Adapter {
@@ -471,7 +498,7 @@ pub fn build(
 
 impl Device {
     pub fn builder() -> NicBuilder {
-        NicBuilder { max_queues: !0 }
+        NicBuilder { max_queues: 1 }
     }
 }
 
@@ -482,21 +509,59 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
 }
 
 impl Device {
-    fn insert_coordinator(&mut self, recv: mesh::Receiver<CoordinatorMessage>, num_queues: u16) {
+    fn insert_coordinator(
+        &mut self,
+        recv: mesh::Receiver<CoordinatorMessage>,
+        num_queues: u16,
+        control_queue: Option<VirtioQueue>,
+    ) {
         self.coordinator.insert(
             &self.adapter.driver,
             "virtio-net-coordinator".to_string(),
             Coordinator {
                 recv,
+                control_queue,
+                memory: self.memory.clone(),
                 workers: (0..self.adapter.max_queues)
                     .map(|_| TaskControl::new(NetQueue { state: None }))
                     .collect(),
                 num_queues,
+                // The guest must enable additional queue pairs through the
+                // control queue before they are used.
+                active_queues: num_queues.min(1),
                 restart: true,
             },
         );
     }
 
+    fn control_queue(&self, features: u64, resources: QueueResources) -> Option<VirtioQueue> {
+        if !resources.params.enable {
+            return None;
+        }
+        let queue_event = PolledWait::new(&self.adapter.driver, resources.event)
+            .inspect_err(|err| {
+                tracing::error!(
+                    err = err as &dyn std::error::Error,
+                    "Failed creating queue event"
+                )
+            })
+            .ok()?;
+        VirtioQueue::new(
+            features,
+            resources.params,
+            self.memory.clone(),
+            resources.notify,
+            queue_event,
+        )
+        .inspect_err(|err| {
+            tracing::error!(
+                err = err as &dyn std::error::Error,
+                "Failed creating virtio net control queue"
+            )
+        })
+        .ok()
+    }
+
     /// Allocates and inserts a worker.
     ///
     /// The coordinator must be stopped.
@@ -527,6 +592,43 @@ fn insert_worker(&mut self, virtio_state: VirtioState, idx: usize) {
     }
 }
 
+/// Returns the number of queue pairs in use for the negotiated `features` and
+/// the index of the control queue among the `queue_count` queues, if there is
+/// one.
+fn queue_layout(
+    features: NetworkFeatures,
+    max_queue_pairs: u16,
+    queue_count: usize,
+) -> (u16, Option<usize>) {
+    // Without VIRTIO_NET_F_MQ, only the first queue pair exists and the
+    // control queue (if any) immediately follows it.
+    let queue_pairs = if features.mq() { max_queue_pairs } else { 1 };
+    let control_index = 2 * queue_pairs as usize;
+    let control_index =
+        (features.ctrl_vq() && queue_count > control_index).then_some(control_index);
+    (queue_pairs, control_index)
+}
+
+/// Parses a control queue command, returning the requested queue pair count
+/// if it is a valid VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET for a device with
+/// `num_queues` queue pairs.
+fn vq_pairs_set(command: &[u8], num_queues: u16) -> Option<u16> {
+    let &[class, command, lo, hi] = command else {
+        tracing::warn!(len = command.len(), "truncated virtio net control command");
+        return None;
+    };
+    if class != VIRTIO_NET_CTRL_MQ || command != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
+        tracing::warn!(class, command, "unsupported virtio net control command");
+        return None;
+    }
+    let pairs = u16::from_le_bytes([lo, hi]);
+    if !(1..=num_queues).contains(&pairs) {
+        tracing::warn!(pairs, "invalid virtio net queue pair count");
+        return None;
+    }
+    Some(pairs)
+}
+
 #[derive(PartialEq)]
 enum CoordinatorMessage {
     Disable,
@@ -534,8 +636,14 @@ enum CoordinatorMessage {
 
 struct Coordinator {
     recv: mesh::Receiver<CoordinatorMessage>,
+    control_queue: Option<VirtioQueue>,
+    memory: GuestMemory,
     workers: Vec<TaskControl<NetQueue, Worker>>,
+    /// The number of queue pairs set up when the device was enabled.
     num_queues: u16,
+    /// The number of queue pairs the guest has asked to be used, through the
+    /// control queue.
+    active_queues: u16,
     restart: bool,
 }
 
@@ -560,6 +668,7 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>, coordinator: Option<&mut Co
             .field_mut("endpoint", self.endpoint.as_mut());
 
         if let Some(coordinator) = coordinator {
+            resp.field("active_queues", coordinator.active_queues);
             resp.fields_mut(
                 "queues",
                 coordinator.workers[..coordinator.num_queues as usize]
@@ -604,22 +713,42 @@ enum Message {
                 Internal(CoordinatorMessage),
                 ChannelDisconnected,
                 UpdateFromEndpoint(EndpointAction),
+                Control(Result<VirtioQueueCallbackWork, std::io::Error>),
             }
             let message = {
+                let recv = &mut self.recv;
+                let control_queue = &mut self.control_queue;
                 let wait_for_message = async {
-                    let internal_msg = self
-                        .recv
+                    let internal_msg = recv
                         .next()
                         .map(|x| x.map_or(Message::ChannelDisconnected, Message::Internal));
                     let endpoint_restart = state
                         .endpoint
                         .wait_for_endpoint_action()
                         .map(Message::UpdateFromEndpoint);
-                    (internal_msg, endpoint_restart).race().await
+                    let control = async {
+                        match control_queue {
+                            Some(queue) => {
+                                Message::Control(queue.next().await.expect("queue never completes"))
+                            }
+                            None => pending().await,
+                        }
+                    };
+                    (internal_msg, endpoint_restart, control).race().await
                 };
                 stop.until_stopped(wait_for_message).await?
             };
             match message {
+                Message::Control(work) => match work {
+                    Ok(work) => self.handle_control(work),
+                    Err(err) => {
+                        tracing::error!(
+                            err = &err as &dyn std::error::Error,
+                            "virtio net control queue error"
+                        );
+                        self.control_queue = None;
+                    }
+                },
                 Message::UpdateFromEndpoint(EndpointAction::RestartRequired) => self.restart = true,
                 Message::UpdateFromEndpoint(EndpointAction::LinkStatusNotify(_)) => {
                     tracing::error!("unexpected link status notification")
@@ -633,6 +762,42 @@ enum Message {
         Ok(())
     }
 
+    /// Handles a command from the guest on the control queue.
+    fn handle_control(&mut self, mut work: VirtioQueueCallbackWork) {
+        // The command is a class and command byte followed by the command's
+        // data, and the device writes back a single status byte.
+        let mut command = [0; 4];
+        let status = match work.read(&self.memory, &mut command) {
+            Ok(len) => match vq_pairs_set(&command[..len], self.num_queues) {
+                Some(pairs) => {
+                    if pairs != self.active_queues {
+                        self.active_queues = pairs;
+                        self.restart = true;
+                    }
+                    VIRTIO_NET_OK
+                }
+                None => VIRTIO_NET_ERR,
+            },
+            Err(err) => {
+                tracing::error!(
+                    err = &err as &dyn std::error::Error,
+                    "failed to read virtio net control command"
+                );
+                VIRTIO_NET_ERR
+            }
+        };
+        match work.write(&self.memory, &[status]) {
+            Ok(()) => work.complete(1),
+            Err(err) => {
+                tracing::error!(
+                    err = &err as &dyn std::error::Error,
+                    "failed to write virtio net control status"
+                );
+                work.complete(0);
+            }
+        }
+    }
+
     async fn stop_workers(&mut self) {
         for worker in &mut self.workers {
             worker.stop().await;
@@ -645,8 +810,10 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
             worker.task_mut().state = None;
         }
 
-        let (rx_pools, ready_packets): (Vec<_>, Vec<_>) = self
-            .workers
+        // Only the queue pairs in use by the guest get endpoint queues; the
+        // rest wait for a queue until the guest asks for them.
+        let active_workers = &mut self.workers[..self.active_queues as usize];
+        let (rx_pools, ready_packets): (Vec<_>, Vec<_>) = active_workers
             .iter()
             .map(|worker| {
                 let pool = worker
@@ -677,9 +844,9 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
             .await
             .map_err(WorkerError::Endpoint)?;
 
-        assert_eq!(queues.len(), self.workers.len());
+        assert_eq!(queues.len(), active_workers.len());
 
-        for (worker, queue) in self.workers.iter_mut().zip(queues) {
+        for (worker, queue) in active_workers.iter_mut().zip(queues) {
             worker.task_mut().state = Some(EndpointQueueState { queue });
         }
 
@@ -999,3 +1166,45 @@ fn complete_tx_packet(&mut self, id: TxId) -> Result<(), WorkerError> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_selection() {
+        let mq = NetworkFeatures::new().with_ctrl_vq(true).with_mq(true);
+        // All queue pairs plus the control queue.
+        assert_eq!(queue_layout(mq, 4, 9), (4, Some(8)));
+        // The control queue was not set up by the transport.
+        assert_eq!(queue_layout(mq, 4, 8), (4, None));
+        // Without VIRTIO_NET_F_MQ, the control queue follows the first pair.
+        let ctrl_only = NetworkFeatures::new().with_ctrl_vq(true);
+        assert_eq!(queue_layout(ctrl_only, 4, 9), (1, Some(2)));
+        // Neither feature: a single pair and no control queue.
+        assert_eq!(queue_layout(NetworkFeatures::new(), 4, 9), (1, None));
+        assert_eq!(queue_layout(NetworkFeatures::new(), 1, 2), (1, None));
+    }
+
+    #[test]
+    fn control_commands() {
+        let set = |pairs: u16| {
+            let [lo, hi] = pairs.to_le_bytes();
+            [VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, lo, hi]
+        };
+        assert_eq!(vq_pairs_set(&set(1), 4), Some(1));
+        assert_eq!(vq_pairs_set(&set(4), 4), Some(4));
+        // Out of range pair counts are rejected.
+        assert_eq!(vq_pairs_set(&set(0), 4), None);
+        assert_eq!(vq_pairs_set(&set(5), 4), None);
+        // Truncated commands are rejected.
+        assert_eq!(vq_pairs_set(&set(2)[..3], 4), None);
+        assert_eq!(vq_pairs_set(&[], 4), None);
+        // Other classes and commands are unsupported.
+        assert_eq!(vq_pairs_set(&[VIRTIO_NET_CTRL_MQ, 1, 2, 0], 4), None);
+        assert_eq!(
+            vq_pairs_set(&[0, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, 2, 0], 4),
+            None
+        );
+    }
+}
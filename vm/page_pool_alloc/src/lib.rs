@@ -458,6 +458,17 @@ pub fn new(size_pages: u64) -> anyhow::Result<Self> {
         Ok(Self { mem: fd, len })
     }
 
+    /// Create a new test mapper backed by the same internal buffer as `self`.
+    ///
+    /// This is useful for creating a new pool over the same memory, as is done
+    /// when restoring a pool after servicing.
+    pub fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(Self {
+            mem: self.mem.try_clone().context("cloning shared mem")?,
+            len: self.len,
+        })
+    }
+
     /// Returns [`SparseMapping`] that maps starting at page 0.
     pub fn sparse_mapping(&self) -> SparseMapping {
         let mappable = self.mappable();
@@ -56,6 +56,8 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     pub pm_base: u16,
     /// ACPI IRQ number
     pub acpi_irq: u32,
+    /// Additional raw tables, including their headers, to append to the XSDT.
+    pub extra_tables: &'a [Vec<u8>],
 }
 
 pub const OEM_INFO: acpi::builder::OemInfo = acpi::builder::OemInfo {
@@ -532,6 +534,9 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
         if self.cache_topology.is_some() {
             self.with_pptt(|t| b.append(t));
         }
+        for table in self.extra_tables {
+            b.append_raw(table);
+        }
 
         let (rdsp, tables) = b.build();
 
@@ -598,6 +603,7 @@ fn new_builder<'a>(
             with_psp: false,
             pm_base: 1234,
             acpi_irq: 2,
+            extra_tables: &[],
         }
     }
 
@@ -420,11 +420,41 @@ fn test_log_directory_path(test_name: &str) -> anyhow::Result<PathBuf> {
     } else {
         get_repo_root()?.join("vmm_test_results")
     };
-    // Use a per-test subdirectory, replacing `::` with `__` to avoid issues
-    // with filesystems that don't support `::` in filenames.
-    let path = root.join(test_name.replace("::", "__"));
-    fs_err::create_dir_all(&path)?;
-    Ok(path)
+    create_test_output_dir(&root, test_name, jiff::Timestamp::now())
+}
+
+/// Creates a new output directory for a run of `test_name` under `root`.
+///
+/// The directory is named after the test and the time of the run, so that
+/// repeated or concurrent runs of the same test do not overwrite each other's
+/// output.
+fn create_test_output_dir(
+    root: &Path,
+    test_name: &str,
+    now: jiff::Timestamp,
+) -> anyhow::Result<PathBuf> {
+    fs_err::create_dir_all(root)?;
+    // Replace `::` with `__` to avoid issues with filesystems that don't
+    // support `::` in filenames.
+    let base = format!(
+        "{}__{}",
+        test_name.replace("::", "__"),
+        now.strftime("%Y%m%d-%H%M%S%.3f")
+    );
+    // Runs started in the same millisecond get a numeric suffix.
+    for i in 0.. {
+        let path = if i == 0 {
+            root.join(&base)
+        } else {
+            root.join(format!("{base}_{i}"))
+        };
+        match fs_err::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    unreachable!()
 }
 
 const VMM_TESTS_DIR_ENV_VAR: &str = "VMM_TESTS_CONTENT_DIR";
@@ -569,3 +599,35 @@ fn to_error(self) -> anyhow::Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::create_test_output_dir;
+
+    #[test]
+    fn test_output_dirs_are_distinct() {
+        let root = tempfile::tempdir().unwrap();
+        let now = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+
+        let first = create_test_output_dir(root.path(), "multiarch::boot", now).unwrap();
+        let second = create_test_output_dir(root.path(), "multiarch::boot", now).unwrap();
+        let later = create_test_output_dir(
+            root.path(),
+            "multiarch::boot",
+            now + jiff::SignedDuration::from_secs(1),
+        )
+        .unwrap();
+
+        assert_eq!(
+            first.file_name().unwrap(),
+            "multiarch__boot__20231114-221320.000"
+        );
+        assert_ne!(first, second);
+        assert_ne!(first, later);
+        assert_ne!(second, later);
+        for dir in [&first, &second, &later] {
+            assert!(dir.is_dir());
+            assert_eq!(dir.parent().unwrap(), root.path());
+        }
+    }
+}
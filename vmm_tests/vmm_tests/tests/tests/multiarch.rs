@@ -4,11 +4,17 @@
 //! Integration tests that run on more than one architecture.
 
 use anyhow::Context;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
 use futures::StreamExt;
 use get_resources::ged::FirmwareEvent;
 use hyperv_ic_resources::kvp::KvpRpc;
 use jiff::SignedDuration;
 use mesh::rpc::RpcSend;
+use net_backend_resources::null::NullHandle;
+use pal_async::DefaultDriver;
+use pal_async::timer::PolledTimer;
+use petri::ControllerType;
 use petri::MemoryConfig;
 use petri::PetriGuestStateLifetime;
 use petri::PetriVmBuilder;
@@ -17,13 +23,21 @@
 use petri::ResolvedArtifact;
 use petri::SIZE_1_GB;
 use petri::ShutdownKind;
+use petri::openvmm::NET_QUEUES_MAC_ADDRESS;
 use petri::openvmm::NIC_MAC_ADDRESS;
 use petri::openvmm::OpenVmmPetriBackend;
+use petri::openvmm::VmmCrashError;
+use petri::openvmm::VpRegister;
+use petri::pipette::cmd;
 use petri_artifacts_common::tags::MachineArch;
 use petri_artifacts_common::tags::OsFlavor;
+use petri_artifacts_vmm_test::artifacts::test_vhd::UBUNTU_2204_SERVER_X64;
 use petri_artifacts_vmm_test::artifacts::test_vmgs::VMGS_WITH_BOOT_ENTRY;
 use std::time::Duration;
+use vm_resource::IntoResource;
 use vmm_core_defs::HaltReason;
+#[cfg(windows)]
+use vmm_test_macros::hyperv_test;
 use vmm_test_macros::openvmm_test;
 use vmm_test_macros::vmm_test;
 
@@ -158,6 +172,28 @@ async fn boot_reset_expected(config: PetriVmBuilder<OpenVmmPetriBackend>) -> any
     Ok(())
 }
 
+/// Test inspecting the VM's processor topology from the host.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn inspect_vp_count(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    const VP_COUNT: u32 = 4;
+    let (mut vm, agent) = config
+        .with_processor_topology(ProcessorTopology {
+            vp_count: VP_COUNT,
+            ..Default::default()
+        })
+        .run()
+        .await?;
+
+    let inspect::Node::Dir(vps) = vm.backend().inspect("partition/vp").await? else {
+        anyhow::bail!("expected a directory of VPs");
+    };
+    assert_eq!(vps.len(), VP_COUNT as usize);
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
 /// Test EFI diagnostics with no boot devices on OpenVMM.
 /// TODO:
 ///   - kmsg support in Hyper-V
@@ -191,6 +227,643 @@ async fn efi_diagnostics_no_boot(
     anyhow::bail!("Did not find expected message in kmsg");
 }
 
+/// Validate that the guest sees the configured number of virtio-net queues.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn virtio_net_queues(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    const QUEUES: u16 = 4;
+
+    // Linux only uses as many queue pairs as there are processors. Consomme
+    // only supports a single queue, so back the NIC with the null endpoint;
+    // this test only checks the queues the guest sees.
+    let (vm, agent) = config
+        .with_processor_count(QUEUES.into())
+        .modify_backend(|c| c.with_net_queues(QUEUES, NullHandle.into_resource()))
+        .run()
+        .await?;
+    let sh = agent.unix_shell();
+
+    let mac = NET_QUEUES_MAC_ADDRESS
+        .to_string()
+        .replace('-', ":")
+        .to_lowercase();
+    let links = cmd!(sh, "ip -o link show").read().await?;
+    let iface = links
+        .lines()
+        .find(|line| line.contains(&mac))
+        .and_then(|line| line.split(": ").nth(1))
+        .with_context(|| format!("no interface with mac {mac}:\n{links}"))?;
+
+    // `ethtool -l` reports the maximum and current channel counts, in that
+    // order. Virtio-net reports queue pairs as combined channels.
+    let channels = cmd!(sh, "ethtool -l {iface}").read().await?;
+    let combined = channels
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Combined:"))
+        .map(|count| count.trim().parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse ethtool output:\n{channels}"))?;
+    assert_eq!(combined, [QUEUES, QUEUES], "{channels}");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that pinging the agent is quick while it is running, and fails
+/// once it is gone.
+#[openvmm_test(linux_direct_x64)]
+async fn agent_ping(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+
+    let latency = agent.ping().await?;
+    tracing::info!(?latency, "agent ping");
+    assert!(latency < Duration::from_secs(1), "slow ping: {latency:?}");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    agent.ping().await.unwrap_err();
+    Ok(())
+}
+
+/// Validate logging in to the guest over the serial console with the
+/// configured credentials, as a fallback for when pipette is unavailable.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn serial_login(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.run().await?;
+
+    let mut console = vm.backend().serial_login().await?;
+    // Use arithmetic so that the echoed command line doesn't match.
+    console.write_all(b"echo serial-login-$((6 * 7))\n").await?;
+    let mut output = Vec::new();
+    let result = mesh::CancelContext::new()
+        .with_timeout(Duration::from_secs(60))
+        .until_cancelled(async {
+            let mut buf = [0; 256];
+            while !String::from_utf8_lossy(&output).contains("serial-login-42") {
+                let n = console.read(&mut buf).await?;
+                anyhow::ensure!(n != 0, "console closed");
+                output.extend_from_slice(&buf[..n]);
+            }
+            Ok(())
+        })
+        .await;
+    match result {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timed out waiting for command output")),
+    }
+    .with_context(|| format!("output: {:?}", String::from_utf8_lossy(&output)))?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the resource usage of the VMM process is recorded at
+/// teardown. On Linux hosts this is read from procfs, and on Windows hosts
+/// from the job object the VMM process runs in.
+#[openvmm_test(linux_direct_x64)]
+async fn vmm_resource_usage(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+    let output_dir = vm.output_dir().to_owned();
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    let usage = std::fs::read_to_string(output_dir.join("resource_usage.json"))
+        .context("resource usage was not recorded")?;
+    let usage: serde_json::Value = serde_json::from_str(&usage)?;
+    let peak_memory = usage["peak_memory_bytes"]
+        .as_u64()
+        .context("missing peak memory")?;
+    let cpu_time = usage["cpu_time_secs"]
+        .as_f64()
+        .context("missing cpu time")?;
+    assert_ne!(peak_memory, 0);
+    assert!(cpu_time > 0.0, "no cpu time: {cpu_time}");
+    Ok(())
+}
+
+/// Validate that a crash of the OpenVMM process is reported as a VMM crash,
+/// with a crash report attached to the test results, rather than as the VM
+/// disappearing.
+#[openvmm_test(linux_direct_x64)]
+async fn vmm_crash(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, _agent) = config.run().await?;
+
+    vm.backend().crash_vmm().await?;
+    let err = vm
+        .wait_for_halt()
+        .await
+        .expect_err("the VM halted instead of the VMM crashing");
+    let crash = err
+        .downcast_ref::<VmmCrashError>()
+        .with_context(|| format!("not a VMM crash: {err:#}"))?;
+    assert!(!crash.exit.success(), "{}", crash.exit);
+    assert!(
+        crash.stderr_tail.contains("explicit panic request"),
+        "{}",
+        crash.stderr_tail
+    );
+
+    let report_path = crash.report.as_deref().context("no crash report")?;
+    assert_eq!(
+        report_path.file_name().and_then(|name| name.to_str()),
+        Some("openvmm_crash.log")
+    );
+    let report = std::fs::read_to_string(report_path)?;
+    assert!(report.contains("VMM process crashed"), "{report}");
+    assert!(report.contains("explicit panic request"), "{report}");
+    Ok(())
+}
+
+/// Validate that the agent connection stays usable across an idle period
+/// while the keepalive is running, and that the keepalive does not reconnect
+/// once the agent is gone.
+#[openvmm_test(linux_direct_x64)]
+async fn agent_keepalive(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+    (): (),
+    driver: DefaultDriver,
+) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+    let agent = agent.with_keepalive(&driver, Duration::from_secs(1));
+
+    PolledTimer::new(&driver)
+        .sleep(Duration::from_secs(30))
+        .await;
+    // Allow for slow pings, but the keepalive must have run throughout.
+    let pings = agent.keepalive_pings().unwrap();
+    assert!(pings >= 10, "only {pings} keepalive pings");
+    agent.ping().await?;
+    let output = agent.command("true").output().await?;
+    assert!(output.status.success());
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    // Give the keepalive time to notice that the agent is gone.
+    PolledTimer::new(&driver)
+        .sleep(Duration::from_secs(15))
+        .await;
+    let err = agent.ping().await.unwrap_err();
+    assert!(format!("{err:#}").contains("does not reconnect"), "{err:#}");
+    Ok(())
+}
+
+/// Validate that once the keepalive loses the connection to a killed agent,
+/// every request fails immediately rather than waiting on the dead
+/// connection.
+#[openvmm_test(linux_direct_x64)]
+async fn agent_keepalive_lost(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+    (): (),
+    driver: DefaultDriver,
+) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.run().await?;
+    let agent = agent.with_keepalive(&driver, Duration::from_secs(1));
+
+    // Kill the agent, which is the parent of this shell, leaving the VM
+    // running.
+    agent
+        .command("sh")
+        .args(["-c", "sleep 1; kill -9 $PPID"])
+        .spawn()
+        .await?;
+
+    // Give the keepalive time to notice that the agent is gone.
+    PolledTimer::new(&driver)
+        .sleep(Duration::from_secs(15))
+        .await;
+    let start = std::time::Instant::now();
+    let errors = [
+        agent.ping().await.map(drop),
+        agent.command("true").output().await.map(drop),
+        agent.read_file("/etc/hostname").await.map(drop),
+        agent.write_file("/tmp/petri", b"x".as_slice()).await,
+    ];
+    for result in errors {
+        let err = result.expect_err("request succeeded without an agent");
+        assert!(
+            format!("{err:#}").contains("lost connection to the agent"),
+            "{err:#}"
+        );
+    }
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < Duration::from_secs(1),
+        "slow failures: {elapsed:?}"
+    );
+
+    // Reset the VM to get a new agent to power it off with.
+    vm.backend().reset().await?;
+    let agent = vm.wait_for_agent().await?;
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate running a multi-line script in a Linux guest, including killing
+/// it when it times out.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn guest_script(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+
+    let script = "echo one\n\
+                  echo two >&2\n\
+                  for i in 1 2 3; do printf $i; done\n\
+                  exit 3\n";
+    let output = agent.run_script(script, Duration::from_secs(30)).await?;
+    assert_eq!(String::from_utf8(output.stdout)?, "one\n123");
+    assert_eq!(String::from_utf8(output.stderr)?, "two\n");
+    assert_eq!(output.status.code(), Some(3));
+
+    agent
+        .run_script("sleep 600\n", Duration::from_secs(5))
+        .await
+        .unwrap_err();
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that environment variables set on the agent apply to later
+/// commands.
+#[openvmm_test(linux_direct_x64)]
+async fn agent_env(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+
+    agent.set_env("PETRI_TEST_VAR", "one");
+    agent.set_env("PETRI_TEST_VAR", "two");
+    let output = agent
+        .command("sh")
+        .args(["-c", "echo $PETRI_TEST_VAR"])
+        .output()
+        .await?;
+    assert_eq!(String::from_utf8(output.stdout)?, "two\n");
+
+    let output = agent
+        .command("sh")
+        .args(["-c", "echo $PETRI_TEST_VAR"])
+        .env_remove("PETRI_TEST_VAR")
+        .output()
+        .await?;
+    assert_eq!(String::from_utf8(output.stdout)?, "\n");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that SCSI disks added with `with_disk` are visible to the guest.
+#[vmm_test(
+    openvmm_uefi_x64(vhd(ubuntu_2204_server_x64)),
+    hyperv_uefi_x64(vhd(ubuntu_2204_server_x64))
+)]
+async fn extra_disks<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow::Result<()> {
+    extra_disks_core(config, ControllerType::Scsi).await
+}
+
+/// Validate that IDE disks added with `with_disk` are visible to the guest.
+#[vmm_test(
+    openvmm_pcat_x64(vhd(ubuntu_2204_server_x64)),
+    hyperv_pcat_x64(vhd(ubuntu_2204_server_x64))
+)]
+async fn extra_ide_disks<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow::Result<()> {
+    extra_disks_core(config, ControllerType::Ide).await
+}
+
+async fn extra_disks_core<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+    controller: ControllerType,
+) -> anyhow::Result<()> {
+    const DISK_SIZE: u64 = 64 * 1024 * 1024;
+
+    let dir = tempfile::tempdir()?;
+    let mut config = config;
+    for i in 0..2 {
+        let path = dir.path().join(format!("disk{i}.vhd"));
+        let file = std::fs::File::create(&path)?;
+        file.set_len(DISK_SIZE)?;
+        disk_vhd1::Vhd1Disk::make_fixed(&file)?;
+        config = config.with_disk(path, controller);
+    }
+    let (vm, agent) = config.run().await?;
+
+    let sh = agent.unix_shell();
+    let sizes = cmd!(sh, "lsblk -dnbo SIZE").read().await?;
+    let count = sizes
+        .lines()
+        .filter(|size| size.trim() == DISK_SIZE.to_string())
+        .count();
+    assert_eq!(count, 2, "disk sizes: {sizes}");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the guest makes no progress while the VM is paused, and
+/// continues once it is resumed.
+#[openvmm_test(linux_direct_x64)]
+async fn paused_vm_makes_no_progress(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> anyhow::Result<()> {
+    const COUNTER: &str = "/tmp/petri_counter";
+
+    let (mut vm, agent) = config.run().await?;
+
+    // The command never completes, so don't wait for it.
+    let _child = agent
+        .command("sh")
+        .args([
+            "-c",
+            &format!("i=0; while true; do i=$((i+1)); echo $i > {COUNTER}; sleep 1; done"),
+        ])
+        .spawn()
+        .await?;
+    let sh = agent.unix_shell();
+    let counter = async || -> anyhow::Result<u32> {
+        let count = cmd!(sh, "cat {COUNTER}").read().await?;
+        count
+            .trim()
+            .parse()
+            .with_context(|| format!("bad counter {count}"))
+    };
+    agent
+        .wait_for_process("sleep", Duration::from_secs(30))
+        .await?;
+
+    let before = counter().await?;
+    vm.with_paused(async |_| {
+        mesh::CancelContext::new()
+            .with_timeout(Duration::from_secs(10))
+            .cancelled()
+            .await;
+    })
+    .await?;
+    let paused = counter().await?;
+    // The counter would have advanced by about 10 if the guest had kept
+    // running.
+    assert!(
+        paused - before <= 3,
+        "counter advanced from {before} to {paused} while paused"
+    );
+
+    mesh::CancelContext::new()
+        .with_timeout(Duration::from_secs(5))
+        .cancelled()
+        .await;
+    let resumed = counter().await?;
+    assert!(resumed > paused, "counter stuck at {paused} after resuming");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate reading and writing guest physical memory, including that the
+/// guest observes the written data.
+#[openvmm_test(linux_direct_x64)]
+async fn guest_memory_access(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    // In the first 1MB, which Linux reserves and allows /dev/mem to access,
+    // but above the pages used by the loader.
+    const GPA: u64 = 0x80000;
+    const PATTERN: &[u8] = b"petri\x01\x02\x03";
+
+    let (mut vm, agent) = config.run().await?;
+
+    let read = vm
+        .with_paused(async |vm| {
+            vm.write_gpa(GPA, PATTERN).await?;
+            vm.read_gpa(GPA, PATTERN.len()).await
+        })
+        .await??;
+    assert_eq!(read, PATTERN);
+
+    let output = agent
+        .command("sh")
+        .args([
+            "-c",
+            &format!(
+                "dd if=/dev/mem bs=1 skip={GPA} count={} | od -An -tx1",
+                PATTERN.len()
+            ),
+        ])
+        .output()
+        .await?;
+    let output = String::from_utf8(output.stdout)?;
+    let expected = PATTERN
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>();
+    assert_eq!(output.split_whitespace().collect::<Vec<_>>(), expected);
+
+    // Accesses outside of RAM fail.
+    let ram_end = vm.memory_map().await?.ram.last().unwrap().end;
+    vm.read_gpa(ram_end - 4, 8).await.unwrap_err();
+    vm.write_gpa(u64::MAX - 4, PATTERN).await.unwrap_err();
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate reading and writing VP registers of a paused VM.
+#[openvmm_test(linux_direct_x64)]
+async fn vp_register_access(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    const CR0_PG: u64 = 1 << 31;
+    const EFER_LMA: u64 = 1 << 10;
+
+    let (mut vm, agent) = config
+        .modify_backend(|c| c.with_vp_register_access())
+        .run()
+        .await?;
+
+    // Registers can't be accessed while the VM is running.
+    vm.read_vp_register(0, VpRegister::X86Rip)
+        .await
+        .unwrap_err();
+
+    let (rip, cr0, efer, cs) = vm
+        .with_paused(async |vm| {
+            let rip = vm.read_vp_register(0, VpRegister::X86Rip).await?;
+            let cr0 = vm.read_vp_register(0, VpRegister::X86Cr0).await?;
+            let efer = vm.read_vp_register(0, VpRegister::X86Efer).await?;
+            let cs = vm.read_vp_register(0, VpRegister::X86CsSelector).await?;
+
+            // Writing back the current value round trips.
+            let rax = vm.read_vp_register(0, VpRegister::X86Gp(0)).await?;
+            vm.write_vp_register(0, VpRegister::X86Gp(0), rax).await?;
+            assert_eq!(vm.read_vp_register(0, VpRegister::X86Gp(0)).await?, rax);
+
+            // Registers from the wrong architecture are rejected.
+            vm.read_vp_register(0, VpRegister::Aarch64Pc)
+                .await
+                .unwrap_err();
+            anyhow::Ok((rip, cr0, efer, cs))
+        })
+        .await??;
+
+    // The booted kernel is running in long mode with paging enabled.
+    assert_ne!(cr0 & CR0_PG, 0, "cr0 = {cr0:#x}");
+    assert_ne!(efer & EFER_LMA, 0, "efer = {efer:#x}");
+    // The VP may have been paused in user mode, so RIP is only known to be a
+    // kernel address in the upper half of the address space when CPL is 0.
+    if cs & 3 == 0 {
+        assert!(rip >= 0xffff_8000_0000_0000, "rip = {rip:#x}");
+    } else {
+        assert!(rip < 0x0000_8000_0000_0000, "rip = {rip:#x}, cs = {cs:#x}");
+    }
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate waiting for a guest process that starts after a delay, including
+/// timing out when it never does.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn wait_for_guest_process(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    // A uniquely named copy of sleep, so that no other guest process matches.
+    const PROCESS: &str = "petri-delayed";
+    const DELAY: Duration = Duration::from_secs(5);
+
+    let (vm, agent) = config.run().await?;
+
+    let sh = agent.unix_shell();
+    cmd!(sh, "cp /bin/sleep /tmp/{PROCESS}").run().await?;
+    agent
+        .wait_for_process(PROCESS, Duration::from_secs(1))
+        .await
+        .unwrap_err();
+
+    // The command never completes, so don't wait for it.
+    let script = format!("sleep {}; exec /tmp/{PROCESS} 600", DELAY.as_secs());
+    let _child = agent
+        .command("sh")
+        .args(["-c", script.as_str()])
+        .spawn()
+        .await?;
+    let start = std::time::Instant::now();
+    agent
+        .wait_for_process(PROCESS, Duration::from_secs(60))
+        .await?;
+    // Allow for the time taken to spawn the command.
+    assert!(
+        start.elapsed() >= DELAY - Duration::from_secs(2),
+        "{PROCESS} found after {:?}, before it was started",
+        start.elapsed()
+    );
+    agent
+        .wait_for_process("petri-not-running", Duration::from_secs(3))
+        .await
+        .unwrap_err();
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the OpenVMM process log is written to the requested file at
+/// the requested level.
+#[openvmm_test(linux_direct_x64)]
+async fn openvmm_log_file(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let log_path = dir.path().join("openvmm.log");
+
+    let (vm, agent) = config
+        .modify_backend({
+            let log_path = log_path.clone();
+            move |c| c.with_openvmm_log(log_path, "debug")
+        })
+        .run()
+        .await?;
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    // The default level is info, so debug entries show the filter was applied.
+    let log = std::fs::read_to_string(&log_path)?;
+    assert!(
+        log.lines().any(|line| line.contains(" DEBUG ")),
+        "no debug entries in {}",
+        log_path.display()
+    );
+    Ok(())
+}
+
+/// Validate that a guest behind the NAT (consomme) network can connect to a
+/// TCP listener on the host, and that an unreachable port is reported.
+#[openvmm_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn guest_can_reach_host(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    // Connections from the guest are made from host sockets, so the guest can
+    // reach the host's listeners on any of its non-loopback addresses (the
+    // guest's own loopback would shadow the host's).
+    let host_ip = host_ipv4_address()?;
+    let listener = std::net::TcpListener::bind("0.0.0.0:0")?;
+    let open_port = listener.local_addr()?.port();
+    let closed_port = std::net::TcpListener::bind("0.0.0.0:0")?
+        .local_addr()?
+        .port();
+
+    let (vm, agent) = config.modify_backend(|c| c.with_nic()).run().await?;
+
+    vm.assert_guest_can_reach(&agent, &host_ip, open_port, Duration::from_secs(30))
+        .await?;
+
+    let err = vm
+        .assert_guest_can_reach(&agent, &host_ip, closed_port, Duration::from_secs(5))
+        .await
+        .expect_err("connection to a closed port should fail");
+    assert!(
+        err.to_string()
+            .contains(&format!("{host_ip}:{closed_port}")),
+        "{err:#}"
+    );
+
+    drop(listener);
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Returns a non-loopback IPv4 address assigned to the host.
+fn host_ipv4_address() -> anyhow::Result<String> {
+    let output = if cfg!(windows) {
+        std::process::Command::new("powershell.exe")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "Get-NetIPAddress -AddressFamily IPv4 -AddressState Preferred | Select-Object -ExpandProperty IPAddress",
+            ])
+            .output()?
+    } else {
+        std::process::Command::new("ip")
+            .args(["-4", "-o", "address", "show"])
+            .output()?
+    };
+    anyhow::ensure!(
+        output.status.success(),
+        "failed to list host addresses: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // `ip -o` prints one address per line, as "<index>: <interface> inet
+    // <address>/<prefix> ...".
+    let output = String::from_utf8(output.stdout)?;
+    output
+        .lines()
+        .filter_map(|line| {
+            let addr = match line.split_once(" inet ") {
+                Some((_, rest)) => rest.split('/').next()?,
+                None => line.trim(),
+            };
+            addr.parse::<std::net::Ipv4Addr>().ok()
+        })
+        .find(|addr| !addr.is_loopback())
+        .map(|addr| addr.to_string())
+        .with_context(|| format!("no non-loopback host address:\n{output}"))
+}
+
 /// Test the KVP IC.
 ///
 /// Windows-only right now, because the Linux images do not include the KVP IC
@@ -316,6 +989,31 @@ async fn timesync_ic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Res
     Ok(())
 }
 
+/// Validate that the guest clock keeps pace with the host clock.
+#[vmm_test(
+    openvmm_linux_direct_x64,
+    openvmm_uefi_x64(vhd(ubuntu_2204_server_x64)),
+    openvmm_uefi_x64(vhd(windows_datacenter_core_2022_x64)),
+    openvmm_uefi_aarch64(vhd(ubuntu_2404_server_aarch64)),
+    hyperv_openhcl_uefi_x64(vhd(ubuntu_2204_server_x64))
+)]
+async fn clock_drift<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow::Result<()> {
+    let (vm, agent) = config.run().await?;
+
+    let drift = vm
+        .measure_clock_drift(&agent, Duration::from_secs(10))
+        .await?;
+    tracing::info!(%drift, "guest clock drift");
+    assert!(
+        drift.abs() < SignedDuration::from_millis(100),
+        "guest clock drifted by {drift}"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
 /// Validate we can reboot a VM and reconnect to pipette.
 // TODO: Reenable guests that use the framebuffer once #74 is fixed.
 #[openvmm_test(
@@ -349,6 +1047,429 @@ async fn reboot(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyho
     Ok(())
 }
 
+/// Validate that a host directory shared via virtio-9p is readable in the guest.
+#[openvmm_test(openvmm_uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn shared_folder(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    const CONTENTS: &str = "hello from the host";
+
+    let share = tempfile::tempdir()?;
+    std::fs::write(share.path().join("hello.txt"), CONTENTS)?;
+
+    let (vm, agent) = config
+        .modify_backend(|b| b.with_shared_folder(share.path(), "petri_share"))
+        .run()
+        .await?;
+
+    let sh = agent.unix_shell();
+    cmd!(sh, "mkdir -p /mnt/share").run().await?;
+    cmd!(sh, "mount -t 9p -o trans=virtio petri_share /mnt/share")
+        .run()
+        .await?;
+    let output = sh.read_file("/mnt/share/hello.txt").await?;
+    assert_eq!(output, CONTENTS);
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate from the host that the boot disk, NIC, and ICs are offered over
+/// VMBus and opened by the guest.
+#[openvmm_test(openvmm_uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn vmbus_channels(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.modify_backend(|b| b.with_nic()).run().await?;
+
+    let channels = vm.backend().vmbus_channels().await?;
+    for name in ["scsi", "net", "shutdown_ic"] {
+        let channel = channels
+            .iter()
+            .find(|c| c.interface_name == name)
+            .with_context(|| format!("no {name} channel in {channels:?}"))?;
+        assert_eq!(channel.state, "open", "{channel:?}");
+    }
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that a guest kernel panic produces a crash artifact.
+#[openvmm_test(openvmm_linux_direct_x64)]
+async fn guest_crash_dump(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let crash_dir = tempfile::tempdir()?;
+    let (vm, agent) = config.with_guest_crash_dump(crash_dir.path()).run().await?;
+
+    // The command never completes, so don't wait for it.
+    let _child = agent
+        .command("sh")
+        .args(["-c", "echo c > /proc/sysrq-trigger"])
+        .spawn()
+        .await?;
+
+    // Teardown waits for the serial log to be drained.
+    vm.wait_for_teardown().await?;
+
+    let output =
+        std::fs::read_to_string(crash_dir.path().join(petri::GuestPanicCapture::FILE_NAME))?;
+    assert!(output.contains("Kernel panic"), "{output}");
+    Ok(())
+}
+
+/// Validate that the guest disks are differencing disks of the guest VHD
+/// artifacts, including after a disk reset.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn guest_differencing_disks(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    use petri::hyperv::powershell;
+
+    fn check_disks(vm: &petri::hyperv::HyperVPetriRuntime) -> anyhow::Result<()> {
+        let mut count = 0;
+        for (path, parent) in vm.guest_disks() {
+            let info = powershell::run_get_vhd(path)?;
+            assert_eq!(info.vhd_type, powershell::VhdType::Differencing);
+            assert_eq!(
+                info.parent_path.as_deref(),
+                Some(parent),
+                "unexpected parent of {}",
+                path.display()
+            );
+            count += 1;
+        }
+        assert_ne!(count, 0);
+        Ok(())
+    }
+
+    let (mut vm, agent) = config.run().await?;
+    check_disks(vm.backend())?;
+
+    vm.backend().reset_disk().await?;
+    let agent = vm.wait_for_agent().await?;
+    check_disks(vm.backend())?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that resetting the guest disk discards changes made by the guest.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn reset_disk(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    const MARKER: &str = "/var/petri_reset_disk_marker";
+
+    let (mut vm, agent) = config.run().await?;
+    let sh = agent.unix_shell();
+    cmd!(sh, "touch {MARKER}").run().await?;
+    cmd!(sh, "sync").run().await?;
+    cmd!(sh, "test -e {MARKER}").run().await?;
+
+    vm.backend().reset_disk().await?;
+    let agent = vm.wait_for_agent().await?;
+    let sh = agent.unix_shell();
+    let output = cmd!(sh, "test -e {MARKER}")
+        .ignore_status()
+        .output()
+        .await?;
+    assert!(!output.status.success(), "marker survived the disk reset");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the guest disk can be merged into a copy of its parent at
+/// teardown, including the guest's writes, without modifying the shared
+/// parent.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn merge_disks(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.with_merged_disks().run().await?;
+    let output_dir = vm.output_dir().to_owned();
+    let parents = vm
+        .backend()
+        .guest_disk_parents()
+        .map(|path| Ok((path.to_owned(), std::fs::metadata(path)?.modified()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(!parents.is_empty());
+
+    // A unique marker, so that it can only be found in the merged disk if the
+    // guest's write was merged.
+    let marker = format!("petri-merge-disks-{}", guid::Guid::new_random());
+    agent
+        .write_file("/var/petri_merge_disks_marker", marker.as_bytes())
+        .await?;
+    let sh = agent.unix_shell();
+    cmd!(sh, "sync").run().await?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    for (parent, modified) in parents {
+        assert_eq!(
+            std::fs::metadata(&parent)?.modified()?,
+            modified,
+            "{} was modified",
+            parent.display()
+        );
+    }
+    let merged = std::fs::read_dir(&output_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("merged_"))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    assert_eq!(merged.len(), 1);
+    assert!(
+        file_contains(&merged[0], marker.as_bytes())?,
+        "marker not found in {}",
+        merged[0].display()
+    );
+    Ok(())
+}
+
+/// Returns whether the file at `path` contains `needle` anywhere in its raw
+/// bytes.
+#[cfg(windows)]
+fn file_contains(path: &std::path::Path, needle: &[u8]) -> anyhow::Result<bool> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0; 1024 * 1024];
+    // Keep the end of the previous chunk, in case the needle spans chunks.
+    let mut len = 0;
+    loop {
+        let n = file.read(&mut buf[len..])?;
+        if n == 0 {
+            return Ok(false);
+        }
+        len += n;
+        if buf[..len].windows(needle.len()).any(|w| w == needle) {
+            return Ok(true);
+        }
+        let keep = (needle.len() - 1).min(len);
+        buf.copy_within(len - keep..len, 0);
+        len = keep;
+    }
+}
+
+/// Validate that a graceful shutdown leaves the guest filesystem clean, and
+/// that a forced power off is detected as an unclean shutdown.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn clean_shutdown(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    let (mut vm, _agent) = config.run().await?;
+
+    vm.backend().power_cycle(false).await?;
+    let agent = vm.wait_for_agent().await?;
+    vm.assert_clean_shutdown(&agent).await?;
+
+    // Write to the filesystem so that it is in use when the VM is killed.
+    // The marker is named after this test process, so that one left behind
+    // by another run can't be mistaken for it.
+    let marker = format!("/var/petri_clean_shutdown_marker_{}", std::process::id());
+    let sh = agent.unix_shell();
+    cmd!(sh, "touch {marker}").run().await?;
+    cmd!(sh, "sync").run().await?;
+
+    vm.backend().power_cycle(true).await?;
+    let agent = vm.wait_for_agent().await?;
+    let err = vm
+        .assert_clean_shutdown(&agent)
+        .await
+        .expect_err("forced power off should be detected as unclean");
+    tracing::info!(
+        error = err.as_ref() as &dyn std::error::Error,
+        "detected unclean shutdown"
+    );
+    // The recovered filesystem is the one this run wrote to.
+    let sh = agent.unix_shell();
+    cmd!(sh, "test -e {marker}").run().await?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the default Hyper-V devices can be kept.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(none))]
+async fn keep_default_devices(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    let mut vm = config.with_default_devices().run_without_agent().await?;
+    assert_eq!(vm.backend().vm().scsi_controller_count()?, 1);
+
+    // There is no guest to power the VM off, so turn it off from the host.
+    vm.backend().vm().kill()?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that attaching to an existing VM uses that VM, and leaves it in
+/// place when the test is done with it.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(none))]
+async fn attach_existing_vm(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    use petri::hyperv::powershell;
+
+    // Put the VM in its own group so that it can be cleaned up afterwards.
+    let name = format!("petri-attach-{}", guid::Guid::new_random());
+    let group = format!("{name}-group");
+    let vmid = powershell::run_new_vm(powershell::HyperVNewVMArgs {
+        name: &name,
+        generation: Some(powershell::HyperVGeneration::Two),
+        guest_state_isolation_type: None,
+        memory_startup_bytes: None,
+        path: None,
+        vhd_path: None,
+    })?;
+    powershell::run_ensure_vm_group(&group)?;
+    powershell::run_add_vm_group_member(&vmid, &group)?;
+
+    let result = async {
+        let mut vm = config.with_existing_vm(&name).run_without_agent().await?;
+        assert_eq!(*vm.backend().vm().vmid(), vmid);
+        drop(vm);
+        assert_eq!(powershell::vm_id_from_name(&name)?, [vmid]);
+        anyhow::Ok(())
+    }
+    .await;
+    let cleanup = petri::hyperv::vm::remove_vm_group(&group);
+    result?;
+    cleanup
+}
+
+/// Validate the Hyper-V VM power state reported while running and after
+/// shutdown.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(none))]
+async fn power_state(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    use petri::hyperv::VmState;
+
+    let mut vm = config.run_without_agent().await?;
+    let state = vm.backend().power_state()?;
+    assert!(
+        matches!(state, VmState::Starting | VmState::Running),
+        "{state:?}"
+    );
+
+    // There is no guest to power the VM off, so turn it off from the host.
+    vm.backend().vm().kill()?;
+    vm.backend().vm().wait_for_off().await?;
+    assert_eq!(vm.backend().power_state()?, VmState::Off);
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that the VM can be paused and resumed, and saved and restored.
+#[cfg(windows)]
+#[hyperv_test(uefi_x64(vhd(ubuntu_2204_server_x64)))]
+async fn save_resume(
+    config: PetriVmBuilder<petri::hyperv::HyperVPetriBackend>,
+) -> anyhow::Result<()> {
+    use petri::hyperv::VmState;
+
+    let (mut vm, agent) = config.run().await?;
+
+    vm.backend().vm().pause().await?;
+    vm.backend().vm().resume().await?;
+    agent.ping().await?;
+
+    vm.backend().vm().save().await?;
+    assert_eq!(vm.backend().power_state()?, VmState::Saved);
+    vm.backend().vm().resume().await?;
+    assert_eq!(vm.backend().power_state()?, VmState::Running);
+
+    // The agent's connection does not survive the save, so shut down through
+    // the shutdown IC instead.
+    vm.backend().vm().stop().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Validate that a cloud-init failure in the guest is reported.
+async fn cloud_init_failure_reported<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+) -> anyhow::Result<()> {
+    // Keep the default configuration so that pipette still starts, but add
+    // a command that fails.
+    let mut config_yaml: serde_yaml::Mapping =
+        serde_yaml::from_slice(petri::disk_image::DEFAULT_CLOUD_INIT_USER_DATA)?;
+    let runcmd = config_yaml
+        .entry("runcmd".into())
+        .or_insert_with(|| serde_yaml::Sequence::new().into());
+    runcmd
+        .as_sequence_mut()
+        .context("runcmd is not a list")?
+        .push(vec!["false"].into());
+    let mut user_data = b"#cloud-config\n".to_vec();
+    user_data.extend_from_slice(serde_yaml::to_string(&config_yaml)?.as_bytes());
+
+    let (vm, agent) = config.with_cloud_init_user_data(user_data).run().await?;
+
+    let err = vm.assert_cloud_init_ok(&agent).await.unwrap_err();
+    assert!(
+        format!("{err:#}").contains("cloud-init reported failure"),
+        "{err:#}"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    Ok(())
+}
+
+petri::backend_matrix_test!(
+    cloud_init_failure_reported,
+    MachineArch::X86_64,
+    |resolver| {
+        petri::Firmware::uefi(
+            resolver,
+            MachineArch::X86_64,
+            petri::UefiGuest::Vhd(petri::BootImageConfig::from_vhd(
+                resolver.require(UBUNTU_2204_SERVER_X64),
+            )),
+        )
+    }
+);
+
+/// Validate that a guest-initiated reboot on Hyper-V, where the VM restarts
+/// without halting, is reported as a reset and can be followed by a fresh
+/// pipette connection.
+#[vmm_test(
+    hyperv_uefi_x64(vhd(ubuntu_2204_server_x64)),
+    hyperv_openhcl_uefi_x64(vhd(ubuntu_2204_server_x64))
+)]
+#[cfg_attr(not(windows), expect(dead_code))]
+async fn reboot_reconnect<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+) -> Result<(), anyhow::Error> {
+    let (mut vm, agent) = config.run().await?;
+
+    agent.ping().await?;
+    agent.reboot().await?;
+    assert_eq!(vm.wait_for_halt().await?, HaltReason::Reset);
+
+    let agent = vm.wait_for_agent().await?;
+    agent.ping().await?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    Ok(())
+}
+
 /// Basic boot test without agent
 // TODO: investigate why the shutdown ic doesn't work reliably with hyper-v
 // in our ubuntu image
@@ -383,6 +1504,22 @@ async fn boot_no_agent<T: PetriVmmBackend>(config: PetriVmBuilder<T>) -> anyhow:
     Ok(())
 }
 
+/// Request a shutdown as soon as the VM has been started, without waiting for
+/// a boot event, to make sure the VM is not stopped while still starting.
+#[vmm_test(
+    hyperv_uefi_x64(vhd(windows_datacenter_core_2022_x64)),
+    hyperv_openhcl_uefi_x64(vhd(windows_datacenter_core_2022_x64))
+)]
+#[cfg_attr(not(windows), expect(dead_code))]
+async fn shutdown_immediately_after_start<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+) -> anyhow::Result<()> {
+    let mut vm = config.run_without_agent().await?;
+    vm.send_enlightened_shutdown(ShutdownKind::Shutdown).await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
 // Basic vp "heavy" boot test without agent with 16 VPs.
 #[vmm_test(
     openvmm_linux_direct_x64,
@@ -622,6 +1759,48 @@ async fn file_transfer_test<T: PetriVmmBackend>(
     Ok(())
 }
 
+/// Test transferring a file to the guest and reading it back, both buffered
+/// and streamed to a host file.
+#[vmm_test(
+    openvmm_linux_direct_x64,
+    openvmm_uefi_x64(vhd(windows_datacenter_core_2022_x64)),
+    openvmm_uefi_x64(vhd(ubuntu_2204_server_x64)),
+    hyperv_openhcl_uefi_x64(vhd(ubuntu_2204_server_x64))
+)]
+async fn file_get_round_trip<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+) -> Result<(), anyhow::Error> {
+    const FILE_NAME: &str = "round_trip.bin";
+    let test_content = (0..=255u8).cycle().take(1024 * 1024).collect::<Vec<_>>();
+
+    let (vm, agent) = config.run().await?;
+
+    agent.write_file(FILE_NAME, test_content.as_slice()).await?;
+
+    let host_dir = tempfile::tempdir()?;
+    let host_path = host_dir.path().join(FILE_NAME);
+    let n = agent.get_file(FILE_NAME, &host_path).await?;
+    assert_eq!(n, test_content.len() as u64);
+    assert_eq!(std::fs::read(&host_path)?, test_content);
+    // The file spans many pipe messages either way.
+    assert_eq!(agent.read_file(FILE_NAME).await?, test_content);
+
+    // Missing files fail without leaving a partial file behind.
+    let missing_path = host_dir.path().join("missing.bin");
+    assert!(
+        agent
+            .get_file("this_file_does_not_exist", &missing_path)
+            .await
+            .is_err()
+    );
+    assert!(!missing_path.exists());
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    Ok(())
+}
+
 /// Boot Linux and have it write the visible memory size.
 #[openvmm_test(linux_direct_x64, uefi_aarch64(vhd(ubuntu_2404_server_aarch64)))]
 async fn five_gb(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyhow::Error> {
@@ -153,6 +153,48 @@ async fn keepalive<T: PetriVmmBackend>(
     .await
 }
 
+/// Test that servicing does not log any new errors in the guest, and that an
+/// error logged by the guest would be caught.
+#[openvmm_test(openhcl_linux_direct_x64 [LATEST_LINUX_DIRECT_TEST_X64])]
+async fn guest_kernel_log<T: PetriVmmBackend>(
+    config: PetriVmBuilder<T>,
+    (igvm_file,): (ResolvedArtifact<impl petri_artifacts_common::tags::IsOpenhclIgvm>,),
+) -> Result<(), anyhow::Error> {
+    if !host_supports_servicing() {
+        tracing::info!("skipping OpenHCL servicing test on unsupported host");
+        return Ok(());
+    }
+
+    let (mut vm, agent) = config.run().await?;
+
+    let before = vm.capture_guest_kernel_log(&agent).await?;
+    vm.restart_openhcl(
+        igvm_file,
+        OpenHclServicingFlags {
+            override_version_checks: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    agent.ping().await?;
+    let after = vm.capture_guest_kernel_log(&agent).await?;
+    after.assert_no_new_errors_since(&before)?;
+
+    // Log an error from the guest and make sure it is detected.
+    agent
+        .write_file("/dev/kmsg", b"<3>petri: injected error\n".as_slice())
+        .await?;
+    let injected = vm.capture_guest_kernel_log(&agent).await?;
+    let errors = injected.new_errors_since(&after);
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].ends_with("petri: injected error"), "{errors:?}");
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    Ok(())
+}
+
 #[openvmm_test(openhcl_linux_direct_x64 [LATEST_LINUX_DIRECT_TEST_X64])]
 async fn shutdown_ic(
     config: PetriVmBuilder<OpenVmmPetriBackend>,
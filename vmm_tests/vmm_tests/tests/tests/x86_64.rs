@@ -13,9 +13,11 @@
 use petri::PetriVmmBackend;
 use petri::ProcessorTopology;
 use petri::ShutdownKind;
+use petri::openvmm::MemoryMap;
 use petri::openvmm::OpenVmmPetriBackend;
 use petri::pipette::cmd;
 use petri_artifacts_common::tags::OsFlavor;
+use vmbus_core::protocol::Version;
 use vmm_core_defs::HaltReason;
 use vmm_test_macros::openvmm_test;
 use vmm_test_macros::vmm_test;
@@ -262,6 +264,174 @@ async fn mtrrs(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<(), anyhow
     Ok(())
 }
 
+/// Validate that a custom ACPI table is visible to the guest.
+#[openvmm_test(linux_direct_x64)]
+async fn custom_acpi_table(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    // An SSDT with just a header, and so no definition blocks.
+    let mut ssdt = Vec::new();
+    ssdt.extend_from_slice(b"SSDT");
+    ssdt.extend_from_slice(&36u32.to_le_bytes());
+    ssdt.push(2); // revision
+    ssdt.push(0); // checksum
+    ssdt.extend_from_slice(b"PETRI ");
+    ssdt.extend_from_slice(b"PETRISST");
+    ssdt.extend_from_slice(&1u32.to_le_bytes());
+    ssdt.extend_from_slice(b"MSFT");
+    ssdt.extend_from_slice(&1u32.to_le_bytes());
+    ssdt[9] = ssdt.iter().fold(0u8, |sum, &b| sum.wrapping_sub(b));
+
+    let (vm, agent) = config
+        .modify_backend({
+            let ssdt = ssdt.clone();
+            move |b| b.with_custom_acpi_table(*b"SSDT", ssdt)
+        })
+        .run()
+        .await?;
+    let table = agent.read_file("/sys/firmware/acpi/tables/SSDT").await?;
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    assert_eq!(table, ssdt);
+    Ok(())
+}
+
+/// Validate the guest memory map of a default configuration: 4GB of RAM split
+/// around the MMIO gap below 4GB, with a second MMIO gap below 64GB.
+#[openvmm_test(linux_direct_x64)]
+async fn default_memory_map(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, agent) = config.run().await?;
+
+    vm.backend()
+        .assert_memory_map(&MemoryMap {
+            ram: vec![0..0xf800_0000, 0x1_0000_0000..0x1_0800_0000],
+            mmio: vec![0xf800_0000..0x1_0000_0000, 0xf_e000_0000..0x10_0000_0000],
+            vtl2: None,
+        })
+        .await?;
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Boot with the Hyper-V enlightenments emulated by OpenVMM instead of the
+/// hypervisor, and validate that the guest still detects Hyper-V and that
+/// VMBus works.
+///
+/// This only has an effect on WHP.
+#[cfg(windows)]
+#[openvmm_test(linux_direct_x64)]
+async fn user_mode_enlightenments(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> anyhow::Result<()> {
+    let (mut vm, agent) = config
+        .modify_backend(|b| b.with_offloaded_enlightenments(false))
+        .run()
+        .await?;
+
+    let enlightened = vm
+        .backend()
+        .inspect("partition/vtl0/hypervisor_enlightened")
+        .await?;
+    assert!(
+        matches!(
+            enlightened,
+            inspect::Node::Value(inspect::Value {
+                kind: inspect::ValueKind::Bool(false),
+                ..
+            })
+        ),
+        "enlightenments were offloaded to the hypervisor: {enlightened:?}"
+    );
+
+    // The agent connects over VMBus, so reaching this point means the
+    // emulated SynIC works.
+    let sh = agent.unix_shell();
+    let dmesg = cmd!(sh, "dmesg").read().await?;
+    assert!(
+        dmesg.contains("Hypervisor detected: Microsoft Hyper-V"),
+        "guest did not detect Hyper-V"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Boot with the APIC emulated by OpenVMM, and validate that it is used.
+///
+/// The APIC is only ever emulated in user mode on WHP, which is the
+/// hypervisor OpenVMM uses on Windows.
+#[cfg(windows)]
+#[openvmm_test(linux_direct_x64)]
+async fn user_mode_apic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    check_user_mode_apic(config, true).await
+}
+
+/// Boot with the user mode APIC explicitly disabled, and validate that the
+/// APIC is offloaded to the hypervisor.
+#[cfg(windows)]
+#[openvmm_test(linux_direct_x64)]
+async fn offloaded_apic(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    check_user_mode_apic(config, false).await
+}
+
+#[cfg(windows)]
+async fn check_user_mode_apic(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+    enable: bool,
+) -> anyhow::Result<()> {
+    let (mut vm, agent) = config
+        .modify_backend(move |b| b.with_user_mode_apic(enable))
+        .run()
+        .await?;
+
+    // The local APIC is inspected as a single field named for how it is
+    // implemented.
+    let lapic = vm.backend().inspect("partition/vtl0/lapic").await?;
+    let inspect::Node::Dir(entries) = &lapic else {
+        anyhow::bail!("unexpected lapic node: {lapic:?}");
+    };
+    let expected = if enable { "emulated" } else { "offloaded" };
+    assert!(
+        entries.iter().any(|entry| entry.name == expected),
+        "expected an {expected} APIC: {lapic:?}"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
+/// Cap the VMBus protocol version and validate that the guest falls back to
+/// it.
+#[openvmm_test(linux_direct_x64)]
+async fn vmbus_max_version(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Result<()> {
+    let (mut vm, agent) = config
+        .modify_backend(|b| b.with_vmbus_max_version(Version::Win10 as u32))
+        .run()
+        .await?;
+
+    let version = vm
+        .backend()
+        .inspect("vmbus/connection_info/version/version")
+        .await?;
+    assert!(
+        matches!(
+            &version,
+            inspect::Node::Value(inspect::Value {
+                kind: inspect::ValueKind::String(v),
+                ..
+            }) if v == "win10"
+        ),
+        "unexpected negotiated version: {version:?}"
+    );
+
+    agent.power_off().await?;
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+    Ok(())
+}
+
 /// Boot with vmbus redirection and shut down.
 #[openvmm_test(
     openhcl_linux_direct_x64,
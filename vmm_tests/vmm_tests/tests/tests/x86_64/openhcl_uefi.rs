@@ -5,6 +5,8 @@
 
 use anyhow::Context;
 use futures::StreamExt;
+use hvlite_defs::config::LoadMode;
+use hvlite_defs::config::Vtl2BaseAddressType;
 use petri::OpenHclServicingFlags;
 use petri::PetriVmBuilder;
 use petri::ProcessorTopology;
@@ -136,6 +138,44 @@ async fn auto_vtl2_range(config: PetriVmBuilder<OpenVmmPetriBackend>) -> Result<
     Ok(())
 }
 
+/// Boot the UEFI firmware with an explicit VTL2 memory size, and validate that
+/// the size reaches the load config and the resulting VTL2 range.
+#[openvmm_test(openhcl_uefi_x64(none))]
+async fn vtl2_memory_size(
+    config: PetriVmBuilder<OpenVmmPetriBackend>,
+) -> Result<(), anyhow::Error> {
+    const VTL2_SIZE: u64 = 512 * 1024 * 1024;
+
+    let mut vm = config
+        .modify_backend(|b| {
+            b.with_vtl2_memory_size(VTL2_SIZE).with_custom_config(|c| {
+                assert!(matches!(
+                    c.load_mode,
+                    LoadMode::Igvm {
+                        vtl2_base_address: Vtl2BaseAddressType::MemoryLayout {
+                            size: Some(VTL2_SIZE)
+                        },
+                        ..
+                    }
+                ));
+            })
+        })
+        .run_without_agent()
+        .await?;
+
+    vm.wait_for_successful_boot_event().await?;
+    let vtl2 = vm
+        .backend()
+        .memory_map()
+        .await?
+        .vtl2
+        .context("no vtl2 range in memory layout")?;
+    assert_eq!(vtl2.end - vtl2.start, VTL2_SIZE);
+    assert_eq!(vm.wait_for_teardown().await?, HaltReason::PowerOff);
+
+    Ok(())
+}
+
 /// Boot OpenHCL, and validate that we did not see any numa errors from the
 /// kernel parsing the bootloader provided device tree.
 ///